@@ -12,6 +12,117 @@ pub struct NodesConfig {
     pub peers: HashMap<NodeID, NodeConfig>,
     pub this: (NodeID, NodeConfig),
     pub file_dir: PathBuf,
+    /// holders planned for each data item, app.yaml can override it per key pattern
+    pub replication_factor: usize,
+    pub batch_transfer: BatchTransferConfig,
+    /// None when this node doesn't serve the s3 gateway
+    pub s3_gateway: Option<S3GatewayConfig>,
+    /// storage limits of the apps, checked by master
+    pub quotas: QuotaConfig,
+    /// retention of the data mutation audit log on master
+    pub audit: AuditConfig,
+    /// data export and import only use archive dirs under it, None disables them over http
+    pub archive_root: Option<PathBuf>,
+}
+
+/// Block transfer of large items to cache nodes, streaming writes send segments of the same size
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchTransferConfig {
+    #[serde(default = "default_batch_block_size")]
+    pub block_size: usize,
+    /// blocks in flight per transfer
+    #[serde(default = "default_batch_concurrency")]
+    pub concurrency: usize,
+    /// retries of a failed block before the transfer fails
+    #[serde(default = "default_batch_block_retries")]
+    pub block_retries: usize,
+}
+
+impl Default for BatchTransferConfig {
+    fn default() -> Self {
+        Self {
+            block_size: default_batch_block_size(),
+            concurrency: default_batch_concurrency(),
+            block_retries: default_batch_block_retries(),
+        }
+    }
+}
+
+fn default_batch_block_size() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_batch_concurrency() -> usize {
+    32
+}
+
+fn default_batch_block_retries() -> usize {
+    3
+}
+
+/// Local S3 compatible gateway over the datasets, there's no auth so keep it on a local address
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3GatewayConfig {
+    #[serde(default = "default_s3_gateway_addr")]
+    pub addr: SocketAddr,
+    /// nodes serving the gateway, empty for all of them
+    #[serde(default)]
+    pub nodes: Vec<NodeID>,
+}
+
+fn default_s3_gateway_addr() -> SocketAddr {
+    "127.0.0.1:9000".parse().unwrap()
+}
+
+/// Storage quotas by app, an app not listed gets the default one, no quota when both are absent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    #[serde(default)]
+    pub default: Option<AppQuota>,
+    #[serde(default)]
+    pub apps: HashMap<String, AppQuota>,
+}
+
+impl QuotaConfig {
+    pub fn of_app(&self, app: &str) -> Option<&AppQuota> {
+        self.apps.get(app).or(self.default.as_ref())
+    }
+}
+
+/// Over a soft limit only warns, a write over a hard limit fails. Absent limits are unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppQuota {
+    #[serde(default)]
+    pub soft_bytes: Option<u64>,
+    #[serde(default)]
+    pub hard_bytes: Option<u64>,
+    #[serde(default)]
+    pub soft_keys: Option<u64>,
+    #[serde(default)]
+    pub hard_keys: Option<u64>,
+}
+
+/// Records of the audit log older than `max_age_secs`, or beyond the newest `max_records`,
+///  are purged. 0 doesn't limit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    #[serde(default = "default_audit_max_age_secs")]
+    pub max_age_secs: u64,
+    #[serde(default)]
+    pub max_records: u64,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            max_age_secs: default_audit_max_age_secs(),
+            max_records: 0,
+        }
+    }
+}
+
+fn default_audit_max_age_secs() -> u64 {
+    7 * 24 * 3600
 }
 
 impl NodesConfig {
@@ -112,9 +223,26 @@ impl NodeConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct YamlConfig {
     pub nodes: HashMap<NodeID, NodeConfig>,
+    #[serde(default = "default_replication_factor")]
+    pub replication_factor: usize,
+    #[serde(default)]
+    pub batch_transfer: BatchTransferConfig,
+    #[serde(default)]
+    pub s3_gateway: Option<S3GatewayConfig>,
+    #[serde(default)]
+    pub quotas: QuotaConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// relative to the node's data dir
+    #[serde(default)]
+    pub archive_root: Option<PathBuf>,
     // pub this: NodeID,
 }
 
+fn default_replication_factor() -> usize {
+    1
+}
+
 fn read_yaml_config(file_path: impl AsRef<Path>) -> YamlConfig {
     tracing::info!("Running at dir: {:?}", std::env::current_dir());
     let path = file_path.as_ref().to_owned();
@@ -134,5 +262,19 @@ pub fn read_config(this_id: NodeID, file_path: impl AsRef<Path>) -> NodesConfig
         this: (this_id, yaml_config.nodes.remove(&this_id).unwrap()),
         peers: yaml_config.nodes,
         file_dir: file_path.as_ref().to_path_buf(),
+        replication_factor: yaml_config.replication_factor.max(1),
+        batch_transfer: BatchTransferConfig {
+            block_size: yaml_config.batch_transfer.block_size.max(1),
+            concurrency: yaml_config.batch_transfer.concurrency.max(1),
+            block_retries: yaml_config.batch_transfer.block_retries,
+        },
+        s3_gateway: yaml_config
+            .s3_gateway
+            .filter(|conf| conf.nodes.is_empty() || conf.nodes.contains(&this_id)),
+        quotas: yaml_config.quotas,
+        audit: yaml_config.audit,
+        archive_root: yaml_config
+            .archive_root
+            .map(|root| file_path.as_ref().join(root)),
     }
 }
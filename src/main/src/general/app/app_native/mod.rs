@@ -75,6 +75,9 @@ pub fn native_apps() -> HashMap<String, AppMeta> {
                             set: false,
                             delete: false,
                             event: None,
+                            replication: None,
+                            erasure: None,
+                            cache: None,
                         }
                     })),
                     affinity: Some(AffinityRule {
@@ -93,6 +96,9 @@ pub fn native_apps() -> HashMap<String, AppMeta> {
                             event: Some(DataEventTrigger::WriteWithCondition {
                                 condition: "checkpointable".to_string(),
                             }),
+                            replication: None,
+                            erasure: None,
+                            cache: None,
                         }
                     })),
                     affinity: Some(AffinityRule {
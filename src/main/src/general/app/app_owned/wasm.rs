@@ -45,13 +45,17 @@ impl EventCtx {
             (ptr, v.len() as i32)
         }
         match self {
-            EventCtx::Http(text) => {
+            EventCtx::Http(text) | EventCtx::FnCall(text) => {
                 // if text.len() == 0 {
                 //     return vec![];
                 // }
                 let (ptr, len) = prepare_vec_in_vm(vm, text.as_bytes());
                 vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)]
             }
+            EventCtx::KvDelete { key, .. } => {
+                let (ptr, len) = prepare_vec_in_vm(vm, key);
+                vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)]
+            }
             // (EventCtx::KvSet { key, .. }) => {
             //     let (ptr, len) = prepare_vec_in_vm(vm, &key);
             //     vec![WasmValue::from_i32(ptr), WasmValue::from_i32(len)]
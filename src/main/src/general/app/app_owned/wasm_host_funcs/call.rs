@@ -0,0 +1,99 @@
+use super::{utils, utils::m_executor, HostFuncRegister};
+use crate::general::app::m_executor::{FnExeCtxBase, DEFAULT_FN_CALL_TIMEOUT};
+use moka::sync::Cache;
+use std::{sync::atomic::AtomicI32, time::Duration};
+
+#[cfg(target_os = "linux")]
+use wasmedge_sdk::{
+    async_host_function, error::HostFuncError, host_function, Caller, ImportObjectBuilder,
+    NeverType, WasmValue,
+};
+
+lazy_static::lazy_static! {
+    static ref RECENT_CALL_CACHE: Cache<i32, String>=Cache::builder()
+        .time_to_live(Duration::from_secs(10))
+        .max_capacity(10240)
+        .build();
+    static ref NEXT_CACHE_ID: AtomicI32=AtomicI32::new(0);
+}
+
+/// result len written back when the callee returns nothing
+const CALL_NO_RESULT: i32 = -1;
+/// result len written back when the call failed
+const CALL_FAILED: i32 = -2;
+
+// fn call_fn(app_ptr, app_len, func_ptr, func_len, arg_ptr, arg_len, res_len: &mut i32, res_id: &mut i32);
+type CallFnArgs = (i32, i32, i32, i32, i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn call_fn<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let app = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32());
+    let func = utils::u8slice(&caller, args[2].to_i32(), args[3].to_i32());
+    let payload = utils::u8slice(&caller, args[4].to_i32(), args[5].to_i32());
+    let res_len = utils::mutref::<i32>(&caller, args[6].to_i32());
+    let res_id = utils::mutref::<i32>(&caller, args[7].to_i32());
+
+    let (Ok(app), Ok(func), Ok(payload)) = (
+        std::str::from_utf8(app),
+        std::str::from_utf8(func),
+        std::str::from_utf8(payload),
+    ) else {
+        tracing::warn!("call_fn args are not utf8");
+        *res_len = CALL_FAILED;
+        return Ok(vec![]);
+    };
+    let caller_depth = unsafe { utils::current_app_fn_ctx(&caller).0.as_ref() }.call_depth();
+
+    match m_executor()
+        .call_fn(
+            caller_depth,
+            app,
+            func,
+            payload.to_owned(),
+            DEFAULT_FN_CALL_TIMEOUT,
+        )
+        .await
+    {
+        Ok(Some(res)) => {
+            let id = NEXT_CACHE_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            *res_len = res.len() as i32;
+            *res_id = id;
+            RECENT_CALL_CACHE.insert(id, res);
+        }
+        Ok(None) => {
+            *res_len = CALL_NO_RESULT;
+        }
+        Err(err) => {
+            tracing::warn!("call_fn {}/{} failed: {:?}", app, func, err);
+            *res_len = CALL_FAILED;
+        }
+    }
+    Ok(vec![])
+}
+
+// fn call_fn_res(res_id: i32, res_ptr: *mut u8);
+type CallFnResArgs = (i32, i32);
+#[host_function]
+fn call_fn_res(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let id = args[0].to_i32();
+    if let Some(res) = RECENT_CALL_CACHE.remove(&id) {
+        let slice = utils::mutu8sclice(&caller, args[1].to_i32(), res.len() as i32).unwrap();
+        slice.copy_from_slice(res.as_bytes());
+    }
+    Ok(vec![])
+}
+
+pub(super) struct CallFuncsRegister;
+
+impl HostFuncRegister for CallFuncsRegister {
+    fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
+        builder
+            .with_async_func::<CallFnArgs, (), NeverType>("call_fn", call_fn, None)
+            .unwrap()
+            .with_func::<CallFnResArgs, (), NeverType>("call_fn_res", call_fn_res, None)
+            .unwrap()
+    }
+}
@@ -1,12 +1,19 @@
 use super::{utils, utils::m_kv_user_client, HostFuncRegister};
 use crate::general::app::m_executor::FnExeCtxBase;
+use crate::general::data::kv_interface::KvOps;
+use crate::general::data::m_data_general::stream::DataStreamWriter;
+use crate::general::data::m_data_general::{new_data_unique_id_fn_kv, CondWriteRes, WriteDataOpts};
 use crate::general::network::proto::{
     self,
     kv::{KeyRange, KvPair, KvRequest, KvRequests, KvResponses},
 };
 use crate::general::network::proto_ext::ProtoExtKvResponse;
+use crate::result::WSError;
 use moka::sync::Cache;
-use std::{sync::atomic::AtomicI32, time::Duration};
+use std::{
+    sync::{atomic::AtomicI32, Arc},
+    time::Duration,
+};
 #[cfg(target_os = "macos")]
 use wasmer::{imports, Function, FunctionType, Imports};
 
@@ -108,13 +115,61 @@ lazy_static::lazy_static! {
         .max_capacity(10240)
         .build();
     static ref NEXT_CACHE_ID: AtomicI32=AtomicI32::new(0);
+
+    // open data streams of the guests, a stream left idle is dropped and its staged data discarded
+    static ref DATA_STREAMS: Cache<i32, Arc<tokio::sync::Mutex<Option<DataStreamWriter>>>>=Cache::builder()
+        .time_to_idle(Duration::from_secs(60))
+        .build();
+    static ref NEXT_DATA_STREAM_ID: AtomicI32=AtomicI32::new(1);
 }
 
 const SET_ID: usize = 1;
 const GET_ID: usize = 2;
 const LOCK_ID: usize = 3;
 const DELETE_ID: usize = 4;
+// [op, kptr, klen, vptr, vlen, version_ptr(u64 le, in: expected, out: current), ok_ptr, cur_len_ptr]
+const CAS_ID: usize = 5;
+// [op, kptr, klen, delta_ptr(i64 le), res_ptr(i64 le, out: new value), ok_ptr]
+const INCR_ID: usize = 6;
+// [op, kptr, klen, vptr, vlen, ok_ptr]
+const PUT_IF_ABSENT_ID: usize = 7;
+// [op, start_ptr, start_len, end_ptr, end_len, prefix_ptr, prefix_len, cursor_ptr, cursor_len,
+//  limit, res_len_ptr(out: len of the encoded page, read it by kv_batch_res)]
+const SCAN_ID: usize = 8;
+// [op, kptr, klen, vptr, vlen, ttl_ms]
+const SET_TTL_ID: usize = 9;
+
+/// written to the out arg when the function didn't declare the data access in app.yaml
+const KV_DENIED: i32 = -2;
+
+fn is_denied(err: &WSError) -> bool {
+    matches!(err, WSError::WsPermissionErr(_))
+}
 
+/// scan page layout: cursor_len(u32 le) cursor cnt(u32 le) [klen(u32 le) key vlen(u32 le) value]*
+fn encode_scan_page(page: &proto::kv::kv_response::KvScanResponse) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(
+        8 + page.next_cursor.len()
+            + page
+                .kvs
+                .iter()
+                .map(|kv| 8 + kv.key.len() + kv.value.len())
+                .sum::<usize>(),
+    );
+    buf.extend_from_slice(&(page.next_cursor.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&page.next_cursor);
+    buf.extend_from_slice(&(page.kvs.len() as u32).to_le_bytes());
+    for kv in &page.kvs {
+        buf.extend_from_slice(&(kv.key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&kv.key);
+        buf.extend_from_slice(&(kv.value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&kv.value);
+    }
+    buf
+}
+
+// [opes_ptr, opes_len, opes_id_ptr(i32 out: id to read the results by kv_batch_res,
+//  -2 when an operation of the batch is not allowed to the function)]
 type KvBatchOpe = (i32, i32, i32);
 #[cfg_attr(target_os = "linux", async_host_function)]
 async fn kv_batch_ope<T>(
@@ -122,6 +177,27 @@ async fn kv_batch_ope<T>(
     args: Vec<WasmValue>,
     _ctx: *mut T,
 ) -> Result<Vec<WasmValue>, HostFuncError> {
+    batch_ope(caller, args, false).await
+}
+
+// same as kv_batch_ope, with an extra committed_ptr(i32 out),
+//  results are only written back when the batch is committed
+type KvBatchOpeAtomic = (i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn kv_batch_ope_atomic<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    batch_ope(caller, args, true).await
+}
+
+async fn batch_ope(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    atomic: bool,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let committed_ptr = if atomic { Some(args[3].to_i32()) } else { None };
     let opes_arg_ptr = args[0].to_i32();
     let opes_arg_len = args[1].to_i32();
     let opes_id = utils::mutref::<i32>(&caller, args[2].to_i32());
@@ -155,11 +231,28 @@ async fn kv_batch_ope<T>(
                                 key: key.to_owned(),
                                 value: value.to_owned(),
                             }),
+                            ttl_ms: 0,
                         },
                     )),
                 });
                 cur_idx += 5;
             }
+            SET_TTL_ID => {
+                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
+                let value = utils::u8slice(&caller, args[cur_idx + 3], args[cur_idx + 4]);
+                requests.push(KvRequest {
+                    op: Some(proto::kv::kv_request::Op::Set(
+                        proto::kv::kv_request::KvPutRequest {
+                            kv: Some(KvPair {
+                                key: key.to_owned(),
+                                value: value.to_owned(),
+                            }),
+                            ttl_ms: args[cur_idx + 5].max(0) as u64,
+                        },
+                    )),
+                });
+                cur_idx += 6;
+            }
             // get
             GET_ID => {
                 let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
@@ -215,6 +308,79 @@ async fn kv_batch_ope<T>(
                 });
                 cur_idx += 3;
             }
+            CAS_ID => {
+                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
+                let value = utils::u8slice(&caller, args[cur_idx + 3], args[cur_idx + 4]);
+                let expected_version = u64::from_le_bytes(
+                    utils::u8slice(&caller, args[cur_idx + 5], 8)
+                        .try_into()
+                        .unwrap(),
+                );
+                requests.push(KvRequest {
+                    op: Some(proto::kv::kv_request::Op::Cas(
+                        proto::kv::kv_request::KvCasRequest {
+                            kv: Some(KvPair {
+                                key: key.to_owned(),
+                                value: value.to_owned(),
+                            }),
+                            expected_version,
+                        },
+                    )),
+                });
+                cur_idx += 8;
+            }
+            INCR_ID => {
+                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
+                let delta = i64::from_le_bytes(
+                    utils::u8slice(&caller, args[cur_idx + 3], 8)
+                        .try_into()
+                        .unwrap(),
+                );
+                requests.push(KvRequest {
+                    op: Some(proto::kv::kv_request::Op::Incr(
+                        proto::kv::kv_request::KvIncrRequest {
+                            key: key.to_owned(),
+                            delta,
+                        },
+                    )),
+                });
+                cur_idx += 6;
+            }
+            PUT_IF_ABSENT_ID => {
+                let key = utils::u8slice(&caller, args[cur_idx + 1], args[cur_idx + 2]);
+                let value = utils::u8slice(&caller, args[cur_idx + 3], args[cur_idx + 4]);
+                requests.push(KvRequest {
+                    op: Some(proto::kv::kv_request::Op::PutIfAbsent(
+                        proto::kv::kv_request::KvPutIfAbsentRequest {
+                            kv: Some(KvPair {
+                                key: key.to_owned(),
+                                value: value.to_owned(),
+                            }),
+                        },
+                    )),
+                });
+                cur_idx += 6;
+            }
+            SCAN_ID => {
+                let bytes = |ptr_idx: usize| {
+                    utils::u8slice(&caller, args[cur_idx + ptr_idx], args[cur_idx + ptr_idx + 1])
+                        .to_owned()
+                };
+                requests.push(KvRequest {
+                    op: Some(proto::kv::kv_request::Op::Scan(
+                        proto::kv::kv_request::KvScanRequest {
+                            range: Some(KeyRange {
+                                start: bytes(1),
+                                end: bytes(3),
+                            }),
+                            prefix: bytes(5),
+                            cursor: bytes(7),
+                            limit: args[cur_idx + 9].max(0) as u32,
+                        },
+                    )),
+                });
+                cur_idx += 11;
+            }
             _ => {
                 panic!("not implemented, reqs{:?},{:X}", requests, ope_type);
             }
@@ -234,11 +400,19 @@ async fn kv_batch_ope<T>(
                 app: func_ctx.app().to_owned(),
                 func: func_ctx.func().to_owned(),
                 prev_kv_opeid,
+                atomic,
             },
         )
         .await
     {
+        Ok(res) if res.txn_status.as_ref().map_or(false, |s| !s.committed) => {
+            tracing::debug!("kv atomic batch aborted: {:?}", res.txn_status);
+            *utils::mutref::<i32>(&caller, committed_ptr.unwrap()) = 0;
+        }
         Ok(res) => {
+            if let Some(committed_ptr) = committed_ptr {
+                *utils::mutref::<i32>(&caller, committed_ptr) = 1;
+            }
             let id = NEXT_CACHE_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
             // Write back the results to wasm runtime
             let mut cur_idx = 1;
@@ -252,6 +426,10 @@ async fn kv_batch_ope<T>(
                         let _ = resps.next().unwrap();
                         cur_idx += 5;
                     }
+                    SET_TTL_ID => {
+                        let _ = resps.next().unwrap();
+                        cur_idx += 6;
+                    }
                     // get
                     GET_ID => {
                         let kvs = resps.next().unwrap().common_kvs().unwrap();
@@ -277,6 +455,47 @@ async fn kv_batch_ope<T>(
                         let _ = resps.next().unwrap();
                         cur_idx += 3;
                     }
+                    CAS_ID => {
+                        let resp = resps.next().unwrap().atomic_resp().unwrap();
+                        utils::mutu8sclice(&caller, args[cur_idx + 5], 8)
+                            .unwrap()
+                            .copy_from_slice(&resp.version.to_le_bytes());
+                        *utils::mutref::<i32>(&caller, args[cur_idx + 6]) = resp.success as i32;
+                        // current value is returned when rejected, read it by kv_batch_res
+                        *utils::mutref::<i32>(&caller, args[cur_idx + 7]) =
+                            if !resp.success && resp.value.len() > 0 {
+                                resp.value.len() as i32
+                            } else {
+                                -1
+                            };
+                        cur_idx += 8;
+                    }
+                    INCR_ID => {
+                        let resp = resps.next().unwrap().atomic_resp().unwrap();
+                        let new_value = std::str::from_utf8(&resp.value)
+                            .ok()
+                            .and_then(|v| v.parse::<i64>().ok());
+                        if let (true, Some(new_value)) = (resp.success, new_value) {
+                            utils::mutu8sclice(&caller, args[cur_idx + 4], 8)
+                                .unwrap()
+                                .copy_from_slice(&new_value.to_le_bytes());
+                            *utils::mutref::<i32>(&caller, args[cur_idx + 5]) = 1;
+                        } else {
+                            *utils::mutref::<i32>(&caller, args[cur_idx + 5]) = 0;
+                        }
+                        cur_idx += 6;
+                    }
+                    PUT_IF_ABSENT_ID => {
+                        let resp = resps.next().unwrap().atomic_resp().unwrap();
+                        *utils::mutref::<i32>(&caller, args[cur_idx + 5]) = resp.success as i32;
+                        cur_idx += 6;
+                    }
+                    SCAN_ID => {
+                        let page = resps.next().unwrap().scan_resp().unwrap();
+                        *utils::mutref::<i32>(&caller, args[cur_idx + 10]) =
+                            encode_scan_page(page).len() as i32;
+                        cur_idx += 11;
+                    }
                     _ => {
                         panic!("not implemented");
                     }
@@ -287,11 +506,230 @@ async fn kv_batch_ope<T>(
         }
         Err(err) => {
             tracing::error!("kv batch ope error:{}", err);
+            if is_denied(&err) {
+                *opes_id = KV_DENIED;
+            }
+            if let Some(committed_ptr) = committed_ptr {
+                *utils::mutref::<i32>(&caller, committed_ptr) = 0;
+            }
         }
     }
     Ok(vec![])
 }
 
+// [kptr, klen, version_ptr(u64 le, in: known version, 0 means not exist, out: new version),
+//  timeout_ms, op_ptr(i32 out: -1 timeout, -2 denied, 0 set, 1 delete)]
+type KvWaitKey = (i32, i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn kv_wait_key<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let key = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32()).to_owned();
+    let version_slice = utils::mutu8sclice(&caller, args[2].to_i32(), 8).unwrap();
+    let after_version = u64::from_le_bytes(version_slice[..].try_into().unwrap());
+    let timeout = Duration::from_millis(args[3].to_i32().max(0) as u64);
+    let op = utils::mutref::<i32>(&caller, args[4].to_i32());
+    let func_ctx = unsafe { utils::current_app_fn_ctx(&caller).0.as_ref() };
+    let key = match m_kv_user_client()
+        .fn_kv_ns_key(func_ctx.app(), func_ctx.func(), KvOps::Get, &key)
+        .await
+    {
+        Ok(ns_key) => ns_key,
+        Err(err) => {
+            *op = if is_denied(&err) { KV_DENIED } else { -1 };
+            return Ok(vec![]);
+        }
+    };
+
+    match utils::m_kv_watch()
+        .wait_key(&key, after_version, timeout)
+        .await
+    {
+        Ok(Some(event)) => {
+            version_slice.copy_from_slice(&event.version.to_le_bytes());
+            *op = event.op as i32;
+        }
+        Ok(None) => *op = -1,
+        Err(err) => {
+            tracing::warn!("kv wait key failed: {:?}", err);
+            *op = -1;
+        }
+    }
+    Ok(vec![])
+}
+
+// [kptr, klen, offset(u64 as i64), buf_ptr, buf_len, retlen_ptr(i32 out: bytes read, -1 failed, -2 denied)]
+type ReadDataAt = (i32, i32, i64, i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn read_data_at<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let key = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32()).to_owned();
+    let offset = args[2].to_i64() as u64;
+    let buf = utils::mutu8sclice(&caller, args[3].to_i32(), args[4].to_i32()).unwrap();
+    let retlen = utils::mutref::<i32>(&caller, args[5].to_i32());
+    if buf.is_empty() {
+        *retlen = 0;
+        return Ok(vec![]);
+    }
+
+    let func_ctx = unsafe { utils::current_app_fn_ctx(&caller).0.as_ref() };
+    let key = match m_kv_user_client()
+        .fn_kv_ns_key(func_ctx.app(), func_ctx.func(), KvOps::Get, &key)
+        .await
+    {
+        Ok(ns_key) => ns_key,
+        Err(err) => {
+            *retlen = if is_denied(&err) { KV_DENIED } else { -1 };
+            return Ok(vec![]);
+        }
+    };
+
+    let data_general = utils::m_data_general();
+    let uid = new_data_unique_id_fn_kv(&key);
+    let res = match data_general.get_datameta_cached(&uid).await {
+        Ok((meta, _)) => {
+            data_general
+                .get_item_range(&meta, &uid, 0, offset, buf.len() as u64)
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok((bytes, _)) => {
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            *retlen = bytes.len() as i32;
+        }
+        Err(err) => {
+            tracing::warn!("read data at failed: {:?}", err);
+            *retlen = -1;
+        }
+    }
+    Ok(vec![])
+}
+
+// [kptr, klen, ttl_ms, id_ptr(i32 out: stream id, -1 failed, -2 denied)]
+type DataStreamOpen = (i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn data_stream_open<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let key = utils::u8slice(&caller, args[0].to_i32(), args[1].to_i32()).to_owned();
+    let ttl_ms = args[2].to_i32().max(0) as u64;
+    let id = utils::mutref::<i32>(&caller, args[3].to_i32());
+    let func_ctx = unsafe { utils::current_app_fn_ctx(&caller).0.as_ref() };
+    let key = match m_kv_user_client()
+        .fn_kv_ns_key(func_ctx.app(), func_ctx.func(), KvOps::Set, &key)
+        .await
+    {
+        Ok(ns_key) => ns_key,
+        Err(err) => {
+            *id = if is_denied(&err) { KV_DENIED } else { -1 };
+            return Ok(vec![]);
+        }
+    };
+    let write_ctx = m_kv_user_client().func_call_write_ctx(func_ctx.app(), func_ctx.func());
+
+    match utils::m_data_general()
+        .open_data_stream(
+            new_data_unique_id_fn_kv(&key),
+            Some(write_ctx),
+            WriteDataOpts {
+                ttl_ms,
+                ..Default::default()
+            },
+        )
+        .await
+    {
+        Ok(writer) => {
+            let stream_id = NEXT_DATA_STREAM_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            DATA_STREAMS.insert(stream_id, Arc::new(tokio::sync::Mutex::new(Some(writer))));
+            *id = stream_id;
+        }
+        Err(err) => {
+            tracing::warn!("open data stream failed: {:?}", err);
+            *id = -1;
+        }
+    }
+    Ok(vec![])
+}
+
+// [id, ptr, len, ok_ptr(i32 out)], the stream is discarded when a write fails
+type DataStreamWrite = (i32, i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn data_stream_write<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let stream_id = args[0].to_i32();
+    let data = utils::u8slice(&caller, args[1].to_i32(), args[2].to_i32());
+    let ok = utils::mutref::<i32>(&caller, args[3].to_i32());
+    let Some(writer) = DATA_STREAMS.get(&stream_id) else {
+        *ok = 0;
+        return Ok(vec![]);
+    };
+    let mut writer = writer.lock().await;
+    let res = match writer.as_mut() {
+        Some(writer) => writer.write(data).await,
+        None => Ok(()),
+    };
+    *ok = match res {
+        Ok(()) if writer.is_some() => 1,
+        Ok(()) => 0,
+        Err(err) => {
+            tracing::warn!("write data stream failed: {:?}", err);
+            DATA_STREAMS.invalidate(&stream_id);
+            if let Some(writer) = writer.take() {
+                writer.abort().await;
+            }
+            0
+        }
+    };
+    Ok(vec![])
+}
+
+// [id, abort, version_ptr(u64 le out: new version, 0 failed or aborted)]
+type DataStreamClose = (i32, i32, i32);
+#[cfg_attr(target_os = "linux", async_host_function)]
+async fn data_stream_close<T>(
+    caller: Caller,
+    args: Vec<WasmValue>,
+    _ctx: *mut T,
+) -> Result<Vec<WasmValue>, HostFuncError> {
+    let stream_id = args[0].to_i32();
+    let abort = args[1].to_i32() != 0;
+    let version_slice = utils::mutu8sclice(&caller, args[2].to_i32(), 8).unwrap();
+    let writer = DATA_STREAMS.remove(&stream_id);
+    let writer = match &writer {
+        Some(writer) => writer.lock().await.take(),
+        None => None,
+    };
+    let version = match writer {
+        Some(writer) if abort => {
+            writer.abort().await;
+            0
+        }
+        Some(writer) => match writer.commit().await {
+            Ok(CondWriteRes::Written(version)) => version,
+            Ok(CondWriteRes::Rejected(_)) => 0,
+            Err(err) => {
+                tracing::warn!("commit data stream failed: {:?}", err);
+                0
+            }
+        },
+        None => 0,
+    };
+    version_slice.copy_from_slice(&version.to_le_bytes());
+    Ok(vec![])
+}
+
 #[host_function]
 fn kv_batch_res(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
     let id = args[0].to_i32();
@@ -313,6 +751,22 @@ fn kv_batch_res(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>,
                     }
                 } else if let Some(_lock_id) = res.lock_id() {
                     // do nothing
+                } else if let Some(page) = res.scan_resp() {
+                    let encoded = encode_scan_page(page);
+                    let slice =
+                        utils::mutu8sclice(&caller, args[cur_idx + 1], encoded.len() as i32)
+                            .unwrap();
+                    slice.copy_from_slice(&encoded);
+                } else if let Some(atomic) = res.atomic_resp() {
+                    if atomic.value.len() > 0 {
+                        let slice = utils::mutu8sclice(
+                            &caller,
+                            args[cur_idx + 1],
+                            atomic.value.len() as i32,
+                        )
+                        .unwrap();
+                        slice.copy_from_slice(atomic.value.as_slice());
+                    }
                 } else {
                     panic!("not implemented");
                 }
@@ -330,8 +784,36 @@ impl HostFuncRegister for KvFuncsRegister {
         builder
             .with_async_func::<KvBatchOpe, (), NeverType>("kv_batch_ope", kv_batch_ope, None)
             .unwrap()
+            .with_async_func::<KvBatchOpeAtomic, (), NeverType>(
+                "kv_batch_ope_atomic",
+                kv_batch_ope_atomic,
+                None,
+            )
+            .unwrap()
             .with_func::<KvBatchOpe, (), NeverType>("kv_batch_res", kv_batch_res, None)
             .unwrap()
+            .with_async_func::<KvWaitKey, (), NeverType>("kv_wait_key", kv_wait_key, None)
+            .unwrap()
+            .with_async_func::<ReadDataAt, (), NeverType>("read_data_at", read_data_at, None)
+            .unwrap()
+            .with_async_func::<DataStreamOpen, (), NeverType>(
+                "data_stream_open",
+                data_stream_open,
+                None,
+            )
+            .unwrap()
+            .with_async_func::<DataStreamWrite, (), NeverType>(
+                "data_stream_write",
+                data_stream_write,
+                None,
+            )
+            .unwrap()
+            .with_async_func::<DataStreamClose, (), NeverType>(
+                "data_stream_close",
+                data_stream_close,
+                None,
+            )
+            .unwrap()
         // .with_async_func::<KvGetLenArgs, (), NeverType>("kv_get_len", kv_get_len_async, None)
         // .unwrap()
         // .with_func::<KvGetArgs, (), NeverType>("kv_get", kv_get, None)
@@ -0,0 +1,36 @@
+use super::{utils, utils::m_executor, HostFuncRegister};
+use crate::general::app::fn_log::FnLogLevel;
+use crate::general::app::m_executor::FnExeCtxBase;
+
+#[cfg(target_os = "linux")]
+use wasmedge_sdk::{
+    error::HostFuncError, host_function, Caller, ImportObjectBuilder, NeverType, WasmValue,
+};
+
+// fn log(level: i32, msg_ptr: *const u8, msg_len: i32);
+type LogArgs = (i32, i32, i32);
+#[host_function]
+fn log(caller: Caller, args: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let level = FnLogLevel::from_code(args[0].to_i32() as u32);
+    let msg = utils::u8slice(&caller, args[1].to_i32(), args[2].to_i32());
+    let msg = String::from_utf8_lossy(msg).into_owned();
+    let fn_ctx = unsafe { utils::current_app_fn_ctx(&caller).0.as_ref() };
+    m_executor().fn_log(
+        fn_ctx.app(),
+        fn_ctx.func(),
+        fn_ctx.req_id() as u64,
+        level,
+        msg,
+    );
+    Ok(vec![])
+}
+
+pub(super) struct LogFuncsRegister;
+
+impl HostFuncRegister for LogFuncsRegister {
+    fn register(&self, builder: ImportObjectBuilder) -> ImportObjectBuilder {
+        builder
+            .with_func::<LogArgs, (), NeverType>("log", log, None)
+            .unwrap()
+    }
+}
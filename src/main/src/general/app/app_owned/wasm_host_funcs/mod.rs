@@ -1,22 +1,31 @@
 #[cfg(target_os = "linux")]
 use wasmedge_sdk::{ImportObject, ImportObjectBuilder, NeverType};
+mod call;
 mod fs;
 mod kv;
+mod log;
 mod result;
 
 use crate::general::app::instance::m_instance_manager::UnsafeFunctionCtx;
 use crate::sys::LogicalModulesRef;
+use call::CallFuncsRegister;
 use fs::FsFuncsRegister;
 use kv::KvFuncsRegister;
+use log::LogFuncsRegister;
 use result::ResultFuncsRegister;
 
 mod utils {
 
     use super::UnsafeFunctionCtx;
-    use crate::general::app::m_executor::{FnExeCtxAsync};
+    use crate::general::app::m_executor::{Executor, FnExeCtxAsync};
     use crate::general::app::InstanceManager;
     use crate::{
-        general::m_os::OperatingSystem, sys::LogicalModulesRef, util::SendNonNull,
+        general::{
+            data::{m_data_general::DataGeneral, m_kv_watch::KvWatch},
+            m_os::OperatingSystem,
+        },
+        sys::LogicalModulesRef,
+        util::SendNonNull,
         worker::m_kv_user_client::KvUserClient,
     };
     use wasmedge_sdk::{Caller, Instance, Memory};
@@ -137,6 +146,24 @@ mod utils {
         }
     }
 
+    pub fn m_kv_watch() -> &'static KvWatch {
+        unsafe {
+            &(*MODULES.as_ref().unwrap().inner.as_ptr())
+                .as_ref()
+                .unwrap()
+                .kv_watch
+        }
+    }
+
+    pub fn m_data_general() -> &'static DataGeneral {
+        unsafe {
+            &(*MODULES.as_ref().unwrap().inner.as_ptr())
+                .as_ref()
+                .unwrap()
+                .data_general
+        }
+    }
+
     pub fn m_fs<'a>() -> &'a OperatingSystem {
         unsafe {
             &(*MODULES.as_ref().unwrap().inner.as_ptr())
@@ -146,6 +173,15 @@ mod utils {
         }
     }
 
+    pub fn m_executor() -> &'static Executor {
+        unsafe {
+            &(*MODULES.as_ref().unwrap().inner.as_ptr())
+                .as_ref()
+                .unwrap()
+                .executor
+        }
+    }
+
     pub fn m_instance_manager() -> &'static InstanceManager {
         unsafe {
             &(*MODULES.as_ref().unwrap().inner.as_ptr())
@@ -187,6 +223,8 @@ pub fn new_import_obj() -> ImportObject {
     let builder = KvFuncsRegister {}.register(builder);
     let builder = FsFuncsRegister {}.register(builder);
     let builder = ResultFuncsRegister.register(builder);
+    let builder = CallFuncsRegister.register(builder);
+    let builder = LogFuncsRegister.register(builder);
 
     builder.build::<NeverType>("env", None).unwrap()
 }
@@ -209,7 +209,13 @@ impl InstanceTrait for ProcessInstance {
         );
         tracing::debug!("before process_rpc::call_func ");
         let res =
-            process_rpc::call_func(fn_ctx.app(), fn_ctx.func(), fn_ctx.http_str_unwrap()).await;
+            process_rpc::call_func(
+                fn_ctx.app(),
+                fn_ctx.func(),
+                fn_ctx.http_str_unwrap(),
+                fn_ctx.req_id() as u64,
+            )
+            .await;
         tracing::debug!("after process_rpc::call_func ");
         return res.map(|v| Some(v.ret_str));
     }
@@ -5,9 +5,12 @@ pub mod proc_proto {
 use self::proc_proto::{FuncCallReq, FuncCallResp};
 use super::SharedInstance;
 use crate::general::app::app_shared::process_rpc::proc_proto::AppStarted;
+use crate::general::app::fn_log::FnLogLevel;
 use crate::{
     general::network::rpc_model::{self, HashValue, MsgIdBind, ReqMsg, RpcCustom},
-    modules_global_bridge::process_func::ModulesGlobalBrigeInstanceManager,
+    modules_global_bridge::process_func::{
+        ModulesGlobalBrigeExecutor, ModulesGlobalBrigeInstanceManager,
+    },
     result::WSResult,
     sys::LogicalModulesRef,
 };
@@ -103,10 +106,10 @@ impl RpcCustom for ProcessRpc {
         Some(HashValue::Str(res.appid))
     }
 
-    fn handle_remote_call(_conn: &HashValue, id: u8, buf: &[u8]) -> bool {
+    fn handle_remote_call(conn: &HashValue, id: u8, buf: &[u8]) -> bool {
         tracing::debug!("handle_remote_call: id: {}", id);
         let _ = match id {
-            4 => (),
+            4 | 5 => (),
             id => {
                 tracing::warn!("handle_remote_call: unsupported id: {}", id);
                 return false;
@@ -127,6 +130,25 @@ impl RpcCustom for ProcessRpc {
                 }
                 Err(e) => e,
             },
+            5 => match proc_proto::FuncLog::decode(buf) {
+                Ok(log) => {
+                    let Some(app) = conn.as_str() else {
+                        tracing::warn!("func log from unverified conn {:?}", conn);
+                        return true;
+                    };
+                    unsafe {
+                        ProcessRpc::global_m_executor().fn_log(
+                            app,
+                            &log.func,
+                            log.invocation_id.unwrap_or(0),
+                            FnLogLevel::from_code(log.level),
+                            log.msg,
+                        );
+                    }
+                    return true;
+                }
+                Err(e) => e,
+            },
             _ => unreachable!(),
         };
         tracing::warn!("handle_remote_call error: {:?}", err);
@@ -152,15 +174,27 @@ impl MsgIdBind for proc_proto::FuncCallResp {
     }
 }
 
+impl MsgIdBind for proc_proto::FuncLog {
+    fn id() -> u16 {
+        5
+    }
+}
+
 impl ReqMsg for FuncCallReq {
     type Resp = FuncCallResp;
 }
 
-pub async fn call_func(app: &str, func: &str, arg: String) -> WSResult<FuncCallResp> {
+pub async fn call_func(
+    app: &str,
+    func: &str,
+    arg: String,
+    invocation_id: u64,
+) -> WSResult<FuncCallResp> {
     rpc_model::call(
         FuncCallReq {
             func: func.to_owned(),
             arg_str: arg,
+            invocation_id: Some(invocation_id),
         },
         HashValue::Str(app.into()),
         Duration::from_secs(20),
@@ -0,0 +1,155 @@
+use crate::sys::NodeID;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// max log records kept for one invocation, older records are dropped
+pub const MAX_LOGS_PER_INVOCATION: usize = 256;
+/// max invocations kept for one function
+pub const MAX_INVOCATIONS_PER_FN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FnLogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl FnLogLevel {
+    /// level code used by the host abi and process rpc, unknown codes fall back to info
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            0 => FnLogLevel::Trace,
+            1 => FnLogLevel::Debug,
+            2 => FnLogLevel::Info,
+            3 => FnLogLevel::Warn,
+            4 => FnLogLevel::Error,
+            _ => FnLogLevel::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FnLogRecord {
+    pub level: FnLogLevel,
+    pub msg: String,
+    /// unix time in ms
+    pub time: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FnInvocationLogs {
+    pub invocation: u64,
+    pub node: NodeID,
+    pub records: VecDeque<FnLogRecord>,
+    /// records dropped because the buffer is full
+    pub dropped: usize,
+}
+
+/// Bounded log buffers of recent invocations, keyed by (app, func)
+pub struct FnLogs {
+    fns: Mutex<HashMap<(String, String), VecDeque<FnInvocationLogs>>>,
+}
+
+impl FnLogs {
+    pub fn new() -> Self {
+        Self {
+            fns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Emit a tracing event tagged with the function info and keep it in the invocation buffer
+    pub fn log(
+        &self,
+        app: &str,
+        func: &str,
+        invocation: u64,
+        node: NodeID,
+        level: FnLogLevel,
+        msg: String,
+    ) {
+        match level {
+            FnLogLevel::Trace => {
+                tracing::trace!(app, func, invocation, node, "[fn log] {}", msg)
+            }
+            FnLogLevel::Debug => {
+                tracing::debug!(app, func, invocation, node, "[fn log] {}", msg)
+            }
+            FnLogLevel::Info => tracing::info!(app, func, invocation, node, "[fn log] {}", msg),
+            FnLogLevel::Warn => tracing::warn!(app, func, invocation, node, "[fn log] {}", msg),
+            FnLogLevel::Error => {
+                tracing::error!(app, func, invocation, node, "[fn log] {}", msg)
+            }
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64;
+
+        let mut fns = self.fns.lock();
+        let invocations = fns
+            .entry((app.to_owned(), func.to_owned()))
+            .or_insert_with(VecDeque::new);
+        let pos = match invocations
+            .iter()
+            .position(|inv| inv.invocation == invocation)
+        {
+            Some(pos) => pos,
+            None => {
+                if invocations.len() >= MAX_INVOCATIONS_PER_FN {
+                    let _ = invocations.pop_front();
+                }
+                invocations.push_back(FnInvocationLogs {
+                    invocation,
+                    node,
+                    records: VecDeque::new(),
+                    dropped: 0,
+                });
+                invocations.len() - 1
+            }
+        };
+        let inv = &mut invocations[pos];
+        if inv.records.len() >= MAX_LOGS_PER_INVOCATION {
+            let _ = inv.records.pop_front();
+            inv.dropped += 1;
+        }
+        inv.records.push_back(FnLogRecord { level, msg, time });
+    }
+
+    /// Logs of recent invocations, oldest first
+    pub fn get(&self, app: &str, func: &str) -> Vec<FnInvocationLogs> {
+        self.fns
+            .lock()
+            .get(&(app.to_owned(), func.to_owned()))
+            .map(|invs| invs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fn_logs_bounded() {
+        let logs = FnLogs::new();
+        for i in 0..MAX_LOGS_PER_INVOCATION + 3 {
+            logs.log("app", "fn", 1, 1, FnLogLevel::Info, format!("{}", i));
+        }
+        for inv in 0..MAX_INVOCATIONS_PER_FN as u64 + 1 {
+            logs.log("app", "fn", inv + 2, 1, FnLogLevel::from_code(3), "x".to_owned());
+        }
+        let got = logs.get("app", "fn");
+        assert_eq!(got.len(), MAX_INVOCATIONS_PER_FN);
+        // invocation 1 and 2 are evicted
+        assert_eq!(got[0].invocation, 3);
+        assert_eq!(got[0].records[0].level, FnLogLevel::Warn);
+        assert!(logs.get("app", "other").is_empty());
+    }
+}
@@ -1,13 +1,29 @@
+use std::convert::Infallible;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use axum::extract::{DefaultBodyLimit, Multipart, Path};
+use axum::extract::{BodyStream, DefaultBodyLimit, Multipart, Path, Query};
 use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
-use axum::{routing::post, Router};
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use futures::StreamExt;
 use lazy_static::lazy_static;
+use serde::Deserialize;
 
+use crate::general::data::kv_interface::KvOps;
+use crate::general::data::m_data_general::{
+    archive::{resolve_archive_dir, DataArchiveReport},
+    compress, new_data_unique_id_fn_kv, split_fn_kv_ns_key, CondWriteRes, WriteDataOpts,
+};
+use crate::general::network::proto;
+use crate::master::data::data_audit::{format_audit_cursor, parse_audit_cursor, AuditQuery};
 use crate::master::m_master::ScheduleWorkload;
+use crate::result::{WSError, WSResult, WsDataError};
 use crate::util;
+use crate::worker::kv_namespace::KvNamespace;
 
 lazy_static! {
     static ref VIEW: Option<super::View> = None;
@@ -34,6 +50,17 @@ pub(super) fn binds(router: Router, view: super::View) -> Router {
         .route("/appmgmt/upload_app", post(upload_app))
         .layer(DefaultBodyLimit::disable())
         .route("/:app/:fn", post(call_app_fn))
+        .route("/logs/:app/:fn", get(get_fn_logs))
+        .route("/kv/watch", get(watch_kv))
+        .route("/data/gc", post(run_data_gc))
+        .route("/data/cache/evict", post(evict_data_cache))
+        .route("/data/compression", get(data_compression_metrics))
+        .route("/data/usage", get(data_usage))
+        .route("/data/audit", get(data_audit))
+        .route("/data/stream/:key", post(upload_data_stream))
+        .route("/data/export", post(export_data))
+        .route("/data/import", post(import_data))
+        .route("/data/kv/migrate", post(migrate_fn_kv))
     // .layer(RequestBodyLimitLayer::new(
     //     250 * 1024 * 1024, /* 250mb */
     // ))
@@ -95,6 +122,378 @@ async fn call_app_fn(Path((app, func)): Path<(String, String)>, body: String) ->
     }
 }
 
+/// logs of recent invocations that ran on this node
+async fn get_fn_logs(Path((app, func)): Path<(String, String)>) -> Response {
+    let logs = view().executor().fn_logs.get(&app, &func);
+    match serde_json::to_string(&logs) {
+        Ok(res) => (StatusCode::OK, res).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+    }
+}
+
+/// fn kv key in the namespace of `app`, the raw key when no app is given
+async fn fn_kv_key(app: Option<&str>, key: &str) -> WSResult<Vec<u8>> {
+    let Some(app) = app else {
+        return Ok(key.as_bytes().to_vec());
+    };
+    let appmeta = view().appmeta_manager().get_app_meta(app).await?;
+    Ok(KvNamespace::new(app, appmeta.as_ref().map(|(appmeta, _)| appmeta)).key(key.as_bytes()))
+}
+
+#[derive(Deserialize)]
+struct WatchKvQuery {
+    key: String,
+    #[serde(default)]
+    prefix: bool,
+    /// watch the key in the namespace of this app
+    app: Option<String>,
+}
+
+/// server sent events of the changes on a fn kv key, or on all keys with the prefix
+async fn watch_kv(Query(query): Query<WatchKvQuery>) -> Response {
+    let key = match fn_kv_key(query.app.as_deref(), &query.key).await {
+        Ok(key) => key,
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response()
+        }
+    };
+    let watcher = match view().kv_watch().watch(&key, query.prefix).await {
+        Ok(watcher) => watcher,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+    };
+    let in_app = query.app.is_some();
+    let events = futures::stream::unfold(watcher, move |mut watcher| async move {
+        let event = watcher.next().await?;
+        // keys of an app are reported without its namespace
+        let key = match split_fn_kv_ns_key(&event.key) {
+            Some((_, key)) if in_app => key,
+            _ => &event.key,
+        };
+        let data = serde_json::json!({
+            "key": String::from_utf8_lossy(key),
+            "version": event.version,
+            "op": match event.op {
+                proto::kv::KvWatchOp::Set => "set",
+                proto::kv::KvWatchOp::Delete => "delete",
+            },
+        });
+        Some((
+            Ok::<_, Infallible>(Event::default().data(data.to_string())),
+            watcher,
+        ))
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct DataGcQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// run data gc on this node now, with `dry_run` only list what would be reclaimed
+async fn run_data_gc(Query(query): Query<DataGcQuery>) -> Response {
+    let report = view().data_gc().run(query.dry_run).await;
+    let res = serde_json::json!({
+        "report": report,
+        "metrics": view().data_gc().metrics(),
+    });
+    (StatusCode::OK, res.to_string()).into_response()
+}
+
+#[derive(Deserialize)]
+struct DataCacheEvictQuery {
+    #[serde(default = "default_evict_max_items")]
+    max_items: usize,
+    /// copies used within this are kept
+    #[serde(default)]
+    min_idle_secs: u64,
+}
+
+fn default_evict_max_items() -> usize {
+    64
+}
+
+/// evict the coldest cached copies on this node now, whatever the memory usage
+async fn evict_data_cache(Query(query): Query<DataCacheEvictQuery>) -> Response {
+    let (items, bytes) = view()
+        .data_cache()
+        .evict_cold_copies(
+            query.max_items,
+            std::time::Duration::from_secs(query.min_idle_secs),
+        )
+        .await;
+    let res = serde_json::json!({
+        "evicted_items": items,
+        "evicted_bytes": bytes,
+        "metrics": view().data_cache().metrics(),
+    });
+    (StatusCode::OK, res.to_string()).into_response()
+}
+
+#[derive(Deserialize)]
+struct DataArchiveQuery {
+    /// archive dir on the node serving the request, relative to its archive root
+    dir: String,
+}
+
+/// The archive dir asked for, or the response refusing it
+fn data_archive_dir(query: &DataArchiveQuery) -> Result<std::path::PathBuf, Response> {
+    resolve_archive_dir(
+        view().p2p().nodes_config.archive_root.as_deref(),
+        &query.dir,
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, format!("err: {:?}", e)).into_response())
+}
+
+fn data_archive_response(res: WSResult<DataArchiveReport>) -> Response {
+    match res.map(|report| serde_json::to_string(&report)) {
+        Ok(Ok(report)) => (StatusCode::OK, report).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+    }
+}
+
+/// export all the datasets of the cluster to an archive dir, only on master
+async fn export_data(Query(query): Query<DataArchiveQuery>) -> Response {
+    if !view().p2p().nodes_config.this.1.is_master() {
+        return (StatusCode::BAD_REQUEST, "export runs on master").into_response();
+    }
+    let dir = match data_archive_dir(&query) {
+        Ok(dir) => dir,
+        Err(resp) => return resp,
+    };
+    let res = view().data_general().export_datasets(&dir).await;
+    data_archive_response(res)
+}
+
+/// write the datasets of an archive dir made by export into this cluster
+async fn import_data(Query(query): Query<DataArchiveQuery>) -> Response {
+    let dir = match data_archive_dir(&query) {
+        Ok(dir) => dir,
+        Err(resp) => return resp,
+    };
+    let res = view().data_general().import_datasets(&dir).await;
+    data_archive_response(res)
+}
+
+#[derive(Deserialize)]
+struct FnKvMigrateQuery {
+    app: String,
+    /// only the keys with this prefix
+    #[serde(default)]
+    prefix: String,
+    /// also move the keys charged to no app, they may be another app's
+    #[serde(default)]
+    claim_unowned: bool,
+}
+
+/// move the fn kv keys of an app written before keys were scoped per app into its namespace,
+///  only on master
+async fn migrate_fn_kv(Query(query): Query<FnKvMigrateQuery>) -> Response {
+    if !view().p2p().nodes_config.this.1.is_master() {
+        return (StatusCode::BAD_REQUEST, "fn kv migration runs on master").into_response();
+    }
+    let res = view()
+        .data_general()
+        .migrate_fn_kv_namespace(&query.app, query.prefix.as_bytes(), query.claim_unowned)
+        .await;
+    match res.map(|report| serde_json::to_string(&report)) {
+        Ok(Ok(report)) => (StatusCode::OK, report).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+    }
+}
+
+/// compression ratio of the data sent and stored by this node
+async fn data_compression_metrics() -> Response {
+    match serde_json::to_string(&compress::metrics()) {
+        Ok(res) => (StatusCode::OK, res).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+    }
+}
+
+/// storage used by each app with its quota, only on master
+async fn data_usage() -> Response {
+    if !view().p2p().nodes_config.this.1.is_master() {
+        return (StatusCode::BAD_REQUEST, "usage is kept on master").into_response();
+    }
+    let usages: serde_json::Map<String, serde_json::Value> = view()
+        .data_master()
+        .app_usages()
+        .into_iter()
+        .map(|(app, (usage, quota))| {
+            let usage = serde_json::json!({
+                "bytes": usage.bytes,
+                "keys": usage.keys,
+                "quota": quota,
+            });
+            (app, usage)
+        })
+        .collect();
+    (
+        StatusCode::OK,
+        serde_json::Value::Object(usages).to_string(),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct DataAuditQuery {
+    /// raw unique ids, fn kv ones are `fkv{app}\0{key}`
+    #[serde(default)]
+    uid_prefix: String,
+    app: Option<String>,
+    #[serde(default)]
+    start_ms: u64,
+    /// 0 means no upper bound
+    #[serde(default)]
+    end_ms: u64,
+    /// `next_cursor` of the previous page
+    cursor: Option<String>,
+    #[serde(default = "default_audit_limit")]
+    limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+/// page of the data mutation audit log ordered by time, only on master
+async fn data_audit(Query(query): Query<DataAuditQuery>) -> Response {
+    if !view().p2p().nodes_config.this.1.is_master() {
+        return (StatusCode::BAD_REQUEST, "audit log is kept on master").into_response();
+    }
+    let cursor = query.cursor.as_deref().map(parse_audit_cursor);
+    if cursor == Some(None) {
+        return (StatusCode::BAD_REQUEST, "bad audit cursor").into_response();
+    }
+    let page = view().data_master().query_audit(&AuditQuery {
+        uid_prefix: query.uid_prefix.into_bytes(),
+        app: query.app,
+        start_ms: query.start_ms,
+        end_ms: query.end_ms,
+        cursor: cursor.flatten(),
+        limit: query.limit,
+    });
+    let records: Vec<serde_json::Value> = page
+        .records
+        .into_iter()
+        .map(|record| {
+            serde_json::json!({
+                "ts_ms": record.ts_ms,
+                "unique_id": String::from_utf8_lossy(&record.unique_id),
+                "op": record.op,
+                "old_version": record.old_version,
+                "new_version": record.new_version,
+                "node": record.node,
+                "app": record.app,
+                "func": record.func,
+            })
+        })
+        .collect();
+    let res = serde_json::json!({
+        "records": records,
+        "next_cursor": page.next_cursor.map(format_audit_cursor),
+    });
+    (StatusCode::OK, res.to_string()).into_response()
+}
+
+#[derive(Deserialize)]
+struct DataStreamQuery {
+    #[serde(default)]
+    ttl_ms: u64,
+    /// the key is written in the namespace of this app and charged to it
+    app: String,
+    /// the write is made as this function, it must be granted `set` on the key in app.yaml
+    func: String,
+}
+
+/// write the request body as the value of fn kv `key` while it's received, without buffering it
+async fn upload_data_stream(
+    Path(key): Path<String>,
+    Query(query): Query<DataStreamQuery>,
+    body: BodyStream,
+) -> Response {
+    let Some(kv_user_client) = view().kv_user_client() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "data streams are written on workers",
+        )
+            .into_response();
+    };
+    let appmeta_manager = view().appmeta_manager();
+    if !appmeta_manager.native_apps.contains_key(&query.app) {
+        match appmeta_manager.get_app_meta(&query.app).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("app {} not found", query.app),
+                )
+                    .into_response()
+            }
+            Err(e) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response()
+            }
+        }
+    }
+    let uid = match kv_user_client
+        .fn_kv_ns_key(&query.app, &query.func, KvOps::Set, key.as_bytes())
+        .await
+    {
+        Ok(key) => new_data_unique_id_fn_kv(&key),
+        Err(WSError::WsPermissionErr(e)) => {
+            return (StatusCode::FORBIDDEN, format!("err: {:?}", e)).into_response()
+        }
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response()
+        }
+    };
+    let body = body.map(|chunk| {
+        chunk.map_err(|e| {
+            WSError::from(WsDataError::WriteDataFailed {
+                unique_id: key.as_bytes().to_vec(),
+                message: format!("read request body failed: {}", e),
+            })
+        })
+    });
+    let res = view()
+        .data_general()
+        .write_data_stream(
+            uid,
+            body,
+            Some((
+                view().p2p().nodes_config.this_node(),
+                proto::DataOpeType::Write,
+                proto::data_schedule_context::OpeRole::FuncCall(
+                    kv_user_client.func_call_role(&query.app, &query.func),
+                ),
+            )),
+            WriteDataOpts {
+                ttl_ms: query.ttl_ms,
+                ..Default::default()
+            },
+        )
+        .await;
+    match res {
+        Ok(CondWriteRes::Written(version)) => (
+            StatusCode::OK,
+            serde_json::json!({ "version": version }).to_string(),
+        )
+            .into_response(),
+        // nothing was written, the version is the current one
+        Ok(CondWriteRes::Rejected(version)) => (
+            StatusCode::CONFLICT,
+            serde_json::json!({ "version": version }).to_string(),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("err: {:?}", e)).into_response(),
+    }
+}
+
 async fn upload_app(mut multipart: Multipart) -> Response {
     tracing::debug!("upload_app called");
     // only worker can upload app
@@ -1,18 +1,21 @@
+use crate::general::app::fn_log::{FnLogLevel, FnLogs};
 use crate::general::app::instance::m_instance_manager::InstanceManager;
 use crate::general::app::instance::m_instance_manager::UnsafeFunctionCtx;
 use crate::general::app::instance::InstanceTrait;
 use crate::general::app::AppType;
 use crate::general::app::FnMeta;
+use crate::master::m_master::Master;
 use crate::result::WSError;
 use crate::{
     general::{
         app::AppMetaManager,
+        data::m_data_general::fn_kv_user_key,
         network::{
             http_handler::ReqId,
-            m_p2p::{P2PModule, RPCHandler, RPCResponsor},
+            m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
             proto::{
                 self,
-                sche::{distribute_task_req, DistributeTaskResp},
+                sche::{distribute_task_req, DistributeTaskResp, FnCallReq, FnCallResp},
             },
         },
     },
@@ -25,7 +28,7 @@ use async_trait::async_trait;
 use std::{
     ptr::NonNull,
     sync::atomic::{AtomicU32, AtomicUsize},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
@@ -38,13 +41,41 @@ pub type SubTaskNotifier = oneshot::Sender<bool>;
 
 pub type SubTaskWaiter = oneshot::Receiver<bool>;
 
+/// max depth of a function to function call chain, the entry function is depth 0
+pub const MAX_FN_CALL_DEPTH: u32 = 8;
+
+pub const DEFAULT_FN_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Clone, Debug)]
 pub enum EventCtx {
     Http(String),
     KvSet { key: Vec<u8>, opeid: Option<u32> },
+    /// the key is deleted or expired
+    KvDelete { key: Vec<u8>, opeid: Option<u32> },
+    /// called by another function through `call_fn`
+    FnCall(String),
 }
 
 impl EventCtx {
+    /// The triggering key is the one the function wrote, without the namespace of its app
+    fn of_trigger(trigger: distribute_task_req::Trigger) -> Self {
+        let user_key = |key: Vec<u8>| fn_kv_user_key(&key).to_vec();
+        match trigger {
+            distribute_task_req::Trigger::EventNew(new) => EventCtx::KvSet {
+                key: user_key(new.key),
+                opeid: Some(new.opeid),
+            },
+            distribute_task_req::Trigger::EventWrite(write) => EventCtx::KvSet {
+                key: user_key(write.key),
+                opeid: Some(write.opeid),
+            },
+            distribute_task_req::Trigger::EventDelete(delete) => EventCtx::KvDelete {
+                key: user_key(delete.key),
+                opeid: Some(delete.opeid),
+            },
+        }
+    }
+
     pub fn take_prev_kv_opeid(&mut self) -> Option<u32> {
         match self {
             EventCtx::KvSet { opeid, .. } => opeid.take(),
@@ -58,11 +89,13 @@ struct FnExeCtx {
     pub app_type: AppType,
     pub func: String,
     pub _func_meta: FnMeta,
-    pub _req_id: ReqId,
+    pub req_id: ReqId,
     pub event_ctx: EventCtx,
     pub res: Option<String>,
     /// remote scheduling tasks
     pub sub_waiters: Vec<JoinHandle<()>>, // pub trigger_node: NodeID,
+    /// depth in the function to function call chain
+    pub call_depth: u32,
     _dummy_private: (),
 }
 
@@ -110,12 +143,13 @@ impl FnExeCtxAsync {
             inner: FnExeCtx {
                 app,
                 func,
-                _req_id: req_id,
+                req_id,
                 event_ctx,
                 res: None,
                 sub_waiters: vec![],
                 app_type: apptype.into(),
                 _func_meta: func_meta,
+                call_depth: 0,
                 _dummy_private: (),
             },
         }
@@ -125,6 +159,11 @@ impl FnExeCtxAsync {
         &self.inner.event_ctx
     }
 
+    pub fn with_call_depth(mut self, depth: u32) -> Self {
+        self.inner.call_depth = depth;
+        self
+    }
+
     pub fn empty_http(&self) -> bool {
         match &self.inner.event_ctx {
             EventCtx::Http(text) => text.is_empty(),
@@ -134,7 +173,7 @@ impl FnExeCtxAsync {
 
     pub fn http_str_unwrap(&self) -> String {
         match &self.inner.event_ctx {
-            EventCtx::Http(text) => text.clone(),
+            EventCtx::Http(text) | EventCtx::FnCall(text) => text.clone(),
             _ => panic!("not http event ctx"),
         }
     }
@@ -185,12 +224,13 @@ impl FnExeCtxSync {
             inner: FnExeCtx {
                 app,
                 func,
-                _req_id: req_id,
+                req_id,
                 event_ctx,
                 res: None,
                 sub_waiters: vec![],
                 app_type: apptype.into(),
                 _func_meta: func_meta,
+                call_depth: 0,
                 _dummy_private: (),
             },
         }
@@ -218,12 +258,17 @@ logical_module_view_impl!(ExecutorView, p2p, P2PModule);
 logical_module_view_impl!(ExecutorView, appmeta_manager, AppMetaManager);
 logical_module_view_impl!(ExecutorView, instance_manager, InstanceManager);
 logical_module_view_impl!(ExecutorView, executor, Executor);
+logical_module_view_impl!(ExecutorView, master, Option<Master>);
 
 #[derive(LogicalModule)]
 pub struct Executor {
     sub_task_id: AtomicU32,
     rpc_handler_distribute_task: RPCHandler<proto::sche::DistributeTaskReq>,
+    rpc_handler_fn_call: RPCHandler<FnCallReq>,
+    rpc_caller_fn_call: RPCCaller<FnCallReq>,
     next_req_id: AtomicUsize,
+    /// recent logs emitted by functions running on this node
+    pub fn_logs: FnLogs,
     view: ExecutorView,
 }
 
@@ -237,6 +282,10 @@ pub trait FnExeCtxBase {
     fn event_ctx(&self) -> &EventCtx;
     /// Get mutable reference to event context
     fn event_ctx_mut(&mut self) -> &mut EventCtx;
+    /// Get the depth in the function to function call chain
+    fn call_depth(&self) -> u32;
+    /// Get the invocation id
+    fn req_id(&self) -> ReqId;
 }
 
 impl FnExeCtxBase for FnExeCtxAsync {
@@ -252,6 +301,12 @@ impl FnExeCtxBase for FnExeCtxAsync {
     fn event_ctx_mut(&mut self) -> &mut EventCtx {
         &mut self.inner.event_ctx
     }
+    fn call_depth(&self) -> u32 {
+        self.inner.call_depth
+    }
+    fn req_id(&self) -> ReqId {
+        self.inner.req_id
+    }
 }
 
 impl FnExeCtxBase for FnExeCtxSync {
@@ -267,6 +322,12 @@ impl FnExeCtxBase for FnExeCtxSync {
     fn event_ctx_mut(&mut self) -> &mut EventCtx {
         &mut self.inner.event_ctx
     }
+    fn call_depth(&self) -> u32 {
+        self.inner.call_depth
+    }
+    fn req_id(&self) -> ReqId {
+        self.inner.req_id
+    }
 }
 
 #[async_trait]
@@ -277,9 +338,12 @@ impl LogicalModule for Executor {
     {
         Self {
             rpc_handler_distribute_task: RPCHandler::default(),
+            rpc_handler_fn_call: RPCHandler::default(),
+            rpc_caller_fn_call: RPCCaller::default(),
             view: ExecutorView::new(args.logical_modules_ref.clone()),
             sub_task_id: AtomicU32::new(0),
             next_req_id: AtomicUsize::new(0),
+            fn_logs: FnLogs::new(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
@@ -302,6 +366,16 @@ impl LogicalModule for Executor {
                 Ok(())
             },
         );
+        self.rpc_caller_fn_call.regist(self.view.p2p());
+        let view = self.view.clone();
+        self.rpc_handler_fn_call
+            .regist(self.view.p2p(), move |responser, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    view.executor().handle_fn_call(responser, req).await;
+                });
+                Ok(())
+            });
         // self.view
         //     .p2p()
         //     .regist_rpc::<proto::sche::ScheReq, _>();
@@ -317,6 +391,18 @@ impl Executor {
         taskid
     }
 
+    /// Log from a running function, attributed to the function and this node
+    pub fn fn_log(&self, app: &str, func: &str, invocation: u64, level: FnLogLevel, msg: String) {
+        self.fn_logs.log(
+            app,
+            func,
+            invocation,
+            self.view.p2p().nodes_config.this_node(),
+            level,
+            msg,
+        );
+    }
+
     pub async fn local_call_execute_async(&self, ctx: FnExeCtxAsync) -> WSResult<Option<String>> {
         self.execute(ctx).await
     }
@@ -325,6 +411,199 @@ impl Executor {
         self.execute_sync(ctx)
     }
 
+    /// Call another function from a running function.
+    ///
+    /// - `caller_depth`: call depth of the caller, the callee runs at `caller_depth + 1`
+    ///
+    /// The callee runs locally when its app already has instances on this worker,
+    /// otherwise the call goes to the master and is scheduled like other tasks.
+    pub async fn call_fn(
+        &self,
+        caller_depth: u32,
+        app: &str,
+        func: &str,
+        payload: String,
+        timeout: Duration,
+    ) -> WSResult<Option<String>> {
+        let depth = caller_depth + 1;
+        if depth > MAX_FN_CALL_DEPTH {
+            tracing::warn!(
+                "fn call chain too deep, app:{} fn:{} depth:{}",
+                app,
+                func,
+                depth
+            );
+            return Err(WsFuncError::FnCallDepthExceeded {
+                app: app.to_owned(),
+                func: func.to_owned(),
+                depth,
+            }
+            .into());
+        }
+
+        let nodes_config = &self.view.p2p().nodes_config;
+        if nodes_config.this.1.is_worker()
+            && self
+                .view
+                .instance_manager()
+                .app_instances
+                .contains_key(app)
+        {
+            return tokio::time::timeout(timeout, self.execute_fn_call(app, func, payload, depth))
+                .await
+                .map_err(|_| WsFuncError::FnCallTimeout {
+                    app: app.to_owned(),
+                    func: func.to_owned(),
+                })?;
+        }
+
+        let resp = self
+            .rpc_caller_fn_call
+            .call(
+                self.view.p2p(),
+                nodes_config.get_master_node(),
+                FnCallReq {
+                    app: app.to_owned(),
+                    func: func.to_owned(),
+                    payload,
+                    depth,
+                    timeout_ms: timeout.as_millis() as u32,
+                },
+                Some(timeout),
+            )
+            .await?;
+        Self::fn_call_resp_to_result(app, func, resp)
+    }
+
+    fn fn_call_resp_to_result(app: &str, func: &str, resp: FnCallResp) -> WSResult<Option<String>> {
+        if !resp.success {
+            return Err(WsFuncError::FnCallRemoteFailed {
+                app: app.to_owned(),
+                func: func.to_owned(),
+                msg: resp.err_msg,
+            }
+            .into());
+        }
+        Ok(resp.has_result.then(|| resp.result))
+    }
+
+    /// master relays the call to a scheduled worker, worker runs it
+    async fn handle_fn_call(&self, responser: RPCResponsor<FnCallReq>, req: FnCallReq) {
+        let timeout = if req.timeout_ms == 0 {
+            DEFAULT_FN_CALL_TIMEOUT
+        } else {
+            Duration::from_millis(req.timeout_ms as u64)
+        };
+        let res = if self.view.p2p().nodes_config.this.1.is_master() {
+            let target = self.view.master().select_node();
+            tracing::debug!(
+                "relay fn call app:{} fn:{} to node {}",
+                req.app,
+                req.func,
+                target
+            );
+            let (app, func) = (req.app.clone(), req.func.clone());
+            self.rpc_caller_fn_call
+                .call(self.view.p2p(), target, req, Some(timeout))
+                .await
+                .and_then(|resp| Self::fn_call_resp_to_result(&app, &func, resp))
+        } else {
+            tokio::time::timeout(
+                timeout,
+                self.execute_fn_call(&req.app, &req.func, req.payload, req.depth),
+            )
+            .await
+            .unwrap_or_else(|_| {
+                Err(WsFuncError::FnCallTimeout {
+                    app: req.app.clone(),
+                    func: req.func.clone(),
+                }
+                .into())
+            })
+        };
+
+        let resp = match res {
+            Ok(res) => FnCallResp {
+                success: true,
+                err_msg: "".to_owned(),
+                has_result: res.is_some(),
+                result: res.unwrap_or_default(),
+            },
+            Err(err) => FnCallResp {
+                success: false,
+                err_msg: format!("{:?}", err),
+                has_result: false,
+                result: "".to_owned(),
+            },
+        };
+        if let Err(err) = responser.send_resp(resp).await {
+            tracing::error!("send fn call resp failed with err: {}", err);
+        }
+    }
+
+    async fn execute_fn_call(
+        &self,
+        app: &str,
+        func: &str,
+        payload: String,
+        depth: u32,
+    ) -> WSResult<Option<String>> {
+        let req_id: ReqId = self
+            .next_req_id
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let Some((appmeta, datameta_opt)) = self.view.appmeta_manager().get_app_meta(app).await?
+        else {
+            return Err(WsFuncError::AppNotFound {
+                app: app.to_owned(),
+            }
+            .into());
+        };
+        let Some(fnmeta) = appmeta.get_fn_meta(func) else {
+            return Err(WsFuncError::FuncNotFound {
+                app: app.to_owned(),
+                func: func.to_owned(),
+            }
+            .into());
+        };
+        if !fnmeta.allow_rpc_call() {
+            return Err(WsFuncError::FuncRpcNotSupported {
+                app: app.to_owned(),
+                func: func.to_owned(),
+            }
+            .into());
+        }
+        if let Some(datameta) = datameta_opt {
+            self.view
+                .appmeta_manager()
+                .load_app_file(app, datameta)
+                .await?;
+        }
+
+        if fnmeta.sync_async.asyncable() {
+            let ctx = FnExeCtxAsync::new(
+                FnExeCtxAsyncAllowedType::try_from(appmeta.app_type.clone())?,
+                app.to_owned(),
+                func.to_owned(),
+                fnmeta.clone(),
+                req_id,
+                EventCtx::FnCall(payload),
+            )
+            .with_call_depth(depth);
+            self.execute(ctx).await
+        } else {
+            let mut ctx = FnExeCtxSync::new(
+                FnExeCtxAsyncAllowedType::try_from(appmeta.app_type.clone())?,
+                app.to_owned(),
+                func.to_owned(),
+                fnmeta.clone(),
+                req_id,
+                EventCtx::FnCall(payload),
+            );
+            ctx.inner.call_depth = depth;
+            self.execute_sync(ctx)
+        }
+    }
+
     pub async fn handle_distribute_task(
         &self,
         resp: RPCResponsor<proto::sche::DistributeTaskReq>,
@@ -472,16 +751,7 @@ impl Executor {
                 req.func,
                 fnmeta.clone(),
                 req.task_id as usize,
-                match req.trigger.unwrap() {
-                    distribute_task_req::Trigger::EventNew(new) => EventCtx::KvSet {
-                        key: new.key,
-                        opeid: Some(new.opeid),
-                    },
-                    distribute_task_req::Trigger::EventWrite(write) => EventCtx::KvSet {
-                        key: write.key,
-                        opeid: Some(write.opeid),
-                    },
-                },
+                EventCtx::of_trigger(req.trigger.unwrap()),
             );
 
             if let Err(err) = resp
@@ -522,16 +792,7 @@ impl Executor {
                 req.func,
                 fnmeta.clone(),
                 req.task_id as usize,
-                match req.trigger.unwrap() {
-                    distribute_task_req::Trigger::EventNew(new) => EventCtx::KvSet {
-                        key: new.key,
-                        opeid: Some(new.opeid),
-                    },
-                    distribute_task_req::Trigger::EventWrite(write) => EventCtx::KvSet {
-                        key: write.key,
-                        opeid: Some(write.opeid),
-                    },
-                },
+                EventCtx::of_trigger(req.trigger.unwrap()),
             );
 
             if let Err(err) = resp
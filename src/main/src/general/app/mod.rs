@@ -1,6 +1,7 @@
 pub mod app_native;
 pub mod app_owned;
 pub mod app_shared;
+pub mod fn_log;
 mod http;
 pub mod instance;
 pub mod m_executor;
@@ -14,6 +15,7 @@ use crate::general::app::instance::m_instance_manager::InstanceManager;
 use crate::general::app::m_executor::Executor;
 use crate::general::app::m_executor::FnExeCtxAsyncAllowedType;
 use crate::general::app::v_os::AppMetaVisitOs;
+use crate::general::data::kv_interface::KvOps;
 use crate::general::data::m_data_general::dataitem::DataItemArgWrapper;
 use crate::general::network::proto_ext::ProtoExtDataItem;
 use crate::util::VecExt;
@@ -21,8 +23,13 @@ use crate::{general::network::proto, result::WSResultExt};
 use crate::{
     general::{
         data::{
-            m_data_general::{DataGeneral, DATA_UID_PREFIX_APP_META},
+            m_data_cache::DataCache,
+            m_data_gc::DataGc,
+            m_data_general::{
+                DataGeneral, DATA_UID_PREFIX_APP_META, DATA_UID_PREFIX_FN_KV, FN_KV_NS_SEP,
+            },
             m_kv_store_engine::{KeyTypeServiceList, KvAdditionalConf, KvStoreEngine},
+            m_kv_watch::KvWatch,
         },
         m_os::OperatingSystem,
         network::{
@@ -35,10 +42,11 @@ use crate::{
 };
 use crate::{
     logical_module_view_impl,
-    master::m_master::Master,
+    master::{data::m_data_master::DataMaster, m_master::Master},
     result::{WSResult, WsFuncError},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::{self, JoinHandleWrapper},
+    worker::{kv_namespace::KvNamespace, m_kv_user_client::KvUserClient},
 };
 use async_trait::async_trait;
 use axum::body::Bytes;
@@ -65,6 +73,11 @@ logical_module_view_impl!(View, master, Option<Master>);
 logical_module_view_impl!(View, instance_manager, InstanceManager);
 logical_module_view_impl!(View, data_general, DataGeneral);
 logical_module_view_impl!(View, executor, Executor);
+logical_module_view_impl!(View, kv_watch, KvWatch);
+logical_module_view_impl!(View, data_gc, DataGc);
+logical_module_view_impl!(View, data_cache, DataCache);
+logical_module_view_impl!(View, data_master, Option<DataMaster>);
+logical_module_view_impl!(View, kv_user_client, Option<KvUserClient>);
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -243,6 +256,8 @@ pub enum DataEventTrigger {
     New,
     WriteWithCondition { condition: String },
     NewWithCondition { condition: String },
+    /// deleted by user or expired
+    Delete,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -251,6 +266,83 @@ pub struct DataAccess {
     get: bool,
     delete: bool,
     pub event: Option<DataEventTrigger>,
+    /// overrides the global replication factor for keys of the pattern
+    #[serde(default)]
+    pub replication: Option<usize>,
+    /// erasure codes the items of the pattern instead of replicating them
+    #[serde(default)]
+    pub erasure: Option<ErasureCoding>,
+    /// placement and eviction of the items of the pattern, time auto and pos auto if none
+    #[serde(default)]
+    pub cache: Option<CachePolicy>,
+}
+
+impl DataAccess {
+    pub fn allows(&self, ope: KvOps) -> bool {
+        match ope {
+            KvOps::Get => self.get,
+            KvOps::Set => self.set,
+            KvOps::Delete => self.delete,
+        }
+    }
+}
+
+/// Reed-Solomon shape of an item, any `data_shards` of the shards give it back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureCoding {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+}
+
+/// Cache modes of the items of a key pattern
+///
+/// ```yaml
+/// - cache: {time: auto, pos: specnode, nodes: [2, 3], map: file}
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachePolicy {
+    #[serde(default)]
+    pub time: CacheTime,
+    #[serde(default)]
+    pub pos: CachePos,
+    /// the holders of a `specnode` item
+    #[serde(default)]
+    pub nodes: Vec<NodeID>,
+    #[serde(default)]
+    pub map: CacheMap,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheTime {
+    /// kept until overwritten or deleted
+    #[default]
+    Forever,
+    /// copies beyond the replication factor may be evicted under memory pressure,
+    ///  the ones counting toward it are kept forever
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CachePos {
+    /// a copy on every live node
+    AllNode,
+    /// a copy on each live node of `nodes`
+    SpecNode,
+    /// replicas by the replication factor, next to the bound functions
+    #[default]
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheMap {
+    /// blob in the kv store
+    #[default]
+    Kv,
+    /// file under the data dir, the kv store keeps its path
+    File,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -288,6 +380,22 @@ pub struct FnMeta {
 #[derive(Debug, Deserialize)]
 pub struct AppMetaYaml {
     pub fns: HashMap<String, FnMetaYaml>,
+    #[serde(default)]
+    pub shared_kvs: Vec<SharedKvs>,
+}
+
+/// Keys of another app used by the functions of this one, they stay in the namespace of
+///  that app, the other keys are in the namespace of this app
+///
+/// ```yaml
+/// shared_kvs:
+/// - app: word_count
+///   kvs: [wordcount_{}]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedKvs {
+    pub app: String,
+    pub kvs: Vec<KeyPattern>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -301,6 +409,7 @@ pub enum AppType {
 pub struct AppMeta {
     pub app_type: AppType,
     pub fns: HashMap<String, FnMeta>,
+    pub shared_kvs: Vec<SharedKvs>,
     cache_contains_http_fn: Option<bool>,
 }
 
@@ -309,6 +418,7 @@ impl AppMeta {
         Self {
             app_type,
             fns,
+            shared_kvs: vec![],
             cache_contains_http_fn: None,
         }
     }
@@ -330,9 +440,26 @@ impl AppMeta {
         Ok(Self {
             app_type,
             fns,
+            shared_kvs: metayaml.shared_kvs,
             cache_contains_http_fn: None,
         })
     }
+
+    /// Data unique id prefix of a key pattern of this app, native apps access system
+    ///  datasets by their unique ids, the others their fn kv namespace
+    pub fn data_uid_prefix(&self, app_name: &str, pattern: &KeyPattern) -> String {
+        let prefix = pattern.prefix();
+        if self.app_type == AppType::Native {
+            return prefix.to_owned();
+        }
+        format!(
+            "{}{}{}{}",
+            DATA_UID_PREFIX_FN_KV,
+            KvNamespace::new(app_name, Some(self)).owner(prefix.as_bytes()),
+            FN_KV_NS_SEP as char,
+            prefix
+        )
+    }
     pub fn fns(&self) -> Vec<String> {
         self.fns.iter().map(|(fnname, _)| fnname.clone()).collect()
     }
@@ -428,6 +555,10 @@ impl KeyPattern {
     pub fn new(input: String) -> Self {
         Self(input)
     }
+    /// the fixed part before the first `{}`
+    pub fn prefix(&self) -> &str {
+        self.0.split("{}").next().unwrap_or_default()
+    }
     // match {} for any words
     // "xxxx_{}_{}" matches "xxxx_abc_123"
     // "xxxx{}{}" matches "xxxxabc123"
@@ -534,6 +665,9 @@ impl From<(AppType, FnMetaYaml)> for FnMeta {
                             let mut get = false;
                             let mut delete = false;
                             let mut event = None;
+                            let mut replication = None;
+                            let mut erasure = None;
+                            let mut cache = None;
                             for op in ops {
                                 #[derive(Serialize, Deserialize)]
                                 struct TriggerWithCondition {
@@ -550,10 +684,47 @@ impl From<(AppType, FnMetaYaml)> for FnMeta {
                                         "trigger_by_new" => {
                                             event = Some(DataEventTrigger::New);
                                         }
+                                        "trigger_by_delete" => {
+                                            event = Some(DataEventTrigger::Delete);
+                                        }
                                         _ => {
                                             panic!("invalid op: {:?}", op);
                                         }
                                     }
+                                } else if let Some(factor) = op
+                                    .as_mapping()
+                                    .and_then(|m| m.get("replication"))
+                                    .and_then(|v| v.as_u64())
+                                {
+                                    replication = Some((factor as usize).max(1));
+                                } else if let Some(ec) =
+                                    op.as_mapping().and_then(|m| m.get("erasure"))
+                                {
+                                    let ec: ErasureCoding = serde_yaml::from_value(ec.clone())
+                                        .unwrap_or_else(|err| {
+                                            panic!("invalid erasure: {:?}, {}", op, err)
+                                        });
+                                    // the parity count is kept in 4 bits of the cache mode
+                                    if ec.data_shards == 0
+                                        || ec.parity_shards == 0
+                                        || ec.parity_shards > 15
+                                        || ec.data_shards + ec.parity_shards > 256
+                                    {
+                                        panic!("invalid erasure: {:?}", op);
+                                    }
+                                    erasure = Some(ec);
+                                } else if let Some(policy) =
+                                    op.as_mapping().and_then(|m| m.get("cache"))
+                                {
+                                    let policy: CachePolicy =
+                                        serde_yaml::from_value(policy.clone()).unwrap_or_else(
+                                            |err| panic!("invalid cache: {:?}, {}", op, err),
+                                        );
+                                    if (policy.pos == CachePos::SpecNode) == policy.nodes.is_empty()
+                                    {
+                                        panic!("cache nodes go with pos specnode only: {:?}", op);
+                                    }
+                                    cache = Some(policy);
                                 } else if let Ok(trigger_with_condition) =
                                     serde_yaml::from_value::<HashMap<String, TriggerWithCondition>>(
                                         op.clone(),
@@ -590,6 +761,9 @@ impl From<(AppType, FnMetaYaml)> for FnMeta {
                                     set,
                                     get,
                                     event,
+                                    replication,
+                                    erasure,
+                                    cache,
                                 },
                             )
                         })
@@ -821,6 +995,7 @@ impl AppMetaManager {
             .get_or_del_datameta_from_master(
                 format!("{}{}", DATA_UID_PREFIX_APP_META, app).as_bytes(),
                 false,
+                0,
             )
             .await
         {
@@ -962,6 +1137,25 @@ impl AppMetaManager {
         Ok(Some((meta, Some(datameta))))
     }
 
+    /// app.yaml of a wasm app without its pack, for tests calling the kv client directly
+    #[cfg(test)]
+    pub async fn insert_test_app_meta(&self, app: &str, app_yaml: &str) {
+        let metayaml: AppMetaYaml = serde_yaml::from_str(app_yaml).unwrap();
+        let fns = metayaml
+            .fns
+            .into_iter()
+            .map(|(fnname, fnmeta)| (fnname, (AppType::Wasm, fnmeta).into()))
+            .collect();
+        let mut appmeta = AppMeta::new(AppType::Wasm, fns);
+        appmeta.shared_kvs = metayaml.shared_kvs;
+        let _ = self
+            .meta
+            .write()
+            .await
+            .tmp_app_metas
+            .insert(app.to_owned(), appmeta);
+    }
+
     pub async fn app_uploaded(&self, appname: String, data: Bytes) -> WSResult<()> {
         // 1. tmpapp name & dir
         // TODO: fobidden tmpapp public access
@@ -1035,8 +1229,9 @@ impl AppMetaManager {
 
         // 3. broadcast meta and appfile
         let write_data_id = format!("{}{}", DATA_UID_PREFIX_APP_META, appname);
+        let appmeta_bytes = bincode::serialize(&appmeta).unwrap();
         let write_datas = vec![
-            DataItemArgWrapper::from_bytes(bincode::serialize(&appmeta).unwrap()),
+            DataItemArgWrapper::from_bytes(appmeta_bytes.clone()),
             //DataItemArgWrapper::from_file(rel_app_dir),
             //虞光勇修改，因为编译器提示在调用 DataItemArgWrapper::from_file 方法时，传递的参数类型不匹配。
             // 具体来说，from_file 方法期望的是一个 PathBuf 类型的参数，但你传递的是一个 String 类型。
@@ -1062,7 +1257,9 @@ impl AppMetaManager {
                 Some((
                     self.view.p2p().nodes_config.this_node(),
                     proto::DataOpeType::Write,
-                    OpeRole::UploadApp(DataOpeRoleUploadApp {}),
+                    OpeRole::UploadApp(DataOpeRoleUploadApp {
+                        app_meta: appmeta_bytes,
+                    }),
                 )),
             )
             .await?;
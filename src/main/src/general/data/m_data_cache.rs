@@ -0,0 +1,249 @@
+//! Eviction of the copies of time auto items.
+//!  Each node tracks when its local items were last read or written, under memory pressure
+//!  it gives up the coldest copies beyond the replication factor. Master takes a copy out of
+//!  the plan first, so no reader is sent to a node that dropped it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use serde::Serialize;
+use sysinfo::{RefreshKind, System, SystemExt};
+use ws_derive::LogicalModule;
+
+use crate::general::data::m_data_general::{DataItemIdx, DataVersion};
+use crate::general::data::m_kv_store_engine::{
+    KeyType, KeyTypeDataSetItem, KeyTypeDataSetItemVersion, KeyTypeDataSetMeta, KvAdditionalConf,
+    KvStoreEngine,
+};
+use crate::general::network::{
+    m_p2p::{P2PModule, RPCCaller},
+    proto,
+};
+use crate::sys::{LogicalModule, LogicalModulesRef};
+use crate::{
+    logical_module_view_impl, result::WSResult, sys::LogicalModuleNewArgs, util::JoinHandleWrapper,
+};
+
+logical_module_view_impl!(View);
+logical_module_view_impl!(View, p2p, P2PModule);
+logical_module_view_impl!(View, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(View, data_cache, DataCache);
+
+/// how often a node checks its memory
+const CACHE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// used / total memory above which cold copies are evicted
+const CACHE_EVICT_MEM_RATIO: f64 = 0.85;
+/// max copies evicted in one check
+const CACHE_EVICT_BATCH: usize = 64;
+/// a copy used more recently than this is not cold
+const CACHE_MIN_IDLE: Duration = Duration::from_secs(60);
+/// items whose access is tracked, the untracked ones count as the coldest
+const ACCESS_TRACK_CAPACITY: u64 = 1 << 20;
+
+/// Totals since the node started
+#[derive(Debug, Clone, Serialize)]
+pub struct DataCacheMetrics {
+    pub evicted_items: u64,
+    pub evicted_bytes: u64,
+}
+
+#[derive(LogicalModule)]
+pub struct DataCache {
+    view: View,
+    rpc_caller_evict: RPCCaller<proto::DataCacheEvictRequest>,
+    /// (unique id, idx) -> last local read or write
+    last_access: moka::sync::Cache<(Vec<u8>, DataItemIdx), Instant>,
+    /// one eviction round at a time
+    evicting: tokio::sync::Mutex<()>,
+    evicted_items: AtomicU64,
+    evicted_bytes: AtomicU64,
+}
+
+#[async_trait]
+impl LogicalModule for DataCache {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: View::new(args.logical_modules_ref.clone()),
+            rpc_caller_evict: RPCCaller::new(),
+            last_access: moka::sync::Cache::new(ACCESS_TRACK_CAPACITY),
+            evicting: tokio::sync::Mutex::new(()),
+            evicted_items: AtomicU64::new(0),
+            evicted_bytes: AtomicU64::new(0),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        self.rpc_caller_evict.regist(self.view.p2p());
+
+        let view = self.view.clone();
+        let checker = tokio::spawn(async move {
+            let mut sys = System::new_with_specifics(RefreshKind::new().with_memory());
+            loop {
+                tokio::time::sleep(CACHE_CHECK_INTERVAL).await;
+                sys.refresh_memory();
+                if !memory_pressured(sys.used_memory(), sys.total_memory()) {
+                    continue;
+                }
+                let (items, bytes) = view
+                    .data_cache()
+                    .evict_cold_copies(CACHE_EVICT_BATCH, CACHE_MIN_IDLE)
+                    .await;
+                if items > 0 {
+                    tracing::info!(
+                        "memory pressured, evicted {} cached copies, {} bytes",
+                        items,
+                        bytes
+                    );
+                }
+            }
+        });
+        Ok(vec![JoinHandleWrapper::from(checker)])
+    }
+}
+
+fn memory_pressured(used: u64, total: u64) -> bool {
+    total > 0 && used as f64 / total as f64 > CACHE_EVICT_MEM_RATIO
+}
+
+impl DataCache {
+    pub fn metrics(&self) -> DataCacheMetrics {
+        DataCacheMetrics {
+            evicted_items: self.evicted_items.load(Ordering::Relaxed),
+            evicted_bytes: self.evicted_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The local item is read or written
+    pub fn touch(&self, unique_id: &[u8], idx: DataItemIdx) {
+        self.last_access
+            .insert((unique_id.to_vec(), idx), Instant::now());
+    }
+
+    /// Evict up to `max_items` local copies of time auto items unused for `min_idle`,
+    ///  the coldest first. Returns the evicted items and bytes.
+    pub async fn evict_cold_copies(&self, max_items: usize, min_idle: Duration) -> (u64, u64) {
+        let _evicting = self.evicting.lock().await;
+        let kv_store_engine = self.view.kv_store_engine();
+        let this_node = self.view.p2p().nodes_config.this_node();
+
+        // (last access, unique id, idx, version, bytes)
+        let mut candidates = vec![];
+        for (uid, idx, bytes) in kv_store_engine.data_items(None) {
+            let last_access = self.last_access.get(&(uid.clone(), idx));
+            if last_access.map_or(false, |t| t.elapsed() < min_idle) {
+                continue;
+            }
+            let Some((_, version)) =
+                kv_store_engine.get(&KeyTypeDataSetItemVersion(&uid), false, KvAdditionalConf {})
+            else {
+                continue;
+            };
+            // the local meta is the one the items were written for, master checks again
+            let evictable = kv_store_engine
+                .get(&KeyTypeDataSetMeta(&uid), false, KvAdditionalConf {})
+                .map_or(false, |(_, meta)| {
+                    meta.version == version && meta.evictable_copy(idx, this_node)
+                });
+            if evictable {
+                candidates.push((last_access, uid, idx, version, bytes));
+            }
+        }
+        // never used since the node started sorts first
+        candidates.sort_by_key(|(last_access, ..)| *last_access);
+
+        let (mut items, mut bytes) = (0, 0);
+        for (_, uid, idx, version, size) in candidates.into_iter().take(max_items) {
+            match self.evict_copy(&uid, idx, version).await {
+                Ok(true) => {
+                    items += 1;
+                    bytes += size;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    tracing::warn!("evict data({:?}) item {} failed: {:?}", uid, idx, err);
+                }
+            }
+        }
+        let _ = self.evicted_items.fetch_add(items, Ordering::Relaxed);
+        let _ = self.evicted_bytes.fetch_add(bytes, Ordering::Relaxed);
+        (items, bytes)
+    }
+
+    /// Ask master to take the copy out of the plan, then drop it
+    async fn evict_copy(
+        &self,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        version: DataVersion,
+    ) -> WSResult<bool> {
+        let p2p = self.view.p2p();
+        let resp = self
+            .rpc_caller_evict
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                proto::DataCacheEvictRequest {
+                    unique_id: unique_id.to_vec(),
+                    idx,
+                    version,
+                },
+                Some(Duration::from_secs(30)),
+            )
+            .await?;
+        if !resp.evicted {
+            tracing::debug!(
+                "master keeps data({:?}) item {} here: {}",
+                unique_id,
+                idx,
+                resp.message
+            );
+            return Ok(false);
+        }
+
+        let kv_store_engine = self.view.kv_store_engine();
+        let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(unique_id).make_key());
+        let _guard = lock.write();
+        // a newer version written here meanwhile is not ours to drop, the data gc
+        //  reclaims the evicted one with it
+        let items_version = kv_store_engine
+            .get(
+                &KeyTypeDataSetItemVersion(unique_id),
+                true,
+                KvAdditionalConf {},
+            )
+            .map(|(_, version)| version);
+        if items_version != Some(version) {
+            return Ok(false);
+        }
+        let _ = kv_store_engine.del(
+            KeyTypeDataSetItem {
+                uid: unique_id,
+                idx,
+            },
+            true,
+        )?;
+        if kv_store_engine.data_items(Some(unique_id)).is_empty() {
+            let _ = kv_store_engine.del(KeyTypeDataSetItemVersion(unique_id), true)?;
+        }
+        kv_store_engine.flush();
+        self.last_access.invalidate(&(unique_id.to_vec(), idx));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::memory_pressured;
+
+    #[test]
+    fn test_memory_pressured() {
+        assert!(!memory_pressured(0, 0));
+        assert!(!memory_pressured(50, 100));
+        assert!(!memory_pressured(85, 100));
+        assert!(memory_pressured(86, 100));
+        assert!(memory_pressured(100, 100));
+    }
+}
@@ -0,0 +1,490 @@
+//! Reclaim data items that no current dataset version needs on this node.
+//!  Every node periodically checks its local items against the meta on master,
+//!  master also sends tombstones to the nodes dropped from the plan of a new version.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::async_trait;
+use serde::Serialize;
+use ws_derive::LogicalModule;
+
+use crate::general::data::m_data_general::{
+    DataGeneral, DataItemIdx, DataSetMetaV2, DataVersion, BATCH_TRANSFER_MAX_IDLE,
+};
+use crate::general::data::m_kv_store_engine::{
+    KeyTypeDataSetItem, KeyTypeDataSetItemVersion, KeyTypeDataSetMeta, KvAdditionalConf,
+    KvStoreEngine, KvVersion,
+};
+use crate::general::network::{
+    m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
+    proto,
+};
+use crate::result::{WSError, WsDataError};
+use crate::sys::{LogicalModule, LogicalModulesRef, NodeID};
+use crate::{
+    logical_module_view_impl, result::WSResult, sys::LogicalModuleNewArgs, util::JoinHandleWrapper,
+};
+
+logical_module_view_impl!(View);
+logical_module_view_impl!(View, p2p, P2PModule);
+logical_module_view_impl!(View, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(View, data_general, DataGeneral);
+logical_module_view_impl!(View, data_gc, DataGc);
+
+/// how often a node reconciles its local items with master
+const GC_INTERVAL: Duration = Duration::from_secs(300);
+
+/// One reclaimed (or reclaimable in a dry run) item
+#[derive(Debug, Clone, Serialize)]
+pub struct GcReclaimedItem {
+    pub unique_id: String,
+    pub idx: DataItemIdx,
+    pub bytes: u64,
+}
+
+/// Result of one gc run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub scanned_items: u64,
+    pub reclaimed: Vec<GcReclaimedItem>,
+    pub reclaimed_bytes: u64,
+    /// datasets left untouched because master couldn't be asked or they changed meanwhile
+    pub skipped_datasets: u64,
+}
+
+/// Totals since the node started, dry runs are not counted
+#[derive(Debug, Clone, Serialize)]
+pub struct GcMetrics {
+    pub runs: u64,
+    pub reclaimed_items: u64,
+    pub reclaimed_bytes: u64,
+    pub tombstones: u64,
+}
+
+#[derive(LogicalModule)]
+pub struct DataGc {
+    view: View,
+    rpc_caller_tombstone: RPCCaller<proto::DataGcTombstoneRequest>,
+    rpc_handler_tombstone: RPCHandler<proto::DataGcTombstoneRequest>,
+    /// one run at a time, a manual run waits for the periodic one
+    running: tokio::sync::Mutex<()>,
+    runs: AtomicU64,
+    reclaimed_items: AtomicU64,
+    reclaimed_bytes: AtomicU64,
+    tombstones: AtomicU64,
+}
+
+#[async_trait]
+impl LogicalModule for DataGc {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: View::new(args.logical_modules_ref.clone()),
+            rpc_caller_tombstone: RPCCaller::new(),
+            rpc_handler_tombstone: RPCHandler::new(),
+            running: tokio::sync::Mutex::new(()),
+            runs: AtomicU64::new(0),
+            reclaimed_items: AtomicU64::new(0),
+            reclaimed_bytes: AtomicU64::new(0),
+            tombstones: AtomicU64::new(0),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        self.rpc_caller_tombstone.regist(self.view.p2p());
+
+        let view = self.view.clone();
+        self.rpc_handler_tombstone.regist(
+            self.view.p2p(),
+            move |responsor: RPCResponsor<proto::DataGcTombstoneRequest>,
+                  req: proto::DataGcTombstoneRequest| {
+                let resp = view.data_gc().handle_tombstone(&req.unique_id, req.version);
+                let _ = tokio::spawn(async move {
+                    if let Err(err) = responsor.send_resp(resp).await {
+                        tracing::warn!("send data gc tombstone response failed: {:?}", err);
+                    }
+                });
+                Ok(())
+            },
+        );
+
+        let view = self.view.clone();
+        let gc = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(GC_INTERVAL).await;
+                let report = view.data_gc().run(false).await;
+                if !report.reclaimed.is_empty() {
+                    tracing::info!(
+                        "data gc reclaimed {} items, {} bytes",
+                        report.reclaimed.len(),
+                        report.reclaimed_bytes
+                    );
+                }
+            }
+        });
+        Ok(vec![JoinHandleWrapper::from(gc)])
+    }
+}
+
+/// Idxs of the local items of one dataset that can be reclaimed, None to keep them all.
+///  `items_version` is the version the local items were written for,
+///  `meta` the current meta on master, None if the dataset no longer exists.
+fn reclaimable_idxs(
+    idxs: &[DataItemIdx],
+    items_version: Option<DataVersion>,
+    meta: Option<&DataSetMetaV2>,
+    this_node: NodeID,
+) -> Option<Vec<DataItemIdx>> {
+    let Some(meta) = meta else {
+        return Some(idxs.to_vec());
+    };
+    match items_version {
+        // written for a version master doesn't know yet, leave it to a later run
+        Some(version) if version > meta.version => None,
+        Some(version) if version == meta.version => Some(
+            idxs.iter()
+                .copied()
+                .filter(|idx| !meta.holds_item(*idx, this_node))
+                .collect(),
+        ),
+        // superseded, or no version recorded for them at all
+        _ => Some(idxs.to_vec()),
+    }
+}
+
+impl DataGc {
+    pub fn metrics(&self) -> GcMetrics {
+        GcMetrics {
+            runs: self.runs.load(Ordering::Relaxed),
+            reclaimed_items: self.reclaimed_items.load(Ordering::Relaxed),
+            reclaimed_bytes: self.reclaimed_bytes.load(Ordering::Relaxed),
+            tombstones: self.tombstones.load(Ordering::Relaxed),
+        }
+    }
+
+    fn items_version(&self, uid: &[u8]) -> Option<(KvVersion, DataVersion)> {
+        self.view.kv_store_engine().get(
+            &KeyTypeDataSetItemVersion(uid),
+            true,
+            KvAdditionalConf {},
+        )
+    }
+
+    /// Check every local dataset against master and reclaim the items no longer needed here,
+    ///  with `dry_run` only report what would be reclaimed
+    pub async fn run(&self, dry_run: bool) -> GcReport {
+        let _running = self.running.lock().await;
+        let kv_store_engine = self.view.kv_store_engine();
+        let p2p = self.view.p2p();
+        let this_node = p2p.nodes_config.this_node();
+        let is_master = p2p.nodes_config.this.1.is_master();
+
+        let mut datasets: BTreeMap<Vec<u8>, Vec<(DataItemIdx, u64)>> = BTreeMap::new();
+        for (uid, idx, bytes) in kv_store_engine.data_items(None) {
+            datasets.entry(uid).or_default().push((idx, bytes));
+        }
+        // metas copied to this node outlive their dataset if nobody tells it
+        if !is_master {
+            for uid in kv_store_engine.data_set_meta_uids() {
+                let _ = datasets.entry(uid).or_default();
+            }
+        }
+
+        let mut report = GcReport {
+            dry_run,
+            ..Default::default()
+        };
+        for (uid, items) in datasets {
+            report.scanned_items += items.len() as u64;
+            let meta = match self
+                .view
+                .data_general()
+                .get_or_del_datameta_from_master(&uid, false, 0)
+                .await
+            {
+                Ok(meta) => Some(meta),
+                Err(WSError::WsDataError(WsDataError::DataSetNotFound { .. })) => None,
+                Err(err) => {
+                    tracing::debug!("data gc skips {:?}, get meta failed: {:?}", uid, err);
+                    report.skipped_datasets += 1;
+                    continue;
+                }
+            };
+
+            let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(&uid).make_key());
+            let observed = {
+                let _guard = lock.read();
+                self.items_version(&uid)
+            };
+            let idxs: Vec<DataItemIdx> = items.iter().map(|(idx, _)| *idx).collect();
+            let Some(reclaim) = reclaimable_idxs(
+                &idxs,
+                observed.map(|(_, version)| version),
+                meta.as_ref(),
+                this_node,
+            ) else {
+                continue;
+            };
+            let drop_meta = meta.is_none() && !is_master;
+            if reclaim.is_empty() && !drop_meta {
+                continue;
+            }
+
+            if !dry_run {
+                let _guard = lock.write();
+                // a write may have landed since the decision
+                if self.items_version(&uid) != observed {
+                    report.skipped_datasets += 1;
+                    continue;
+                }
+                for idx in reclaim.iter() {
+                    if let Err(err) = kv_store_engine.del(
+                        KeyTypeDataSetItem {
+                            uid: &uid,
+                            idx: *idx,
+                        },
+                        true,
+                    ) {
+                        tracing::warn!("data gc delete item failed: {:?}", err);
+                    }
+                }
+                if reclaim.len() == items.len() {
+                    let _ = kv_store_engine.del(KeyTypeDataSetItemVersion(&uid), true);
+                }
+                if drop_meta {
+                    let _ = kv_store_engine.del(KeyTypeDataSetMeta(&uid), true);
+                    self.view.data_general().invalidate_meta_cache(&uid);
+                }
+            }
+            for (idx, bytes) in items.iter().filter(|(idx, _)| reclaim.contains(idx)) {
+                report.reclaimed_bytes += bytes;
+                report.reclaimed.push(GcReclaimedItem {
+                    unique_id: String::from_utf8_lossy(&uid).into_owned(),
+                    idx: *idx,
+                    bytes: *bytes,
+                });
+            }
+        }
+
+        if !dry_run {
+            // staged data of streaming writes whose writer is gone
+            self.view.data_general().remove_stale_stream_stages().await;
+            // batch transfers whose sender is gone
+            self.view
+                .data_general()
+                .remove_stale_batch_transfers(BATCH_TRANSFER_MAX_IDLE)
+                .await;
+            // files of map file items overwritten or deleted since
+            self.view.data_general().remove_unused_mapped_items().await;
+            // files of streamed items overwritten or deleted since
+            self.view.data_general().remove_unused_stream_items().await;
+            kv_store_engine.flush();
+            let _ = self.runs.fetch_add(1, Ordering::Relaxed);
+            let _ = self
+                .reclaimed_items
+                .fetch_add(report.reclaimed.len() as u64, Ordering::Relaxed);
+            let _ = self
+                .reclaimed_bytes
+                .fetch_add(report.reclaimed_bytes, Ordering::Relaxed);
+        }
+        report
+    }
+
+    /// Drop everything of `uid` kept for `version` or older, this node is not in the newer plan
+    fn handle_tombstone(&self, uid: &[u8], version: DataVersion) -> proto::DataGcTombstoneResponse {
+        let kv_store_engine = self.view.kv_store_engine();
+        let mut resp = proto::DataGcTombstoneResponse {
+            reclaimed_items: 0,
+            reclaimed_bytes: 0,
+        };
+        let _ = self.tombstones.fetch_add(1, Ordering::Relaxed);
+
+        let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(uid).make_key());
+        let _guard = lock.write();
+        if self
+            .items_version(uid)
+            .map_or(true, |(_, items_version)| items_version <= version)
+        {
+            for (_, idx, bytes) in kv_store_engine.data_items(Some(uid)) {
+                match kv_store_engine.del(KeyTypeDataSetItem { uid, idx }, true) {
+                    Ok(_) => {
+                        resp.reclaimed_items += 1;
+                        resp.reclaimed_bytes += bytes;
+                    }
+                    Err(err) => tracing::warn!("data gc delete item failed: {:?}", err),
+                }
+            }
+            let _ = kv_store_engine.del(KeyTypeDataSetItemVersion(uid), true);
+        }
+        if !self.view.p2p().nodes_config.this.1.is_master() {
+            let stale_meta = kv_store_engine
+                .get(&KeyTypeDataSetMeta(uid), true, KvAdditionalConf {})
+                .map_or(false, |(_, meta)| meta.version <= version);
+            if stale_meta {
+                let _ = kv_store_engine.del(KeyTypeDataSetMeta(uid), true);
+                self.view.data_general().invalidate_meta_cache(uid);
+            }
+        }
+        kv_store_engine.flush();
+
+        let _ = self
+            .reclaimed_items
+            .fetch_add(resp.reclaimed_items, Ordering::Relaxed);
+        let _ = self
+            .reclaimed_bytes
+            .fetch_add(resp.reclaimed_bytes, Ordering::Relaxed);
+        resp
+    }
+
+    /// Tell `node` to reclaim what it keeps of `uid` up to `version`
+    pub async fn tombstone(
+        &self,
+        uid: &[u8],
+        version: DataVersion,
+        node: NodeID,
+    ) -> WSResult<proto::DataGcTombstoneResponse> {
+        let resp = self
+            .rpc_caller_tombstone
+            .call(
+                self.view.p2p(),
+                node,
+                proto::DataGcTombstoneRequest {
+                    unique_id: uid.to_vec(),
+                    version,
+                },
+                Some(Duration::from_secs(30)),
+            )
+            .await?;
+        tracing::debug!(
+            "node {} reclaimed {} items ({} bytes) on tombstone",
+            node,
+            resp.reclaimed_items,
+            resp.reclaimed_bytes
+        );
+        Ok(resp)
+    }
+
+    /// Tell `nodes`, dropped from the plan after `version`, to reclaim what they keep of `uid`
+    pub fn send_tombstones(&self, uid: &[u8], version: DataVersion, nodes: Vec<NodeID>) {
+        for node in nodes {
+            let view = self.view.clone();
+            let unique_id = uid.to_vec();
+            let _ = tokio::spawn(async move {
+                if let Err(err) = view.data_gc().tombstone(&unique_id, version, node).await {
+                    // the periodic run on that node reclaims them later
+                    tracing::debug!("send data gc tombstone to {} failed: {:?}", node, err);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{reclaimable_idxs, DataGc};
+    use crate::general::data::m_data_general::{DataSetMetaBuilder, DataSplit, EachNodeSplit};
+    use crate::general::data::m_kv_store_engine::{
+        KeyTypeDataSetItem, KeyTypeDataSetItemVersion, KvStoreEngine,
+    };
+    use crate::general::network::m_p2p::P2PModule;
+    use crate::general::test_utils;
+    use crate::logical_module_view_impl;
+    use crate::sys::LogicalModulesRef;
+
+    logical_module_view_impl!(TestView);
+    logical_module_view_impl!(TestView, p2p, P2PModule);
+    logical_module_view_impl!(TestView, kv_store_engine, KvStoreEngine);
+    logical_module_view_impl!(TestView, data_gc, DataGc);
+
+    #[test]
+    fn test_reclaimable_idxs() {
+        let split = |node_id| EachNodeSplit {
+            node_id,
+            data_offset: 0,
+            data_size: 10,
+            cache_mode: 0,
+        };
+        let mut builder = DataSetMetaBuilder::new();
+        let _ = builder.version(3);
+        let _ = builder.set_data_splits(vec![
+            DataSplit {
+                splits: vec![split(1), split(2)],
+            },
+            DataSplit {
+                splits: vec![split(2)],
+            },
+        ]);
+        let meta = builder.build();
+
+        // dataset deleted on master
+        assert_eq!(reclaimable_idxs(&[0, 1], Some(3), None, 1), Some(vec![0, 1]));
+        // superseded version
+        assert_eq!(reclaimable_idxs(&[0, 1], Some(2), Some(&meta), 2), Some(vec![0, 1]));
+        // current version, node 1 only keeps item 0, idx 2 is past the item count
+        assert_eq!(reclaimable_idxs(&[0, 1, 2], Some(3), Some(&meta), 1), Some(vec![1, 2]));
+        assert_eq!(reclaimable_idxs(&[0, 1], Some(3), Some(&meta), 2), Some(vec![]));
+        // written for a version master hasn't committed
+        assert_eq!(reclaimable_idxs(&[0, 1], Some(4), Some(&meta), 1), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_gc_reclaims_items() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let master = TestView::new(sys1);
+        let worker = TestView::new(sys2);
+        let kv_store_engine = worker.kv_store_engine();
+        let put_items = |uid: &[u8]| {
+            for idx in 0..2 {
+                let _ = kv_store_engine
+                    .set(KeyTypeDataSetItem { uid, idx }, &vec![7u8; 16], false)
+                    .unwrap();
+            }
+            let _ = kv_store_engine
+                .set(KeyTypeDataSetItemVersion(uid), &1, false)
+                .unwrap();
+        };
+        let local_items = |uid: &[u8]| kv_store_engine.data_items(Some(uid)).len();
+
+        // items of a version the worker is dropped from after a tombstone
+        let tombstoned: &[u8] = b"test_gc_tombstoned";
+        put_items(tombstoned);
+        let tombstones = worker.data_gc().metrics().tombstones;
+        master.data_gc().send_tombstones(
+            tombstoned,
+            1,
+            vec![worker.p2p().nodes_config.this_node()],
+        );
+        for _ in 0..50 {
+            if local_items(tombstoned) == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(local_items(tombstoned), 0);
+        assert_eq!(worker.data_gc().metrics().tombstones, tombstones + 1);
+
+        // items of a dataset master doesn't know, only reported by a dry run
+        let orphan: &[u8] = b"test_gc_orphan";
+        put_items(orphan);
+        let reclaimed_of = |report: &super::GcReport| {
+            report
+                .reclaimed
+                .iter()
+                .filter(|item| item.unique_id.as_bytes() == orphan)
+                .count()
+        };
+        let report = worker.data_gc().run(true).await;
+        assert_eq!(reclaimed_of(&report), 2);
+        assert_eq!(local_items(orphan), 2);
+        let report = worker.data_gc().run(false).await;
+        assert_eq!(reclaimed_of(&report), 2);
+        assert_eq!(local_items(orphan), 0);
+    }
+}
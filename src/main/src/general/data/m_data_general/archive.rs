@@ -0,0 +1,394 @@
+/// Data Export / Import
+///
+/// Backup or migration of all the datasets of a cluster through a self-describing archive,
+/// which is a directory of:
+///
+/// - `manifest.json`: format version, export time, and for each dataset its uid and version,
+///   with the cache mode, size, md5 and file of each item
+/// - `items/<dataset seq>_<idx>`: content of one item
+///
+/// Export runs on master, where all the metas are, and pulls the items with range reads.
+/// Import runs on any node and streams each dataset again with its uid and cache modes,
+/// so nothing is buffered beyond one block either way. Items come back as raw bytes,
+/// a file item as the content of its file, and versions restart in the target cluster.
+///
+/// Over http the archive dir is relative to the configured `archive_root`.
+use super::*;
+use md5::{Digest, Md5};
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const ARCHIVE_FORMAT_VERSION: u32 = 1;
+const ARCHIVE_MANIFEST: &str = "manifest.json";
+const ARCHIVE_ITEMS_DIR: &str = "items";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchiveManifest {
+    format_version: u32,
+    exported_at_ms: u64,
+    datasets: Vec<ArchivedDataset>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedDataset {
+    /// hex, uids are arbitrary bytes
+    unique_id: String,
+    /// version in the source cluster
+    version: DataVersion,
+    items: Vec<ArchivedItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedItem {
+    cache_mode: CacheMode,
+    size: u64,
+    /// hex md5 of the content
+    md5: String,
+    /// relative to the archive dir
+    file: String,
+}
+
+/// Outcome of an export or import
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DataArchiveReport {
+    pub datasets: u64,
+    pub items: u64,
+    pub bytes: u64,
+    /// datasets left out and why, the rest are done anyway
+    pub failed: Vec<(String, String)>,
+}
+
+impl DataArchiveReport {
+    /// `unique_id` is hex, as in the manifest
+    fn fail(&mut self, unique_id: String, err: WSError) {
+        tracing::warn!("data archive skips dataset({}): {:?}", unique_id, err);
+        self.failed.push((unique_id, format!("{:?}", err)));
+    }
+}
+
+/// Archive dir `dir` under `root`, which must be configured. `dir` is relative and can't
+///  leave the root, through `..` or a symlink
+pub fn resolve_archive_dir(root: Option<&Path>, dir: &str) -> WSResult<PathBuf> {
+    let Some(root) = root else {
+        return Err(archive_err(Path::new(dir), "no archive root configured"));
+    };
+    let relative = Path::new(dir);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(archive_err(
+            relative,
+            "must be a relative path inside the archive root",
+        ));
+    }
+    let path = root.join(relative);
+    // an existing dir may still be a symlink out of the root
+    if let (Ok(real_root), Ok(real_path)) = (root.canonicalize(), path.canonicalize()) {
+        if !real_path.starts_with(real_root) {
+            return Err(archive_err(relative, "resolves outside the archive root"));
+        }
+    }
+    Ok(path)
+}
+
+fn archive_err(path: &Path, msg: impl Into<String>) -> WSError {
+    WsDataError::DataArchiveErr {
+        path: path.to_path_buf(),
+        msg: msg.into(),
+    }
+    .into()
+}
+
+/// Size and md5 of the item read back from the archive must be the exported ones
+fn verify_archived_item(
+    unique_id: &[u8],
+    idx: DataItemIdx,
+    item: &ArchivedItem,
+    size: u64,
+    md5: Vec<u8>,
+) -> WSResult<()> {
+    if size != item.size {
+        return Err(WsDataError::SizeMismatch {
+            expected: item.size as usize,
+            actual: size as usize,
+        }
+        .into());
+    }
+    let expected = hex::decode(&item.md5).unwrap_or_default();
+    if expected != md5 {
+        return Err(WsDataError::ChecksumMismatch {
+            unique_id: unique_id.to_vec(),
+            idx,
+            expected,
+            actual: md5,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+impl DataGeneral {
+    /// Export every dataset to the archive dir, a dataset that can't be read is reported
+    ///  and left out
+    pub async fn export_datasets(&self, dir: &Path) -> WSResult<DataArchiveReport> {
+        if !self.view.p2p().nodes_config.this.1.is_master() {
+            return Err(archive_err(dir, "export runs on master, where the metas are"));
+        }
+        let items_dir = dir.join(ARCHIVE_ITEMS_DIR);
+        tokio::fs::create_dir_all(&items_dir)
+            .await
+            .map_err(|err| WsDataError::FileWriteErr {
+                path: items_dir.clone(),
+                err,
+            })?;
+
+        let kv_store_engine = self.view.kv_store_engine();
+        let mut manifest = ArchiveManifest {
+            format_version: ARCHIVE_FORMAT_VERSION,
+            exported_at_ms: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            datasets: vec![],
+        };
+        let mut report = DataArchiveReport::default();
+        for unique_id in kv_store_engine.data_set_meta_uids() {
+            // deleted since listed
+            let Some((_, meta)) = kv_store_engine.get(
+                &KeyTypeDataSetMeta(&unique_id),
+                false,
+                KvAdditionalConf::default(),
+            ) else {
+                continue;
+            };
+            // a failed dataset's seq is taken by the next one, its files are overwritten
+            let seq = manifest.datasets.len();
+            match self.export_dataset(dir, seq, &unique_id, &meta).await {
+                Ok(dataset) => {
+                    report.datasets += 1;
+                    report.items += dataset.items.len() as u64;
+                    report.bytes += dataset.items.iter().map(|item| item.size).sum::<u64>();
+                    manifest.datasets.push(dataset);
+                }
+                Err(err) => report.fail(hex::encode(&unique_id), err),
+            }
+        }
+
+        let manifest_path = dir.join(ARCHIVE_MANIFEST);
+        let encoded = serde_json::to_vec_pretty(&manifest)
+            .map_err(|err| archive_err(&manifest_path, format!("encode manifest: {}", err)))?;
+        tokio::fs::write(&manifest_path, encoded)
+            .await
+            .map_err(|err| WsDataError::FileWriteErr {
+                path: manifest_path.clone(),
+                err,
+            })?;
+        tracing::info!(
+            "exported {} datasets, {} bytes to {:?}, {} failed",
+            report.datasets,
+            report.bytes,
+            dir,
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    async fn export_dataset(
+        &self,
+        dir: &Path,
+        seq: usize,
+        unique_id: &[u8],
+        meta: &DataSetMetaV2,
+    ) -> WSResult<ArchivedDataset> {
+        let block_size = self.view.p2p().nodes_config.batch_transfer.block_size as u64;
+        let mut items = vec![];
+        for idx in 0..meta.data_item_cnt() as DataItemIdx {
+            // an erasure coded item is decoded as a whole for any range, read it at once
+            let chunk_size = match meta.cache_mode_visitor(idx).erasure_parity_shards() {
+                Some(_) => 0,
+                None => block_size,
+            };
+            let file = format!("{}/{}_{}", ARCHIVE_ITEMS_DIR, seq, idx);
+            let path = dir.join(&file);
+            let mut out = tokio::fs::File::create(&path)
+                .await
+                .map_err(|err| WsDataError::FileOpenErr {
+                    path: path.clone(),
+                    err,
+                })?;
+            let mut hasher = Md5::new();
+            let mut offset = 0;
+            loop {
+                let (bytes, total) = self
+                    .get_item_range(meta, unique_id, idx, offset, chunk_size)
+                    .await?;
+                hasher.update(&bytes);
+                out.write_all(&bytes)
+                    .await
+                    .map_err(|err| WsDataError::FileWriteErr {
+                        path: path.clone(),
+                        err,
+                    })?;
+                offset += bytes.len() as u64;
+                if bytes.is_empty() || offset >= total {
+                    break;
+                }
+            }
+            out.flush().await.map_err(|err| WsDataError::FileWriteErr {
+                path: path.clone(),
+                err,
+            })?;
+
+            let md5 = hasher.finalize().to_vec();
+            // the checksum given by the writer catches an item corrupted on all holders
+            if let Some(expected) = meta
+                .data_checksums
+                .get(idx as usize)
+                .filter(|checksum| !checksum.is_empty())
+            {
+                if *expected != md5 {
+                    return Err(WsDataError::ChecksumMismatch {
+                        unique_id: unique_id.to_vec(),
+                        idx,
+                        expected: expected.clone(),
+                        actual: md5,
+                    }
+                    .into());
+                }
+            }
+            items.push(ArchivedItem {
+                cache_mode: meta.cache_mode_visitor(idx).0,
+                size: offset,
+                md5: hex::encode(md5),
+                file,
+            });
+        }
+        Ok(ArchivedDataset {
+            unique_id: hex::encode(unique_id),
+            version: meta.version,
+            items,
+        })
+    }
+
+    /// Write every dataset of the archive dir into this cluster, an existing dataset with
+    ///  the same uid gets the archived content as its next version
+    pub async fn import_datasets(&self, dir: &Path) -> WSResult<DataArchiveReport> {
+        let manifest_path = dir.join(ARCHIVE_MANIFEST);
+        let encoded = tokio::fs::read(&manifest_path)
+            .await
+            .map_err(|err| WsDataError::FileReadErr {
+                path: manifest_path.clone(),
+                err,
+            })?;
+        let manifest: ArchiveManifest = serde_json::from_slice(&encoded)
+            .map_err(|err| archive_err(&manifest_path, format!("decode manifest: {}", err)))?;
+        if manifest.format_version != ARCHIVE_FORMAT_VERSION {
+            return Err(archive_err(
+                &manifest_path,
+                format!("unsupported format version {}", manifest.format_version),
+            ));
+        }
+
+        let mut report = DataArchiveReport::default();
+        for dataset in manifest.datasets {
+            let Ok(unique_id) = hex::decode(&dataset.unique_id) else {
+                report.fail(
+                    dataset.unique_id,
+                    archive_err(&manifest_path, "unique id is not hex"),
+                );
+                continue;
+            };
+            let items = dataset.items.len() as u64;
+            let bytes = dataset.items.iter().map(|item| item.size).sum::<u64>();
+            match self.import_dataset(dir, &unique_id, dataset).await {
+                Ok(()) => {
+                    report.datasets += 1;
+                    report.items += items;
+                    report.bytes += bytes;
+                }
+                Err(err) => report.fail(hex::encode(&unique_id), err),
+            }
+        }
+        tracing::info!(
+            "imported {} datasets, {} bytes from {:?}, {} failed",
+            report.datasets,
+            report.bytes,
+            dir,
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    /// Stream the items of the dataset from their files as one new version,
+    ///  a dataset without items is written as such
+    async fn import_dataset(
+        &self,
+        dir: &Path,
+        unique_id: &[u8],
+        dataset: ArchivedDataset,
+    ) -> WSResult<()> {
+        let this_node = self.view.p2p().nodes_config.this_node();
+        let context = Some((
+            this_node,
+            proto::DataOpeType::Write,
+            proto::data_schedule_context::OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+                app_func: "data/import".to_owned(),
+                node_id: this_node,
+            }),
+        ));
+        let opts = WriteDataOpts {
+            cache_modes: dataset.items.iter().map(|item| item.cache_mode).collect(),
+            ..Default::default()
+        };
+        let mut writer = self
+            .open_data_stream_items(unique_id, dataset.items.len(), context, opts)
+            .await?;
+        let mut buf = vec![0; self.view.p2p().nodes_config.batch_transfer.block_size];
+        let res = async {
+            for (idx, item) in dataset.items.iter().enumerate() {
+                if idx > 0 {
+                    writer.next_item().await?;
+                }
+                let path = dir.join(&item.file);
+                let mut file =
+                    tokio::fs::File::open(&path)
+                        .await
+                        .map_err(|err| WsDataError::FileOpenErr {
+                            path: path.clone(),
+                            err,
+                        })?;
+                let mut size = 0;
+                loop {
+                    let n = file
+                        .read(&mut buf)
+                        .await
+                        .map_err(|err| WsDataError::FileReadErr {
+                            path: path.clone(),
+                            err,
+                        })?;
+                    if n == 0 {
+                        break;
+                    }
+                    size += n as u64;
+                    writer.write(&buf[..n]).await?;
+                }
+                verify_archived_item(
+                    unique_id,
+                    idx as DataItemIdx,
+                    item,
+                    size,
+                    writer.item_checksum(),
+                )?;
+            }
+            Ok::<(), WSError>(())
+        }
+        .await;
+        if let Err(err) = res {
+            writer.abort().await;
+            return Err(err);
+        }
+        let _ = writer.commit().await?;
+        Ok(())
+    }
+}
@@ -22,9 +22,14 @@
 /// - Concurrent processing of received blocks
 /// - Support for both memory and file-based transfers
 /// - Real-time block validation and assembly
+/// - Resuming: the sender asks for the blocks already received and skips them,
+///   a failed block is retried with backoff
+///
+/// Block size, concurrency and retries come from `batch_transfer` of the node config.
 ///
 /// For detailed implementation of the regular data interface, see the data.rs module.
 use super::*;
+use crate::general::data::m_data_general::batch_handler::{block_type_of, BatchProgress};
 use crate::general::network::proto;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::Semaphore;
@@ -32,6 +37,12 @@ use std::sync::Arc;
 use std::time::Duration;
 use crate::general::data::m_data_general::dataitem::DataItemSource;
 
+/// backoff before the first retry of a block, doubled on each retry
+const BLOCK_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+const BLOCK_RPC_TIMEOUT: Duration = Duration::from_secs(30);
+/// a pull covers the whole item, large ones take a while
+const REPLICATE_ITEM_TIMEOUT: Duration = Duration::from_secs(600);
+
 impl proto::DataItem {
     pub fn size(&self) -> usize {
         match &self.data_item_dispatch {
@@ -42,6 +53,71 @@ impl proto::DataItem {
     }
 }
 
+/// 读取数据块
+async fn read_block(data: &DataItemSource, offset: u64, size: usize) -> WSResult<Vec<u8>> {
+    match data {
+        DataItemSource::Memory { data } => Ok(data[offset as usize..offset as usize + size].to_vec()),
+        DataItemSource::File { path } => {
+            let mut file = tokio::fs::File::open(path).await.map_err(|err| WsDataError::FileOpenErr {
+                path: path.clone(),
+                err,
+            })?;
+            let mut buffer = vec![0; size];
+            let _ = file.seek(std::io::SeekFrom::Start(offset)).await.map_err(|err| WsDataError::FileSeekErr {
+                path: path.clone(),
+                err,
+            })?;
+            // read_exact保证读取指定长度的数据或返回错误
+            let _ = file.read_exact(&mut buffer).await.map_err(|err| WsDataError::FileReadErr {
+                path: path.clone(),
+                err,
+            })?;
+            Ok(buffer)
+        }
+    }
+}
+
+/// Send one block, retried with backoff on rpc errors and on rejections the receiver marks retryable
+async fn send_block(
+    view: DataGeneralView,
+    target_node: NodeID,
+    request: proto::BatchDataRequest,
+    retries: usize,
+) -> WSResult<()> {
+    let mut attempt = 0;
+    loop {
+        let (err, retryable) = match view
+            .data_general()
+            .rpc_call_batch_data
+            .call(view.p2p(), target_node, request.clone(), Some(BLOCK_RPC_TIMEOUT))
+            .await
+        {
+            Ok(resp) if resp.success => return Ok(()),
+            Ok(resp) => (
+                WSError::from(WsDataError::BatchTransferFailed {
+                    request_id: request.request_id.clone().unwrap(),
+                    reason: resp.error_message,
+                }),
+                resp.retryable,
+            ),
+            Err(err) => (err, true),
+        };
+        if !retryable || attempt >= retries {
+            return Err(err);
+        }
+        let backoff = BLOCK_RETRY_BACKOFF * 2u32.pow(attempt.min(6) as u32);
+        tracing::warn!(
+            "batch block {} to node {} failed, retry in {:?}: {:?}",
+            request.block_index,
+            target_node,
+            backoff,
+            err
+        );
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
 impl DataGeneral {
     /// 发起批量数据传输
     pub async fn call_batch_data(
@@ -50,114 +126,450 @@ impl DataGeneral {
         unique_id: Vec<u8>,
         version: u64,
         data: proto::DataItem,
+        compression: DataCompression,
     ) -> WSResult<proto::BatchDataResponse> {
-        // 调用 batch_transfer 函数处理数据传输
-        async fn batch_transfer(
-            unique_id: Vec<u8>,
-            version: u64,
-            target_node: NodeID,
-            data: Arc<DataItemSource>,
-            view: DataGeneralView,
-        ) -> WSResult<()> {
-            let total_size = match data.as_ref() {
-                DataItemSource::Memory { data } => data.len(),
-                DataItemSource::File { path } => {
-                    tokio::fs::metadata(path).await?.len() as usize
-                }
-            };
-            let total_blocks = (total_size + DEFAULT_BLOCK_SIZE - 1) / DEFAULT_BLOCK_SIZE;
-            let semaphore = Arc::new(Semaphore::new(32));
-            let mut handles: Vec<tokio::task::JoinHandle<WSResult<()>>> = Vec::new();
-
-            // 发送所有数据块
-            for block_idx in 0..total_blocks {
-                // 获取信号量许可
-                let permit = semaphore.clone().acquire_owned().await.unwrap();
-                let offset = block_idx as usize * DEFAULT_BLOCK_SIZE;
-                let size = DEFAULT_BLOCK_SIZE.min(total_size - offset);
-
-                // 读取数据块
-                let block_data = match data.as_ref() {
-                    DataItemSource::Memory { data } => data[offset..offset + size].to_vec(),
-                    DataItemSource::File { path } => {
-                        let mut file = tokio::fs::File::open(path).await?;
-                        let mut buffer = vec![0; size];
-                        let _ = file.seek(std::io::SeekFrom::Start(offset as u64)).await?;
-                        let _ = file.read_exact(&mut buffer).await?;
-                        buffer
-                    }
-                };
+        self.batch_transfer(unique_id, 0, version, node_id, data, compression).await?;
+
+        Ok(proto::BatchDataResponse {
+            request_id: Some(proto::BatchRequestId {
+                node_id: node_id,
+                sequence: 0,
+            }),
+            success: true,
+            error_message: String::new(),
+            version,
+            retryable: false,
+        })
+    }
 
-                // 构造请求
+    /// Transfer a whole data item to `target_node` block by block,
+    ///  blocks the target already has from an interrupted transfer of the same version are skipped
+    pub(super) async fn batch_transfer(
+        &self,
+        unique_id: UniqueId,
+        data_item_idx: DataItemIdx,
+        version: u64,
+        target_node: NodeID,
+        data: proto::DataItem,
+        compression: DataCompression,
+    ) -> WSResult<()> {
+        let block_type = block_type_of(&data);
+        self.batch_transfer_source(
+            unique_id,
+            data_item_idx,
+            version,
+            target_node,
+            DataItemSource::new(data),
+            block_type,
+            compression,
+        )
+        .await
+    }
+
+    /// [`Self::batch_transfer`] of an item read from `data`, the target assembles it as `block_type`
+    async fn batch_transfer_source(
+        &self,
+        unique_id: UniqueId,
+        data_item_idx: DataItemIdx,
+        version: u64,
+        target_node: NodeID,
+        data: DataItemSource,
+        block_type: proto::DataItem,
+        compression: DataCompression,
+    ) -> WSResult<()> {
+        let conf = self.view.p2p().nodes_config.batch_transfer.clone();
+        let data = Arc::new(data);
+        let total_size = data.size().await? as u64;
+        let block_size = conf.block_size as u64;
+        let item_checksum = match data.as_ref() {
+            DataItemSource::Memory { data } => bytes_checksum(data),
+            DataItemSource::File { path } => file_checksum(path).await?,
+        };
+
+        // 续传握手, 对方返回已收到的块
+        let resumed = self
+            .rpc_call_batch_resume
+            .call(
+                self.view.p2p(),
+                target_node,
+                proto::BatchResumeRequest {
+                    unique_id: unique_id.clone(),
+                    data_item_idx,
+                    version,
+                    total_size,
+                    block_size,
+                    item_checksum: item_checksum.clone(),
+                },
+                Some(BLOCK_RPC_TIMEOUT),
+            )
+            .await?;
+        let mut progress = BatchProgress::new(version, total_size, block_size, item_checksum.clone());
+        if resumed.received.len() == progress.received.len() {
+            progress.received = resumed.received;
+        }
+        tracing::debug!(
+            "batch_transfer total size({}), blocks: {}, {} bytes received before, to node {}",
+            total_size,
+            progress.block_cnt(),
+            progress.received_size(),
+            target_node
+        );
+
+        let semaphore = Arc::new(Semaphore::new(conf.concurrency));
+        let mut handles: Vec<tokio::task::JoinHandle<WSResult<()>>> = Vec::new();
+        for block_idx in 0..progress.block_cnt() {
+            if progress.is_received(block_idx) {
+                continue;
+            }
+            // 获取信号量许可
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            let offset = block_idx as u64 * block_size;
+            let size = block_size.min(total_size - offset) as usize;
+            let data = data.clone();
+            let view = self.view.clone();
+            let unique_id = unique_id.clone();
+            let block_type = block_type.clone();
+            let item_checksum = item_checksum.clone();
+            let retries = conf.block_retries;
+            handles.push(tokio::spawn(async move {
+                let _permit = permit; // 持有permit直到任务完成
+                let block_data = read_block(&data, offset, size).await?;
+                // the checksum is of the raw block, checked after decompression
+                let block_checksum = bytes_checksum(&block_data);
+                let (block_data, block_compression) = match compress::compress(compression, &block_data) {
+                    Some(compressed) => (compressed, compression),
+                    None => (block_data, DataCompression::Raw),
+                };
                 let request = proto::BatchDataRequest {
                     request_id: Some(proto::BatchRequestId {
                         node_id: target_node as u32,
-                        sequence: block_idx as u64, // 修复：使用 u64
+                        sequence: block_idx as u64,
                     }),
                     dataset_unique_id: unique_id.clone(),
-                    data_item_idx: 0, // 因为是整体传输，所以使用0
-                    // block_type: match data.as_ref() {
-                    //     DataItemSource::Memory { .. } => proto::BatchDataBlockType::Memory as i32,
-                    //     DataItemSource::File { .. } => proto::BatchDataBlockType::File as i32,
-                    // },
-                    //原代码block_type: data.as_ref(),  类型不匹配，使用自定义的to_data_item函数转化     曾俊
-                    block_type: Some(data.to_data_item()),
+                    data_item_idx,
+                    block_type: Some(block_type),
                     block_index: block_idx as u32,
                     data: block_data,
                     operation: proto::DataOpeType::Write as i32,
-                    unique_id: unique_id.clone(),
+                    unique_id,
                     version,
-                    total_size: total_size as u64,
+                    total_size,
+                    block_checksum,
+                    item_checksum,
+                    compression: proto::DataCompression::from(block_compression) as i32,
+                    block_size,
                 };
+                send_block(view, target_node, request, retries).await
+            }));
+        }
+
+        // 等待所有请求完成
+        for handle in handles {
+            handle.await??;
+        }
+        Ok(())
+    }
+}
+
+/// Block type of an item the target assembles in the file `name` under its data dir
+fn file_block_type(name: String) -> proto::DataItem {
+    proto::DataItem {
+        data_item_dispatch: Some(proto::data_item::DataItemDispatch::File(proto::FileData {
+            file_name_opt: name,
+            is_dir_opt: false,
+            file_content: vec![],
+        })),
+    }
+}
+
+impl DataGeneral {
+    /// Have `req.target` pull an item of a lost holder, master sends it the new meta first
+    pub async fn replicate_item(&self, req: proto::DataReplicateItemRequest) -> WSResult<()> {
+        let unique_id = req.unique_id.clone();
+        let resp = self
+            .rpc_call_replicate_item
+            .call(
+                self.view.p2p(),
+                req.target,
+                req,
+                Some(REPLICATE_ITEM_TIMEOUT),
+            )
+            .await?;
+        if !resp.success {
+            return Err(WsDataError::WriteDataFailed {
+                unique_id,
+                message: resp.message,
+            }
+            .into());
+        }
+        Ok(())
+    }
 
-                // 发送请求
-                let view = view.clone();
-                let handle = tokio::spawn(async move {
-                    let _permit = permit; // 持有permit直到任务完成
-                    let resp = view.data_general()
-                        .rpc_call_batch_data
-                        .call(
-                            view.p2p(),
-                            target_node,
-                            request,
-                            Some(Duration::from_secs(30)),
-                        )
-                        .await?;
-                    
-                    if !resp.success {
-                        return Err(WsDataError::BatchTransferError {
-                            request_id: proto::BatchRequestId {
-                                node_id: target_node as u32,
-                                sequence: block_idx as u64, // 修复：使用 u64
-                            },
-                            msg: resp.error_message,
-                        }.into());
-                    }
-                    Ok(())
-                });
-
-                handles.push(handle);
+    /// Asked by master on the target, which forwards it to the source
+    pub(super) async fn rpc_handle_replicate_item(
+        &self,
+        responsor: RPCResponsor<proto::DataReplicateItemRequest>,
+        req: proto::DataReplicateItemRequest,
+    ) {
+        let res = if req.source == self.view.p2p().nodes_config.this_node() {
+            self.push_replica(&req).await
+        } else {
+            self.pull_replica(&req).await.map(|_| None)
+        };
+        let resp = match res {
+            Ok(file) => {
+                let (file_name, mapped) = file.unwrap_or_default();
+                proto::DataReplicateItemResponse {
+                    success: true,
+                    message: String::new(),
+                    file_name,
+                    mapped,
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "replicate data({:?}) item {} failed: {:?}",
+                    req.unique_id,
+                    req.idx,
+                    err
+                );
+                proto::DataReplicateItemResponse {
+                    success: false,
+                    message: format!("{:?}", err),
+                    ..Default::default()
+                }
             }
+        };
+        if let Err(err) = responsor.send_resp(resp).await {
+            tracing::warn!("send replicate item response failed: {:?}", err);
+        }
+    }
 
-            // 等待所有请求完成
-            for handle in handles {
-                handle.await??;
+    /// The source sends its copy, a copy in a file is read block by block rather than loaded.
+    ///  Returns the file name under the data dir the target got it in and whether it's a map
+    ///  file item, none if sent as bytes.
+    async fn push_replica(
+        &self,
+        req: &proto::DataReplicateItemRequest,
+    ) -> WSResult<Option<(String, bool)>> {
+        let kv_store_engine = self.view.kv_store_engine();
+        let uid = req.unique_id.as_slice();
+        let idx = req.idx as DataItemIdx;
+        let (persisted, compression) = {
+            let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(uid).make_key());
+            let _guard = lock.read();
+            let items_version = kv_store_engine
+                .get(&KeyTypeDataSetItemVersion(uid), false, KvAdditionalConf {})
+                .map_or(0, |(_, version)| version);
+            let persisted = kv_store_engine
+                .get(&KeyTypeDataSetItem { uid, idx }, false, KvAdditionalConf {})
+                .filter(|_| items_version == req.version)
+                .map(|(_, persisted)| persisted);
+            let compression = kv_store_engine
+                .get(&KeyTypeDataSetMeta(uid), true, KvAdditionalConf {})
+                .map_or(DataCompression::Raw, |(_, meta)| meta.compression);
+            (persisted, compression)
+        };
+        let Some(persisted) = persisted else {
+            return Err(WsDataError::GetDataFailed {
+                unique_id: req.unique_id.clone(),
+                msg: format!("no copy of item {} at version {}", idx, req.version),
             }
+            .into());
+        };
 
-            Ok(())
+        let (source, block_type, file) = match persisted.first() {
+            Some(&PERSIST_TAG_MAPPED_FILE) => {
+                // placed where the target maps its own items
+                let name = format!(
+                    "{}/{}",
+                    MAPPED_ITEM_DIR,
+                    mapped_item_file_name(uid, idx, req.version)
+                );
+                (
+                    DataItemSource::File {
+                        path: persisted_path(&persisted[1..])?,
+                    },
+                    file_block_type(name.clone()),
+                    Some((name, true)),
+                )
+            }
+            Some(&0) => {
+                let name = persisted_path(&persisted[1..])?;
+                let name = name.to_string_lossy().to_string();
+                (
+                    DataItemSource::File {
+                        path: self.view.os().abs_file_path(name.clone().into()),
+                    },
+                    file_block_type(name.clone()),
+                    Some((name, false)),
+                )
+            }
+            _ => {
+                let item = decode_persist_item(persisted).await?;
+                let block_type = block_type_of(&item);
+                (DataItemSource::new(item), block_type, None)
+            }
+        };
+        if let DataItemSource::File { path } = &source {
+            if path.is_dir() {
+                return Err(WsDataError::GetDataFailed {
+                    unique_id: req.unique_id.clone(),
+                    msg: format!("directory item {} isn't re-replicated", idx),
+                }
+                .into());
+            }
         }
+        self.batch_transfer_source(
+            req.unique_id.clone(),
+            idx,
+            req.version,
+            req.target,
+            source,
+            block_type,
+            compression,
+        )
+        .await?;
+        Ok(file)
+    }
 
-        let data = Arc::new(DataItemSource::new(data));
-        batch_transfer(unique_id.clone(), version, node_id, data, self.view.clone()).await?;
+    /// The target stores the item at the version of the meta from master
+    async fn pull_replica(&self, req: &proto::DataReplicateItemRequest) -> WSResult<()> {
+        let uid = req.unique_id.as_slice();
+        let idx = req.idx as DataItemIdx;
+        let Some((_, meta)) = self
+            .view
+            .kv_store_engine()
+            .get(&KeyTypeDataSetMeta(uid), true, KvAdditionalConf {})
+            .filter(|(_, meta)| meta.version == req.version)
+        else {
+            return Err(WsDataError::DataSetNotFound {
+                uniqueid: req.unique_id.clone(),
+            }
+            .into());
+        };
 
-        Ok(proto::BatchDataResponse {
-            request_id: Some(proto::BatchRequestId {
-                node_id: node_id,
-                sequence: 0,
-            }),
-            success: true,
-            error_message: String::new(),
-            version,
-        })
+        let stored = match meta.cache_mode_visitor(idx).erasure_parity_shards() {
+            Some(parity_shards) => self.rebuild_shard(req, &meta, parity_shards).await?,
+            None => self.pull_copy(req).await?,
+        };
+        if !stored.success {
+            return Err(WsDataError::WriteDataFailed {
+                unique_id: req.unique_id.clone(),
+                message: stored.message,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// A shard of an erasure coded item is encoded again from the other shards
+    async fn rebuild_shard(
+        &self,
+        req: &proto::DataReplicateItemRequest,
+        meta: &DataSetMetaV2,
+        parity_shards: usize,
+    ) -> WSResult<WriteOneDataResponse> {
+        let idx = req.idx as DataItemIdx;
+        let (_, mut items) = self
+            .get_or_del_data(GetOrDelDataArg {
+                meta: None,
+                unique_id: req.unique_id.clone(),
+                ty: GetOrDelDataArgType::PartialOne { idx },
+            })
+            .await?;
+        let bytes = match items.remove(&idx).and_then(|item| item.data_item_dispatch) {
+            Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) => bytes,
+            _ => vec![],
+        };
+        let shard_cnt = meta.datas_splits[idx as usize].splits.len();
+        let mut shards = erasure::encode(&bytes, shard_cnt - parity_shards, parity_shards);
+        let shard = shards.swap_remove(req.split_idx as usize);
+        Ok(self
+            .store_items_of_version(
+                &req.unique_id,
+                req.version,
+                vec![(idx, DataItemSource::Memory { data: shard })],
+            )
+            .await)
+    }
+
+    /// A full copy is asked from the source, which sends it through the batch transfer
+    async fn pull_copy(
+        &self,
+        req: &proto::DataReplicateItemRequest,
+    ) -> WSResult<WriteOneDataResponse> {
+        let uid = req.unique_id.as_slice();
+        let idx = req.idx as DataItemIdx;
+        let key: BatchTransferKey = (req.unique_id.clone(), idx, req.version);
+        let (pulled_tx, pulled) = tokio::sync::oneshot::channel();
+        let _ = self.replica_pulls.insert(key.clone(), pulled_tx);
+        let mapped_dir = self.view.os().file_path.join(MAPPED_ITEM_DIR);
+        let pushed = match tokio::fs::create_dir_all(&mapped_dir).await {
+            Ok(()) => {
+                self.rpc_call_replicate_item
+                    .call(
+                        self.view.p2p(),
+                        req.source,
+                        req.clone(),
+                        Some(REPLICATE_ITEM_TIMEOUT),
+                    )
+                    .await
+            }
+            Err(err) => Err(WsDataError::FileWriteErr {
+                path: mapped_dir,
+                err,
+            }
+            .into()),
+        };
+        let _ = self.replica_pulls.remove(&key);
+        let pushed = pushed?;
+        if !pushed.success {
+            return Err(WsDataError::GetDataFailed {
+                unique_id: req.unique_id.clone(),
+                msg: pushed.message,
+            }
+            .into());
+        }
+
+        if pushed.file_name.is_empty() {
+            let Ok(item) = pulled.await else {
+                return Err(WsDataError::GetDataFailed {
+                    unique_id: req.unique_id.clone(),
+                    msg: "pulled item not received".to_owned(),
+                }
+                .into());
+            };
+            return Ok(self
+                .store_items_of_version(uid, req.version, vec![(idx, DataItemSource::new(item))])
+                .await);
+        }
+        if pushed.mapped {
+            // the persisted path is read back without the view, so it's absolute
+            let path = self.view.os().file_path.join(&pushed.file_name);
+            let path = tokio::fs::canonicalize(&path)
+                .await
+                .map_err(|err| WsDataError::FileMetadataErr { path, err })?;
+            return Ok(self
+                .store_items_with_mapped(
+                    uid,
+                    req.version,
+                    vec![(idx, DataItemSource::File { path: path.clone() })],
+                    HashMap::from([(idx, path)]),
+                )
+                .await);
+        }
+        let path = PathBuf::from(&pushed.file_name);
+        let stored = self
+            .store_items_of_version(
+                uid,
+                req.version,
+                vec![(idx, DataItemSource::File { path: path.clone() })],
+            )
+            .await;
+        if !stored.success {
+            let _ = tokio::fs::remove_file(self.view.os().abs_file_path(path)).await;
+        }
+        Ok(stored)
     }
 }
@@ -1,77 +1,170 @@
-use crate::general::network::{
-    proto::BatchDataRequest,
-    proto::BatchDataResponse,
-    m_p2p::RPCResponsor,
-};
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing;
-
-/// 共享状态,用于记录最新的请求响应器
-/// 当收到新的请求时,会更新响应器并自动处理旧的请求
-#[derive(Clone)]
-pub struct SharedWithBatchHandler {
-    /// 当前活跃的响应器
-    /// 使用 Arc<Mutex> 保证线程安全
-    responsor: Arc<Mutex<Option<RPCResponsor<BatchDataRequest>>>>,
+use crate::general::data::m_data_general::dataitem::WriteSplitDataTaskHandle;
+use crate::general::data::m_data_general::{DataItemIdx, UniqueId};
+use crate::general::network::proto;
+use crate::result::WSResult;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// 批量传输的标识: 数据集, 数据项, 版本
+pub type BatchTransferKey = (UniqueId, DataItemIdx, u64);
+
+/// Blocks of a batch transfer written on the receiver,
+///  persisted in the kv store for file transfers so that they can resume after a restart
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchProgress {
+    pub version: u64,
+    pub total_size: u64,
+    pub block_size: u64,
+    pub item_checksum: Vec<u8>,
+    /// bit i of byte i / 8 is set once block i is written
+    pub received: Vec<u8>,
 }
 
-impl SharedWithBatchHandler {
-    /// 创建新的共享状态
-    #[must_use]
-    pub fn new() -> Self {
-        Self {
-            responsor: Arc::new(Mutex::new(None)),
-        }
+impl BatchProgress {
+    pub fn new(version: u64, total_size: u64, block_size: u64, item_checksum: Vec<u8>) -> Self {
+        let mut progress = Self {
+            version,
+            total_size,
+            block_size,
+            item_checksum,
+            received: Vec::new(),
+        };
+        progress.received = vec![0; (progress.block_cnt() + 7) / 8];
+        progress
     }
 
-    /// 更新响应器
-    /// 如果存在旧的响应器,会自动返回成功
-    /// 
-    /// # 参数
-    /// * `responsor` - 新的响应器
-    pub async fn update_responsor(&self, responsor: RPCResponsor<BatchDataRequest>) {
-        let mut guard = self.responsor.lock().await;
-        if let Some(old_responsor) = guard.take() {
-            // 旧的responsor直接返回成功
-            if let Err(e) = old_responsor.send_resp(BatchDataResponse {
-                request_id: None,  // 这里需要正确的 request_id
-                version: 0,  // 这里需要正确的版本号
-                success: true,
-                error_message: String::new(),
-            }).await {
-                tracing::error!("Failed to respond to old request: {}", e);
-            }
-        }
-        *guard = Some(responsor);
+    /// Whether the blocks describe the same transfer, the version is part of the state key
+    pub fn same_transfer(&self, total_size: u64, block_size: u64, item_checksum: &[u8]) -> bool {
+        self.total_size == total_size
+            && self.block_size == block_size
+            && self.item_checksum == item_checksum
+    }
+
+    pub fn block_cnt(&self) -> usize {
+        ((self.total_size + self.block_size - 1) / self.block_size) as usize
     }
 
-    /// 获取最终的响应器
-    /// 用于在所有数据都写入完成后发送最终响应
-    pub async fn get_final_responsor(&self) -> Option<RPCResponsor<BatchDataRequest>> {
-        self.responsor.lock().await.take()
+    pub fn is_received(&self, block: usize) -> bool {
+        self.received
+            .get(block / 8)
+            .map_or(false, |byte| byte & (1 << (block % 8)) != 0)
+    }
+
+    pub fn mark_received(&mut self, block: usize) {
+        self.received[block / 8] |= 1 << (block % 8);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        (0..self.block_cnt()).all(|block| self.is_received(block))
+    }
+
+    /// Bytes of the received blocks, the last block may be short
+    pub fn received_size(&self) -> u64 {
+        (0..self.block_cnt())
+            .filter(|&block| self.is_received(block))
+            .map(|block| {
+                let offset = block as u64 * self.block_size;
+                self.block_size.min(self.total_size - offset)
+            })
+            .sum()
     }
 }
 
+/// Progress plus the blocks being written, a duplicate of one of them is refused
+#[derive(Debug)]
+pub struct BatchReceiveProgress {
+    pub progress: BatchProgress,
+    pub writing: HashSet<usize>,
+    /// last time a block of the transfer arrived, a transfer idle for long is dropped by the data gc
+    pub last_active: Instant,
+}
+
 /// 批量数据传输状态
 /// 用于管理单个批量数据传输请求的生命周期
 pub struct BatchReceiveState {
     /// 写入任务句柄
-    pub handle: super::dataitem::WriteSplitDataTaskHandle,
-    /// 共享状态,用于处理请求响应
-    pub shared: SharedWithBatchHandler,
+    pub handle: WriteSplitDataTaskHandle,
+    /// whether the progress is persisted, only file transfers survive a restart
+    pub persist: bool,
+    pub progress: Mutex<BatchReceiveProgress>,
+    /// 数据项组装及校验结果, 由写入最后一块的请求取走
+    pub done: tokio::sync::Mutex<Option<tokio::task::JoinHandle<WSResult<()>>>>,
 }
 
 impl BatchReceiveState {
     /// 创建新的批量数据传输状态
-    /// 
+    ///
     /// # 参数
     /// * `handle` - 写入任务句柄
-    /// * `shared` - 共享状态
-    pub fn new(handle: super::dataitem::WriteSplitDataTaskHandle, shared: SharedWithBatchHandler) -> Self {
+    /// * `progress` - 已接收的数据块
+    /// * `done` - 数据项组装任务
+    pub fn new(
+        handle: WriteSplitDataTaskHandle,
+        persist: bool,
+        progress: BatchProgress,
+        done: tokio::task::JoinHandle<WSResult<()>>,
+    ) -> Self {
         Self {
             handle,
-            shared,
+            persist,
+            progress: Mutex::new(BatchReceiveProgress {
+                progress,
+                writing: HashSet::new(),
+                last_active: Instant::now(),
+            }),
+            done: tokio::sync::Mutex::new(Some(done)),
+        }
+    }
+
+    /// No block arrived for `max_idle` and none is being written
+    pub fn is_stale(&self, max_idle: Duration) -> bool {
+        let progress = self.progress.lock();
+        progress.writing.is_empty() && progress.last_active.elapsed() > max_idle
+    }
+}
+
+/// Block type sent with each block, tells the receiver where the item goes
+pub fn block_type_of(data: &proto::DataItem) -> proto::DataItem {
+    let dispatch = match &data.data_item_dispatch {
+        Some(proto::data_item::DataItemDispatch::File(file_data)) => {
+            proto::data_item::DataItemDispatch::File(proto::FileData {
+                file_name_opt: file_data.file_name_opt.clone(),
+                is_dir_opt: file_data.is_dir_opt,
+                file_content: Vec::new(),
+            })
+        }
+        _ => proto::data_item::DataItemDispatch::RawBytes(Vec::new()),
+    };
+    proto::DataItem {
+        data_item_dispatch: Some(dispatch),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BatchProgress;
+
+    #[test]
+    fn test_batch_progress_bitmap() {
+        let mut progress = BatchProgress::new(1, 10 * 4 + 3, 4, vec![1]);
+        assert_eq!(progress.block_cnt(), 11);
+        assert_eq!(progress.received.len(), 2);
+        assert!(!progress.is_received(10));
+        assert!(!progress.is_received(11));
+
+        progress.mark_received(0);
+        progress.mark_received(10);
+        assert_eq!(progress.received_size(), 4 + 3);
+        assert!(!progress.is_complete());
+
+        for block in 0..11 {
+            progress.mark_received(block);
         }
+        assert!(progress.is_complete());
+        assert_eq!(progress.received_size(), 43);
+        assert!(progress.same_transfer(43, 4, &[1]));
+        assert!(!progress.same_transfer(43, 8, &[1]));
     }
 }
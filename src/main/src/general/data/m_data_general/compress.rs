@@ -0,0 +1,311 @@
+//! Block compression on the data path.
+//!  The writer asks for a compression when scheduling a write, master grants it only if every
+//!  node reported it can decode it and records the granted one in the dataset meta.
+//!  Transferred payloads are tagged with the compression actually applied, so a payload left raw
+//!  (small or incompressible) needs nothing from the receiver.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use super::dataitem::item_range;
+use crate::general::network::proto;
+use crate::result::{WSResult, WsDataError};
+
+/// data smaller than this is left raw, the frame overhead isn't worth it
+pub const COMPRESS_MIN_BYTES: usize = 4 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+/// persist tag of raw bytes stored compressed with zstd, next to 0 (file path) and 1 (raw bytes).
+///  The payload is the u64 raw size, the u32 block count and the u32 compressed size of each
+///  block, followed by the blocks, each compressed on its own
+pub const PERSIST_TAG_ZSTD: u8 = 2;
+/// raw size of a persisted block, a range is read by decompressing only the blocks it covers
+pub const PERSIST_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Compressions this node decodes besides raw, reported to master
+pub const SUPPORTED: [DataCompression; 1] = [DataCompression::Zstd];
+
+/// Compression of a dataset, recorded in its meta
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataCompression {
+    #[default]
+    Raw,
+    Zstd,
+}
+
+impl From<proto::DataCompression> for DataCompression {
+    fn from(p: proto::DataCompression) -> Self {
+        match p {
+            proto::DataCompression::Raw => DataCompression::Raw,
+            proto::DataCompression::Zstd => DataCompression::Zstd,
+        }
+    }
+}
+
+impl From<DataCompression> for proto::DataCompression {
+    fn from(c: DataCompression) -> Self {
+        match c {
+            DataCompression::Raw => proto::DataCompression::Raw,
+            DataCompression::Zstd => proto::DataCompression::Zstd,
+        }
+    }
+}
+
+static RAW_BYTES: AtomicU64 = AtomicU64::new(0);
+static COMPRESSED_BYTES: AtomicU64 = AtomicU64::new(0);
+static SKIPPED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Compression totals of this node since it started
+#[derive(Debug, Clone, Serialize)]
+pub struct CompressionMetrics {
+    /// size before compression of the data sent or stored compressed
+    pub raw_bytes: u64,
+    /// size after compression of the same data
+    pub compressed_bytes: u64,
+    /// data of compressed datasets left raw, below the threshold or incompressible
+    pub skipped_bytes: u64,
+    /// raw_bytes / compressed_bytes, 1 before anything is compressed
+    pub ratio: f64,
+}
+
+pub fn metrics() -> CompressionMetrics {
+    let raw_bytes = RAW_BYTES.load(Ordering::Relaxed);
+    let compressed_bytes = COMPRESSED_BYTES.load(Ordering::Relaxed);
+    CompressionMetrics {
+        raw_bytes,
+        compressed_bytes,
+        skipped_bytes: SKIPPED_BYTES.load(Ordering::Relaxed),
+        ratio: if compressed_bytes == 0 {
+            1.0
+        } else {
+            raw_bytes as f64 / compressed_bytes as f64
+        },
+    }
+}
+
+/// Compressed `data`, None if it should be left raw
+pub fn compress(compression: DataCompression, data: &[u8]) -> Option<Vec<u8>> {
+    if compression == DataCompression::Raw {
+        return None;
+    }
+    if data.len() < COMPRESS_MIN_BYTES {
+        let _ = SKIPPED_BYTES.fetch_add(data.len() as u64, Ordering::Relaxed);
+        return None;
+    }
+    let compressed = match zstd::bulk::compress(data, ZSTD_LEVEL) {
+        Ok(compressed) => compressed,
+        Err(err) => {
+            tracing::warn!("zstd compress failed, left raw: {:?}", err);
+            return None;
+        }
+    };
+    if compressed.len() >= data.len() {
+        let _ = SKIPPED_BYTES.fetch_add(data.len() as u64, Ordering::Relaxed);
+        return None;
+    }
+    let _ = RAW_BYTES.fetch_add(data.len() as u64, Ordering::Relaxed);
+    let _ = COMPRESSED_BYTES.fetch_add(compressed.len() as u64, Ordering::Relaxed);
+    Some(compressed)
+}
+
+pub fn decompress(compression: DataCompression, data: Vec<u8>) -> WSResult<Vec<u8>> {
+    match compression {
+        DataCompression::Raw => Ok(data),
+        DataCompression::Zstd => zstd::stream::decode_all(data.as_slice()).map_err(|e| {
+            WsDataError::DataDecodeError {
+                reason: format!("zstd decompress failed: {}", e),
+                data_type: "DataCompression::Zstd".to_string(),
+            }
+            .into()
+        }),
+    }
+}
+
+fn item_content(item: &mut proto::DataItem) -> Option<&mut Vec<u8>> {
+    match item.data_item_dispatch.as_mut()? {
+        proto::data_item::DataItemDispatch::RawBytes(bytes) => Some(bytes),
+        proto::data_item::DataItemDispatch::File(file_data) => Some(&mut file_data.file_content),
+    }
+}
+
+/// Compress the content carried by the item in place, returns the compression applied
+pub fn compress_item(compression: DataCompression, item: &mut proto::DataItem) -> DataCompression {
+    let Some(content) = item_content(item) else {
+        return DataCompression::Raw;
+    };
+    match compress(compression, content) {
+        Some(compressed) => {
+            *content = compressed;
+            compression
+        }
+        None => DataCompression::Raw,
+    }
+}
+
+/// Undo [`compress_item`]
+pub fn decompress_item(compression: DataCompression, item: &mut proto::DataItem) -> WSResult<()> {
+    if compression == DataCompression::Raw {
+        return Ok(());
+    }
+    if let Some(content) = item_content(item) {
+        *content = decompress(compression, std::mem::take(content))?;
+    }
+    Ok(())
+}
+
+/// Store the raw bytes of an item encoded by `encode_persist` compressed, others are kept
+pub fn compress_persist(compression: DataCompression, persist: Vec<u8>) -> Vec<u8> {
+    if persist.first() != Some(&1) || compression == DataCompression::Raw {
+        return persist;
+    }
+    let raw = &persist[1..];
+    if raw.len() < COMPRESS_MIN_BYTES {
+        let _ = SKIPPED_BYTES.fetch_add(raw.len() as u64, Ordering::Relaxed);
+        return persist;
+    }
+    let mut blocks = Vec::with_capacity(raw.len().div_ceil(PERSIST_BLOCK_SIZE));
+    for block in raw.chunks(PERSIST_BLOCK_SIZE) {
+        match zstd::bulk::compress(block, ZSTD_LEVEL) {
+            Ok(compressed) => blocks.push(compressed),
+            Err(err) => {
+                tracing::warn!("zstd compress failed, left raw: {:?}", err);
+                return persist;
+            }
+        }
+    }
+    let header_len = 1 + 8 + 4 + 4 * blocks.len();
+    let stored_len = header_len + blocks.iter().map(|b| b.len()).sum::<usize>();
+    if stored_len >= persist.len() {
+        let _ = SKIPPED_BYTES.fetch_add(raw.len() as u64, Ordering::Relaxed);
+        return persist;
+    }
+    let _ = RAW_BYTES.fetch_add(raw.len() as u64, Ordering::Relaxed);
+    let _ = COMPRESSED_BYTES.fetch_add(stored_len as u64, Ordering::Relaxed);
+
+    let mut ret = Vec::with_capacity(stored_len);
+    ret.push(PERSIST_TAG_ZSTD);
+    ret.extend_from_slice(&(raw.len() as u64).to_le_bytes());
+    ret.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+    for block in &blocks {
+        ret.extend_from_slice(&(block.len() as u32).to_le_bytes());
+    }
+    for block in &blocks {
+        ret.extend_from_slice(block);
+    }
+    ret
+}
+
+fn persist_decode_err(reason: String) -> WsDataError {
+    WsDataError::DataDecodeError {
+        reason,
+        data_type: "compress::PERSIST_TAG_ZSTD".to_string(),
+    }
+}
+
+/// Raw bytes in range of a payload stored by [`compress_persist`] (after the tag),
+///  returns the raw size and the bytes. Only the blocks covering the range are decompressed
+pub fn decompress_persist_range(
+    payload: &[u8],
+    offset: u64,
+    length: u64,
+) -> WSResult<(u64, Vec<u8>)> {
+    let truncated = || persist_decode_err("truncated compressed payload".to_string());
+    let read_u32 = |at: usize| -> WSResult<usize> {
+        let bytes = payload.get(at..at + 4).ok_or_else(truncated)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    let total = u64::from_le_bytes(payload.get(0..8).ok_or_else(truncated)?.try_into().unwrap());
+    let block_cnt = read_u32(8)?;
+    let range = item_range(total, offset, length);
+
+    let mut ret = Vec::with_capacity((range.end - range.start) as usize);
+    let mut block_at = 12 + 4 * block_cnt;
+    for idx in 0..block_cnt {
+        let block_len = read_u32(12 + 4 * idx)?;
+        let block_start = (idx * PERSIST_BLOCK_SIZE) as u64;
+        let block_end = (block_start + PERSIST_BLOCK_SIZE as u64).min(total);
+        if block_start >= range.end {
+            break;
+        }
+        if block_end > range.start {
+            let block = payload
+                .get(block_at..block_at + block_len)
+                .ok_or_else(truncated)?;
+            let raw = zstd::bulk::decompress(block, PERSIST_BLOCK_SIZE)
+                .map_err(|e| persist_decode_err(format!("zstd decompress failed: {}", e)))?;
+            let from = range.start.max(block_start) - block_start;
+            let to = range.end.min(block_end) - block_start;
+            ret.extend_from_slice(raw.get(from as usize..to as usize).ok_or_else(truncated)?);
+        }
+        block_at += block_len;
+    }
+    Ok((total, ret))
+}
+
+/// Whole raw bytes of a payload stored by [`compress_persist`] (after the tag)
+pub fn decompress_persist(payload: &[u8]) -> WSResult<Vec<u8>> {
+    decompress_persist_range(payload, 0, 0).map(|(_, raw)| raw)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compress_threshold_and_roundtrip() {
+        let text = "the quick brown fox jumps over the lazy dog\n".repeat(1000);
+        let compressed = compress(DataCompression::Zstd, text.as_bytes()).unwrap();
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(
+            decompress(DataCompression::Zstd, compressed).unwrap(),
+            text.as_bytes()
+        );
+
+        // below the threshold or not asked for
+        assert!(compress(DataCompression::Zstd, b"small").is_none());
+        assert!(compress(DataCompression::Raw, text.as_bytes()).is_none());
+
+        let mut persist = vec![1];
+        persist.extend_from_slice(text.as_bytes());
+        let stored = compress_persist(DataCompression::Zstd, persist);
+        assert_eq!(stored[0], PERSIST_TAG_ZSTD);
+        assert_eq!(decompress_persist(&stored[1..]).unwrap(), text.as_bytes());
+        // file paths are never compressed
+        let path = compress_persist(DataCompression::Zstd, vec![0; COMPRESS_MIN_BYTES * 2]);
+        assert_eq!(path, vec![0; COMPRESS_MIN_BYTES * 2]);
+    }
+
+    #[test]
+    fn test_persist_range_reads_covering_blocks() {
+        let text: Vec<u8> = (0..PERSIST_BLOCK_SIZE * 3 + 100)
+            .map(|i| (i / 7 % 251) as u8)
+            .collect();
+        let mut persist = vec![1];
+        persist.extend_from_slice(&text);
+        let stored = compress_persist(DataCompression::Zstd, persist);
+        assert_eq!(stored[0], PERSIST_TAG_ZSTD);
+        assert!(stored.len() < text.len());
+
+        let read = |offset: usize, length: usize| {
+            decompress_persist_range(&stored[1..], offset as u64, length as u64).unwrap()
+        };
+        // across a block boundary
+        let (total, bytes) = read(PERSIST_BLOCK_SIZE - 10, 20);
+        assert_eq!(total, text.len() as u64);
+        assert_eq!(
+            bytes,
+            &text[PERSIST_BLOCK_SIZE - 10..PERSIST_BLOCK_SIZE + 10]
+        );
+        // the short last block, cut at the end
+        let (_, bytes) = read(PERSIST_BLOCK_SIZE * 3 + 50, 1000);
+        assert_eq!(bytes, &text[PERSIST_BLOCK_SIZE * 3 + 50..]);
+        // past the end
+        assert!(read(text.len() + 1, 10).1.is_empty());
+
+        // a truncated last block only fails the ranges covering it
+        let broken = &stored[..stored.len() - 4];
+        assert!(decompress_persist_range(&broken[1..], 0, 100).is_ok());
+        assert!(decompress_persist_range(&broken[1..], 0, 0).is_err());
+    }
+}
@@ -13,17 +13,15 @@ use futures::stream::{FuturesUnordered, StreamExt};
 use std::cell::RefCell;
 use std::collections::btree_set;
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::broadcast;
 use tracing;
-use base64::{engine::general_purpose::STANDARD, Engine as _};
+use md5::{Digest, Md5};
 use std::sync::RwLock;
 
-const DEFAULT_BLOCK_SIZE: usize = 4096;
-
 logical_module_view_impl!(DataItemView);
 logical_module_view_impl!(DataItemView,os,OperatingSystem);
 
@@ -42,8 +40,8 @@ pub(super) enum WantIdxIter<'a> {
     /// 遍历所有或删除操作的索引
     Other {
         ty: GetOrDelDataArgType,
-        itercnt: u8,
-        len: u8,
+        itercnt: DataItemIdx,
+        len: DataItemIdx,
     },
 }
 
@@ -74,7 +72,7 @@ impl<'a> Iterator for WantIdxIter<'a> {
     type Item = DataItemIdx;
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            WantIdxIter::PartialMany { iter, .. } => iter.next().map(|v| *v as DataItemIdx),
+            WantIdxIter::PartialMany { iter, .. } => iter.next().copied(),
             WantIdxIter::PartialOne { idx, itercnt } => {
                 if *itercnt == 0 {
                     *itercnt += 1;
@@ -186,23 +184,182 @@ pub fn new_shared_mem(splits: &[Range<usize>]) -> (SharedMemHolder, Vec<SharedMe
     (SharedMemHolder { data }, owned_accesses)
 }
 
-/// 计算数据分片范围
-/// 
-/// # 参数
-/// * `total_size` - 总大小
-/// 
-/// # 返回
-/// * `Vec<Range<usize>>` - 分片范围列表
-#[must_use]
-pub fn calculate_splits(total_size: usize) -> Vec<Range<usize>> {
-    let total_blocks = (total_size + DEFAULT_BLOCK_SIZE - 1) / DEFAULT_BLOCK_SIZE;
-    let mut splits = Vec::with_capacity(total_blocks);
-    for i in 0..total_blocks {
-        let start = i * DEFAULT_BLOCK_SIZE;
-        let end = (start + DEFAULT_BLOCK_SIZE).min(total_size);
-        splits.push(start..end);
+/// md5 of the bytes
+pub fn bytes_checksum(data: &[u8]) -> Vec<u8> {
+    Md5::digest(data).to_vec()
+}
+
+/// md5 of the file content, read in blocks
+pub async fn file_checksum(path: &std::path::Path) -> WSResult<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| WsDataError::FileOpenErr {
+            path: path.to_path_buf(),
+            err: e,
+        })?;
+    let mut hasher = Md5::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await.map_err(|e| WsDataError::FileReadErr {
+            path: path.to_path_buf(),
+            err: e,
+        })?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
     }
-    splits
+    Ok(hasher.finalize().to_vec())
+}
+
+/// md5 of the content carried by the item, None if the content is not in the item
+pub fn item_checksum(item: &proto::DataItem) -> Option<Vec<u8>> {
+    match item.data_item_dispatch.as_ref()? {
+        proto::data_item::DataItemDispatch::RawBytes(bytes) => Some(bytes_checksum(bytes)),
+        proto::data_item::DataItemDispatch::File(file_data) if !file_data.file_content.is_empty() => {
+            Some(bytes_checksum(&file_data.file_content))
+        }
+        proto::data_item::DataItemDispatch::File(_) => None,
+    }
+}
+
+/// [start, end) of the range within an item of `total` bytes, 0 length means to the end
+pub fn item_range(total: u64, offset: u64, length: u64) -> Range<u64> {
+    let start = offset.min(total);
+    let end = if length == 0 {
+        total
+    } else {
+        start.saturating_add(length).min(total)
+    };
+    start..end
+}
+
+/// persist tag of the raw bytes of a map file item kept in a file of its own,
+///  the payload is the absolute path of the file
+pub const PERSIST_TAG_MAPPED_FILE: u8 = 3;
+
+/// Dir under the data dir of the files of map file items
+pub const MAPPED_ITEM_DIR: &str = "mapped_items";
+
+/// `<hex uid>_<idx>_<version>`, the data gc tells from it whether the file is still in use
+pub fn mapped_item_file_name(unique_id: &[u8], idx: DataItemIdx, version: u64) -> String {
+    format!("{}_{}_{}", hex::encode(unique_id), idx, version)
+}
+
+/// Dir under the data dir of the file items still being received by batch transfers
+pub const BATCH_STAGE_DIR: &str = "batch_stage";
+
+/// File the blocks of a batch transfer are written to until the item completes,
+///  one per (uid, idx, version) so that transfers of other items of the dataset keep theirs
+pub fn batch_stage_path(
+    file_path: &Path,
+    unique_id: &[u8],
+    idx: DataItemIdx,
+    version: u64,
+) -> PathBuf {
+    file_path.join(BATCH_STAGE_DIR).join(format!(
+        "{}.data",
+        mapped_item_file_name(unique_id, idx, version)
+    ))
+}
+
+/// Reverse of [`mapped_item_file_name`]
+pub fn parse_mapped_item_file_name(name: &str) -> Option<(Vec<u8>, DataItemIdx, u64)> {
+    let mut parts = name.rsplitn(3, '_');
+    let version = parts.next()?.parse().ok()?;
+    let idx = parts.next()?.parse().ok()?;
+    let unique_id = hex::decode(parts.next()?).ok()?;
+    Some((unique_id, idx, version))
+}
+
+/// Persisted form of a map file item stored at `path`
+pub fn mapped_item_persist(path: &std::path::Path) -> Vec<u8> {
+    let mut ret = vec![PERSIST_TAG_MAPPED_FILE];
+    ret.extend_from_slice(path.to_string_lossy().as_bytes());
+    ret
+}
+
+/// Path in the persisted payload of a file item, tag 0 or [`PERSIST_TAG_MAPPED_FILE`]
+pub fn persisted_path(payload: &[u8]) -> WSResult<PathBuf> {
+    String::from_utf8(payload.to_vec())
+        .map(PathBuf::from)
+        .map_err(|e| {
+            WsDataError::DataDecodeError {
+                reason: format!("Failed to decode path string: {}", e),
+                data_type: "proto::DataItem::File".to_string(),
+            }
+            .into()
+        })
+}
+
+/// Content of a map file item from the persisted payload
+pub async fn read_mapped_item(payload: &[u8]) -> WSResult<Vec<u8>> {
+    let path = persisted_path(payload)?;
+    tokio::fs::read(&path)
+        .await
+        .map_err(|err| WsDataError::FileReadErr { path, err }.into())
+}
+
+/// Error of the sync decoders on a map file item, its file is read by [`decode_persist_item`]
+pub fn mapped_item_decode_err(data_type: &str) -> WSError {
+    WsDataError::DataDecodeError {
+        reason: "map file item, read its file with decode_persist_item".to_string(),
+        data_type: data_type.to_string(),
+    }
+    .into()
+}
+
+/// Persisted item as stored, the file of a map file item is read without blocking
+pub async fn decode_persist_item(data: Vec<u8>) -> WSResult<proto::DataItem> {
+    if data.first() == Some(&PERSIST_TAG_MAPPED_FILE) {
+        return Ok(proto::DataItem {
+            data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(
+                read_mapped_item(&data[1..]).await?,
+            )),
+        });
+    }
+    proto::DataItem::decode_persist(data)
+}
+
+/// Bytes of the file in the range and the file size, only the range is read
+pub async fn file_read_range(
+    path: &std::path::Path,
+    offset: u64,
+    length: u64,
+) -> WSResult<(Vec<u8>, u64)> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| WsDataError::FileOpenErr {
+            path: path.to_path_buf(),
+            err: e,
+        })?;
+    let total = file
+        .metadata()
+        .await
+        .map_err(|e| WsDataError::FileMetadataErr {
+            path: path.to_path_buf(),
+            err: e,
+        })?
+        .len();
+    let range = item_range(total, offset, length);
+    let _ = file
+        .seek(std::io::SeekFrom::Start(range.start))
+        .await
+        .map_err(|e| WsDataError::FileSeekErr {
+            path: path.to_path_buf(),
+            err: e,
+        })?;
+    let mut buf = vec![0; (range.end - range.start) as usize];
+    let _ = file
+        .read_exact(&mut buf)
+        .await
+        .map_err(|e| WsDataError::FileReadErr {
+            path: path.to_path_buf(),
+            err: e,
+        })?;
+    Ok((buf, total))
 }
 
 /// 写入类型
@@ -289,11 +446,14 @@ pub enum WriteSplitDataTaskGroup {
 
 impl WriteSplitDataTaskGroup {
     /// 创建新的任务组
+    /// `received_size` 为续传时已写入的大小, 文件模式下已写入的数据保留在临时文件中
     pub async fn new(
         unique_id: UniqueId,
+        idx: DataItemIdx,
         total_size: usize,
         block_type: proto::data_item::DataItemDispatch,
         version: u64,
+        received_size: usize,
         // file_name: Option<&str>,     函数体并没有用到这个参数    查看引用发现也没有使用到这个参数   这里直接删除     曾俊
     ) -> WSResult<(Self, WriteSplitDataTaskHandle)> {
         let (tx, rx) = mpsc::channel(32);
@@ -305,8 +465,27 @@ impl WriteSplitDataTaskGroup {
 
         match block_type {
             proto::data_item::DataItemDispatch::File(file_data) => {
-                let tmp_file_path = pathbase.join(format!("{}.data", 
-                    STANDARD.encode(&unique_id)));
+                let tmp_file_path = batch_stage_path(&pathbase, &unique_id, idx, version);
+                if let Err(err) = tokio::fs::create_dir_all(pathbase.join(BATCH_STAGE_DIR)).await {
+                    return Err(WsDataError::FileWriteErr {
+                        path: pathbase.join(BATCH_STAGE_DIR),
+                        err,
+                    }
+                    .into());
+                }
+                if received_size == 0 {
+                    // a stale file of an abandoned transfer of this item may be longer than this one
+                    match tokio::fs::remove_file(&tmp_file_path).await {
+                        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(WsDataError::FileWriteErr {
+                                path: tmp_file_path,
+                                err,
+                            }
+                            .into());
+                        }
+                        _ => {}
+                    }
+                }
                 
                 let handle = WriteSplitDataTaskHandle {
                     tx,
@@ -325,7 +504,7 @@ impl WriteSplitDataTaskGroup {
                     tasks: Vec::new(),
                     rx,
                     expected_size: total_size,
-                    current_size: 0,
+                    current_size: received_size,
                     broadcast_tx: broadcast_tx.clone(),
                 };
                 
@@ -352,7 +531,7 @@ impl WriteSplitDataTaskGroup {
                     tasks: Vec::new(),
                     rx,
                     expected_size: total_size,
-                    current_size: 0,
+                    current_size: received_size,
                     broadcast_tx: broadcast_tx.clone(),
                 };
                 
@@ -500,25 +679,6 @@ impl WriteSplitDataTaskGroup {
     }
 }
 
-/// 简化的任务完成等待器
-pub struct WriteSplitDataWaiter {
-    rx: broadcast::Receiver<()>,
-}
-
-impl WriteSplitDataWaiter {
-    /// 等待所有任务完成
-    pub async fn wait(mut self) -> WSResult<()> {
-        // 持续接收直到通道关闭
-        while let Ok(_) = self.rx.recv().await {
-            // 不需要处理具体消息内容，只需要知道有消息到达
-        }
-        
-        // 通道关闭表示所有发送端都已释放
-        Ok(())
-    }
-}
-
-
 /// 写入分片任务的句柄
 /// 用于提交新的分片任务和等待任务完成
 #[derive(Clone)]
@@ -542,68 +702,53 @@ impl WriteSplitDataTaskHandle {
         self.version
     }
 
-    pub fn get_all_tasks_waiter(&self) -> WriteSplitDataWaiter {
-        WriteSplitDataWaiter {
-            rx: self.broadcast_tx.subscribe(),
-        }
-    }
-
-    /// 提交新的分片任务
+    /// 提交新的分片任务, 数据写入后才返回, 写入大小交给任务组累计
     /// 
     /// # 参数
     /// * `idx` - 分片索引,表示数据在整体中的偏移位置
     /// * `data` - 分片数据
     /// 
     /// # 返回
-    /// * `Ok(())` - 任务提交成功
-    /// * `Err(e)` - 任务提交失败,可能是通道已关闭
+    /// * `Ok(())` - 分片已写入
+    /// * `Err(e)` - 写入失败, 或通道已关闭
     pub async fn submit_split(&self, idx: DataSplitIdx, data: proto::DataItem) -> WSResult<()> {
-        let task = match &self.write_type {
+        let written_size = match &self.write_type {
             // WriteSplitDataType::File { path } | WriteSplitDataType::Dir { path } => {   原WriteSplitDataType::Dir忽视了zip_file字段   发现没有用到修改为直接忽视   曾俊
             WriteSplitDataType::File { path } | WriteSplitDataType::Dir { path ,..} => {
-                let path = path.clone();
+                use tokio::io::{AsyncSeekExt, AsyncWriteExt};
                 let offset = idx;
-                let data = data.as_raw_bytes().unwrap_or(&[]).to_vec();
-                let written_size = data.len();
-                tokio::spawn(async move {
-                    let result = tokio::fs::OpenOptions::new()
+                let data = data.as_raw_bytes().unwrap_or(&[]);
+                let written = async {
+                    let mut file = tokio::fs::OpenOptions::new()
                         .create(true)
                         .write(true)
-                        .open(&path)
-                        .await;
-                    
-                    match result {
-                        Ok(mut file) => {
-                            use tokio::io::{AsyncSeekExt, AsyncWriteExt};
-                            if let Err(e) = async move {
-                                // 验证seek结果
-                                let seek_pos = file.seek(std::io::SeekFrom::Start(offset as u64)).await?;
-                                if seek_pos != offset as u64 {
-                                    return Err(std::io::Error::new(
-                                        std::io::ErrorKind::Other,
-                                        format!("Seek position mismatch: expected {}, got {}", offset, seek_pos)
-                                    ));
-                                }
-                                // write_all保证写入所有数据或返回错误
-                                file.write_all(&data).await?;
-                                Ok::<_, std::io::Error>(())
-                            }.await {
-                                tracing::error!("Failed to write file data at offset {}: {}", offset, e);
-                                panic!("Failed to write file: {}", e);
-                            }
-                            WriteSplitTaskResult { written_size }
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed to open file at offset {}: {}", offset, e);
-                            panic!("Failed to open file: {}", e);
-                        }
+                        .open(path)
+                        .await?;
+                    // 验证seek结果
+                    let seek_pos = file.seek(std::io::SeekFrom::Start(offset as u64)).await?;
+                    if seek_pos != offset as u64 {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            format!("Seek position mismatch: expected {}, got {}", offset, seek_pos)
+                        ));
                     }
-                })
+                    // write_all保证写入所有数据或返回错误
+                    file.write_all(data).await?;
+                    Ok::<_, std::io::Error>(())
+                }.await;
+                if let Err(e) = written {
+                    tracing::error!("Failed to write file data at offset {}: {}", offset, e);
+                    return Err(WsDataError::FileWriteErr {
+                        path: path.clone(),
+                        err: e,
+                    }
+                    .into());
+                }
+                data.len()
             }
             WriteSplitDataType::Mem { shared_mem } => {
-                let mem = shared_mem.clone();
                 let offset = idx;
-                let Some(data) = data.as_raw_bytes().map(|data| data.to_vec()) else {
+                let Some(data) = data.as_raw_bytes() else {
                     return Err(WSError::WsDataError(WsDataError::BatchTransferFailed {
                         request_id: proto::BatchRequestId {
                             node_id: 0,
@@ -612,21 +757,25 @@ impl WriteSplitDataTaskHandle {
                         reason: format!("mem data expected"),
                     }));
                 };
-                let written_size = data.len();
                 tracing::debug!("submit_split: Mem, len:{}, target len:{}", data.len(), shared_mem.len());
-
-                tokio::spawn(async move {
-                    unsafe {
-                        let slice = std::slice::from_raw_parts_mut(
-                            mem.data.as_ptr() as *mut u8,
-                            mem.data.len()
-                        );
-                        slice[offset..offset + data.len()].copy_from_slice(&data);
+                if offset + data.len() > shared_mem.len() {
+                    return Err(WsDataError::SizeMismatch {
+                        expected: shared_mem.len(),
+                        actual: offset + data.len(),
                     }
-                    WriteSplitTaskResult { written_size }
-                })
+                    .into());
+                }
+                unsafe {
+                    let slice = std::slice::from_raw_parts_mut(
+                        shared_mem.data.as_ptr() as *mut u8,
+                        shared_mem.data.len()
+                    );
+                    slice[offset..offset + data.len()].copy_from_slice(data);
+                }
+                data.len()
             }
         };
+        let task = tokio::spawn(async move { WriteSplitTaskResult { written_size } });
 
         // 发送到通道
         let _ = self.broadcast_tx.send(());
@@ -795,6 +944,10 @@ impl DataItemExt for DataItemSource {
             1 => Ok(DataItemSource::Memory {
                 data: data[1..].to_owned(),
             }),
+            super::compress::PERSIST_TAG_ZSTD => Ok(DataItemSource::Memory {
+                data: super::compress::decompress_persist(&data[1..])?,
+            }),
+            PERSIST_TAG_MAPPED_FILE => Err(mapped_item_decode_err("DataItemSource")),
             _ => Err(WSError::WsDataError(WsDataError::DataDecodeError {
                 reason: format!("Unknown data item type id: {}", data[0]),
                 data_type: "DataItemSource".to_string(),
@@ -825,8 +978,9 @@ enum DataItemZip {
     /// 不需要压缩（非目录）
     NoNeed,
     /// 已压缩的目录
+    /// the zip is deleted once the last wrapper holding it is dropped
     Directory {
-        zipped_file: PathBuf,
+        zipped_file: Arc<tempfile::TempPath>,
     }
 }
 
@@ -878,7 +1032,7 @@ impl DataItemArgWrapper {
         }
     }
 
-    pub async fn get_tmpzipfile(&mut self) -> WSResult<Option<&PathBuf>> {
+    pub async fn get_tmpzipfile(&mut self) -> WSResult<Option<&Path>> {
         match &self.tmpzipfile {
             DataItemZip::Uninitialized => {
                 self.init_tmpzipfile().await?;
@@ -887,7 +1041,7 @@ impl DataItemArgWrapper {
         }
 
         match &self.tmpzipfile {
-            DataItemZip::Directory { zipped_file } => Ok(Some(zipped_file)),
+            DataItemZip::Directory { zipped_file } => Ok(Some(&***zipped_file)),
             DataItemZip::NoNeed => Ok(None),
             DataItemZip::Uninitialized => unreachable!(),
         }
@@ -922,18 +1076,19 @@ impl DataItemArgWrapper {
                     err: e,
                 })
             })?;
-            let tmp_path = tmp_file.path().to_path_buf();
+            // the zip is streamed from the file in splits, it lives as long as the wrapper
+            let (tmp_file, tmp_path) = tmp_file.into_parts();
             
             // 压缩目录到临时文件
             crate::util::zip::zip_dir_2_file(
                 &filedata.file_name_opt,
                 //zip::CompressionMethod::Stored,
                 CompressionMethod::Stored,//（续）虞光勇修改，修改内容删除zip::
-                tmp_file.into_file(),
+                tmp_file,
             ).await?;
 
             self.tmpzipfile = DataItemZip::Directory {
-                zipped_file: tmp_path,
+                zipped_file: Arc::new(tmp_path),
             };
         } else {
             self.tmpzipfile = DataItemZip::NoNeed;
@@ -967,6 +1122,37 @@ impl DataItemArgWrapper {
         }
     }
 
+    /// md5 of the whole item as it's transferred, a directory is checked as its zip
+    pub async fn checksum(&mut self) -> WSResult<Vec<u8>> {
+        let file_path = match &self.dataitem.data_item_dispatch {
+            Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) => {
+                return Ok(bytes_checksum(bytes))
+            }
+            Some(proto::data_item::DataItemDispatch::File(file_data)) => {
+                PathBuf::from(&file_data.file_name_opt)
+            }
+            None => return Ok(bytes_checksum(&[])),
+        };
+        match self.get_tmpzipfile().await? {
+            Some(zipped) => file_checksum(zipped).await,
+            None => file_checksum(&file_path).await,
+        }
+    }
+
+    /// Bytes of `range` as transferred, raw bytes are sliced without cloning the whole item
+    pub async fn read_range(&mut self, range: Range<usize>) -> WSResult<Vec<u8>> {
+        if let Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) =
+            &self.dataitem.data_item_dispatch
+        {
+            return Ok(bytes[range].to_vec());
+        }
+        Ok(match self.clone_split_range(range).await?.data_item_dispatch {
+            Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) => bytes,
+            Some(proto::data_item::DataItemDispatch::File(file_data)) => file_data.file_content,
+            None => vec![],
+        })
+    }
+
     pub async fn clone_split_range(&mut self, range: Range<usize>) -> WSResult<proto::DataItem> {
         match &self.dataitem.data_item_dispatch {
             Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) => {
@@ -991,7 +1177,7 @@ impl DataItemArgWrapper {
 
         // if zipped, use zipped file
         // else use file_data.file_name_opt
-        if let Some(tmp_path) = self.get_tmpzipfile().await?.cloned() {
+        if let Some(tmp_path) = self.get_tmpzipfile().await?.map(Path::to_path_buf) {
             let file_data=get_filedata(self);
             Ok(proto::DataItem::new_partial_file_data(NewPartialFileDataArg::FilePath { path: PathBuf::from_str(&file_data.file_name_opt).map_err(|err|{
                 let err=WsDataError::FilePathParseErr {
@@ -0,0 +1,276 @@
+//! Reed-Solomon erasure coding of data items.
+//!  An item is cut into k data shards and m parity shards are computed over GF(2^8),
+//!  any k of the k + m shards give the item back. The code is systematic, data shards
+//!  are plain ranges of the item, the parity rows form a Cauchy matrix.
+
+/// items smaller than this are replicated, the shards wouldn't save anything
+pub const ERASURE_MIN_BYTES: u64 = 4 * 1024;
+/// k + m is bounded by the field size
+pub const ERASURE_MAX_SHARDS: usize = 256;
+
+/// x^8 + x^4 + x^3 + x^2 + 1
+const GF_POLY: u16 = 0x11d;
+
+struct GfTables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const fn gf_tables() -> GfTables {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= GF_POLY;
+        }
+        i += 1;
+    }
+    // doubled so the sum of two logs needs no modulo
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    GfTables { exp, log }
+}
+
+const GF: GfTables = gf_tables();
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    GF.exp[GF.log[a as usize] as usize + GF.log[b as usize] as usize]
+}
+
+fn gf_inv(a: u8) -> u8 {
+    assert!(a != 0, "0 has no inverse");
+    GF.exp[255 - GF.log[a as usize] as usize]
+}
+
+/// Coefficients of shard `row` over the k data shards
+fn encode_row(row: usize, k: usize) -> Vec<u8> {
+    if row < k {
+        let mut coefs = vec![0; k];
+        coefs[row] = 1;
+        return coefs;
+    }
+    // 1 / (x_i + y_j) with x_i = row and y_j = j, distinct as row >= k > j
+    (0..k).map(|j| gf_inv((row ^ j) as u8)).collect()
+}
+
+/// Inverse of the square matrix, None if singular
+fn invert(mut m: Vec<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+    let n = m.len();
+    let mut inv: Vec<Vec<u8>> = (0..n).map(|i| encode_row(i, n)).collect();
+    for col in 0..n {
+        let pivot = (col..n).find(|&r| m[r][col] != 0)?;
+        m.swap(col, pivot);
+        inv.swap(col, pivot);
+        let scale = gf_inv(m[col][col]);
+        for c in 0..n {
+            m[col][c] = gf_mul(m[col][c], scale);
+            inv[col][c] = gf_mul(inv[col][c], scale);
+        }
+        for r in 0..n {
+            let factor = m[r][col];
+            if r == col || factor == 0 {
+                continue;
+            }
+            for c in 0..n {
+                m[r][c] ^= gf_mul(factor, m[col][c]);
+                inv[r][c] ^= gf_mul(factor, inv[col][c]);
+            }
+        }
+    }
+    Some(inv)
+}
+
+/// out ^= coef * src, src shorter than out is zero padded
+fn mul_add(out: &mut [u8], coef: u8, src: &[u8]) {
+    if coef == 0 {
+        return;
+    }
+    for (o, s) in out.iter_mut().zip(src) {
+        *o ^= gf_mul(coef, *s);
+    }
+}
+
+/// Length of every parity shard and of the data shards but the trailing ones
+pub fn shard_len(item_size: u64, data_shards: usize) -> u64 {
+    (item_size + data_shards as u64 - 1) / data_shards as u64
+}
+
+/// (offset, size) of each shard. Data shards are ranges of the item, parity shards
+///  follow as if appended to the item padded to k shards.
+pub fn shard_ranges(item_size: u64, data_shards: usize, parity_shards: usize) -> Vec<(u64, u64)> {
+    let len = shard_len(item_size, data_shards);
+    (0..data_shards + parity_shards)
+        .map(|i| {
+            let offset = i as u64 * len;
+            if i < data_shards {
+                (offset, len.min(item_size.saturating_sub(offset)))
+            } else {
+                (offset, len)
+            }
+        })
+        .collect()
+}
+
+/// Shape of an erasure coded item, read back from the sizes of its splits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErasureLayout {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub item_size: u64,
+}
+
+impl ErasureLayout {
+    /// `sizes` are of the k + m splits in shard order
+    pub fn from_split_sizes(sizes: &[u64], parity_shards: usize) -> Option<Self> {
+        if parity_shards == 0 || sizes.len() <= parity_shards {
+            return None;
+        }
+        let data_shards = sizes.len() - parity_shards;
+        Some(Self {
+            data_shards,
+            parity_shards,
+            item_size: sizes[..data_shards].iter().sum(),
+        })
+    }
+
+    pub fn shard_cnt(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+}
+
+/// Data shards followed by parity shards, the data shards are trimmed as in [`shard_ranges`]
+pub fn encode(data: &[u8], data_shards: usize, parity_shards: usize) -> Vec<Vec<u8>> {
+    assert!(data_shards > 0 && data_shards + parity_shards <= ERASURE_MAX_SHARDS);
+    let len = shard_len(data.len() as u64, data_shards) as usize;
+    let mut shards: Vec<Vec<u8>> = shard_ranges(data.len() as u64, data_shards, 0)
+        .into_iter()
+        .map(|(offset, size)| data[offset as usize..(offset + size) as usize].to_vec())
+        .collect();
+    let parity = encode_parity(&shards, parity_shards, len);
+    shards.extend(parity);
+    shards
+}
+
+/// Parity of `len` bytes at the same offset of each data shard, data shorter than `len` is
+///  zero padded. The code works byte by byte, so the shards can be encoded block by block.
+pub fn encode_parity(data: &[Vec<u8>], parity_shards: usize, len: usize) -> Vec<Vec<u8>> {
+    let data_shards = data.len();
+    assert!(data_shards > 0 && data_shards + parity_shards <= ERASURE_MAX_SHARDS);
+    (data_shards..data_shards + parity_shards)
+        .map(|row| {
+            let mut parity = vec![0; len];
+            for (coef, shard) in encode_row(row, data_shards).into_iter().zip(data) {
+                mul_add(&mut parity, coef, shard);
+            }
+            parity
+        })
+        .collect()
+}
+
+/// The item from any k of its shards, `shards` is indexed as returned by [`encode`].
+///  None if fewer than k shards are present.
+pub fn reconstruct(shards: &[Option<Vec<u8>>], layout: ErasureLayout) -> Option<Vec<u8>> {
+    let k = layout.data_shards;
+    let len = shard_len(layout.item_size, k) as usize;
+    let mut data = Vec::with_capacity(k * len);
+    if shards[..k].iter().all(|s| s.is_some()) {
+        for shard in &shards[..k] {
+            data.extend_from_slice(shard.as_ref().unwrap());
+        }
+        data.truncate(layout.item_size as usize);
+        return Some(data);
+    }
+
+    let chosen: Vec<usize> = (0..shards.len())
+        .filter(|&i| shards[i].is_some())
+        .take(k)
+        .collect();
+    if chosen.len() < k {
+        return None;
+    }
+    let inv = invert(chosen.iter().map(|&row| encode_row(row, k)).collect())?;
+    for coefs in inv {
+        let mut shard = vec![0; len];
+        for (coef, &row) in coefs.into_iter().zip(&chosen) {
+            mul_add(&mut shard, coef, shards[row].as_ref().unwrap());
+        }
+        data.extend_from_slice(&shard);
+    }
+    data.truncate(layout.item_size as usize);
+    Some(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reconstruct_from_any_k_shards() {
+        let data: Vec<u8> = (0..10_007u32).map(|i| (i * 31 % 251) as u8).collect();
+        let (k, m) = (4, 2);
+        let shards = encode(&data, k, m);
+        let sizes: Vec<u64> = shards.iter().map(|s| s.len() as u64).collect();
+        assert_eq!(
+            sizes,
+            shard_ranges(data.len() as u64, k, m)
+                .into_iter()
+                .map(|(_, size)| size)
+                .collect::<Vec<_>>()
+        );
+        let layout = ErasureLayout::from_split_sizes(&sizes, m).unwrap();
+        assert_eq!(layout.item_size, data.len() as u64);
+
+        // every way of losing m shards
+        for a in 0..k + m {
+            for b in a + 1..k + m {
+                let mut lossy: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+                lossy[a] = None;
+                lossy[b] = None;
+                assert_eq!(reconstruct(&lossy, layout).unwrap(), data, "lost {} {}", a, b);
+            }
+        }
+
+        let mut lossy: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        for shard in lossy.iter_mut().take(m + 1) {
+            *shard = None;
+        }
+        assert!(reconstruct(&lossy, layout).is_none());
+    }
+
+    #[test]
+    fn test_encode_block_by_block() {
+        let data: Vec<u8> = (0..10_007u32).map(|i| (i * 13 % 251) as u8).collect();
+        let (k, m, block) = (3, 2, 1000);
+        let whole = encode(&data, k, m);
+
+        let ranges = shard_ranges(data.len() as u64, k, m);
+        let len = shard_len(data.len() as u64, k) as usize;
+        let mut shards: Vec<Vec<u8>> = vec![vec![]; k + m];
+        for offset in (0..len).step_by(block) {
+            let stripe = block.min(len - offset);
+            let blocks: Vec<Vec<u8>> = ranges[..k]
+                .iter()
+                .map(|&(shard_offset, size)| {
+                    let start = shard_offset as usize + offset;
+                    let end = shard_offset as usize + (offset + stripe).min(size as usize);
+                    data[start.min(end)..end].to_vec()
+                })
+                .collect();
+            let parity = encode_parity(&blocks, m, stripe);
+            for (shard, block) in shards.iter_mut().zip(blocks.into_iter().chain(parity)) {
+                shard.extend(block);
+            }
+        }
+        assert_eq!(shards, whole);
+    }
+}
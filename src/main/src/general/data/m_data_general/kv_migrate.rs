@@ -0,0 +1,152 @@
+/// Fn Kv Namespace Migration
+///
+/// Fn kv keys written before keys were scoped per app live at `fkv{key}`, the app's
+/// functions now read and write `fkv{app}\0{key}`. Migration moves the legacy keys of one
+/// app into its namespace:
+///
+/// - legacy keys are the fn kv index entries without the namespace separator
+/// - a key is the app's when master charged it to the app. A key charged to no app, written
+///   by no function, is only moved when the caller claims such keys for the app
+/// - the value is written to the namespaced key only if it doesn't exist yet, with the
+///   cache modes and the remaining ttl of the legacy one, then the legacy key is deleted
+///
+/// Runs on master, where the index and the usage records are. Keys shared by several apps
+/// can be moved to the app listing them in `shared_kvs` of the others.
+use super::*;
+use crate::general::data::m_kv_store_engine::{KeyTypeDataTtl, KeyTypeDataUsage};
+
+/// Outcome of a migration
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FnKvMigrateReport {
+    /// keys moved into the namespace
+    pub moved: u64,
+    /// keys left as they are because the namespaced one already exists
+    pub skipped: Vec<String>,
+    /// keys charged to no app, left as they are unless claimed
+    pub unowned: Vec<String>,
+    /// keys left as they are and why
+    pub failed: Vec<(String, String)>,
+}
+
+impl DataGeneral {
+    /// Move the legacy fn kv keys with `prefix` that belong to `app` into its namespace,
+    ///  with the ones charged to no app if `claim_unowned`
+    pub async fn migrate_fn_kv_namespace(
+        &self,
+        app: &str,
+        prefix: &[u8],
+        claim_unowned: bool,
+    ) -> WSResult<FnKvMigrateReport> {
+        if !self.view.p2p().nodes_config.this.1.is_master() {
+            return Err(WsDataError::WriteDataFailed {
+                unique_id: new_data_unique_id_fn_kv(prefix),
+                message: "fn kv migration runs on master, where the index is".to_owned(),
+            }
+            .into());
+        }
+        let kv_store_engine = self.view.kv_store_engine();
+        let legacy_uids: Vec<Vec<u8>> = kv_store_engine
+            .fn_kv_index_range(&new_data_unique_id_fn_kv(prefix), &[])
+            .take_while(|uid| uid[DATA_UID_PREFIX_FN_KV.len()..].starts_with(prefix))
+            .filter(|uid| split_fn_kv_ns_key(&uid[DATA_UID_PREFIX_FN_KV.len()..]).is_none())
+            .collect();
+
+        let mut report = FnKvMigrateReport::default();
+        for uid in legacy_uids {
+            let key = &uid[DATA_UID_PREFIX_FN_KV.len()..];
+            let key_str = String::from_utf8_lossy(key).into_owned();
+            let owner = kv_store_engine
+                .get(&KeyTypeDataUsage(&uid), false, KvAdditionalConf::default())
+                .map(|(_, usage)| usage.app);
+            match owner {
+                Some(owner) if owner != app => continue,
+                None if !claim_unowned => {
+                    report.unowned.push(key_str);
+                    continue;
+                }
+                _ => {}
+            }
+            match self.migrate_fn_kv_key(app, key).await {
+                Ok(true) => report.moved += 1,
+                Ok(false) => report.skipped.push(key_str),
+                Err(err) => {
+                    tracing::warn!("fn kv migration skips key({}): {:?}", key_str, err);
+                    report.failed.push((key_str, format!("{:?}", err)));
+                }
+            }
+        }
+        tracing::info!(
+            "migrated {} fn kv keys into the namespace of {}, {} skipped, {} unowned, {} failed",
+            report.moved,
+            app,
+            report.skipped.len(),
+            report.unowned.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    /// false if the namespaced key already exists
+    async fn migrate_fn_kv_key(&self, app: &str, key: &[u8]) -> WSResult<bool> {
+        let legacy_uid = new_data_unique_id_fn_kv(key);
+        let (meta, _) = self.get_datameta_cached(&legacy_uid).await?;
+        let mut items = vec![];
+        for idx in 0..meta.data_item_cnt() as DataItemIdx {
+            let (bytes, _) = self.get_item_range(&meta, &legacy_uid, idx, 0, 0).await?;
+            items.push(DataItemArgWrapper::from_bytes(bytes));
+        }
+        let ttl_ms = match self.view.kv_store_engine().get(
+            &KeyTypeDataTtl(&legacy_uid),
+            false,
+            KvAdditionalConf::default(),
+        ) {
+            Some((_, deadline_ms)) => {
+                let now_ms = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                // expired, left to the gc
+                if deadline_ms <= now_ms {
+                    return Ok(false);
+                }
+                deadline_ms - now_ms
+            }
+            None => 0,
+        };
+
+        let this_node = self.view.p2p().nodes_config.this_node();
+        let res = self
+            .write_data_with(
+                new_data_unique_id_fn_kv(&fn_kv_ns_key(app, key)),
+                items,
+                Some((
+                    this_node,
+                    proto::DataOpeType::Write,
+                    proto::data_schedule_context::OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+                        app_func: format!("{}/migrate", app),
+                        node_id: this_node,
+                    }),
+                )),
+                WriteDataOpts {
+                    condition: Some(proto::data_write_condition::Cond::Absent(true)),
+                    ttl_ms,
+                    cache_modes: (0..meta.data_item_cnt() as DataItemIdx)
+                        .map(|idx| meta.cache_mode_visitor(idx).0)
+                        .collect(),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        if let CondWriteRes::Rejected(_) = res {
+            return Ok(false);
+        }
+        let _ = self
+            .get_or_del_data(GetOrDelDataArg {
+                meta: None,
+                unique_id: legacy_uid,
+                ty: GetOrDelDataArgType::Delete,
+            })
+            .await?;
+        Ok(true)
+    }
+}
@@ -1,20 +1,36 @@
 /// 缓存模式类型
 pub type CacheMode = u16;
 
+pub mod archive;
 pub mod dataitem;
 pub mod batch;
 pub mod batch_handler;
-
-use crate::general::data::m_data_general::dataitem::{calculate_splits, WantIdxIter, WriteSplitDataTaskGroup, DataItemSource};
-use crate::general::data::m_data_general::batch_handler::{BatchReceiveState, SharedWithBatchHandler};
+pub mod compress;
+pub mod erasure;
+pub mod kv_migrate;
+pub mod stream;
+
+use crate::general::data::m_data_general::dataitem::{WantIdxIter, WriteSplitDataTaskGroup};
+use dataitem::{
+    batch_stage_path, bytes_checksum, decode_persist_item, file_checksum, file_read_range,
+    item_checksum, item_range, mapped_item_file_name, mapped_item_persist,
+    parse_mapped_item_file_name, persisted_path, BATCH_STAGE_DIR, MAPPED_ITEM_DIR,
+    PERSIST_TAG_MAPPED_FILE,
+};
+use compress::{compress_item, compress_persist, decompress, decompress_item, DataCompression};
+use erasure::ErasureLayout;
+use md5::{Digest, Md5};
+use crate::general::data::m_data_general::batch_handler::{BatchProgress, BatchReceiveState, BatchTransferKey};
 use crate::general::network::proto::DataItem;
-use dataitem::{DataItemArgWrapper, WriteSplitTaskResult};
-use tokio::io::{AsyncSeekExt, AsyncReadExt};
+use dataitem::{DataItemArgWrapper, DataItemSource, WriteSplitTaskResult};
 
 use crate::general::{
+    app::{CacheMap, CachePolicy, CachePos, CacheTime},
     data::m_kv_store_engine::{
-        KeyTypeDataSetItem, KeyTypeDataSetMeta, KvAdditionalConf, KvStoreEngine, KvVersion,
+        KeyTypeBatchProgress, KeyTypeDataSetItem, KeyTypeDataSetItemVersion, KeyTypeDataSetMeta,
+        KeyTypeFnKvIndex, KvAdditionalConf, KvStoreEngine, KvVersion,
     },
+    data::m_kv_watch::KvWatch,
     m_os::OperatingSystem,
     network::{
         m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
@@ -35,16 +51,22 @@ use crate::{
     util::{JoinHandleWrapper, container::async_init_map::AsyncInitMap},
 };
 use crate::{result::WsDataError, sys::LogicalModulesRef};
+use crate::master::data::m_data_master::DataMaster;
+use crate::general::data::m_data_cache::DataCache;
 use async_trait::async_trait;
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
 use camelpaste::paste;
 use core::str;
+use prost::bytes::Bytes;
 
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeSet, HashMap, HashSet},
+    future::Future,
+    path::PathBuf,
     sync::Arc,
-    time::Duration,
-    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
 };
 use tokio::sync::Semaphore;
 use tokio::task::JoinError;
@@ -70,16 +92,59 @@ logical_module_view_impl!(DataGeneralView, p2p, P2PModule);
 logical_module_view_impl!(DataGeneralView, data_general, DataGeneral);
 logical_module_view_impl!(DataGeneralView, kv_store_engine, KvStoreEngine);
 logical_module_view_impl!(DataGeneralView, os, OperatingSystem);
+logical_module_view_impl!(DataGeneralView, kv_watch, KvWatch);
+logical_module_view_impl!(DataGeneralView, data_master, Option<DataMaster>);
+logical_module_view_impl!(DataGeneralView, data_cache, DataCache);
 
 pub type DataVersion = u64;
-pub type DataItemIdx = u8;
+pub type DataItemIdx = u64;
 
 pub const DATA_UID_PREFIX_APP_META: &str = "app";
 pub const DATA_UID_PREFIX_FN_KV: &str = "fkv";
+/// between the app and the key of a namespaced fn kv key
+pub const FN_KV_NS_SEP: u8 = 0;
+
+/// max dataset metas cached on one node
+const META_CACHE_CAPACITY: u64 = 10000;
+/// cached meta expires even without invalidation, in case an invalidation is lost
+const META_CACHE_TTL: Duration = Duration::from_secs(60);
+/// a read is hedged to the next holder once it takes this many times the usual
+///  latency of its node, the default is for a node not read from yet
+const READ_HEDGE_LATENCY_FACTOR: u32 = 3;
+const READ_HEDGE_DEFAULT_DELAY: Duration = Duration::from_millis(50);
+const READ_HEDGE_MIN_DELAY: Duration = Duration::from_millis(10);
+
+/// moving average of the read latency of a node, the last sample weighs 1/8
+fn smoothed_read_latency(prev: Option<Duration>, sample: Duration) -> Duration {
+    match prev {
+        Some(prev) => (prev * 7 + sample) / 8,
+        None => sample,
+    }
+}
+
+fn read_hedge_delay(latency: Option<Duration>) -> Duration {
+    latency.map_or(READ_HEDGE_DEFAULT_DELAY, |latency| {
+        (latency * READ_HEDGE_LATENCY_FACTOR).max(READ_HEDGE_MIN_DELAY)
+    })
+}
+
+fn new_meta_cache() -> moka::sync::Cache<Vec<u8>, DataSetMetaV2> {
+    moka::sync::CacheBuilder::new(META_CACHE_CAPACITY)
+        .time_to_live(META_CACHE_TTL)
+        .build()
+}
 
 /// 默认数据块大小 (4MB)
 pub const DEFAULT_BLOCK_SIZE: usize = 4 * 1024 * 1024;
 
+/// retry times when the data node hasn't received the data of the latest version
+const LATEST_READ_RETRY: usize = 50;
+const LATEST_READ_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+/// files of map file items younger than this may belong to a write in progress
+const MAPPED_ITEM_MIN_AGE: Duration = Duration::from_secs(60);
+/// batch transfers no block arrived for this long are dropped by the data gc
+pub const BATCH_TRANSFER_MAX_IDLE: Duration = Duration::from_secs(3600);
+
 pub const CACHE_MODE_TIME_MASK: u16 = 0xf000;
 pub const CACHE_MODE_TIME_FOREVER_MASK: u16 = 0x0fff;
 pub const CACHE_MODE_TIME_AUTO_MASK: u16 = 0x1fff;
@@ -92,6 +157,15 @@ pub const CACHE_MODE_POS_AUTO_MASK: u16 = 0xf2ff;
 pub const CACHE_MODE_MAP_MASK: u16 = 0x00f0;
 pub const CACHE_MODE_MAP_COMMON_KV_MASK: u16 = 0xff0f;
 pub const CACHE_MODE_MAP_FILE_MASK: u16 = 0xff1f;
+
+/// 0 for replicas, otherwise the parity shard count of an erasure coded item
+pub const CACHE_MODE_REDUNDANCY_MASK: u16 = 0x000f;
+pub const CACHE_MODE_REDUNDANCY_REPLICA_MASK: u16 = 0xfff0;
+
+/// `EachNodeSplit::cache_mode` of a copy beyond the replication factor, time auto.
+///  The time mode of the item only applies to such copies, the others are kept forever
+pub const CACHE_MODE_SPLIT_EXTRA_COPY: u32 =
+    (CACHE_MODE_TIME_AUTO_MASK & CACHE_MODE_TIME_MASK) as u32;
 // const DATA_UID_PREFIX_OBJ: &str = "obj";
 
 pub fn new_data_unique_id_app(app_name: &str) -> String {
@@ -106,6 +180,31 @@ pub fn new_data_unique_id_fn_kv(key: &[u8]) -> Vec<u8> {
     // format!("{}{}", DATA_UID_PREFIX_FN_KV, key_str)
 }
 
+/// Key of a function in the namespace of an app, `{app}\0{key}`, its dataset is
+///  `new_data_unique_id_fn_kv` of it. Keys written before namespaces have no separator.
+pub fn fn_kv_ns_key(app: &str, key: &[u8]) -> Vec<u8> {
+    let mut ns_key = Vec::with_capacity(app.len() + 1 + key.len());
+    ns_key.extend(app.as_bytes());
+    ns_key.push(FN_KV_NS_SEP);
+    ns_key.extend(key);
+    ns_key
+}
+
+/// (app, key) of a namespaced key, None for a key written before namespaces
+pub fn split_fn_kv_ns_key(ns_key: &[u8]) -> Option<(&str, &[u8])> {
+    let sep = ns_key.iter().position(|b| *b == FN_KV_NS_SEP)?;
+    let app = std::str::from_utf8(&ns_key[..sep]).ok()?;
+    Some((app, &ns_key[sep + 1..]))
+}
+
+/// Key of a dataset as functions see it, fn kv keys without their prefix and namespace
+pub fn fn_kv_user_key(unique_id: &[u8]) -> &[u8] {
+    let Some(key) = unique_id.strip_prefix(DATA_UID_PREFIX_FN_KV.as_bytes()) else {
+        return unique_id;
+    };
+    split_fn_kv_ns_key(key).map_or(key, |(_, key)| key)
+}
+
 /// 唯一标识符类型
 pub type UniqueId = Vec<u8>;
 
@@ -115,8 +214,13 @@ pub struct DataGeneral {
     pub rpc_call_data_version_schedule: RPCCaller<proto::DataVersionScheduleRequest>,
     rpc_call_write_once_data: RPCCaller<proto::WriteOneDataRequest>,
     rpc_call_batch_data: RPCCaller<proto::BatchDataRequest>,
+    rpc_call_batch_resume: RPCCaller<proto::BatchResumeRequest>,
     rpc_call_get_data_meta: RPCCaller<proto::DataMetaGetRequest>,
     rpc_call_get_data: RPCCaller<proto::GetOneDataRequest>,
+    rpc_call_data_stream_open: RPCCaller<proto::DataStreamOpenRequest>,
+    rpc_call_data_stream_segment: RPCCaller<proto::DataStreamSegmentRequest>,
+    rpc_call_data_stream_commit: RPCCaller<proto::DataStreamCommitRequest>,
+    rpc_call_replicate_item: RPCCaller<proto::DataReplicateItemRequest>,
 
     //费新文
     // rpc_call_distribute_task: RPCCaller<DistributeTaskReq>,
@@ -124,16 +228,30 @@ pub struct DataGeneral {
 
     rpc_handler_write_once_data: RPCHandler<proto::WriteOneDataRequest>,
     rpc_handler_batch_data: RPCHandler<proto::BatchDataRequest>,
+    rpc_handler_batch_resume: RPCHandler<proto::BatchResumeRequest>,
     rpc_handler_data_meta_update: RPCHandler<proto::DataMetaUpdateRequest>,
     rpc_handler_get_data_meta: RPCHandler<proto::DataMetaGetRequest>,
     rpc_handler_get_data: RPCHandler<proto::GetOneDataRequest>,
+    rpc_handler_data_stream_segment: RPCHandler<proto::DataStreamSegmentRequest>,
+    rpc_handler_data_stream_commit: RPCHandler<proto::DataStreamCommitRequest>,
+    rpc_handler_replicate_item: RPCHandler<proto::DataReplicateItemRequest>,
 
     //费新文
     // rpc_handler_distribute_task: RPCHandler<DistributeTaskReq>,
 
     
     // 批量数据接收状态管理
-    batch_receive_states: AsyncInitMap<proto::BatchRequestId, Arc<BatchReceiveState>>,
+    batch_receive_states: AsyncInitMap<BatchTransferKey, Arc<BatchReceiveState>>,
+    /// items pulled by re-replication, handed over when their batch transfer completes
+    replica_pulls:
+        dashmap::DashMap<BatchTransferKey, tokio::sync::oneshot::Sender<proto::DataItem>>,
+
+    /// dataset metas read from master, invalidated by the master on update
+    meta_cache: moka::sync::Cache<Vec<u8>, DataSetMetaV2>,
+    /// bumped on every invalidation
+    meta_cache_epoch: AtomicU64,
+    /// smoothed latency of item reads from each node, reads are hedged by it
+    read_latency: dashmap::DashMap<NodeID, Duration>,
 }
 
 impl DataGeneral {
@@ -143,8 +261,13 @@ impl DataGeneral {
             rpc_call_data_version_schedule: RPCCaller::new(),
             rpc_call_write_once_data: RPCCaller::new(),
             rpc_call_batch_data: RPCCaller::new(),
+            rpc_call_batch_resume: RPCCaller::new(),
             rpc_call_get_data_meta: RPCCaller::new(),
             rpc_call_get_data: RPCCaller::new(),
+            rpc_call_data_stream_open: RPCCaller::new(),
+            rpc_call_data_stream_segment: RPCCaller::new(),
+            rpc_call_data_stream_commit: RPCCaller::new(),
+            rpc_call_replicate_item: RPCCaller::new(),
 
             //费新文
             // rpc_call_distribute_task: RPCCaller::new(),
@@ -152,10 +275,18 @@ impl DataGeneral {
 
             rpc_handler_write_once_data: RPCHandler::new(),
             rpc_handler_batch_data: RPCHandler::new(),
+            rpc_handler_batch_resume: RPCHandler::new(),
             rpc_handler_data_meta_update: RPCHandler::new(),
             rpc_handler_get_data_meta: RPCHandler::new(),
             rpc_handler_get_data: RPCHandler::new(),
+            rpc_handler_data_stream_segment: RPCHandler::new(),
+            rpc_handler_data_stream_commit: RPCHandler::new(),
+            rpc_handler_replicate_item: RPCHandler::new(),
             batch_receive_states: AsyncInitMap::new(),
+            replica_pulls: dashmap::DashMap::new(),
+            meta_cache: new_meta_cache(),
+            meta_cache_epoch: AtomicU64::new(0),
+            read_latency: dashmap::DashMap::new(),
 
             //费新文
             // rpc_handler_distribute_task: RPCHandler::new(),
@@ -176,162 +307,31 @@ impl DataGeneral {
         data: proto::DataItem,
         data_item_idx: DataItemIdx,
         node_id: NodeID,
+        compression: DataCompression,
     ) -> WSResult<()> {
-        // 调用 batch_transfer 函数处理数据传输
-        async fn batch_transfer(
-            data_item_idx: DataItemIdx,
-            unique_id: UniqueId,
-            version: u64,
-            target_node: NodeID,
-            data: Arc<DataItemSource>,
-            view: DataGeneralView,
-        ) -> WSResult<()> {
-            let (tx, mut rx) = tokio::sync::mpsc::channel(32);
-            let mut handles = Vec::new();
-            
-            let data_size = data.size().await?;
-            let splits = calculate_splits(data_size);
-            
-            tracing::debug!("batch_transfer total size({}), splits: {:?}, to node {}", data_size, splits, target_node);
-
-            for (block_idx, split_range) in splits.iter().enumerate() {
-                let block_data = match data.as_ref() {
-                    DataItemSource::Memory { data } => data[split_range.clone()].to_vec(),
-                    DataItemSource::File { path } => {
-                        // 读取文件对应块的数据
-                        let mut file = tokio::fs::File::open(path).await.map_err(|e| WsDataError::BatchTransferFailed {
-                            request_id: proto::BatchRequestId {
-                                node_id: target_node as u32,
-                                sequence: block_idx as u64,
-                            },
-                            reason: format!("Failed to open file: {}", e),
-                        })?;
-                        let mut buffer = vec![0; split_range.len()];
-                        // 验证seek结果
-                        let seek_pos = file.seek(std::io::SeekFrom::Start(split_range.start as u64)).await.map_err(|e| WsDataError::BatchTransferFailed {
-                            request_id: proto::BatchRequestId {
-                                node_id: target_node as u32,
-                                sequence: block_idx as u64,
-                            },
-                            reason: format!("Failed to seek file: {}", e),
-                        })?;
-                        if seek_pos != split_range.start as u64 {
-                            return Err(WsDataError::BatchTransferFailed {
-                                request_id: proto::BatchRequestId {
-                                    node_id: target_node as u32,
-                                    sequence: block_idx as u64,
-                                },
-                                reason: format!("Seek position mismatch: expected {}, got {}", split_range.start, seek_pos),
-                            }.into());
-                        }
-                        // read_exact保证读取指定长度的数据或返回错误
-                        let _ = file.read_exact(&mut buffer).await.map_err(|e| WsDataError::BatchTransferFailed {
-                            request_id: proto::BatchRequestId {
-                                node_id: target_node as u32,
-                                sequence: block_idx as u64,
-                            },
-                            reason: format!("Failed to read file: {}", e),
-                        })?;
-                        buffer
-                    }
-                };
-
-                let request = proto::BatchDataRequest {
-                    request_id: Some(proto::BatchRequestId {
-                        node_id: target_node as u32,
-                        sequence: block_idx as u64,
-                    }),
-                    dataset_unique_id: unique_id.clone(),
-                    data_item_idx: data_item_idx as u32,
-                    // 用空的 DataItem 代替
-                    block_type: match data.as_ref() {
-                        DataItemSource::Memory { .. } => Some(proto::DataItem{
-                            data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(Vec::new())),
-                        }),
-                        DataItemSource::File { .. } => Some(proto::DataItem{
-                            data_item_dispatch: Some(proto::data_item::DataItemDispatch::File(proto::FileData { file_name_opt: String::new(), is_dir_opt: true, file_content: Vec::new() })),
-                        }),
-                    },
-                    block_index: block_idx as u32,
-                    data: block_data,
-                    operation: proto::DataOpeType::Write as i32,
-                    unique_id: unique_id.clone(),
-                    version,
-                    total_size: data_size as u64,
-                };
-    
-                let tx = tx.clone();
-                let view = view.clone();
-                
-                let handle = tokio::spawn(async move {
-                    let result = view.data_general()
-                        .rpc_call_batch_data
-                        .call(
-                            view.p2p(),
-                            target_node,
-                            request,
-                            Some(Duration::from_secs(30)),
-                        )
-                        .await;
-                    
-                    if let Err(e) = tx.send(result).await {
-                        tracing::error!("Failed to send batch transfer result: {}", e);
-                    }
-                });
-                
-                handles.push(handle);
-            }
-            
-            drop(tx);
-            
-            while let Some(result) = rx.recv().await {
-                match result {
-                    Ok(resp) if !resp.success => {
-                        return Err(WsDataError::BatchTransferFailed {
-                            request_id: proto::BatchRequestId {
-                                node_id: target_node as u32,
-                                sequence: 0, // TODO: Add proper sequence number
-                            },
-                            reason: resp.error_message,
-                        }.into());
-                    }
-                    Ok(_) => continue,
-                    Err(e) => {
-                        return Err(WsDataError::BatchTransferFailed {
-                            request_id: proto::BatchRequestId {
-                                node_id: target_node as u32,
-                                sequence: 0,
-                            },
-                            reason: format!("RPC call failed: {}", e),
-                        }.into());
-                    }
-                }
-            }
-
-            for handle in handles {
-                handle.await.map_err(|e| {
-                    WsDataError::BatchTransferFailed {
-                        request_id: proto::BatchRequestId {
-                            node_id: target_node as u32,
-                            sequence: 0,
-                        },
-                        reason: format!("Task join failed: {}", e),
-                    }
-                })?;
-            }
-
-            Ok(())
-        }
-
-        let data = Arc::new(data.to_data_item_source());
-        batch_transfer(data_item_idx,unique_id, version, node_id, data, self.view.clone()).await
+        self.batch_transfer(unique_id, data_item_idx, version, node_id, data, compression)
+            .await
     }
 
     
+    /// `txn_id` is of the kv transaction reserving the dataset, 0 waits until any reservation is released
     pub async fn get_or_del_datameta_from_master(
         &self,
         unique_id: &[u8],
         delete: bool,
+        txn_id: u64,
+    ) -> WSResult<DataSetMetaV2> {
+        self.datameta_from_master(unique_id, delete, txn_id, None)
+            .await
+    }
+
+    /// `deleted_by` is the function a delete is made for
+    async fn datameta_from_master(
+        &self,
+        unique_id: &[u8],
+        delete: bool,
+        txn_id: u64,
+        deleted_by: Option<&proto::DataOpeRoleFuncCall>,
     ) -> WSResult<DataSetMetaV2> {
         tracing::debug!("get_or_del_datameta_from_master uid: {:?}, delete: {}, whoami: {}", unique_id, delete, self.view.p2p().nodes_config.this.0);
         let p2p = self.view.p2p();
@@ -344,6 +344,8 @@ impl DataGeneral {
                 proto::DataMetaGetRequest {
                     unique_id: unique_id.to_vec(),
                     delete,
+                    txn_id,
+                    deleted_by: deleted_by.cloned(),
                 },
                 Some(Duration::from_secs(60)),
             )
@@ -365,1406 +367,5033 @@ impl DataGeneral {
         })
     }
 
-    pub async fn get_or_del_data(
-        &self,
-        GetOrDelDataArg {
-            meta,
-            unique_id,
-            ty,
-        }: GetOrDelDataArg,
-    ) -> WSResult<(DataSetMetaV2, HashMap<DataItemIdx, proto::DataItem>)> {
-        tracing::debug!("get_or_del_data uid: {:?}, maybe with meta: {:?}", unique_id, meta);
-        let mut data_map = HashMap::new();
-
-        // get meta from master
-        let meta = if let Some(meta) = meta {
-            meta
-        } else {
-            self.get_or_del_datameta_from_master(&unique_id, false)
-                .await?
-        };
-
-        tracing::debug!("start get_or_del_data uid: {:?},meta: {:?}", unique_id, meta);
-
-        // basical verify
-        for idx in 0..meta.data_item_cnt() {
-            let idx = idx as DataItemIdx;
-            let check_cache_map = |meta: &DataSetMetaV2| -> WSResult<()> {
-                if !meta.cache_mode_visitor(idx).is_map_common_kv()
-                    && !meta.cache_mode_visitor(idx).is_map_file()
-                {
-                    return Err(WsDataError::UnknownCacheMapMode {
-                        mode: meta.cache_mode_visitor(idx).0,
-                    }
-                    .into());
-                }
-                Ok(())
-            };
-            check_cache_map(&meta)?;
+    /// Meta of the dataset, served by the local cache when possible.
+    ///  Return whether it's from the cache.
+    pub async fn get_datameta_cached(&self, unique_id: &[u8]) -> WSResult<(DataSetMetaV2, bool)> {
+        if let Some(meta) = self.meta_cache.get(unique_id) {
+            return Ok((meta, true));
         }
+        // an invalidation during the fetch means the fetched meta may be stale already
+        let epoch = self.meta_cache_epoch.load(Ordering::Acquire);
+        let meta = self.get_or_del_datameta_from_master(unique_id, false, 0).await?;
+        if self.meta_cache_epoch.load(Ordering::Acquire) == epoch {
+            self.meta_cache.insert(unique_id.to_vec(), meta.clone());
+        }
+        Ok((meta, false))
+    }
 
-        // get data
-        let p2p = self.view.p2p();
+    /// Drop the cached meta, called when the master reports an update or this node changed it
+    pub fn invalidate_meta_cache(&self, unique_id: &[u8]) {
+        let _ = self.meta_cache_epoch.fetch_add(1, Ordering::AcqRel);
+        self.meta_cache.invalidate(unique_id);
+    }
 
-        match ty {
-            GetOrDelDataArgType::All => {
-                for idx in 0..meta.data_item_cnt() {
-                    let idx = idx as DataItemIdx;
-                    let resp = self
-                        .rpc_call_get_data
-                        .call(
-                            p2p,
-                            meta.get_data_node(idx),
-                            proto::GetOneDataRequest {
-                                unique_id: unique_id.to_vec(),
-                                idxs: vec![idx as u32],
-                                delete: false,
-                                return_data: true,
-                            },
-                            Some(Duration::from_secs(60)),
-                        )
-                        .await?;
+    /// Item stored on this node, only when it's at `version`
+    async fn get_item_local(
+        &self,
+        unique_id: &[u8],
+        version: DataVersion,
+        idx: DataItemIdx,
+    ) -> Option<proto::DataItem> {
+        let kv_store_engine = self.view.kv_store_engine();
+        let item = {
+            // items and their version are written under the meta lock
+            let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(unique_id).make_key());
+            let _guard = lock.read();
+            let (_, items_version) = kv_store_engine.get(
+                &KeyTypeDataSetItemVersion(unique_id),
+                false,
+                KvAdditionalConf {},
+            )?;
+            if items_version != version {
+                return None;
+            }
+            let (_, item) = kv_store_engine.get(
+                &KeyTypeDataSetItem {
+                    uid: unique_id,
+                    idx,
+                },
+                false,
+                KvAdditionalConf {},
+            )?;
+            item
+        };
+        // the file of a map file item is read once the lock is released
+        decode_persist_item(item)
+            .await
+            .map_err(|err| {
+                tracing::warn!("decode local data item failed: {:?}", err);
+            })
+            .ok()
+    }
 
-                    if !resp.success {
-                        return Err(WsDataError::GetDataFailed {
-                            unique_id: unique_id.to_vec(),
-                            msg: resp.message,
-                    }
-                    .into());
+    async fn get_one_item(
+        &self,
+        meta: &DataSetMetaV2,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+    ) -> WSResult<proto::DataItem> {
+        if let Some(parity_shards) = meta.cache_mode_visitor(idx).erasure_parity_shards() {
+            return self
+                .get_one_item_erasure(meta, unique_id, idx, parity_shards)
+                .await;
+        }
+        // holders whose copy is corrupted, repaired after a good copy is read
+        let mut corrupted = vec![];
+        if let Some(item) = self.get_item_local(unique_id, meta.version, idx).await {
+            match meta.verify_item(unique_id, idx, &item) {
+                Ok(()) => {
+                    tracing::debug!("read data({:?}) item {} locally", unique_id, idx);
+                    return Ok(item);
                 }
-
-                    let _ = data_map.insert(idx, resp.data[0].clone());
+                Err(err) => {
+                    tracing::warn!("local copy corrupted: {:?}", err);
+                    corrupted.push(self.view.p2p().nodes_config.this_node());
                 }
             }
-            GetOrDelDataArgType::Delete => {
-                for idx in 0..meta.data_item_cnt() {
-                    let idx = idx as DataItemIdx;
-                    let resp = self
-                        .rpc_call_get_data
-                        .call(
-                            p2p,
-                            meta.get_data_node(idx),
-                            proto::GetOneDataRequest {
-                                unique_id: unique_id.to_vec(),
-                                idxs: vec![idx as u32],
-                                delete: true,
-                                return_data: true,
-                            },
-                            Some(Duration::from_secs(60)),
-                        )
-                        .await?;
-
-                    if !resp.success {
-                        return Err(WsDataError::GetDataFailed {
-                            unique_id: unique_id.to_vec(),
-                            msg: resp.message,
+        }
+        let holders = meta.get_data_holders(idx);
+        let mut holders_iter = holders.iter().copied();
+        let mut inflight = FuturesUnordered::new();
+        let mut errs: Vec<(NodeID, WSError)> = vec![];
+        let launch = |node: NodeID| async move {
+            let started = Instant::now();
+            let res = self.get_one_item_from(meta, unique_id, idx, node).await;
+            if res.is_ok() {
+                let prev = self.read_latency.get(&node).map(|l| *l);
+                let _ = self
+                    .read_latency
+                    .insert(node, smoothed_read_latency(prev, started.elapsed()));
+            }
+            (node, res)
+        };
+        let hedge_delay = |node: NodeID| read_hedge_delay(self.read_latency.get(&node).map(|l| *l));
+
+        // primary first, the next holder starts when a read fails,
+        //  or once when a read takes longer than usual on its node
+        let mut hedge_after = None;
+        if let Some(node) = holders_iter.next() {
+            hedge_after = Some(hedge_delay(node));
+            inflight.push(launch(node));
+        }
+        while !inflight.is_empty() {
+            let next = match hedge_after {
+                Some(delay) => tokio::time::timeout(delay, inflight.next()).await,
+                None => Ok(inflight.next().await),
+            };
+            match next {
+                Ok(Some((_, Ok(item)))) => {
+                    self.repair_item(meta, unique_id, idx, &item, corrupted);
+                    return Ok(item);
+                }
+                Ok(Some((node, Err(err)))) => {
+                    if let WSError::WsDataError(WsDataError::ChecksumMismatch { .. }) = &err {
+                        corrupted.push(node);
+                    }
+                    tracing::warn!(
+                        "read data({:?}) item {} from node {} failed: {:?}",
+                        unique_id,
+                        idx,
+                        node,
+                        err
+                    );
+                    errs.push((node, err));
+                    if let Some(node) = holders_iter.next() {
+                        // not hedged yet, the replacement gets its own hedge delay
+                        hedge_after = hedge_after.map(|_| hedge_delay(node));
+                        inflight.push(launch(node));
                     }
-                    .into());
                 }
-
-                    let _ = data_map.insert(idx, resp.data[0].clone());
+                Ok(None) => break,
+                Err(_) => {
+                    hedge_after = None;
+                    if let Some(node) = holders_iter.next() {
+                        tracing::debug!(
+                            "read data({:?}) item {} hedged to node {}",
+                            unique_id,
+                            idx,
+                            node
+                        );
+                        inflight.push(launch(node));
+                    }
                 }
             }
-            GetOrDelDataArgType::PartialOne { idx } => {
-                let resp = self
-                    .rpc_call_get_data
-                    .call(
-                        p2p,
-                        meta.get_data_node(idx),
-                        proto::GetOneDataRequest {
-                            unique_id: unique_id.to_vec(),
-                            idxs: vec![idx as u32],
-                            delete: false,
-                            return_data: true,
-                        },
-                        Some(Duration::from_secs(60)),
-                    )
-                    .await?;
+        }
 
-                if !resp.success {
-                    return Err(WsDataError::GetDataFailed {
-                        unique_id: unique_id.to_vec(),
-                        msg: resp.message,
-                    }
-                    .into());
-                }
+        Err(WsDataError::GetDataFailedOnHolders {
+            unique_id: unique_id.to_vec(),
+            idx,
+            errs,
+        }
+        .into())
+    }
 
-                let _ = data_map.insert(idx, resp.data[0].clone());
+    /// Decode an erasure coded item from the first k shards read. A local shard is just
+    ///  a part of the item, so it's fetched like the others.
+    async fn get_one_item_erasure(
+        &self,
+        meta: &DataSetMetaV2,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        parity_shards: usize,
+    ) -> WSResult<proto::DataItem> {
+        let splits = &meta.datas_splits[idx as usize].splits;
+        let sizes: Vec<u64> = splits.iter().map(|s| s.data_size).collect();
+        let layout = ErasureLayout::from_split_sizes(&sizes, parity_shards).ok_or_else(|| {
+            WsDataError::GetDataFailed {
+                unique_id: unique_id.to_vec(),
+                msg: format!("bad erasure coded split sizes {:?}", sizes),
             }
-            GetOrDelDataArgType::PartialMany { idxs } => {
-                for idx in idxs {
-                    let resp = self
-                        .rpc_call_get_data
-                        .call(
-                            p2p,
-                            meta.get_data_node(idx),
-                            proto::GetOneDataRequest {
-                                unique_id: unique_id.to_vec(),
-                                idxs: vec![idx as u32],
-                                delete: false,
-                                return_data: true,
-                            },
-                            Some(Duration::from_secs(60)),
-                        )
-                        .await?;
-
-                    if !resp.success {
-                        return Err(WsDataError::GetDataFailed {
-                            unique_id: unique_id.to_vec(),
-                            msg: resp.message,
+        })?;
+
+        let mut inflight: FuturesUnordered<_> = splits
+            .iter()
+            .enumerate()
+            .map(|(shard_idx, split)| async move {
+                let res = self
+                    .get_shard_from(meta, unique_id, idx, split.node_id, split.data_size)
+                    .await;
+                (shard_idx, split.node_id, res)
+            })
+            .collect();
+        let mut shards: Vec<Option<Vec<u8>>> = vec![None; layout.shard_cnt()];
+        let mut got = 0;
+        let mut errs: Vec<(NodeID, WSError)> = vec![];
+        while let Some((shard_idx, node, res)) = inflight.next().await {
+            match res {
+                Ok(shard) => {
+                    shards[shard_idx] = Some(shard);
+                    got += 1;
+                    if got == layout.data_shards {
+                        break;
                     }
-                    .into());
                 }
-
-                    let _ = data_map.insert(idx, resp.data[0].clone());
+                Err(err) => {
+                    tracing::warn!(
+                        "read data({:?}) item {} shard {} from node {} failed: {:?}",
+                        unique_id,
+                        idx,
+                        shard_idx,
+                        node,
+                        err
+                    );
+                    errs.push((node, err));
                 }
             }
         }
+        drop(inflight);
 
-        Ok((meta, data_map))
+        let Some(bytes) = erasure::reconstruct(&shards, layout) else {
+            return Err(WsDataError::ErasureShardsLacking {
+                unique_id: unique_id.to_vec(),
+                idx,
+                needed: layout.data_shards,
+                errs,
+            }
+            .into());
+        };
+        let item = proto::DataItem {
+            data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(bytes)),
+        };
+        meta.verify_item(unique_id, idx, &item)?;
+        Ok(item)
     }
 
-    pub async fn write_data(
+    /// One shard of an erasure coded item, all shards must be of the meta version
+    async fn get_shard_from(
         &self,
-        unique_id: impl Into<Vec<u8>>,
-        mut datas: Vec<DataItemArgWrapper>,
-        context_openode_opetype_operole: Option<(
-            NodeID,
-            proto::DataOpeType,
-            proto::data_schedule_context::OpeRole,
-        )>,
-    ) -> WSResult<()> {
-        let unique_id = unique_id.into();
-        let log_tag = format!("[write_data({})]", String::from_utf8_lossy(&unique_id));
-        tracing::debug!("{} start write data", log_tag);
-        
-        let mut data_transfer_sizes=Vec::new();
-        data_transfer_sizes.reserve(datas.len());
-        for d in datas.iter_mut(){
-            data_transfer_sizes.push(d.transfer_size().await.map_err(|err|{
-                tracing::error!("{} transfer size error: {}", log_tag, err);
-                err
-            })?);
-        }
-        // 获取数据调度计划
-        let version_schedule_resp = self
-            .rpc_call_data_version_schedule
+        meta: &DataSetMetaV2,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        node: NodeID,
+        size: u64,
+    ) -> WSResult<Vec<u8>> {
+        let resp = self
+            .rpc_call_get_data
             .call(
                 self.view.p2p(),
-                self.view.p2p().nodes_config.get_master_node(),
-                proto::DataVersionScheduleRequest {
-                    unique_id: unique_id.clone(),
-                    context: context_openode_opetype_operole.map(|(node, ope, role)| {
-                        proto::DataScheduleContext {
-                            // each_data_sz_bytes: data_transfer_sizes,     原代码类型不匹配       曾俊
-                            each_data_sz_bytes: data_transfer_sizes.iter().map(|&x| x as u32).collect(),
-                            ope_node: node as i64,
-                            ope_type: ope as i32,
-                            ope_role: Some(role),
-                        }
-                    }),
-                    version: 0,
+                node,
+                proto::GetOneDataRequest {
+                    unique_id: unique_id.to_vec(),
+                    idxs: vec![idx],
+                    delete: false,
+                    return_data: true,
+                    offset: 0,
+                    length: 0,
+                    txn_id: 0,
                 },
                 Some(Duration::from_secs(60)),
             )
             .await?;
+        if !resp.success {
+            return Err(WsDataError::GetDataFailed {
+                unique_id: unique_id.to_vec(),
+                msg: resp.message,
+            }
+            .into());
+        }
+        if resp.version != meta.version {
+            return Err(WsDataError::VersionMismatch {
+                expected: meta.version,
+                actual: resp.version,
+            }
+            .into());
+        }
+        match resp.data.into_iter().next() {
+            Some(proto::DataItem {
+                data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(shard)),
+            }) if shard.len() as u64 == size => Ok(shard),
+            Some(proto::DataItem {
+                data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(shard)),
+            }) => Err(WsDataError::SizeMismatch {
+                expected: size as usize,
+                actual: shard.len(),
+            }
+            .into()),
+            _ => Err(WsDataError::GetDataFailed {
+                unique_id: unique_id.to_vec(),
+                msg: "unexpected shard response".to_owned(),
+            }
+            .into()),
+        }
+    }
 
-        // Clone the response to extend its lifetime
-        let version = version_schedule_resp.version;
-        let splits = version_schedule_resp.split.clone();
+    /// Read one item from a given holder, a holder other than the primary must
+    ///  have the same version as the meta
+    async fn get_one_item_from(
+        &self,
+        meta: &DataSetMetaV2,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        node: NodeID,
+    ) -> WSResult<proto::DataItem> {
+        let resp = self
+            .rpc_call_get_data
+            .call(
+                self.view.p2p(),
+                node,
+                proto::GetOneDataRequest {
+                    unique_id: unique_id.to_vec(),
+                    idxs: vec![idx],
+                    delete: false,
+                    return_data: true,
+                    offset: 0,
+                    length: 0,
+                    txn_id: 0,
+                },
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
 
-        // 处理每个数据项
-        let mut iter = WantIdxIter::new(&GetOrDelDataArgType::All, datas.len() as u8);
-        while let Some(data_item_idx) = iter.next() {
-            let data_item: &DataItemArgWrapper = &mut datas[data_item_idx as usize];
-            let split = &splits[data_item_idx as usize];
-            let mut primary_tasks = Vec::new();
-            
-            // 1. 并行写入所有主数据分片
-            let mut split_iter = WantIdxIter::new(&GetOrDelDataArgType::All, split.splits.len() as u8);
-            while let Some(split_idx) = split_iter.next() {
-                let split_info = &split.splits[split_idx as usize];
-                tracing::debug!("{} creating split write task {}/{} for node {}, offset={}, size={}",
-                    log_tag, split_idx + 1, split.splits.len(), split_info.node_id, split_info.data_offset, split_info.data_size);
-                let split_info = split_info.clone();
-                let unique_id_clone = unique_id.clone();
-                // let data_item_primary = data_item.clone_split_range(split_info.data_offset..split_info.data_offset+split_info.data_size);        类型不匹配     曾俊
-                // 生成一个复制的可变数据项
-                let mut data_item_clone = (*data_item).clone();
-                let data_item_primary = data_item_clone.clone_split_range(split_info.data_offset as usize..(split_info.data_offset+split_info.data_size)as usize).await.todo_handle("clone_split_range for write data err")?;
-                // let data_item_primary = data_item.clone_split_range(split_info.data_offset as usize..(split_info.data_offset+split_info.data_size)as usize).await.todo_handle("clone_split_range for write data err")?;
-                let view = self.view.clone();
-                let version_copy = version;
-                let task = tokio::spawn(async move {
-                                        view.data_general()
-                        .rpc_call_write_once_data
-                        .call(
-                            view.p2p(),
-                            split_info.node_id,
-                            proto::WriteOneDataRequest {
-                                unique_id: unique_id_clone.clone(),
-                                version: version_copy,
-                                data: vec![proto::DataItemWithIdx {
-                                    idx: data_item_idx as u32,
-                                    // data: Some(data_item_primary),           类型不匹配    曾俊
-                                    data: Some(data_item_primary),
-                                }],
-                            },
-                            Some(Duration::from_secs(60)),
-                        )
-                        .await
-                });
-                primary_tasks.push(task);
+        if !resp.success {
+            return Err(WsDataError::GetDataFailed {
+                unique_id: unique_id.to_vec(),
+                msg: resp.message,
+            }
+            .into());
+        }
+        if node != meta.get_data_node(idx) && resp.version != meta.version {
+            return Err(WsDataError::VersionMismatch {
+                expected: meta.version,
+                actual: resp.version,
             }
+            .into());
+        }
+        let item = resp.data.into_iter().next().ok_or_else(|| {
+            WSError::from(WsDataError::GetDataFailed {
+                unique_id: unique_id.to_vec(),
+                msg: "empty response".to_owned(),
+            })
+        })?;
+        meta.verify_item(unique_id, idx, &item)?;
+        Ok(item)
+    }
 
-            // 2. 并行写入缓存数据（完整数据）
-            let visitor = CacheModeVisitor(version_schedule_resp.cache_mode[data_item_idx as usize] as u16);
-            let need_cache = visitor.is_map_common_kv() || visitor.is_map_file();
-            let cache_nodes: Vec<NodeID> = if need_cache {
-                split.splits.iter().map(|s| s.node_id).collect()
-            } else {
-                vec![]
+    /// Range of an item stored on this node and the item size, file items are read with seek
+    async fn get_item_range_local(
+        &self,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        offset: u64,
+        length: u64,
+    ) -> WSResult<Option<(Vec<u8>, u64)>> {
+        let Some((tag, total, payload)) = self.view.kv_store_engine().get_data_item_range(
+            &KeyTypeDataSetItem {
+                uid: unique_id,
+                idx,
+            },
+            offset,
+            length,
+            false,
+        ) else {
+            return Ok(None);
+        };
+        self.view.data_cache().touch(unique_id, idx);
+        if tag != 0 && tag != PERSIST_TAG_MAPPED_FILE {
+            return Ok(Some((payload, total)));
+        }
+        let path = self.view.os().abs_file_path(persisted_path(&payload)?);
+        file_read_range(&path, offset, length).await.map(Some)
+    }
+
+    /// Bytes [offset, offset + length) of one item and the item size, 0 length means to the end.
+    ///  Only the range is transferred, so it's not checked against the item checksum.
+    pub async fn get_item_range(
+        &self,
+        meta: &DataSetMetaV2,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        offset: u64,
+        length: u64,
+    ) -> WSResult<(Vec<u8>, u64)> {
+        // shards can't serve a range, the item is decoded first
+        if let Some(parity_shards) = meta.cache_mode_visitor(idx).erasure_parity_shards() {
+            let item = self
+                .get_one_item_erasure(meta, unique_id, idx, parity_shards)
+                .await?;
+            let Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) = item.data_item_dispatch
+            else {
+                unreachable!("decoded items are raw bytes");
             };
+            let total = bytes.len() as u64;
+            let range = item_range(total, offset, length);
+            return Ok((bytes[range.start as usize..range.end as usize].to_vec(), total));
+        }
+        let local_version = self
+            .view
+            .kv_store_engine()
+            .get(
+                &KeyTypeDataSetItemVersion(unique_id),
+                false,
+                KvAdditionalConf {},
+            )
+            .map(|(_, version)| version);
+        if local_version == Some(meta.version) {
+            match self
+                .get_item_range_local(unique_id, idx, offset, length)
+                .await
+            {
+                Ok(Some(res)) => return Ok(res),
+                Ok(None) => {}
+                Err(err) => tracing::warn!("read local data range failed: {:?}", err),
+            }
+        }
 
-            let mut cache_tasks = Vec::new();
-            if !cache_nodes.is_empty() {
-                tracing::debug!("{} found {} cache nodes: {:?}", log_tag, cache_nodes.len(), cache_nodes);
-                const MAX_CONCURRENT_TRANSFERS: usize = 3;
-                let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_TRANSFERS));
-                
-                let mut cache_iter = WantIdxIter::new(&GetOrDelDataArgType::All, cache_nodes.len() as u8);
-                while let Some(cache_idx) = cache_iter.next() {
-                    let node_id = cache_nodes[cache_idx as usize];
-                    let permit = semaphore.clone().acquire_owned().await.unwrap();
-                    tracing::debug!("{} creating cache write task {}/{} for node {}", log_tag, cache_idx + 1, cache_nodes.len(), node_id);
-                    let unique_id_clone = unique_id.clone();
-                    let data_item_cache = data_item.clone();
-                    let view = self.view.clone();
-                    let task = tokio::spawn(async move {
-                        let _permit = permit; // 持有permit直到任务完成
-                        view.data_general()
-                            // .write_data_batch(unique_id_clone.clone(), version, data_item_cache, data_item_idx, node_id)               //类型不匹配  曾俊
-                            .write_data_batch(unique_id_clone.clone(), version, data_item_cache.dataitem, data_item_idx, node_id)
-                            .await?;
-                        Ok::<proto::WriteOneDataResponse, WSError>(proto::WriteOneDataResponse {
-                            remote_version: version,
-                            success: true,
-                            message: String::new(),
-                        })
-                    });
-                    cache_tasks.push(task);
+        let mut errs: Vec<(NodeID, WSError)> = vec![];
+        for node in meta.get_data_holders(idx) {
+            match self
+                .get_item_range_from(meta, unique_id, idx, offset, length, node)
+                .await
+            {
+                Ok(res) => return Ok(res),
+                Err(err) => {
+                    tracing::warn!(
+                        "read data({:?}) item {} range from node {} failed: {:?}",
+                        unique_id,
+                        idx,
+                        node,
+                        err
+                    );
+                    errs.push((node, err));
                 }
             }
+        }
+        Err(WsDataError::GetDataFailedOnHolders {
+            unique_id: unique_id.to_vec(),
+            idx,
+            errs,
+        }
+        .into())
+    }
 
-            let primary_results = futures::future::join_all(primary_tasks).await;
-            tracing::debug!("{} primary_results: {:?}", log_tag, primary_results);
-            let cache_results = futures::future::join_all(cache_tasks).await;
-            tracing::debug!("{} cache_results: {:?}", log_tag, cache_results);
+    async fn get_item_range_from(
+        &self,
+        meta: &DataSetMetaV2,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        offset: u64,
+        length: u64,
+        node: NodeID,
+    ) -> WSResult<(Vec<u8>, u64)> {
+        let resp = self
+            .rpc_call_get_data
+            .call(
+                self.view.p2p(),
+                node,
+                proto::GetOneDataRequest {
+                    unique_id: unique_id.to_vec(),
+                    idxs: vec![idx],
+                    delete: false,
+                    return_data: true,
+                    offset,
+                    // 0 offset with 0 length would read the whole item instead of a range
+                    length: if offset == 0 && length == 0 {
+                        u64::MAX
+                    } else {
+                        length
+                    },
+                    txn_id: 0,
+                },
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
 
-            if primary_results.iter().any(|res| res.is_err()) || cache_results.iter().any(|res| res.is_err()) {
-                let error_msg = format!("主节点或缓存节点数据写入失败");
-                tracing::error!("{}", error_msg);
-                return Err(WSError::WsDataError(WsDataError::WriteDataFailed {
-                    unique_id: unique_id.clone(),
-                    message: error_msg,
-                }));
+        if !resp.success {
+            return Err(WsDataError::GetDataFailed {
+                unique_id: unique_id.to_vec(),
+                msg: resp.message,
+            }
+            .into());
+        }
+        if node != meta.get_data_node(idx) && resp.version != meta.version {
+            return Err(WsDataError::VersionMismatch {
+                expected: meta.version,
+                actual: resp.version,
+            }
+            .into());
+        }
+        match (resp.data.into_iter().next(), resp.item_sizes.first()) {
+            (
+                Some(proto::DataItem {
+                    data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(bytes)),
+                }),
+                Some(size),
+            ) => Ok((bytes, *size)),
+            _ => Err(WsDataError::GetDataFailed {
+                unique_id: unique_id.to_vec(),
+                msg: "unexpected range response".to_owned(),
             }
+            .into()),
         }
+    }
 
-        Ok(())
+    /// Chunks of one item read by range, the next chunk is fetched only when the consumer
+    ///  polls for it, so a slow consumer holds at most one chunk.
+    pub fn read_data_stream<'a>(
+        &'a self,
+        unique_id: &'a [u8],
+        idx: DataItemIdx,
+        chunk_size: u64,
+    ) -> impl Stream<Item = WSResult<Bytes>> + 'a {
+        let chunk_size = chunk_size.max(1);
+        // (meta, next offset, item size), None when finished
+        let init: Option<(Option<DataSetMetaV2>, u64, u64)> = Some((None, 0, u64::MAX));
+        futures::stream::unfold(init, move |state| async move {
+            let (meta, offset, size) = state?;
+            if offset >= size {
+                return None;
+            }
+            let meta = match meta {
+                Some(meta) => meta,
+                None => match self.get_datameta_cached(unique_id).await {
+                    Ok((meta, _)) => meta,
+                    Err(err) => return Some((Err(err), None)),
+                },
+            };
+            match self
+                .get_item_range(&meta, unique_id, idx, offset, chunk_size)
+                .await
+            {
+                Ok((bytes, size)) => {
+                    if bytes.is_empty() {
+                        return None;
+                    }
+                    let next = offset + bytes.len() as u64;
+                    Some((Ok(Bytes::from(bytes)), Some((Some(meta), next, size))))
+                }
+                Err(err) => Some((Err(err), None)),
+            }
+        })
     }
 
-    async fn rpc_handle_write_one_data(
+    /// Overwrite the corrupted copies with a verified one, in background
+    fn repair_item(
         &self,
-        responsor: RPCResponsor<proto::WriteOneDataRequest>,
-        req: proto::WriteOneDataRequest,
+        meta: &DataSetMetaV2,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        item: &proto::DataItem,
+        nodes: Vec<NodeID>,
     ) {
-        tracing::debug!("verify data meta bf write data");
-        let kv_store_engine = self.view.kv_store_engine();
-
-        // Step1: verify version
-        // take old meta
-        #[allow(unused_assignments)]
-        let mut required_meta: Option<(usize, DataSetMetaV2)> = None;
-        {
-            let keybytes: Vec<u8> = KeyTypeDataSetMeta(&req.unique_id).make_key();
-            let fail_by_overwrite = || async {
-                let message = "New data version overwrite".to_owned();
-                tracing::warn!("{}", message);
-                
-                if let Err(e) = responsor                    //返回结果未处理  曾俊
-                    .send_resp(WriteOneDataResponse {
-                        remote_version: 0,
-                        success: false,
-                        message,
-                    })
-                    .await{
-                        tracing::error!("Failed to send write one data response 1: {}", e);
-                    }
-                // .todo_handle("1 err_comment waitting to fill");
+        for node in nodes {
+            let view = self.view.clone();
+            let unique_id = unique_id.to_vec();
+            let version = meta.version;
+            let compression = meta.compression;
+            let item = item.clone();
+            let _ = tokio::spawn(async move {
+                let res = view
+                    .data_general()
+                    .write_item_to_node(&unique_id, version, idx, item, node, compression)
+                    .await;
+                match res {
+                    Ok(()) => tracing::info!(
+                        "repaired data({:?}) item {} on node {}",
+                        unique_id,
+                        idx,
+                        node
+                    ),
+                    Err(err) => tracing::warn!(
+                        "repair data({:?}) item {} on node {} failed: {:?}",
+                        unique_id,
+                        idx,
+                        node,
+                        err
+                    ),
+                }
+            });
+        }
+    }
 
+    /// Check the items stored on this node against the checksums of their local metas,
+    ///  works without other nodes. Returns the corrupted items.
+    pub async fn verify_local_items(&self) -> Vec<(Vec<u8>, DataItemIdx, WSError)> {
+        let kv_store_engine = self.view.kv_store_engine();
+        let mut corrupted = vec![];
+        for uid in kv_store_engine.data_set_meta_uids() {
+            let Some((_, meta)) =
+                kv_store_engine.get(&KeyTypeDataSetMeta(&uid), false, KvAdditionalConf {})
+            else {
+                continue;
             };
-            let fail_with_msg = |message: String| async {
-                tracing::warn!("{}", message);
-                if let Err(e) = responsor                    //返回结果未处理  曾俊
-                    .send_resp(WriteOneDataResponse {
-                        remote_version: 0,
-                        success: false,
-                        message,
-                    })
-                    .await {
-                        tracing::error!("Failed to send write one data response 2 : {}", e);
-                    }
-                    // .todo_handle("2 err_comment waitting to fill");
-            };
-
-            loop {
-                // tracing::debug!("verify version loop");
-                let lock =
-                    kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(&req.unique_id).make_key());
-                let guard = KeyLockGuard::Read(lock.read());
-                required_meta = kv_store_engine.get(
-                    &KeyTypeDataSetMeta(&req.unique_id),
-                    true,
-                    KvAdditionalConf {},
-                ); //tofix, master send maybe not synced
-                let old_dataset_version = if required_meta.is_none() {
-                    0
-                } else {
-                    required_meta.as_ref().unwrap().1.version
+            for idx in 0..meta.data_item_cnt() {
+                let idx = idx as DataItemIdx;
+                // a shard has no checksum of its own, it's checked when decoded
+                if meta.cache_mode_visitor(idx).erasure_parity_shards().is_some() {
+                    continue;
+                }
+                let Some(item) = self.get_item_local(&uid, meta.version, idx).await else {
+                    continue;
                 };
-                // need to wait for new version
-                if required_meta.is_none()
-                    || required_meta.as_ref().unwrap().1.version < req.version
-                {
-                    if required_meta.is_none() {
-                        tracing::debug!("no data version, waiting for notify");
-                    } else {
-                        tracing::debug!(
-                            "data version is old({}) at node({}), waiting for new notify({})",
-                            required_meta.as_ref().unwrap().1.version,
-                            self.view.p2p().nodes_config.this_node(),
-                            req.version
-                        );
-                    }
-
-                    let (kv_version, new_value) = kv_store_engine
-                        .register_waiter_for_new(&keybytes, guard)
-                        .await
-                        .unwrap_or_else(|err| {
-                            panic!("fail to wait for new data version: {:?}", err);
-                        });
-
-                    let Some(new_value) = new_value.as_raw_data() else {
-                        fail_with_msg(format!(
-                            "fatal error, kv value supposed to be DataSetMeta, rathe than {:?}",
-                            new_value
-                        ))
-                        .await;
-                        return;
-                    };
-
-                    // deserialize
-                    let new_value = bincode::deserialize::<DataSetMeta>(&new_value);
-                    if let Err(err) = new_value {
-                        fail_with_msg(format!(
-                            "fatal error, kv value deserialization failed: {}",
-                            err
-                        ))
-                        .await;
-                        return;
-                    }
-                    let new_value = new_value.unwrap();
-
-                    // version check
-                    if new_value.version > req.version {
-                        fail_by_overwrite().await;
-                        return;
-                    } else if new_value.version < req.version {
-                        tracing::debug!("recv data version({}) is old than required({}), waiting for new notify",new_value.version, req.version);
-                        // still need to wait for new version
-                        continue;
-                    } else {
-                        required_meta = Some((kv_version, new_value));
-                        break;
-                    }
-                } else if old_dataset_version > req.version {
-                    drop(guard);
-                    fail_by_overwrite().await;
-                    return;
-                } else {
-                    tracing::debug!(
-                        "data version is matched cur({}) require({}) // 0 should be invalid",
-                        old_dataset_version,
-                        req.version
-                    );
-                    break;
+                if let Err(err) = meta.verify_item(&uid, idx, &item) {
+                    corrupted.push((uid.clone(), idx, err));
                 }
             }
         }
+        tracing::info!("verified local items, {} corrupted", corrupted.len());
+        corrupted
+    }
 
-        // Step3: write data
-        tracing::debug!("start to write partial data");
-        let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(&req.unique_id).make_key());
-        let guard = KeyLockGuard::Write(lock.write());
-        let check_meta = kv_store_engine.get(
-            &KeyTypeDataSetMeta(&req.unique_id),
-            true,
-            KvAdditionalConf {},
-        ); //tofix, master send maybe not synced
-        if check_meta.is_none()
-            || check_meta.as_ref().unwrap().0 != required_meta.as_ref().unwrap().0
-        {
-            drop(guard);
-            if let Err(e) = responsor                    //返回结果未处理  曾俊
-                .send_resp(WriteOneDataResponse {
-                    remote_version: if check_meta.is_none() {
-                        0
-                    } else {
-                        check_meta.as_ref().unwrap().1.version
-                    },
-                    success: false,
-                    message: "meta is updated again, cancel write".to_owned(),
-                })
-                .await{
-                    tracing::error!("Failed to send write one data response 3: {}", e);
-                }
-            // .todo_handle("3 err_comment waitting to fill");
-            return;
-        }
-
-        for data_with_idx in req.data.into_iter() {
-            let proto::DataItemWithIdx { idx, data } = data_with_idx;
-            let data = data.unwrap();
-            let data_source = data.to_data_item_source();
-            let data = Arc::new(data_source);
-            let serialize = data.as_ref().encode_persist();
-            tracing::debug!(
-                "writing data part uid({:?}) idx({}) item({})",
-                req.unique_id,
-                idx,
-                data.to_debug_string()
-            );
-            if let Err(err) = kv_store_engine.set(
-                KeyTypeDataSetItem {
-                    uid: req.unique_id.as_ref(),
-                    idx: idx as u8,
+    /// Write one whole item to a node that already has the dataset meta of `version`
+    pub async fn write_item_to_node(
+        &self,
+        unique_id: &[u8],
+        version: u64,
+        idx: DataItemIdx,
+        mut item: proto::DataItem,
+        node: NodeID,
+        compression: DataCompression,
+    ) -> WSResult<()> {
+        let checksum = item_checksum(&item).unwrap_or_default();
+        let compression = compress_item(compression, &mut item);
+        let resp = self
+            .rpc_call_write_once_data
+            .call(
+                self.view.p2p(),
+                node,
+                proto::WriteOneDataRequest {
+                    unique_id: unique_id.to_vec(),
+                    version,
+                    data: vec![proto::DataItemWithIdx {
+                        idx,
+                        checksum,
+                        data: Some(item),
+                        compression: proto::DataCompression::from(compression) as i32,
+                    }],
                 },
-                &serialize,
-                true,
-            ) {
-                tracing::warn!("flush error: {}", err)
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
+        if !resp.success {
+            return Err(WsDataError::WriteDataFailed {
+                unique_id: unique_id.to_vec(),
+                message: resp.message,
             }
+            .into());
         }
-        kv_store_engine.flush();
-        drop(guard);
-        tracing::debug!("data partial is written");
-        if let Err(e) = responsor               //返回结果未使用  曾俊
-            .send_resp(WriteOneDataResponse {
-                remote_version: req.version,
-                success: true,
-                message: "".to_owned(),
-            })
-            .await{
-                tracing::error!("Failed to send write one data response 4: {}", e);
-            }
-        // .todo_handle("4 err_comment waitting to fill");
+        Ok(())
     }
 
-    async fn rpc_handle_data_meta_update(
+    pub async fn get_or_del_data(
         &self,
-        responsor: RPCResponsor<proto::DataMetaUpdateRequest>,
-        mut req: proto::DataMetaUpdateRequest,
-    ) {
-        struct Defer {
-            node: NodeID,
-        }
-        impl Drop for Defer {
-            fn drop(&mut self) {
-                tracing::debug!("rpc_handle_data_meta_update return at node({})", self.node);
+        GetOrDelDataArg {
+            meta,
+            unique_id,
+            ty,
+        }: GetOrDelDataArg,
+    ) -> WSResult<(DataSetMetaV2, HashMap<DataItemIdx, proto::DataItem>)> {
+        tracing::debug!("get_or_del_data uid: {:?}, maybe with meta: {:?}", unique_id, meta);
+
+        let (meta, from_cache) = match (meta, &ty) {
+            (Some(meta), _) => (meta, false),
+            // delete always follows the master
+            (None, GetOrDelDataArgType::Delete) => {
+                self.invalidate_meta_cache(&unique_id);
+                (
+                    self.get_or_del_datameta_from_master(&unique_id, false, 0)
+                        .await?,
+                    false,
+                )
             }
-        }
-        let _defer = Defer {
-            node: self.view.p2p().nodes_config.this_node(),
+            (None, _) => self.get_datameta_cached(&unique_id).await?,
         };
 
-        let key = KeyTypeDataSetMeta(&req.unique_id);
-        let keybytes = key.make_key();
-        
-        // test only log
-        #[cfg(test)]
-        tracing::debug!("rpc_handle_data_meta_update {:?}\n    {:?}", req,bincode::deserialize::<DataSetMeta>(&req.serialized_meta));
-        // not test log
-        #[cfg(not(test))]
-        tracing::debug!("rpc_handle_data_meta_update {:?}", req);
-
-        let kv_lock = self.view.kv_store_engine().with_rwlock(&keybytes);
-        let _kv_write_lock_guard = kv_lock.write();
-
-        if let Some((_old_version, mut old_meta)) =
-            self.view.kv_store_engine().get(&key, true, KvAdditionalConf {})
+        match self
+            .get_or_del_data_with_meta(meta, &unique_id, ty.clone(), 0, None)
+            .await
         {
-            if old_meta.version > req.version {
-                drop(_kv_write_lock_guard);
-                let err_msg = "New data version is smaller, failed update";
-                tracing::warn!("{}", err_msg);
-                if let Err(e) = responsor                //返回结果未处理  曾俊
-                    .send_resp(proto::DataMetaUpdateResponse {
-                        version: old_meta.version,
-                        message: err_msg.to_owned(),
-                    })
-                    .await{
-                        tracing::error!("Failed to send data meta update response 5: {}", e);
-                    }
-                // .todo_handle("5 err_comment waitting to fill");
-                return;
-            }
-            old_meta.version = req.version;
-            if req.serialized_meta.len() > 0 {
-                if let Err(e) = self.view.kv_store_engine()                  //返回结果未处理  曾俊
-                    .set_raw(&keybytes, std::mem::take(&mut req.serialized_meta), true){
-                        tracing::error!("Failed to set raw data in kv store 6: {}", e);
-                    }
-                // .todo_handle("6 err_comment waitting to fill");
-            } else {
-                if let Err(e) = self.view.kv_store_engine()                  //返回结果未处理  曾俊
-                    .set(key, &old_meta, true){
-                        tracing::error!("Failed to set raw data in kv store 7: {}", e);
-                    }
-                // .todo_handle("7 err_comment waitting to fill");
-            }
-        } else {
-            if req.serialized_meta.len() > 0 {
-                tracing::debug!(
-                    "set new meta data, {:?}",
-                    bincode::deserialize::<DataSetMeta>(&req.serialized_meta)
-                );
-                if let Err(e) = self.view.kv_store_engine()                       //返回结果未处理  曾俊      
-                    .set_raw(&keybytes, std::mem::take(&mut req.serialized_meta), true){
-                        tracing::error!("Failed to set raw data in kv store 8: {}", e);
-                    }
-                // .todo_handle("8 err_comment waitting to fill");
-            } else {
-                drop(_kv_write_lock_guard);
-                let err_msg = "Old meta data not found and missing new meta";
-                tracing::warn!("{}", err_msg);
-                if let Err(e) = responsor                    //返回结果未处理  曾俊
-                    .send_resp(proto::DataMetaUpdateResponse {
-                        version: 0,
-                        message: err_msg.to_owned(),
-                    })
-                    .await{
-                        tracing::error!("Failed to send data meta update response 9: {}", e);
-                    }
-                // .todo_handle("9 err_comment waitting to fill");
-                return;
+            Err(err) if from_cache => {
+                // the cached meta may point to a gone version, retry with the master one
+                tracing::debug!("read with cached meta failed: {:?}, retry", err);
+                self.invalidate_meta_cache(&unique_id);
+                let (meta, _) = self.get_datameta_cached(&unique_id).await?;
+                self.get_or_del_data_with_meta(meta, &unique_id, ty, 0, None)
+                    .await
             }
+            res => res,
         }
-        drop(_kv_write_lock_guard);
-        tracing::debug!("rpc_handle_data_meta_update success");
-        if let Err(e) = responsor                          //返回结果未处理  曾俊
-            .send_resp(proto::DataMetaUpdateResponse {
-                version: req.version,
-                message: "Update success".to_owned(),
-            })
-            .await{
-                tracing::error!("Failed to send data meta update response 10: {}", e);
-            }
-        // .todo_handle("10 err_comment waitting to fill");
     }
 
-    async fn rpc_handle_get_data_meta(
+    /// Delete made for a function, the audit log records it as the function's
+    pub async fn delete_data_for(
         &self,
-        req: proto::DataMetaGetRequest,
-        responsor: RPCResponsor<proto::DataMetaGetRequest>,
-    ) -> WSResult<()> {
-        tracing::debug!("rpc_handle_get_data_meta with req({:?})", req);
-        let meta = self.view.get_data_meta_local(&req.unique_id, req.delete)?;
-        if meta.is_none() {
-            tracing::debug!("rpc_handle_get_data_meta data meta not found");
-        } else {
-            tracing::debug!("rpc_handle_get_data_meta data meta found");
-        }
-        let serialized_meta = meta.map_or(vec![], |(_kvversion, meta)| {
-            bincode::serialize(&meta).unwrap()
-        });
-
-        responsor
-            .send_resp(proto::DataMetaGetResponse { serialized_meta })
+        unique_id: impl Into<Vec<u8>>,
+        deleted_by: proto::DataOpeRoleFuncCall,
+    ) -> WSResult<(DataSetMetaV2, HashMap<DataItemIdx, proto::DataItem>)> {
+        let unique_id = unique_id.into();
+        self.invalidate_meta_cache(&unique_id);
+        let meta = self
+            .get_or_del_datameta_from_master(&unique_id, false, 0)
             .await?;
-
-        Ok(())
+        self.get_or_del_data_with_meta(
+            meta,
+            &unique_id,
+            GetOrDelDataArgType::Delete,
+            0,
+            Some(&deleted_by),
+        )
+        .await
     }
 
-    async fn rpc_handle_get_one_data(
+    /// Delete of a kv transaction, done only when the dataset is still at `expected_version`.
+    ///  The dataset is reserved by the transaction, so its version can't move after the check.
+    pub async fn delete_data_in_txn(
         &self,
-        responsor: RPCResponsor<proto::GetOneDataRequest>,
-        req: proto::GetOneDataRequest,
-    ) -> WSResult<()> {
-        tracing::debug!("starting rpc_handle_get_one_data {:?}", req);
-
-        let kv_store_engine = self.view.kv_store_engine();
-        let _ = self.view
-            .get_metadata(&req.unique_id, req.delete)
+        unique_id: &[u8],
+        txn_id: u64,
+        expected_version: DataVersion,
+        deleted_by: &proto::DataOpeRoleFuncCall,
+    ) -> WSResult<CondWriteRes> {
+        self.invalidate_meta_cache(unique_id);
+        let meta = match self
+            .get_or_del_datameta_from_master(unique_id, false, txn_id)
             .await
-            .map_err(|err| {
-                tracing::warn!("rpc_handle_get_one_data get_metadata failed: {:?}", err);
-                err
-            })?;
+        {
+            Ok(meta) => meta,
+            Err(WSError::WsDataError(WsDataError::DataSetNotFound { .. })) => {
+                return Ok(if expected_version == 0 {
+                    CondWriteRes::Written(0)
+                } else {
+                    CondWriteRes::Rejected(0)
+                });
+            }
+            Err(err) => return Err(err),
+        };
+        if meta.version != expected_version {
+            return Ok(CondWriteRes::Rejected(meta.version));
+        }
+        let _ = self
+            .get_or_del_data_with_meta(
+                meta,
+                unique_id,
+                GetOrDelDataArgType::Delete,
+                txn_id,
+                Some(deleted_by),
+            )
+            .await?;
+        Ok(CondWriteRes::Written(0))
+    }
 
-        let mut got_or_deleted = vec![];
-        let mut kv_ope_err = vec![];
+    /// `txn_id` is of the kv transaction deleting the dataset, 0 means not in transaction,
+    ///  `deleted_by` is the function a delete is made for
+    async fn get_or_del_data_with_meta(
+        &self,
+        meta: DataSetMetaV2,
+        unique_id: &[u8],
+        ty: GetOrDelDataArgType,
+        txn_id: u64,
+        deleted_by: Option<&proto::DataOpeRoleFuncCall>,
+    ) -> WSResult<(DataSetMetaV2, HashMap<DataItemIdx, proto::DataItem>)> {
+        let mut data_map = HashMap::new();
 
-        for idx in req.idxs {
-            let value = if req.delete {
-                match kv_store_engine.del(
-                    KeyTypeDataSetItem {
-                        uid: req.unique_id.as_ref(),
-                        idx: idx as u8,
-                    },
-                    false,
-                ) {
-                    Ok(value) => value,
-                    Err(e) => {
-                        kv_ope_err.push(e);
-                        None
+        tracing::debug!("start get_or_del_data uid: {:?},meta: {:?}", unique_id, meta);
+
+        // basical verify
+        for idx in 0..meta.data_item_cnt() {
+            let idx = idx as DataItemIdx;
+            let check_cache_map = |meta: &DataSetMetaV2| -> WSResult<()> {
+                if !meta.cache_mode_visitor(idx).is_map_common_kv()
+                    && !meta.cache_mode_visitor(idx).is_map_file()
+                {
+                    return Err(WsDataError::UnknownCacheMapMode {
+                        mode: meta.cache_mode_visitor(idx).0,
                     }
+                    .into());
                 }
-            } else {
-                kv_store_engine.get(
-                    &KeyTypeDataSetItem {
-                        uid: req.unique_id.as_ref(),
-                        idx: idx as u8,
-                    },
-                    false,
-                    KvAdditionalConf {},
-                )
+                Ok(())
             };
-            got_or_deleted.push(value);
+            check_cache_map(&meta)?;
         }
 
-        let (mut success, mut message): (bool, String) = if kv_ope_err.len() > 0 {
-            (false, {
-                let mut msg = String::from("KvEngine operation failed: ");
-                for e in kv_ope_err.iter() {
-                    msg.push_str(&format!("{:?}", e));
-                }
-                msg
-            })
-        } else if got_or_deleted.iter().all(|v| v.is_some()) {
-            (true, "success".to_owned())
-        } else {
-            tracing::warn!("some data not found");
-            (false, "some data not found".to_owned())
-        };
+        // get data
+        let p2p = self.view.p2p();
 
-        let mut got_or_deleted_checked: Vec<proto::DataItem> = vec![];
-        if success {
-            for v in got_or_deleted {
-                let decode_res = proto::DataItem::decode_persist(v.unwrap().1);
-                match decode_res {
-                    Ok(item) => {
-                        tracing::debug!("decoded data item: {:?}", item.to_string());
-                        got_or_deleted_checked.push(item);
+        match ty {
+            GetOrDelDataArgType::All => {
+                for idx in 0..meta.data_item_cnt() {
+                    let idx = idx as DataItemIdx;
+                    let item = self.get_one_item(&meta, unique_id, idx).await?;
+                    let _ = data_map.insert(idx, item);
+                }
+            }
+            GetOrDelDataArgType::Delete => {
+                for idx in 0..meta.data_item_cnt() {
+                    let idx = idx as DataItemIdx;
+                    // the primary only has a shard, the deleted item is decoded before
+                    let decoded = if meta.cache_mode_visitor(idx).erasure_parity_shards().is_some() {
+                        match self.get_one_item(&meta, unique_id, idx).await {
+                            Ok(item) => Some(item),
+                            Err(err) => {
+                                tracing::warn!("read erasure coded item bf delete failed: {:?}", err);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    let resp = self
+                        .rpc_call_get_data
+                        .call(
+                            p2p,
+                            meta.get_data_node(idx),
+                            proto::GetOneDataRequest {
+                                unique_id: unique_id.to_vec(),
+                                idxs: vec![idx],
+                                delete: true,
+                                return_data: true,
+                                offset: 0,
+                                length: 0,
+                                txn_id,
+                            },
+                            Some(Duration::from_secs(60)),
+                        )
+                        .await?;
+
+                    if !resp.success {
+                        return Err(WsDataError::GetDataFailed {
+                            unique_id: unique_id.to_vec(),
+                            msg: resp.message,
+                    }
+                    .into());
+                }
+
+                    if meta.cache_mode_visitor(idx).erasure_parity_shards().is_none() {
+                        let _ = data_map.insert(idx, resp.data[0].clone());
+                    } else if let Some(item) = decoded {
+                        let _ = data_map.insert(idx, item);
+                    }
+                }
+
+                // data nodes only drop their local meta copy, the master one must go too
+                match self
+                    .datameta_from_master(unique_id, true, txn_id, deleted_by)
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(WSError::WsDataError(WsDataError::DataSetNotFound { .. })) => {}
+                    Err(err) => return Err(err),
+                }
+                self.invalidate_meta_cache(unique_id);
+            }
+            GetOrDelDataArgType::PartialOne { idx } => {
+                let item = self.get_one_item(&meta, unique_id, idx).await?;
+                let _ = data_map.insert(idx, item);
+            }
+            GetOrDelDataArgType::PartialMany { idxs } => {
+                for idx in idxs {
+                    let item = self.get_one_item(&meta, unique_id, idx).await?;
+                    let _ = data_map.insert(idx, item);
+                }
+            }
+        }
+
+        Ok((meta, data_map))
+    }
+
+    /// Get all items at the latest dataset version, unlike `get_or_del_data`,
+    ///  this waits for the write whose version is scheduled but whose data hasn't arrived.
+    ///
+    /// Return `None` if the dataset doesn't exist
+    pub async fn get_data_latest(
+        &self,
+        unique_id: &[u8],
+    ) -> WSResult<Option<(DataSetMetaV2, HashMap<DataItemIdx, proto::DataItem>)>> {
+        let p2p = self.view.p2p();
+        for _ in 0..LATEST_READ_RETRY {
+            let meta = match self
+                .get_or_del_datameta_from_master(unique_id, false, 0)
+                .await
+            {
+                Ok(meta) => meta,
+                Err(WSError::WsDataError(WsDataError::DataSetNotFound { .. })) => return Ok(None),
+                Err(err) => return Err(err),
+            };
+
+            let mut data_map = HashMap::new();
+            let mut stale = false;
+            for idx in 0..meta.data_item_cnt() {
+                let idx = idx as DataItemIdx;
+                if meta.cache_mode_visitor(idx).erasure_parity_shards().is_some() {
+                    // shards lagging behind the version fail the decode
+                    match self.get_one_item(&meta, unique_id, idx).await {
+                        Ok(item) => {
+                            let _ = data_map.insert(idx, item);
+                            continue;
+                        }
+                        Err(err) => {
+                            tracing::debug!("read erasure coded item failed: {:?}", err);
+                            stale = true;
+                            break;
+                        }
+                    }
+                }
+                let resp = self
+                    .rpc_call_get_data
+                    .call(
+                        p2p,
+                        meta.get_data_node(idx),
+                        proto::GetOneDataRequest {
+                            unique_id: unique_id.to_vec(),
+                            idxs: vec![idx],
+                            delete: false,
+                            return_data: true,
+                            offset: 0,
+                            length: 0,
+                            txn_id: 0,
+                        },
+                        Some(Duration::from_secs(60)),
+                    )
+                    .await?;
+                if resp.version < meta.version {
+                    stale = true;
+                    break;
+                }
+                if !resp.success {
+                    return Err(WsDataError::GetDataFailed {
+                        unique_id: unique_id.to_vec(),
+                        msg: resp.message,
+                    }
+                    .into());
+                }
+                let _ = data_map.insert(idx, resp.data[0].clone());
+            }
+            if !stale {
+                return Ok(Some((meta, data_map)));
+            }
+            tracing::debug!(
+                "data of version {} not arrived for uid({:?}), retry",
+                meta.version,
+                unique_id
+            );
+            tokio::time::sleep(LATEST_READ_RETRY_INTERVAL).await;
+        }
+        Err(WsDataError::GetDataFailed {
+            unique_id: unique_id.to_vec(),
+            msg: "data of the latest version not arrived".to_owned(),
+        }
+        .into())
+    }
+
+    pub async fn write_data(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        datas: Vec<DataItemArgWrapper>,
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+    ) -> WSResult<()> {
+        self.write_data_cond(unique_id, datas, context_openode_opetype_operole, None)
+            .await
+            .map(|_| ())
+    }
+
+    /// Write data only when `condition` is met, the condition is checked by master
+    ///  under the version schedule, so concurrent conditional writes are linearizable.
+    pub async fn write_data_cond(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        datas: Vec<DataItemArgWrapper>,
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+        condition: Option<proto::data_write_condition::Cond>,
+    ) -> WSResult<CondWriteRes> {
+        self.write_data_with(
+            unique_id,
+            datas,
+            context_openode_opetype_operole,
+            WriteDataOpts {
+                condition,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    /// Write with the extra scheduling options, see [`WriteDataOpts`]
+    pub async fn write_data_with(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        mut datas: Vec<DataItemArgWrapper>,
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+        opts: WriteDataOpts,
+    ) -> WSResult<CondWriteRes> {
+        let unique_id = unique_id.into();
+        let log_tag = format!("[write_data({})]", String::from_utf8_lossy(&unique_id));
+        tracing::debug!("{} start write data", log_tag);
+        
+        let mut data_transfer_sizes=Vec::new();
+        data_transfer_sizes.reserve(datas.len());
+        let mut data_checksums = Vec::with_capacity(datas.len());
+        for d in datas.iter_mut(){
+            data_transfer_sizes.push(d.transfer_size().await.map_err(|err|{
+                tracing::error!("{} transfer size error: {}", log_tag, err);
+                err
+            })?);
+            data_checksums.push(d.checksum().await.map_err(|err| {
+                tracing::error!("{} checksum error: {}", log_tag, err);
+                err
+            })?);
+        }
+        // 获取数据调度计划
+        let version_schedule_resp = self
+            .schedule_write_version(
+                &unique_id,
+                context_openode_opetype_operole,
+                // 原代码类型不匹配       曾俊
+                data_transfer_sizes.iter().map(|&x| x as u64).collect(),
+                data_checksums,
+                opts,
+                vec![],
+            )
+            .await?;
+        if version_schedule_resp.condition_failed {
+            tracing::debug!(
+                "{} write condition not met, current version {}",
+                log_tag,
+                version_schedule_resp.version
+            );
+            return Ok(CondWriteRes::Rejected(version_schedule_resp.version));
+        }
+        let version = self
+            .write_scheduled(unique_id, datas, version_schedule_resp, &log_tag)
+            .await?;
+        Ok(CondWriteRes::Written(version))
+    }
+
+    /// Add `delta` to the decimal integer of a single item dataset, missing counts as 0.
+    ///  Master computes the new value under the version schedule, so concurrent incrs all apply.
+    ///  Gives the new version and value, or the current version if it isn't an integer
+    pub async fn incr_data(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        delta: i64,
+        context_openode_opetype_operole: (
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        ),
+    ) -> WSResult<Result<(DataVersion, Vec<u8>), DataVersion>> {
+        let unique_id = unique_id.into();
+        let log_tag = format!("[incr_data({})]", String::from_utf8_lossy(&unique_id));
+        // the size and checksum are of the value master computes
+        let version_schedule_resp = self
+            .schedule_write_version(
+                &unique_id,
+                Some(context_openode_opetype_operole),
+                vec![0],
+                vec![vec![]],
+                WriteDataOpts {
+                    condition: Some(proto::data_write_condition::Cond::Incr(delta)),
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .await?;
+        if version_schedule_resp.condition_failed {
+            tracing::debug!(
+                "{} current value is not an integer, version {}",
+                log_tag,
+                version_schedule_resp.version
+            );
+            return Ok(Err(version_schedule_resp.version));
+        }
+        let value = version_schedule_resp.incr_value.clone();
+        let version = self
+            .write_scheduled(
+                unique_id,
+                vec![DataItemArgWrapper::new(value.clone())],
+                version_schedule_resp,
+                &log_tag,
+            )
+            .await?;
+        Ok(Ok((version, value)))
+    }
+
+    /// Send the items of a write to the nodes master planned for them
+    async fn write_scheduled(
+        &self,
+        unique_id: Vec<u8>,
+        mut datas: Vec<DataItemArgWrapper>,
+        version_schedule_resp: proto::DataVersionScheduleResponse,
+        log_tag: &str,
+    ) -> WSResult<DataVersion> {
+        // Clone the response to extend its lifetime
+        let version = version_schedule_resp.version;
+        let compression: DataCompression = version_schedule_resp.compression().into();
+        let splits = version_schedule_resp.split.clone();
+
+        // 处理每个数据项
+        let mut iter = WantIdxIter::new(&GetOrDelDataArgType::All, datas.len() as DataItemIdx);
+        while let Some(data_item_idx) = iter.next() {
+            let data_item: &DataItemArgWrapper = &mut datas[data_item_idx as usize];
+            let split = &splits[data_item_idx as usize];
+            let mode = CacheModeVisitor(version_schedule_resp.cache_mode[data_item_idx as usize] as u16);
+            if let Some(parity_shards) = mode.erasure_parity_shards() {
+                self.write_erasure_shards(
+                    &unique_id,
+                    version,
+                    data_item_idx,
+                    data_item.clone(),
+                    split,
+                    parity_shards,
+                    compression,
+                )
+                .await?;
+                continue;
+            }
+            let mut primary_tasks = Vec::new();
+            
+            // 1. 并行写入所有主数据分片
+            let mut split_iter = WantIdxIter::new(&GetOrDelDataArgType::All, split.splits.len() as DataItemIdx);
+            while let Some(split_idx) = split_iter.next() {
+                let split_info = &split.splits[split_idx as usize];
+                tracing::debug!("{} creating split write task {}/{} for node {}, offset={}, size={}",
+                    log_tag, split_idx + 1, split.splits.len(), split_info.node_id, split_info.data_offset, split_info.data_size);
+                let split_info = split_info.clone();
+                let unique_id_clone = unique_id.clone();
+                // let data_item_primary = data_item.clone_split_range(split_info.data_offset..split_info.data_offset+split_info.data_size);        类型不匹配     曾俊
+                // 生成一个复制的可变数据项
+                let mut data_item_clone = (*data_item).clone();
+                let mut data_item_primary = data_item_clone.clone_split_range(split_info.data_offset as usize..(split_info.data_offset+split_info.data_size)as usize).await.todo_handle("clone_split_range for write data err")?;
+                // let data_item_primary = data_item.clone_split_range(split_info.data_offset as usize..(split_info.data_offset+split_info.data_size)as usize).await.todo_handle("clone_split_range for write data err")?;
+                let checksum = item_checksum(&data_item_primary).unwrap_or_default();
+                let item_compression = compress_item(compression, &mut data_item_primary);
+                let view = self.view.clone();
+                let version_copy = version;
+                let task = tokio::spawn(async move {
+                                        view.data_general()
+                        .rpc_call_write_once_data
+                        .call(
+                            view.p2p(),
+                            split_info.node_id,
+                            proto::WriteOneDataRequest {
+                                unique_id: unique_id_clone.clone(),
+                                version: version_copy,
+                                data: vec![proto::DataItemWithIdx {
+                                    idx: data_item_idx,
+                                    // data: Some(data_item_primary),           类型不匹配    曾俊
+                                    data: Some(data_item_primary),
+                                    checksum,
+                                    compression: proto::DataCompression::from(item_compression) as i32,
+                                }],
+                            },
+                            Some(Duration::from_secs(60)),
+                        )
+                        .await
+                });
+                primary_tasks.push(task);
+            }
+
+            // 2. 并行写入缓存数据（完整数据）
+            let visitor = CacheModeVisitor(version_schedule_resp.cache_mode[data_item_idx as usize] as u16);
+            let need_cache = visitor.is_map_common_kv() || visitor.is_map_file();
+            let cache_nodes: Vec<NodeID> = if need_cache {
+                split.splits.iter().map(|s| s.node_id).collect()
+            } else {
+                vec![]
+            };
+
+            let mut cache_tasks = Vec::new();
+            if !cache_nodes.is_empty() {
+                tracing::debug!("{} found {} cache nodes: {:?}", log_tag, cache_nodes.len(), cache_nodes);
+                const MAX_CONCURRENT_TRANSFERS: usize = 3;
+                let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_TRANSFERS));
+                
+                let mut cache_iter = WantIdxIter::new(&GetOrDelDataArgType::All, cache_nodes.len() as DataItemIdx);
+                while let Some(cache_idx) = cache_iter.next() {
+                    let node_id = cache_nodes[cache_idx as usize];
+                    let permit = semaphore.clone().acquire_owned().await.unwrap();
+                    tracing::debug!("{} creating cache write task {}/{} for node {}", log_tag, cache_idx + 1, cache_nodes.len(), node_id);
+                    let unique_id_clone = unique_id.clone();
+                    let data_item_cache = data_item.clone();
+                    let view = self.view.clone();
+                    let task = tokio::spawn(async move {
+                        let _permit = permit; // 持有permit直到任务完成
+                        view.data_general()
+                            // .write_data_batch(unique_id_clone.clone(), version, data_item_cache, data_item_idx, node_id)               //类型不匹配  曾俊
+                            .write_data_batch(unique_id_clone.clone(), version, data_item_cache.dataitem, data_item_idx, node_id, compression)
+                            .await?;
+                        Ok::<proto::WriteOneDataResponse, WSError>(proto::WriteOneDataResponse {
+                            remote_version: version,
+                            success: true,
+                            message: String::new(),
+                        })
+                    });
+                    cache_tasks.push(task);
+                }
+            }
+
+            let primary_results = futures::future::join_all(primary_tasks).await;
+            tracing::debug!("{} primary_results: {:?}", log_tag, primary_results);
+            let cache_results = futures::future::join_all(cache_tasks).await;
+            tracing::debug!("{} cache_results: {:?}", log_tag, cache_results);
+
+            if primary_results.iter().any(|res| res.is_err()) || cache_results.iter().any(|res| res.is_err()) {
+                let error_msg = format!("主节点或缓存节点数据写入失败");
+                tracing::error!("{}", error_msg);
+                return Err(WSError::WsDataError(WsDataError::WriteDataFailed {
+                    unique_id: unique_id.clone(),
+                    message: error_msg,
+                }));
+            }
+        }
+
+        Ok(version)
+    }
+
+    /// Ask master for the new version and the split plan of a write,
+    ///  `pinned_nodes` are the holders of streamed data, empty for a regular write
+    async fn schedule_write_version(
+        &self,
+        unique_id: &[u8],
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+        data_sizes: Vec<u64>,
+        data_checksums: Vec<Vec<u8>>,
+        opts: WriteDataOpts,
+        pinned_nodes: Vec<NodeID>,
+    ) -> WSResult<proto::DataVersionScheduleResponse> {
+        let WriteDataOpts {
+            condition,
+            txn_id,
+            ttl_ms,
+            compression,
+            cache_modes,
+        } = opts;
+        let resp = self
+            .rpc_call_data_version_schedule
+            .call(
+                self.view.p2p(),
+                self.view.p2p().nodes_config.get_master_node(),
+                proto::DataVersionScheduleRequest {
+                    unique_id: unique_id.to_vec(),
+                    context: context_openode_opetype_operole.map(|(node, ope, role)| {
+                        proto::DataScheduleContext {
+                            each_data_sz_bytes: data_sizes,
+                            ope_node: node as i64,
+                            ope_type: ope as i32,
+                            ope_role: Some(role),
+                            each_data_checksum: data_checksums,
+                        }
+                    }),
+                    version: 0,
+                    condition: condition.map(|cond| proto::DataWriteCondition { cond: Some(cond) }),
+                    txn_id,
+                    ttl_ms,
+                    compression: proto::DataCompression::from(compression) as i32,
+                    pinned_nodes,
+                    cache_modes: cache_modes.into_iter().map(|mode| mode as u32).collect(),
+                },
+                Some(Duration::from_secs(60)),
+            )
+            .await?;
+        if let Some(exceeded) = resp.quota_exceeded {
+            return Err(WsDataError::QuotaExceeded {
+                app: exceeded.app,
+                resource: exceeded.resource,
+                used: exceeded.used,
+                limit: exceeded.limit,
+            }
+            .into());
+        }
+        // the cached meta is outdated by this write, or by the one that rejected it
+        self.invalidate_meta_cache(unique_id);
+        Ok(resp)
+    }
+
+    /// Encode the item stripe by stripe and stream each shard to the node of its split.
+    ///  A stripe is `batch_transfer.block_size` bytes of every shard, so only one stripe of the
+    ///  item is in memory. The shards are staged like the segments of a streaming write.
+    async fn write_erasure_shards(
+        &self,
+        unique_id: &[u8],
+        version: DataVersion,
+        idx: DataItemIdx,
+        mut data_item: DataItemArgWrapper,
+        split: &proto::DataSplit,
+        parity_shards: usize,
+        compression: DataCompression,
+    ) -> WSResult<()> {
+        let sizes: Vec<u64> = split.splits.iter().map(|s| s.data_size).collect();
+        let layout = ErasureLayout::from_split_sizes(&sizes, parity_shards).ok_or_else(|| {
+            WsDataError::WriteDataFailed {
+                unique_id: unique_id.to_vec(),
+                message: format!("bad erasure coded split sizes {:?}", sizes),
+            }
+        })?;
+        // only the stream id is used, each shard goes to the node planned for it
+        let stream_id = self
+            .rpc_call_data_stream_open
+            .call(
+                self.view.p2p(),
+                self.view.p2p().nodes_config.get_master_node(),
+                proto::DataStreamOpenRequest {
+                    unique_id: unique_id.to_vec(),
+                    compression: proto::DataCompression::Raw as i32,
+                    item_cnt: 1,
+                },
+                Some(Duration::from_secs(30)),
+            )
+            .await?
+            .stream_id;
+        let res = async {
+            let conf = self.view.p2p().nodes_config.batch_transfer.clone();
+            let block_size = conf.block_size as u64;
+            let shard_len = erasure::shard_len(layout.item_size, layout.data_shards);
+            let mut hashers: Vec<Md5> = (0..layout.shard_cnt()).map(|_| Md5::new()).collect();
+            let mut offset = 0;
+            while offset < shard_len {
+                let stripe_len = block_size.min(shard_len - offset);
+                let mut blocks = Vec::with_capacity(layout.shard_cnt());
+                // a file is coded as its bytes, the shards are raw bytes
+                for s in &split.splits[..layout.data_shards] {
+                    let len = stripe_len.min(s.data_size.saturating_sub(offset));
+                    let start = (s.data_offset + offset) as usize;
+                    blocks.push(if len == 0 {
+                        vec![]
+                    } else {
+                        data_item.read_range(start..start + len as usize).await?
+                    });
+                }
+                let parity =
+                    erasure::encode_parity(&blocks, layout.parity_shards, stripe_len as usize);
+                blocks.extend(parity);
+
+                let mut sends = vec![];
+                for ((block, s), hasher) in blocks.into_iter().zip(&split.splits).zip(&mut hashers)
+                {
+                    if block.is_empty() {
+                        continue;
+                    }
+                    hasher.update(&block);
+                    let checksum = bytes_checksum(&block);
+                    let (data, block_compression) = match compress::compress(compression, &block) {
+                        Some(compressed) => (compressed, compression),
+                        None => (block, DataCompression::Raw),
+                    };
+                    sends.push(stream::send_segment(
+                        self.view.clone(),
+                        s.node_id,
+                        proto::DataStreamSegmentRequest {
+                            stream_id,
+                            unique_id: unique_id.to_vec(),
+                            offset,
+                            data,
+                            checksum,
+                            compression: proto::DataCompression::from(block_compression) as i32,
+                        },
+                        conf.block_retries,
+                    ));
+                }
+                for res in futures::future::join_all(sends).await {
+                    res?;
+                }
+                offset += stripe_len;
+            }
+
+            let commits = split.splits.iter().zip(hashers).map(|(s, hasher)| {
+                self.rpc_call_data_stream_commit.call(
+                    self.view.p2p(),
+                    s.node_id,
+                    proto::DataStreamCommitRequest {
+                        stream_id,
+                        unique_id: unique_id.to_vec(),
+                        version,
+                        total_size: s.data_size,
+                        item_checksum: hasher.finalize().to_vec(),
+                        abort: false,
+                        idx,
+                        inline: true,
+                        prepare: false,
+                    },
+                    Some(Duration::from_secs(60)),
+                )
+            });
+            for (s, res) in split
+                .splits
+                .iter()
+                .zip(futures::future::join_all(commits).await)
+            {
+                let res = res?;
+                if !res.success {
+                    return Err(WsDataError::WriteDataFailed {
+                        unique_id: unique_id.to_vec(),
+                        message: format!(
+                            "commit shard on node {} failed: {}",
+                            s.node_id, res.message
+                        ),
+                    }
+                    .into());
+                }
+            }
+            Ok::<(), WSError>(())
+        }
+        .await;
+        if res.is_err() {
+            let nodes = split.splits.iter().map(|s| s.node_id).collect();
+            stream::abort_stream(self.view.clone(), stream_id..stream_id + 1, nodes).await;
+        }
+        res
+    }
+
+    async fn rpc_handle_write_one_data(
+        &self,
+        responsor: RPCResponsor<proto::WriteOneDataRequest>,
+        mut req: proto::WriteOneDataRequest,
+    ) {
+        for data_with_idx in req.data.iter_mut() {
+            let compression = data_with_idx.compression().into();
+            let Some(item) = data_with_idx.data.as_mut() else {
+                continue;
+            };
+            if let Err(err) = decompress_item(compression, item) {
+                tracing::warn!("reject write: {:?}", err);
+                if let Err(e) = responsor
+                    .send_resp(WriteOneDataResponse {
+                        remote_version: 0,
+                        success: false,
+                        message: format!("{:?}", err),
+                    })
+                    .await
+                {
+                    tracing::error!("Failed to send write one data response: {}", e);
+                }
+                return;
+            }
+        }
+        // corrupted in transfer, the writer retries or fails the write
+        for data_with_idx in req.data.iter() {
+            let Some(actual) = data_with_idx.data.as_ref().and_then(item_checksum) else {
+                continue;
+            };
+            if !data_with_idx.checksum.is_empty() && data_with_idx.checksum != actual {
+                let err = WsDataError::ChecksumMismatch {
+                    unique_id: req.unique_id.clone(),
+                    idx: data_with_idx.idx,
+                    expected: data_with_idx.checksum.clone(),
+                    actual,
+                };
+                tracing::warn!("reject write: {:?}", err);
+                if let Err(e) = responsor
+                    .send_resp(WriteOneDataResponse {
+                        remote_version: 0,
+                        success: false,
+                        message: format!("{:?}", err),
+                    })
+                    .await
+                {
+                    tracing::error!("Failed to send write one data response: {}", e);
+                }
+                return;
+            }
+        }
+
+        let items = req
+            .data
+            .into_iter()
+            .map(|data_with_idx| {
+                let proto::DataItemWithIdx { idx, data, .. } = data_with_idx;
+                (idx, data.unwrap().to_data_item_source())
+            })
+            .collect();
+        let resp = self
+            .store_items_of_version(&req.unique_id, req.version, items)
+            .await;
+        if let Err(e) = responsor.send_resp(resp).await {
+            //返回结果未使用  曾俊
+            tracing::error!("Failed to send write one data response: {}", e);
+        }
+    }
+
+    /// Store items of a dataset version once its meta arrives from master,
+    ///  refused when a newer version overwrote it in the meantime
+    async fn store_items_of_version(
+        &self,
+        unique_id: &[u8],
+        version: DataVersion,
+        items: Vec<(DataItemIdx, DataItemSource)>,
+    ) -> WriteOneDataResponse {
+        self.store_items_with_mapped(unique_id, version, items, HashMap::new())
+            .await
+    }
+
+    /// [`Self::store_items_of_version`] with the files of map file items already in place,
+    ///  the files are removed if the items are refused
+    async fn store_items_with_mapped(
+        &self,
+        unique_id: &[u8],
+        version: DataVersion,
+        items: Vec<(DataItemIdx, DataItemSource)>,
+        mut mapped: HashMap<DataItemIdx, PathBuf>,
+    ) -> WriteOneDataResponse {
+        let failed = |remote_version: DataVersion, message: String| {
+            tracing::warn!("{}", message);
+            WriteOneDataResponse {
+                remote_version,
+                success: false,
+                message,
+            }
+        };
+        tracing::debug!("verify data meta bf write data");
+        let kv_store_engine = self.view.kv_store_engine();
+
+        // Step1: verify version
+        // take old meta
+        #[allow(unused_assignments)]
+        let mut required_meta: Option<(usize, DataSetMetaV2)> = None;
+        {
+            let keybytes: Vec<u8> = KeyTypeDataSetMeta(unique_id).make_key();
+            loop {
+                // tracing::debug!("verify version loop");
+                let lock = kv_store_engine.with_rwlock(&keybytes);
+                let guard = KeyLockGuard::Read(lock.read());
+                required_meta = kv_store_engine.get(
+                    &KeyTypeDataSetMeta(unique_id),
+                    true,
+                    KvAdditionalConf {},
+                ); //tofix, master send maybe not synced
+                let old_dataset_version = if required_meta.is_none() {
+                    0
+                } else {
+                    required_meta.as_ref().unwrap().1.version
+                };
+                // need to wait for new version
+                if required_meta.is_none() || required_meta.as_ref().unwrap().1.version < version {
+                    if required_meta.is_none() {
+                        tracing::debug!("no data version, waiting for notify");
+                    } else {
+                        tracing::debug!(
+                            "data version is old({}) at node({}), waiting for new notify({})",
+                            required_meta.as_ref().unwrap().1.version,
+                            self.view.p2p().nodes_config.this_node(),
+                            version
+                        );
+                    }
+
+                    let (kv_version, new_value) = kv_store_engine
+                        .register_waiter_for_new(&keybytes, guard)
+                        .await
+                        .unwrap_or_else(|err| {
+                            panic!("fail to wait for new data version: {:?}", err);
+                        });
+
+                    let Some(new_value) = new_value.as_raw_data() else {
+                        return failed(
+                            0,
+                            format!(
+                                "fatal error, kv value supposed to be DataSetMeta, rathe than {:?}",
+                                new_value
+                            ),
+                        );
+                    };
+
+                    // deserialize
+                    let new_value = match bincode::deserialize::<DataSetMeta>(&new_value) {
+                        Ok(new_value) => new_value,
+                        Err(err) => {
+                            return failed(
+                                0,
+                                format!("fatal error, kv value deserialization failed: {}", err),
+                            );
+                        }
+                    };
+
+                    // version check
+                    if new_value.version > version {
+                        return failed(0, "New data version overwrite".to_owned());
+                    } else if new_value.version < version {
+                        tracing::debug!("recv data version({}) is old than required({}), waiting for new notify",new_value.version, version);
+                        // still need to wait for new version
+                        continue;
+                    } else {
+                        required_meta = Some((kv_version, new_value));
+                        break;
+                    }
+                } else if old_dataset_version > version {
+                    drop(guard);
+                    return failed(0, "New data version overwrite".to_owned());
+                } else {
+                    tracing::debug!(
+                        "data version is matched cur({}) require({}) // 0 should be invalid",
+                        old_dataset_version,
+                        version
+                    );
+                    break;
+                }
+            }
+        }
+
+        // Step2: raw bytes of map file items go to their own files, out of the meta lock
+        {
+            let meta = &required_meta.as_ref().unwrap().1;
+            for (idx, data) in items.iter() {
+                let DataItemSource::Memory { data } = data else {
+                    continue;
+                };
+                if (*idx as usize) >= meta.cache_mode.len()
+                    || !meta.cache_mode_visitor(*idx).is_map_file()
+                {
+                    continue;
+                }
+                match self.map_item_to_file(unique_id, *idx, version, data).await {
+                    Ok(path) => {
+                        let _ = mapped.insert(*idx, path);
+                    }
+                    Err(err) => {
+                        for path in mapped.values() {
+                            let _ = tokio::fs::remove_file(path).await;
+                        }
+                        return failed(0, format!("map item {} to file failed: {:?}", idx, err));
+                    }
+                }
+            }
+        }
+        // Step3: write data
+        tracing::debug!("start to write partial data");
+        let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(unique_id).make_key());
+        let guard = KeyLockGuard::Write(lock.write());
+        let check_meta = kv_store_engine.get(
+            &KeyTypeDataSetMeta(unique_id),
+            true,
+            KvAdditionalConf {},
+        ); //tofix, master send maybe not synced
+        if check_meta.is_none()
+            || check_meta.as_ref().unwrap().0 != required_meta.as_ref().unwrap().0
+        {
+            drop(guard);
+            for path in mapped.values() {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            return failed(
+                check_meta.map_or(0, |(_, meta)| meta.version),
+                "meta is updated again, cancel write".to_owned(),
+            );
+        }
+
+        let compression = check_meta.as_ref().unwrap().1.compression;
+        for (idx, data) in items {
+            let serialize = match mapped.get(&idx) {
+                Some(path) => mapped_item_persist(path),
+                None => compress_persist(compression, data.encode_persist()),
+            };
+            tracing::debug!(
+                "writing data part uid({:?}) idx({}) item({})",
+                unique_id,
+                idx,
+                data.to_debug_string()
+            );
+            if let Err(err) = kv_store_engine.set(
+                KeyTypeDataSetItem {
+                    uid: unique_id,
+                    idx,
+                },
+                &serialize,
+                true,
+            ) {
+                tracing::warn!("flush error: {}", err)
+            }
+            self.view.data_cache().touch(unique_id, idx);
+        }
+        if let Err(err) = kv_store_engine.set(
+            KeyTypeDataSetItemVersion(unique_id),
+            &version,
+            true,
+        ) {
+            tracing::warn!("write item version error: {}", err)
+        }
+        kv_store_engine.flush();
+        drop(guard);
+        tracing::debug!("data partial is written");
+        WriteOneDataResponse {
+            remote_version: version,
+            success: true,
+            message: "".to_owned(),
+        }
+    }
+
+    /// Write the bytes of a map file item to its file under the data dir
+    async fn map_item_to_file(
+        &self,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        version: DataVersion,
+        data: &[u8],
+    ) -> WSResult<PathBuf> {
+        let dir = self.view.os().file_path.join(MAPPED_ITEM_DIR);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|err| WsDataError::FileWriteErr {
+                path: dir.clone(),
+                err,
+            })?;
+        // the persisted path is read back without the view, so it's absolute
+        let dir = tokio::fs::canonicalize(&dir)
+            .await
+            .map_err(|err| WsDataError::FileMetadataErr {
+                path: dir.clone(),
+                err,
+            })?;
+        let path = dir.join(mapped_item_file_name(unique_id, idx, version));
+        tokio::fs::write(&path, data)
+            .await
+            .map_err(|err| WsDataError::FileWriteErr {
+                path: path.clone(),
+                err,
+            })?;
+        Ok(path)
+    }
+
+    /// Remove the files of map file items no longer stored here, called by the data gc
+    pub async fn remove_unused_mapped_items(&self) {
+        let Ok(dir) = tokio::fs::canonicalize(self.view.os().file_path.join(MAPPED_ITEM_DIR)).await
+        else {
+            return;
+        };
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            return;
+        };
+        let kv_store_engine = self.view.kv_store_engine();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            // a write may have stored the file but not yet the item
+            let age = match entry.metadata().await.and_then(|m| m.modified()) {
+                Ok(modified) => modified.elapsed().unwrap_or_default(),
+                Err(_) => continue,
+            };
+            if age < MAPPED_ITEM_MIN_AGE {
+                continue;
+            }
+            let path = entry.path();
+            let in_use = entry
+                .file_name()
+                .to_str()
+                .and_then(dataitem::parse_mapped_item_file_name)
+                .map_or(false, |(uid, idx, _)| {
+                    kv_store_engine
+                        .get(&KeyTypeDataSetItem { uid: &uid, idx }, false, KvAdditionalConf {})
+                        .map_or(false, |(_, persisted)| persisted == mapped_item_persist(&path))
+                });
+            if !in_use {
+                tracing::debug!("remove unused mapped item {:?}", path);
+                if let Err(err) = tokio::fs::remove_file(&path).await {
+                    tracing::warn!("remove unused mapped item failed: {:?}", err);
+                }
+            }
+        }
+    }
+
+    async fn rpc_handle_data_meta_update(
+        &self,
+        responsor: RPCResponsor<proto::DataMetaUpdateRequest>,
+        mut req: proto::DataMetaUpdateRequest,
+    ) {
+        struct Defer {
+            node: NodeID,
+        }
+        impl Drop for Defer {
+            fn drop(&mut self) {
+                tracing::debug!("rpc_handle_data_meta_update return at node({})", self.node);
+            }
+        }
+        let _defer = Defer {
+            node: self.view.p2p().nodes_config.this_node(),
+        };
+
+        self.invalidate_meta_cache(&req.unique_id);
+        if req.cache_only {
+            // this node only reads the dataset, no local copy to update
+            if let Err(e) = responsor
+                .send_resp(proto::DataMetaUpdateResponse {
+                    version: req.version,
+                    message: "".to_owned(),
+                })
+                .await
+            {
+                tracing::error!("Failed to send data meta cache invalidation response: {}", e);
+            }
+            return;
+        }
+
+        let key = KeyTypeDataSetMeta(&req.unique_id);
+        let keybytes = key.make_key();
+        
+        // test only log
+        #[cfg(test)]
+        tracing::debug!("rpc_handle_data_meta_update {:?}\n    {:?}", req,bincode::deserialize::<DataSetMeta>(&req.serialized_meta));
+        // not test log
+        #[cfg(not(test))]
+        tracing::debug!("rpc_handle_data_meta_update {:?}", req);
+
+        let kv_lock = self.view.kv_store_engine().with_rwlock(&keybytes);
+        let _kv_write_lock_guard = kv_lock.write();
+
+        if let Some((_old_version, mut old_meta)) =
+            self.view.kv_store_engine().get(&key, true, KvAdditionalConf {})
+        {
+            if old_meta.version > req.version {
+                drop(_kv_write_lock_guard);
+                let err_msg = "New data version is smaller, failed update";
+                tracing::warn!("{}", err_msg);
+                if let Err(e) = responsor                //返回结果未处理  曾俊
+                    .send_resp(proto::DataMetaUpdateResponse {
+                        version: old_meta.version,
+                        message: err_msg.to_owned(),
+                    })
+                    .await{
+                        tracing::error!("Failed to send data meta update response 5: {}", e);
+                    }
+                // .todo_handle("5 err_comment waitting to fill");
+                return;
+            }
+            old_meta.version = req.version;
+            if req.serialized_meta.len() > 0 {
+                if let Err(e) = self.view.kv_store_engine()                  //返回结果未处理  曾俊
+                    .set_raw(&keybytes, std::mem::take(&mut req.serialized_meta), true){
+                        tracing::error!("Failed to set raw data in kv store 6: {}", e);
+                    }
+                // .todo_handle("6 err_comment waitting to fill");
+            } else {
+                if let Err(e) = self.view.kv_store_engine()                  //返回结果未处理  曾俊
+                    .set(key, &old_meta, true){
+                        tracing::error!("Failed to set raw data in kv store 7: {}", e);
+                    }
+                // .todo_handle("7 err_comment waitting to fill");
+            }
+        } else {
+            if req.serialized_meta.len() > 0 {
+                tracing::debug!(
+                    "set new meta data, {:?}",
+                    bincode::deserialize::<DataSetMeta>(&req.serialized_meta)
+                );
+                if let Err(e) = self.view.kv_store_engine()                       //返回结果未处理  曾俊      
+                    .set_raw(&keybytes, std::mem::take(&mut req.serialized_meta), true){
+                        tracing::error!("Failed to set raw data in kv store 8: {}", e);
+                    }
+                // .todo_handle("8 err_comment waitting to fill");
+            } else {
+                drop(_kv_write_lock_guard);
+                let err_msg = "Old meta data not found and missing new meta";
+                tracing::warn!("{}", err_msg);
+                if let Err(e) = responsor                    //返回结果未处理  曾俊
+                    .send_resp(proto::DataMetaUpdateResponse {
+                        version: 0,
+                        message: err_msg.to_owned(),
+                    })
+                    .await{
+                        tracing::error!("Failed to send data meta update response 9: {}", e);
+                    }
+                // .todo_handle("9 err_comment waitting to fill");
+                return;
+            }
+        }
+        drop(_kv_write_lock_guard);
+        tracing::debug!("rpc_handle_data_meta_update success");
+        if let Err(e) = responsor                          //返回结果未处理  曾俊
+            .send_resp(proto::DataMetaUpdateResponse {
+                version: req.version,
+                message: "Update success".to_owned(),
+            })
+            .await{
+                tracing::error!("Failed to send data meta update response 10: {}", e);
+            }
+        // .todo_handle("10 err_comment waitting to fill");
+    }
+
+    async fn rpc_handle_get_data_meta(
+        &self,
+        req: proto::DataMetaGetRequest,
+        responsor: RPCResponsor<proto::DataMetaGetRequest>,
+    ) -> WSResult<()> {
+        tracing::debug!("rpc_handle_get_data_meta with req({:?})", req);
+        // the reader may cache the meta, so it should be told when the meta changes.
+        //  registered before reading, a change in between still reaches it
+        if self.view.p2p().nodes_config.this.1.is_master() {
+            // a dataset reserved by a kv transaction is neither read nor deleted by others
+            //  until the transaction finishes, so no one sees it partly committed
+            self.view
+                .data_master()
+                .wait_txn_released(&req.unique_id, req.txn_id)
+                .await;
+            if !req.delete {
+                self.view
+                    .data_master()
+                    .add_meta_cache_holder(&req.unique_id, responsor.node_id());
+            }
+        }
+        let meta = self
+            .view
+            .get_data_meta_local(
+                &req.unique_id,
+                req.delete,
+                responsor.node_id(),
+                req.deleted_by.as_ref(),
+            )
+            .await?;
+        if meta.is_none() {
+            tracing::debug!("rpc_handle_get_data_meta data meta not found");
+        } else {
+            tracing::debug!("rpc_handle_get_data_meta data meta found");
+        }
+        let serialized_meta = meta.map_or(vec![], |(_kvversion, meta)| {
+            bincode::serialize(&meta).unwrap()
+        });
+
+        responsor
+            .send_resp(proto::DataMetaGetResponse { serialized_meta })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn rpc_handle_get_one_data(
+        &self,
+        responsor: RPCResponsor<proto::GetOneDataRequest>,
+        req: proto::GetOneDataRequest,
+    ) -> WSResult<()> {
+        tracing::debug!("starting rpc_handle_get_one_data {:?}", req);
+
+        let kv_store_engine = self.view.kv_store_engine();
+        let _ = self.view
+            .get_metadata(&req.unique_id, req.delete, req.txn_id)
+            .await
+            .map_err(|err| {
+                tracing::warn!("rpc_handle_get_one_data get_metadata failed: {:?}", err);
+                err
+            })?;
+
+        let mut got_or_deleted = vec![];
+        let mut kv_ope_err = vec![];
+        let items_version = kv_store_engine
+            .get(
+                &KeyTypeDataSetItemVersion(req.unique_id.as_ref()),
+                false,
+                KvAdditionalConf {},
+            )
+            .map_or(0, |(_, version)| version);
+        if !req.delete && (req.offset > 0 || req.length > 0) {
+            let mut data = vec![];
+            let mut item_sizes = vec![];
+            let mut message = "success".to_owned();
+            for idx in req.idxs.iter() {
+                match self
+                    .get_item_range_local(&req.unique_id, *idx, req.offset, req.length)
+                    .await
+                {
+                    Ok(Some((bytes, size))) => {
+                        data.push(proto::DataItem {
+                            data_item_dispatch: Some(
+                                proto::data_item::DataItemDispatch::RawBytes(bytes),
+                            ),
+                        });
+                        item_sizes.push(size);
+                    }
+                    Ok(None) => {
+                        message = "some data not found".to_owned();
+                        break;
+                    }
+                    Err(e) => {
+                        message = format!("read data range failed: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            responsor
+                .send_resp(proto::GetOneDataResponse {
+                    success: data.len() == req.idxs.len(),
+                    data,
+                    message,
+                    version: items_version,
+                    item_sizes,
+                })
+                .await?;
+            return Ok(());
+        }
+        if req.delete {
+            if let Err(e) =
+                kv_store_engine.del(KeyTypeDataSetItemVersion(req.unique_id.as_ref()), false)
+            {
+                kv_ope_err.push(e);
+            }
+        }
+
+        for idx in req.idxs {
+            let value = if req.delete {
+                match kv_store_engine.del(
+                    KeyTypeDataSetItem {
+                        uid: req.unique_id.as_ref(),
+                        idx,
+                    },
+                    false,
+                ) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        kv_ope_err.push(e);
+                        None
+                    }
+                }
+            } else {
+                self.view.data_cache().touch(&req.unique_id, idx);
+                kv_store_engine.get(
+                    &KeyTypeDataSetItem {
+                        uid: req.unique_id.as_ref(),
+                        idx,
+                    },
+                    false,
+                    KvAdditionalConf {},
+                )
+            };
+            got_or_deleted.push(value);
+        }
+
+        let (mut success, mut message): (bool, String) = if kv_ope_err.len() > 0 {
+            (false, {
+                let mut msg = String::from("KvEngine operation failed: ");
+                for e in kv_ope_err.iter() {
+                    msg.push_str(&format!("{:?}", e));
+                }
+                msg
+            })
+        } else if got_or_deleted.iter().all(|v| v.is_some()) {
+            (true, "success".to_owned())
+        } else {
+            tracing::warn!("some data not found");
+            (false, "some data not found".to_owned())
+        };
+
+        let mut got_or_deleted_checked: Vec<proto::DataItem> = vec![];
+        if success {
+            for v in got_or_deleted {
+                let decode_res = decode_persist_item(v.unwrap().1).await;
+                match decode_res {
+                    Ok(item) => {
+                        tracing::debug!("decoded data item: {:?}", item.to_string());
+                        got_or_deleted_checked.push(item);
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to decode data item: {:?}", e);
+                        success = false;
+                        message = format!("Failed to decode data item: {:?}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        responsor
+            .send_resp(proto::GetOneDataResponse {
+                success,
+                data: got_or_deleted_checked,
+                message,
+                version: items_version,
+                item_sizes: vec![],
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// State of a batch transfer seen for the first time since this node started,
+    ///  a file transfer continues from its persisted progress if it's the same transfer
+    async fn new_batch_receive_state(
+        view: DataGeneralView,
+        key: BatchTransferKey,
+        block_type: proto::data_item::DataItemDispatch,
+        total_size: u64,
+        block_size: u64,
+        checksum: Vec<u8>,
+    ) -> WSResult<Arc<BatchReceiveState>> {
+        let (uid, item_idx, version) = key;
+        let kv_store_engine = view.kv_store_engine();
+        // the staged blocks of a mem transfer are lost with the node
+        let persist = matches!(block_type, proto::data_item::DataItemDispatch::File(_));
+        let progress = match kv_store_engine.get(
+            &KeyTypeBatchProgress {
+                uid: &uid,
+                idx: item_idx,
+            },
+            false,
+            KvAdditionalConf {},
+        ) {
+            Some((_, progress))
+                if persist
+                    && progress.version == version
+                    && progress.same_transfer(total_size, block_size, &checksum)
+                    && !progress.is_complete() =>
+            {
+                tracing::info!(
+                    "resume batch transfer of {:?} idx {} with {} bytes received",
+                    uid,
+                    item_idx,
+                    progress.received_size()
+                );
+                progress
+            }
+            _ => BatchProgress::new(version, total_size, block_size, checksum.clone()),
+        };
+        if persist {
+            let _ = kv_store_engine.set(
+                KeyTypeBatchProgress {
+                    uid: &uid,
+                    idx: item_idx,
+                },
+                &progress,
+                false,
+            )?;
+        }
+
+        // 创建任务组和句柄
+        let (mut group, handle) = WriteSplitDataTaskGroup::new(
+            uid.clone(),
+            item_idx,
+            total_size as usize,
+            block_type,
+            version,
+            progress.received_size() as usize,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create task group: {:?}", e);
+            e
+        })?;
+
+        // 启动process_tasks, 完成后校验整个数据项
+        let expected = checksum;
+        let key = (uid.clone(), item_idx, version);
+        let process = tokio::spawn(async move {
+            let item = group.process_tasks().await.map_err(|e| {
+                tracing::error!("Failed to process tasks: {}", e);
+                e
+            })?;
+            let verified = Self::verify_batch_item(uid, item_idx, &item, expected).await;
+            if verified.is_ok() {
+                if let Some((_, pull)) = view.data_general().replica_pulls.remove(&key) {
+                    let _ = pull.send(item);
+                }
+            }
+            verified
+        });
+
+        Ok(Arc::new(BatchReceiveState::new(
+            handle, persist, progress, process,
+        )))
+    }
+
+    /// Check a batch received item against the checksum the sender gave, if any
+    async fn verify_batch_item(
+        uid: UniqueId,
+        item_idx: DataItemIdx,
+        item: &proto::DataItem,
+        expected: Vec<u8>,
+    ) -> WSResult<()> {
+        if expected.is_empty() {
+            return Ok(());
+        }
+        let actual = match item.data_item_dispatch.as_ref() {
+            Some(proto::data_item::DataItemDispatch::File(f)) if f.is_dir_opt => {
+                // unzipped already, checked by blocks only
+                return Ok(());
+            }
+            Some(proto::data_item::DataItemDispatch::File(f)) => {
+                file_checksum(std::path::Path::new(&f.file_name_opt)).await?
+            }
+            _ => item_checksum(item).unwrap_or_default(),
+        };
+        if actual != expected {
+            return Err(WSError::from(WsDataError::ChecksumMismatch {
+                unique_id: uid,
+                idx: item_idx,
+                expected,
+                actual,
+            }));
+        }
+        Ok(())
+    }
+
+    // 处理批量数据写入请求
+    //  each block is answered once written, the answer to the block completing the item
+    //  carries the result of the whole item. A block rejected alone is marked retryable.
+    pub async fn rpc_handle_batch_data(
+        &self,
+        responsor: RPCResponsor<proto::BatchDataRequest>,
+        req: proto::BatchDataRequest,
+    ) -> WSResult<()> {
+        tracing::debug!("rpc_handle_batch_data with batchid({:?})", req.request_id.clone().unwrap());
+        let block_index = req.block_index as usize;
+        let request_id = req.request_id.clone().unwrap();
+        let version = req.version;
+        let resp = |res: WSResult<()>, retryable: bool| {
+            let error_message = match &res {
+                Ok(()) => String::new(),
+                Err(err) => {
+                    tracing::warn!("reject batch block {}: {:?}", block_index, err);
+                    format!("{:?}", err)
+                }
+            };
+            proto::BatchDataResponse {
+                request_id: Some(request_id.clone()),
+                success: res.is_ok(),
+                error_message,
+                version,
+                retryable: res.is_err() && retryable,
+            }
+        };
+
+        let data = match decompress(req.compression().into(), req.data.clone()) {
+            Ok(data) => data,
+            Err(err) => {
+                responsor.send_resp(resp(Err(err), true)).await?;
+                return Ok(());
+            }
+        };
+
+        // 0. 校验数据块
+        if !req.block_checksum.is_empty() {
+            let actual = bytes_checksum(&data);
+            if actual != req.block_checksum {
+                let err = WsDataError::ChecksumMismatch {
+                    unique_id: req.unique_id.clone(),
+                    idx: req.data_item_idx,
+                    expected: req.block_checksum.clone(),
+                    actual,
+                };
+                responsor.send_resp(resp(Err(err.into()), true)).await?;
+                return Ok(());
+            }
+        }
+        // sent without a block size by older nodes
+        let block_size = if req.block_size == 0 {
+            DEFAULT_BLOCK_SIZE as u64
+        } else {
+            req.block_size
+        };
+
+        // 1. 查找或创建状态
+        let key: BatchTransferKey = (req.unique_id.clone(), req.data_item_idx, version);
+        let state = match self
+            .batch_receive_states
+            .get_or_init(
+                key.clone(),
+                Self::new_batch_receive_state(
+                    self.view.clone(),
+                    key.clone(),
+                    req.block_type.clone().unwrap().data_item_dispatch.unwrap(),
+                    req.total_size,
+                    block_size,
+                    req.item_checksum.clone(),
+                ),
+            )
+            .await
+        {
+            Err(e) => {
+                let err = WsDataError::BatchTransferError {
+                    request_id: request_id.clone(),
+                    msg: format!("Failed to initialize batch state: {}", e),
+                };
+                responsor.send_resp(resp(Err(err.into()), true)).await?;
+                return Ok(());
+            }
+            Ok(state) => state,
+        };
+
+        // 2. 跳过已收到的块, 正在写入的块让发送方稍后重发
+        let claimed = {
+            let mut progress = state.progress.lock();
+            progress.last_active = std::time::Instant::now();
+            let expected_len = if progress
+                .progress
+                .same_transfer(req.total_size, block_size, &req.item_checksum)
+                && block_index < progress.progress.block_cnt()
+            {
+                let offset = block_index as u64 * block_size;
+                Some(block_size.min(req.total_size - offset) as usize)
+            } else {
+                None
+            };
+            match expected_len {
+                Some(len) if len != data.len() => Err(WsDataError::SizeMismatch {
+                    expected: len,
+                    actual: data.len(),
+                }),
+                Some(_) => Ok(!progress.progress.is_received(block_index)
+                    && progress.writing.insert(block_index)),
+                None => Err(WsDataError::BatchTransferError {
+                    request_id: request_id.clone(),
+                    msg: format!(
+                        "block {} doesn't belong to the transfer in progress",
+                        block_index
+                    ),
+                }),
+            }
+        };
+        match claimed {
+            Err(err) => {
+                responsor.send_resp(resp(Err(err.into()), false)).await?;
+                return Ok(());
+            }
+            Ok(false) => {
+                let received = state.progress.lock().progress.is_received(block_index);
+                let res = if received {
+                    Ok(())
+                } else {
+                    Err(WsDataError::BatchTransferError {
+                        request_id: request_id.clone(),
+                        msg: format!("block {} is being written", block_index),
+                    }
+                    .into())
+                };
+                responsor.send_resp(resp(res, true)).await?;
+                return Ok(());
+            }
+            Ok(true) => {}
+        }
+
+        // 3. 写入分片数据
+        let data_item = proto::DataItem {
+            data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(data)),
+            ..Default::default()
+        };
+        tracing::debug!("submit_split with data split idx: {}, at node: {}", block_index, self.view.p2p().nodes_config.this_node());
+        let written = state
+            .handle
+            .submit_split(block_index * block_size as usize, data_item)
+            .await;
+        let (progress, complete) = {
+            let mut progress = state.progress.lock();
+            let _ = progress.writing.remove(&block_index);
+            if written.is_ok() {
+                progress.progress.mark_received(block_index);
+            }
+            (progress.progress.clone(), progress.progress.is_complete())
+        };
+        if let Err(err) = written {
+            responsor.send_resp(resp(Err(err), true)).await?;
+            return Ok(());
+        }
+        let progress_key = KeyTypeBatchProgress {
+            uid: &req.unique_id,
+            idx: req.data_item_idx,
+        };
+        if !complete {
+            if state.persist {
+                // an older snapshot written late only makes blocks be sent again
+                if let Err(err) = self.view.kv_store_engine().set(progress_key, &progress, false) {
+                    tracing::warn!("persist batch progress failed: {:?}", err);
+                }
+            }
+            responsor.send_resp(resp(Ok(()), false)).await?;
+            return Ok(());
+        }
+
+        // 4. 最后一块, 等待数据项组装和校验
+        let done = state.done.lock().await.take();
+        let processed = match done {
+            Some(done) => match done.await {
+                Ok(res) => res,
+                Err(e) => Err(WSError::from(WsDataError::BatchTransferTaskFailed {
+                    reason: format!("process task panicked: {}", e),
+                })),
+            },
+            None => Ok(()),
+        };
+        // 清理状态, 失败的数据项重新传输时从头开始
+        let _ = self.batch_receive_states.remove(&key);
+        if state.persist {
+            if let Err(err) = self.view.kv_store_engine().del(progress_key, false) {
+                tracing::warn!("remove batch progress failed: {:?}", err);
+            }
+        }
+        if let Err(e) = &processed {
+            tracing::warn!("batch data item rejected: {:?}", e);
+        }
+        let mut final_resp = resp(processed, false);
+        final_resp.version = state.handle.version();
+        responsor.send_resp(final_resp).await?;
+        Ok(())
+    }
+
+    /// Blocks of the transfer received so far, from the live state or the persisted progress
+    async fn rpc_handle_batch_resume(
+        &self,
+        responsor: RPCResponsor<proto::BatchResumeRequest>,
+        req: proto::BatchResumeRequest,
+    ) -> WSResult<()> {
+        let key: BatchTransferKey = (req.unique_id.clone(), req.data_item_idx, req.version);
+        let progress = match self.batch_receive_states.get(&key) {
+            Some(state) => {
+                let progress = state.progress.lock().progress.clone();
+                Some(progress)
+            }
+            None => self
+                .view
+                .kv_store_engine()
+                .get(
+                    &KeyTypeBatchProgress {
+                        uid: &req.unique_id,
+                        idx: req.data_item_idx,
+                    },
+                    false,
+                    KvAdditionalConf {},
+                )
+                .map(|(_, progress)| progress)
+                .filter(|progress| progress.version == req.version && !progress.is_complete()),
+        };
+        let received = progress
+            .filter(|progress| {
+                progress.same_transfer(req.total_size, req.block_size, &req.item_checksum)
+            })
+            .map(|progress| progress.received)
+            .unwrap_or_default();
+        responsor
+            .send_resp(proto::BatchResumeResponse { received })
+            .await?;
+        Ok(())
+    }
+
+    /// Drop the batch transfers no block arrived for `max_idle`, with their persisted progress
+    ///  and staged file, called by the data gc. A sender still around starts the item over.
+    pub async fn remove_stale_batch_transfers(&self, max_idle: Duration) {
+        let kv_store_engine = self.view.kv_store_engine();
+        let file_path = self.view.os().file_path.clone();
+        let is_idle = |path: PathBuf| async move {
+            match tokio::fs::metadata(&path).await.and_then(|m| m.modified()) {
+                Ok(modified) => modified.elapsed().unwrap_or_default() > max_idle,
+                // nothing staged
+                Err(_) => true,
+            }
+        };
+
+        for ((uid, idx, version), state) in self
+            .batch_receive_states
+            .remove_ready_if(|_, state| state.is_stale(max_idle))
+        {
+            tracing::info!(
+                "remove stale batch transfer of {:?} idx {} version {}",
+                uid,
+                idx,
+                version
+            );
+            if let Some(done) = state.done.lock().await.take() {
+                done.abort();
+            }
+        }
+
+        // progress of the transfers dropped above or abandoned before a restart
+        for (uid, idx) in kv_store_engine.batch_progress_keys() {
+            let key = KeyTypeBatchProgress { uid: &uid, idx };
+            let Some((_, progress)) = kv_store_engine.get(&key, false, KvAdditionalConf {}) else {
+                continue;
+            };
+            let path = batch_stage_path(&file_path, &uid, idx, progress.version);
+            if self
+                .batch_receive_states
+                .get(&(uid.clone(), idx, progress.version))
+                .is_some()
+                || !is_idle(path.clone()).await
+            {
+                continue;
+            }
+            if let Err(err) = kv_store_engine.del(key, false) {
+                tracing::warn!("remove stale batch progress failed: {:?}", err);
+            }
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("remove stale batch stage failed: {:?}", err);
+                }
+            }
+        }
+
+        // staged files whose progress is gone, e.g. of an item failing its checksum
+        let Ok(mut entries) = tokio::fs::read_dir(file_path.join(BATCH_STAGE_DIR)).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name();
+            let Some((uid, idx, version)) = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".data"))
+                .and_then(parse_mapped_item_file_name)
+            else {
+                continue;
+            };
+            let recorded = kv_store_engine
+                .get(
+                    &KeyTypeBatchProgress { uid: &uid, idx },
+                    false,
+                    KvAdditionalConf {},
+                )
+                .map_or(false, |(_, progress)| progress.version == version);
+            if recorded
+                || self
+                    .batch_receive_states
+                    .get(&(uid, idx, version))
+                    .is_some()
+                || !is_idle(entry.path()).await
+            {
+                continue;
+            }
+            tracing::info!("remove orphaned batch stage {:?}", entry.path());
+            if let Err(err) = tokio::fs::remove_file(entry.path()).await {
+                tracing::warn!("remove orphaned batch stage failed: {:?}", err);
+            }
+        }
+    }
+
+
+
+    //费新文
+    // pub async fn distribute_task_to_worker(
+    //     &self,
+    //     worker_node: NodeID,
+    //     app_name: String,
+    //     func_name: String,
+    //     task_id: u32,
+    //     key: Option<Vec<u8>>,
+    //     is_write_trigger: bool,
+    // ) -> WSResult<DistributeTaskResp> {
+    //     let mut req = DistributeTaskReq {
+    //         app: app_name,
+    //         func: func_name,
+    //         task_id,
+    //         trigger: None,
+    //     };
+
+    //     if let Some(key_data) = key {
+    //         if is_write_trigger {
+    //             req.trigger = Some(proto::sche::distribute_task_req::Trigger::EventWrite(
+    //                 proto::sche::distribute_task_req::DataEventTriggerWrite {
+    //                     key: key_data,
+    //                     opeid: task_id,
+    //                 }
+    //             ));
+    //         } else {
+    //             req.trigger = Some(proto::sche::distribute_task_req::Trigger::EventNew(
+    //                 proto::sche::distribute_task_req::DataEventTriggerNew {
+    //                     key: key_data,
+    //                     opeid: task_id,
+    //                 }
+    //             ));
+    //         }
+    //     }
+
+    //     self.rpc_call_distribute_task
+    //         .call(
+    //             self.view.p2p(),
+    //             worker_node,
+    //             req,
+    //             Some(Duration::from_secs(30)),
+    //         )
+    //         .await
+    //         .map_err(|e| WsNetworkLogicErr::RPCErr {   //这里错误类型需要换
+    //             message: e.to_string(),
+    //         }.into())
+    // }
+
+    // async fn rpc_handle_distribute_task(
+    //     &self,
+    //     responsor: RPCResponsor<DistributeTaskReq>,
+    //     req: DistributeTaskReq,
+    // ) {
+    //     tracing::debug!("rpc_handle_distribute_task with req({:?})", req);
+        
+    //     // TODO: 这里需要实现具体的任务处理逻辑
+        
+    //     if let Err(e) = responsor
+    //         .send_resp(DistributeTaskResp {
+    //             success: true,
+    //             err_msg: String::new(),
+    //         })
+    //         .await {
+    //             tracing::error!("Failed to send distribute task response: {}", e);
+    //         }
+    // }
+
+    
+
+
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DataMetaSys {
+    pub cache: i32,
+    pub distribute: i32,
+}
+impl From<DataMeta> for DataMetaSys {
+    fn from(d: DataMeta) -> Self {
+        Self {
+            cache: d.cache,
+            distribute: d.distribute,
+        }
+    }
+}
+impl Into<DataMeta> for DataMetaSys {
+    fn into(self) -> DataMeta {
+        DataMeta {
+            cache: self.cache,
+            distribute: self.distribute,
+        }
+    }
+}
+
+/// 数据集元信息
+#[derive(Serialize, Deserialize)]
+pub struct DataSetMetaV1 {
+    // unique_id: Vec<u8>,
+    pub version: u64,
+    pub data_metas: Vec<DataMetaSys>,
+    pub synced_nodes: HashSet<NodeID>,
+}
+
+/// 数据集元信息
+///
+/// 注意：新建元信息请使用 `DataSetMetaBuilder`
+///
+/// https://fvd360f8oos.feishu.cn/docx/XoFudWhAgox84MxKC3ccP1TcnUh#share-Tqqkdxubpokwi5xREincb1sFnLc
+#[derive(Serialize, Deserialize, Debug,Clone)]
+pub struct DataSetMetaV2 {
+    // unique_id: Vec<u8>,
+    api_version: u8,
+    pub version: u64,
+    pub datas_splits: Vec<DataSplit>,
+    pub data_metas: Vec<DataMetaSys>,
+    pub synced_nodes: HashSet<NodeID>,
+    pub cache_mode: Vec<CacheMode>,
+    /// md5 of each item given by the writer, empty when unknown
+    pub data_checksums: Vec<Vec<u8>>,
+    /// granted by master for the transfers and the storage of the items
+    pub compression: DataCompression,
+}
+
+impl DataSetMetaV2 {
+    pub fn cache_mode_visitor(&self, idx: DataItemIdx) -> CacheModeVisitor {
+        CacheModeVisitor(self.cache_mode[idx as usize])
+    }
+    
+    pub fn data_item_cnt(&self) -> usize {
+        self.datas_splits.len()
+    }
+
+    /// Size of the item as written, from the planned splits
+    pub fn item_size(&self, idx: DataItemIdx) -> u64 {
+        let Some(split) = self.datas_splits.get(idx as usize) else {
+            return 0;
+        };
+        if let Some(parity_shards) = self.cache_mode_visitor(idx).erasure_parity_shards() {
+            let sizes: Vec<u64> = split.splits.iter().map(|s| s.data_size).collect();
+            return ErasureLayout::from_split_sizes(&sizes, parity_shards)
+                .map(|layout| layout.item_size)
+                .unwrap_or(0);
+        }
+        // each replica holds the whole item
+        split.splits.first().map(|s| s.data_size).unwrap_or(0)
+    }
+
+    pub fn get_data_node(&self, idx: DataItemIdx) -> NodeID {
+        // 获取指定数据项的主节点
+        self.datas_splits[idx as usize].splits[0].node_id
+    }
+
+    /// Items whose content is not carried, like files on disk, are not checked
+    pub fn verify_item(
+        &self,
+        unique_id: &[u8],
+        idx: DataItemIdx,
+        item: &proto::DataItem,
+    ) -> WSResult<()> {
+        let Some(expected) = self.data_checksums.get(idx as usize).filter(|c| !c.is_empty()) else {
+            return Ok(());
+        };
+        let Some(actual) = item_checksum(item) else {
+            return Ok(());
+        };
+        if *expected != actual {
+            return Err(WsDataError::ChecksumMismatch {
+                unique_id: unique_id.to_vec(),
+                idx,
+                expected: expected.clone(),
+                actual,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Nodes holding the whole item, the primary first, then the other full splits
+    ///  and the synced nodes
+    pub fn get_data_holders(&self, idx: DataItemIdx) -> Vec<NodeID> {
+        let splits = &self.datas_splits[idx as usize].splits;
+        let item_size = splits
+            .iter()
+            .map(|s| s.data_offset + s.data_size)
+            .max()
+            .unwrap_or(0);
+        let mut holders = vec![splits[0].node_id];
+        for split in splits.iter().skip(1) {
+            if split.data_offset == 0
+                && split.data_size == item_size
+                && !holders.contains(&split.node_id)
+            {
+                holders.push(split.node_id);
+            }
+        }
+        let mut synced: Vec<NodeID> = self
+            .synced_nodes
+            .iter()
+            .filter(|n| !holders.contains(n))
+            .copied()
+            .collect();
+        synced.sort();
+        holders.extend(synced);
+        holders
+    }
+
+    /// Whether the copy of the item on `node` may be evicted: an extra copy of a time auto item
+    ///  beyond the replication factor, neither the primary nor the last full copy
+    pub fn evictable_copy(&self, idx: DataItemIdx, node: NodeID) -> bool {
+        if idx as usize >= self.datas_splits.len() || self.synced_nodes.contains(&node) {
+            return false;
+        }
+        let visitor = self.cache_mode_visitor(idx);
+        if !visitor.is_time_auto() || visitor.erasure_parity_shards().is_some() {
+            return false;
+        }
+        let extra_copy = self.datas_splits[idx as usize]
+            .splits
+            .iter()
+            .any(|s| s.node_id == node && s.cache_mode_visitor().is_time_auto());
+        let holders = self.get_data_holders(idx);
+        extra_copy && holders.len() > 1 && holders[0] != node
+    }
+
+    /// Whether the current plan keeps any part of the item on `node`
+    pub fn holds_item(&self, idx: DataItemIdx, node: NodeID) -> bool {
+        self.synced_nodes.contains(&node)
+            || self
+                .datas_splits
+                .get(idx as usize)
+                .map_or(false, |split| split.splits.iter().any(|s| s.node_id == node))
+    }
+}
+
+pub type DataSetMeta = DataSetMetaV2;
+
+/// On-disk layout version of `DataSetMetaV2` written by this build.
+///  2: u8 item idx and u32 split offsets and sizes, 3: u64 ones, 4: with compression
+pub const DATA_SET_META_API_VERSION: u8 = 4;
+
+/// Split of the api version 2 layout
+#[derive(Deserialize)]
+struct EachNodeSplitApi2 {
+    node_id: NodeID,
+    data_offset: u32,
+    data_size: u32,
+    cache_mode: u32,
+}
+
+#[derive(Deserialize)]
+struct DataSplitApi2 {
+    splits: Vec<EachNodeSplitApi2>,
+}
+
+/// Meta of the api version 2 layout, `data_checksums` is missing in the
+///  ones written before checksums were added
+#[derive(Deserialize)]
+struct DataSetMetaApi2 {
+    _api_version: u8,
+    version: u64,
+    datas_splits: Vec<DataSplitApi2>,
+    data_metas: Vec<DataMetaSys>,
+    synced_nodes: HashSet<NodeID>,
+    cache_mode: Vec<CacheMode>,
+}
+
+/// Meta of the api version 3 layout, without compression
+#[derive(Deserialize)]
+struct DataSetMetaApi3 {
+    _api_version: u8,
+    version: u64,
+    datas_splits: Vec<DataSplit>,
+    data_metas: Vec<DataMetaSys>,
+    synced_nodes: HashSet<NodeID>,
+    cache_mode: Vec<CacheMode>,
+    data_checksums: Vec<Vec<u8>>,
+}
+
+impl DataSetMetaApi3 {
+    fn upgrade(self) -> DataSetMetaV2 {
+        DataSetMetaV2 {
+            api_version: DATA_SET_META_API_VERSION,
+            version: self.version,
+            datas_splits: self.datas_splits,
+            data_metas: self.data_metas,
+            synced_nodes: self.synced_nodes,
+            cache_mode: self.cache_mode,
+            data_checksums: self.data_checksums,
+            compression: DataCompression::Raw,
+        }
+    }
+}
+
+impl DataSetMetaApi2 {
+    fn upgrade(self, data_checksums: Vec<Vec<u8>>) -> DataSetMetaV2 {
+        DataSetMetaV2 {
+            api_version: DATA_SET_META_API_VERSION,
+            version: self.version,
+            datas_splits: self
+                .datas_splits
+                .into_iter()
+                .map(|split| DataSplit {
+                    splits: split
+                        .splits
+                        .into_iter()
+                        .map(|s| EachNodeSplit {
+                            node_id: s.node_id,
+                            data_offset: s.data_offset as u64,
+                            data_size: s.data_size as u64,
+                            cache_mode: s.cache_mode,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            data_metas: self.data_metas,
+            synced_nodes: self.synced_nodes,
+            cache_mode: self.cache_mode,
+            data_checksums,
+            compression: DataCompression::Raw,
+        }
+    }
+}
+
+impl DataSetMetaV2 {
+    /// Decode a persisted meta of any known layout, an older one is upgraded to the current
+    pub fn decode_persist(bytes: &[u8]) -> Option<Self> {
+        match *bytes.first()? {
+            DATA_SET_META_API_VERSION => bincode::deserialize::<Self>(bytes).ok(),
+            3 => Some(bincode::deserialize::<DataSetMetaApi3>(bytes).ok()?.upgrade()),
+            2 => {
+                // the layout with checksums is a superset, try it first
+                match bincode::deserialize::<(DataSetMetaApi2, Vec<Vec<u8>>)>(bytes) {
+                    Ok((meta, checksums)) => Some(meta.upgrade(checksums)),
+                    Err(_) => Some(
+                        bincode::deserialize::<DataSetMetaApi2>(bytes)
+                            .ok()?
+                            .upgrade(vec![]),
+                    ),
+                }
+            }
+            api_version => {
+                tracing::warn!("unknown data set meta api version {}", api_version);
+                None
+            }
+        }
+    }
+}
+
+// message EachNodeSplit{
+//     uint32 node_id=1;
+//     uint64 data_offset=2;
+//     uint64 data_size=3;
+//   }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EachNodeSplit {
+    pub node_id: NodeID,
+    pub data_offset: u64,
+    pub data_size: u64,
+    pub cache_mode: u32,  // 添加 cache_mode 字段
+}
+
+impl EachNodeSplit {
+    pub fn cache_mode_visitor(&self) -> CacheModeVisitor {
+        CacheModeVisitor(self.cache_mode as u16)
+    }
+}
+
+/// 数据项的分片信息
+/// 我们需要知道每个数据项的分片大小
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DataSplit {
+    pub splits: Vec<EachNodeSplit>,
+}
+
+pub type DataSplitIdx = usize;
+
+// impl DataSplit {
+//     /// node_2_datas will be consumed partially
+//     pub fn recorver_data(
+//         &self,
+//         unique_id: &[u8],
+//         idx: DataItemIdx,
+//         node_2_datas: &mut HashMap<(NodeID, DataItemIdx), proto::DataItem>,
+//     ) -> WSResult<Vec<u8>> {
+//         let nodes = node_2_datas
+//             .iter()
+//             .filter(|v| v.0 .1 == idx)
+//             .map(|v| v.0 .0)
+//             .collect::<Vec<_>>();
+
+//         let mut each_node_splits: HashMap<NodeID, (proto::DataItem, Option<EachNodeSplit>)> =
+//             HashMap::new();
+
+//         for node in nodes {
+//             let data = node_2_datas.remove(&(node, idx)).unwrap();
+//             let _ = each_node_splits.insert(node, (data, None));
+//         }
+
+//         let mut max_size = 0;
+//         let mut missing = vec![];
+
+//         // zip with split info
+//         //  by the way, check if the split is missing
+//         for split in &self.splits {
+//             let Some(find) = each_node_splits.get_mut(&split.node_id) else {
+//                 missing.push((*split).clone());
+//                 continue;
+//             };
+//             find.1 = Some(split.clone());
+//             if split.data_offset + split.data_size > max_size {
+//                 max_size = split.data_offset + split.data_size;
+//             }
+//         }
+
+//         if missing.len() > 0 {
+//             return Err(WsDataError::SplitRecoverMissing {
+//                 unique_id: unique_id.to_owned(),
+//                 idx,
+//                 missing,
+//             }
+//             .into());
+//         }
+
+//         let mut recover = vec![0; max_size.try_into().unwrap()];
+
+//         for (_node, (data, splitmeta)) in each_node_splits {
+//             let splitmeta = splitmeta.unwrap();
+//             let begin = splitmeta.data_offset as usize;
+//             let end = begin + splitmeta.data_size as usize;
+//             recover[begin..end].copy_from_slice(data.as_ref());
+//         }
+
+//         Ok(recover)
+//     }
+// }
+
+impl Into<proto::EachNodeSplit> for EachNodeSplit {
+    fn into(self) -> proto::EachNodeSplit {
+        proto::EachNodeSplit {
+            node_id: self.node_id,
+            data_offset: self.data_offset,
+            data_size: self.data_size,
+        }
+    }
+}
+
+impl Into<proto::DataSplit> for DataSplit {
+    fn into(self) -> proto::DataSplit {
+        proto::DataSplit {
+            splits: self.splits.into_iter().map(|s| s.into()).collect(),
+        }
+    }
+}
+//     uint32 split_size = 1;
+//   repeated uint32 node_ids = 2;
+
+macro_rules! generate_cache_mode_methods {
+    // The macro takes a list of pairs of the form [time, mask] and generates methods.
+    ($(($group:ident, $mode:ident)),*) => {
+        paste!{
+            impl CacheModeVisitor {
+                $(
+                    pub fn [<is _ $group _ $mode>](&self) -> bool {
+                        (self.0 & [<CACHE_MODE_ $group:upper _MASK>]) ==
+                            ([<CACHE_MODE_ $group:upper _ $mode:upper _MASK>] & [<CACHE_MODE_ $group:upper _MASK>])
                     }
-                    Err(e) => {
-                        tracing::error!("Failed to decode data item: {:?}", e);
-                        success = false;
-                        message = format!("Failed to decode data item: {:?}", e);
-                        break;
+                )*
+            }
+            impl DataSetMetaBuilder {
+                $(
+                    pub fn [<cache_mode_ $group _ $mode>](&mut self, idx: DataItemIdx) -> &mut Self {
+                        self.assert_cache_mode_len();
+                        self.building.as_mut().unwrap().cache_mode[idx as usize] =
+                            (self.building.as_mut().unwrap().cache_mode[idx as usize] & ![<CACHE_MODE_ $group:upper _MASK>]) |
+                            ([<CACHE_MODE_ $group:upper _ $mode:upper _MASK>] & [<CACHE_MODE_ $group:upper _MASK>]);
+                        self
                     }
-                }
+                )*
             }
         }
+    };
+}
+generate_cache_mode_methods!(
+    (time, forever),
+    (time, auto),
+    (pos, allnode),
+    (pos, specnode),
+    (pos, auto),
+    (map, common_kv),
+    (map, file),
+    (redundancy, replica)
+);
 
-        responsor
-            .send_resp(proto::GetOneDataResponse {
-                success,
-                data: got_or_deleted_checked,
-                message,
-            })
-            .await?;
-
-        Ok(())
+impl CacheModeVisitor {
+    /// Parity shard count of an erasure coded item, the splits are its shards
+    pub fn erasure_parity_shards(&self) -> Option<usize> {
+        match self.0 & CACHE_MODE_REDUNDANCY_MASK {
+            0 => None,
+            m => Some(m as usize),
+        }
     }
+}
 
-    // 处理批量数据写入请求
-    pub async fn rpc_handle_batch_data(
-        &self,
-        responsor: RPCResponsor<proto::BatchDataRequest>,
-        req: proto::BatchDataRequest,
-    ) -> WSResult<()> {
-        tracing::debug!("rpc_handle_batch_data with batchid({:?})", req.request_id.clone().unwrap());
-        let batch_receive_states = self.batch_receive_states.clone();
-        // 预先克隆闭包外需要的字段
-        let block_index = req.block_index;
-        let data = req.data.clone();
-        let request_id = req.request_id.clone().unwrap();   
-
-        // 1. 查找或创建状态
-        let state = match self.batch_receive_states
-            .get_or_init(req.request_id.clone().unwrap(), async move {
-                // 创建任务组和句柄
-                let (mut group, handle) = match WriteSplitDataTaskGroup::new(    
-                    req.unique_id.clone(),
-                    req.total_size as usize,
-                    // req.block_type(),      类型错误    曾俊
-                    req.block_type.unwrap().data_item_dispatch.unwrap(),
-                    req.version,
-                ).await {
-                    Ok((group, handle)) => (group, handle),
-                    Err(e) => {
-                        tracing::error!("Failed to create task group: {:?}", e);
-                        return Err(e);
-                    }
-                };
-
-                // 再process之前订阅，避免通知先于订阅
-                let waiter = handle.get_all_tasks_waiter();
-
-                // 启动process_tasks
-                let _ = tokio::spawn(async move {
-                    match group.process_tasks().await {
-                        Ok(item) => Ok(item),
-                        Err(e) => {
-                            tracing::error!("Failed to process tasks: {}", e);
-                            Err(e)
-                        }
-                    }
-                });
-
-                let state = Arc::new(BatchReceiveState::new(handle, SharedWithBatchHandler::new()));
-                let state_clone = state.clone();
-
-                // response task
-                let _=tokio::spawn(async move {
-                    tracing::debug!("rpc_handle_batch_data response task started");
-                    // 等待所有任务完成
-                    if let Err(e) = waiter.wait().await {
-                        tracing::error!("Failed to wait for tasks: {}", e);
-                        todo!("use responsor to send error response");
-                        return;
-                    }
+impl DataSetMetaBuilder {
+    pub fn cache_mode_redundancy_erasure(&mut self, idx: DataItemIdx, parity_shards: usize) -> &mut Self {
+        assert!(parity_shards > 0 && parity_shards <= CACHE_MODE_REDUNDANCY_MASK as usize);
+        self.assert_cache_mode_len();
+        let mode = &mut self.building.as_mut().unwrap().cache_mode[idx as usize];
+        *mode = (*mode & !CACHE_MODE_REDUNDANCY_MASK) | parity_shards as u16;
+        self
+    }
 
-                    tracing::debug!("rpc_handle_batch_data response task wait all tasks done");
+    /// Time, position and map of the item as the policy says, the redundancy is kept
+    pub fn cache_mode_by_policy(&mut self, idx: DataItemIdx, policy: &CachePolicy) -> &mut Self {
+        let _ = match policy.time {
+            CacheTime::Forever => self.cache_mode_time_forever(idx),
+            CacheTime::Auto => self.cache_mode_time_auto(idx),
+        };
+        let _ = match policy.pos {
+            CachePos::AllNode => self.cache_mode_pos_allnode(idx),
+            CachePos::SpecNode => self.cache_mode_pos_specnode(idx),
+            CachePos::Auto => self.cache_mode_pos_auto(idx),
+        };
+        match policy.map {
+            CacheMap::Kv => self.cache_mode_map_common_kv(idx),
+            CacheMap::File => self.cache_mode_map_file(idx),
+        }
+    }
+}
 
-                    // 发送最终响应
-                    if let Some(final_responsor) = state_clone.shared.get_final_responsor().await {
-                        if let Err(e) = final_responsor.send_resp(proto::BatchDataResponse {
-                            request_id: Some(req.request_id.clone().unwrap()),
-                            success: true,
-                            error_message: String::new(),
-                            version: state_clone.handle.version(),
-                        }).await {
-                            tracing::error!("Failed to send final response: {}", e);
-                        }
-                    }
+#[test]
+fn test_cache_mode_visitor() {
+    let cache_mode_visitor = CacheModeVisitor(CACHE_MODE_TIME_FOREVER_MASK);
+    assert!(cache_mode_visitor.is_time_forever());
+    assert!(!cache_mode_visitor.is_time_auto());
 
-                    // 清理状态
-                    let _=batch_receive_states.remove(&req.request_id.unwrap());
-                });
+    let cache_mode_visitor = CacheModeVisitor(CACHE_MODE_POS_ALLNODE_MASK);
+    assert!(cache_mode_visitor.is_pos_allnode());
+    assert!(!cache_mode_visitor.is_pos_specnode());
+    assert!(!cache_mode_visitor.is_pos_auto());
 
-                Ok(state)
-            })
-            .await {
-            Err(e) => return Err(WSError::WsDataError(WsDataError::BatchTransferError {
-                request_id,
-                msg: format!("Failed to initialize batch state: {}", e)
-            })),
-            Ok(state) => state,
-        };
+    let cache_mode_visitor = CacheModeVisitor(CACHE_MODE_MAP_FILE_MASK);
+    assert!(cache_mode_visitor.is_map_file());
+    assert!(!cache_mode_visitor.is_map_common_kv());
 
-        tracing::debug!("rpc_handle_batch_data ready with write_split_data_task_group");
+    // test builder
 
-        // 2. 提交分片数据
-        let data_item = proto::DataItem {
-            data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(data)),
-            ..Default::default()
-        };
+    let meta = DataSetMetaBuilder::new()
+        .set_data_splits(vec![DataSplit { splits: vec![] }])
+        .cache_mode_map_file(0)
+        .cache_mode_time_forever(0)
+        .build();
+    assert!(meta.cache_mode_visitor(0).is_map_file());
+    assert!(!meta.cache_mode_visitor(0).is_map_common_kv());
+    assert!(meta.cache_mode_visitor(0).is_time_forever());
+    assert!(!meta.cache_mode_visitor(0).is_time_auto());
+    let meta = DataSetMetaBuilder::new()
+        .set_data_splits(vec![DataSplit { splits: vec![] }])
+        .cache_mode_map_common_kv(0)
+        .cache_mode_time_forever(0)
+        .build();
+    assert!(meta.cache_mode_visitor(0).is_map_common_kv());
+    assert!(!meta.cache_mode_visitor(0).is_map_file());
+    assert!(meta.cache_mode_visitor(0).is_time_forever());
+    assert!(!meta.cache_mode_visitor(0).is_time_auto());
+    assert!(meta.cache_mode_visitor(0).is_redundancy_replica());
+    assert_eq!(meta.cache_mode_visitor(0).erasure_parity_shards(), None);
 
-        tracing::debug!("submit_split with data split idx: {}, at node: {}", block_index, self.view.p2p().nodes_config.this_node());
-        state.handle.submit_split(
-            block_index as usize * DEFAULT_BLOCK_SIZE,
-            data_item,
-        ).await?;
+    let meta = DataSetMetaBuilder::new()
+        .set_data_splits(vec![DataSplit { splits: vec![] }])
+        .cache_mode_map_common_kv(0)
+        .cache_mode_redundancy_erasure(0, 2)
+        .build();
+    assert!(meta.cache_mode_visitor(0).is_map_common_kv());
+    assert!(!meta.cache_mode_visitor(0).is_redundancy_replica());
+    assert_eq!(meta.cache_mode_visitor(0).erasure_parity_shards(), Some(2));
+}
 
-        // 3. 更新响应器
-        state.shared.update_responsor(responsor).await;
+pub struct DataSetMetaBuilder {
+    building: Option<DataSetMetaV2>,
+}
+impl From<DataSetMetaV2> for DataSetMetaBuilder {
+    fn from(d: DataSetMetaV2) -> Self {
+        Self { building: Some(d) }
+    }
+}
+impl DataSetMetaBuilder {
+    pub fn new() -> Self {
+        Self {
+            building: Some(DataSetMetaV2 {
+                version: 0,
+                datas_splits: vec![],
+                data_metas: vec![],
+                api_version: DATA_SET_META_API_VERSION,
+                synced_nodes: HashSet::new(),
+                cache_mode: vec![],
+                data_checksums: vec![],
+                compression: DataCompression::Raw,
+            }),
+        }
+    }
+    fn assert_cache_mode_len(&self) {
+        if self.building.as_ref().unwrap().cache_mode.len() == 0 {
+            panic!("please set_data_splits before set_cache_mode");
+        }
+    }
 
-        Ok(())
+    pub fn version(&mut self, version: u64) -> &mut Self {
+        self.building.as_mut().unwrap().version = version;
+        self
     }
 
+    #[must_use]
+    pub fn set_data_splits(&mut self, splits: Vec<DataSplit>) -> &mut Self {
+        let building = self.building.as_mut().unwrap();
+        building.datas_splits = splits;
+        building.cache_mode = vec![0; building.datas_splits.len()];
+        self
+    }
 
+    pub fn set_cache_mode(&mut self, idx: DataItemIdx, mode: u16) -> &mut Self {
+        self.building.as_mut().unwrap().cache_mode[idx as usize] = mode;
+        self
+    }
 
-    //费新文
-    // pub async fn distribute_task_to_worker(
-    //     &self,
-    //     worker_node: NodeID,
-    //     app_name: String,
-    //     func_name: String,
-    //     task_id: u32,
-    //     key: Option<Vec<u8>>,
-    //     is_write_trigger: bool,
-    // ) -> WSResult<DistributeTaskResp> {
-    //     let mut req = DistributeTaskReq {
-    //         app: app_name,
-    //         func: func_name,
-    //         task_id,
-    //         trigger: None,
-    //     };
+    pub fn set_cache_mode_for_all(&mut self, mode: Vec<u16>) -> &mut Self {
+        self.building.as_mut().unwrap().cache_mode = mode;
+        assert_eq!(
+            self.building.as_mut().unwrap().cache_mode.len(),
+            self.building.as_mut().unwrap().datas_splits.len(),
+            "cache mode len must be equal to data splits len"
+        );
+        self
+    }
 
-    //     if let Some(key_data) = key {
-    //         if is_write_trigger {
-    //             req.trigger = Some(proto::sche::distribute_task_req::Trigger::EventWrite(
-    //                 proto::sche::distribute_task_req::DataEventTriggerWrite {
-    //                     key: key_data,
-    //                     opeid: task_id,
-    //                 }
-    //             ));
-    //         } else {
-    //             req.trigger = Some(proto::sche::distribute_task_req::Trigger::EventNew(
-    //                 proto::sche::distribute_task_req::DataEventTriggerNew {
-    //                     key: key_data,
-    //                     opeid: task_id,
-    //                 }
-    //             ));
-    //         }
-    //     }
+    /// Ignored unless there is one checksum for each item
+    pub fn set_data_checksums(&mut self, checksums: Vec<Vec<u8>>) -> &mut Self {
+        let building = self.building.as_mut().unwrap();
+        building.data_checksums = if checksums.len() == building.datas_splits.len() {
+            checksums
+        } else {
+            vec![]
+        };
+        self
+    }
 
-    //     self.rpc_call_distribute_task
-    //         .call(
-    //             self.view.p2p(),
-    //             worker_node,
-    //             req,
-    //             Some(Duration::from_secs(30)),
-    //         )
-    //         .await
-    //         .map_err(|e| WsNetworkLogicErr::RPCErr {   //这里错误类型需要换
-    //             message: e.to_string(),
-    //         }.into())
-    // }
+    pub fn set_compression(&mut self, compression: DataCompression) -> &mut Self {
+        self.building.as_mut().unwrap().compression = compression;
+        self
+    }
 
-    // async fn rpc_handle_distribute_task(
-    //     &self,
-    //     responsor: RPCResponsor<DistributeTaskReq>,
-    //     req: DistributeTaskReq,
-    // ) {
-    //     tracing::debug!("rpc_handle_distribute_task with req({:?})", req);
-        
-    //     // TODO: 这里需要实现具体的任务处理逻辑
-        
-    //     if let Err(e) = responsor
-    //         .send_resp(DistributeTaskResp {
-    //             success: true,
-    //             err_msg: String::new(),
-    //         })
-    //         .await {
-    //             tracing::error!("Failed to send distribute task response: {}", e);
-    //         }
-    // }
+    pub fn build(&mut self) -> DataSetMetaV2 {
+        self.building.take().unwrap()
+    }
+}
 
-    
+pub struct GetOrDelDataArg {
+    pub meta: Option<DataSetMetaV2>,
+    pub unique_id: Vec<u8>,
+    pub ty: GetOrDelDataArgType,
+}
 
+/// options of [`DataGeneral::write_data_with`]
+#[derive(Debug, Clone, Default)]
+pub struct WriteDataOpts {
+    /// checked by master under the version schedule
+    pub condition: Option<proto::data_write_condition::Cond>,
+    /// write of a kv transaction prepared on master, datasets reserved by the transaction
+    ///  are only writable with its id, 0 means not in transaction
+    pub txn_id: u64,
+    /// the dataset expires after ttl, 0 means never expire
+    pub ttl_ms: u64,
+    /// compression asked for the dataset, granted by master only if every node decodes it,
+    ///  small data is left raw anyway
+    pub compression: DataCompression,
+    /// cache mode of each item, empty to let master plan them
+    pub cache_modes: Vec<CacheMode>,
+}
 
+/// result of [`DataGeneral::write_data_cond`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CondWriteRes {
+    /// written with the new dataset version
+    Written(DataVersion),
+    /// condition not met, carries the current dataset version, 0 means not exist
+    Rejected(DataVersion),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct DataMetaSys {
-    pub cache: i32,
-    pub distribute: i32,
+#[derive(Debug, Clone)]
+pub enum GetOrDelDataArgType {
+    All,
+    Delete,
+    PartialOne { idx: DataItemIdx },
+    PartialMany { idxs: BTreeSet<DataItemIdx> },
 }
-impl From<DataMeta> for DataMetaSys {
-    fn from(d: DataMeta) -> Self {
-        Self {
-            cache: d.cache,
-            distribute: d.distribute,
+
+impl DataGeneralView {
+    /// `ope_node` asks for it for the function `deleted_by`, both recorded in the audit log
+    ///  when master deletes the dataset. A delete on master returns after the nodes caching
+    ///  the meta dropped it.
+    async fn get_data_meta_local(
+        &self,
+        unique_id: &[u8],
+        delete: bool,
+        ope_node: NodeID,
+        deleted_by: Option<&proto::DataOpeRoleFuncCall>,
+    ) -> WSResult<Option<(KvVersion, DataSetMetaV2)>> {
+        let ope_name = if delete { "delete" } else { "get" };
+        tracing::debug!("{} data meta for uid({:?})", ope_name, unique_id);
+
+        let kv_store_engine = self.kv_store_engine();
+        let key = KeyTypeDataSetMeta(&unique_id);
+        let keybytes = key.make_key();
+
+        let mut invalidated = None;
+        let meta_opt = {
+            let write_lock = kv_store_engine.with_rwlock(&keybytes);
+            let _guard = write_lock.write();
+
+            if delete {
+                let (meta_opt, inv) =
+                    self.del_data_meta_locked(unique_id, None, ope_node, deleted_by)?;
+                invalidated = inv;
+                meta_opt
+            } else {
+                kv_store_engine.get(&key, true, KvAdditionalConf {})
+            }
+        };
+        if let Some(invalidated) = invalidated {
+            invalidated.await;
         }
+        Ok(meta_opt)
     }
-}
-impl Into<DataMeta> for DataMetaSys {
-    fn into(self) -> DataMeta {
-        DataMeta {
-            cache: self.cache,
-            distribute: self.distribute,
+
+    /// Caller holds the dataset's meta write lock. With `expected_version` the meta is only
+    ///  deleted at that version, `None` is returned if it was rewritten.
+    pub(crate) fn del_data_meta_locked(
+        &self,
+        unique_id: &[u8],
+        expected_version: Option<u64>,
+        ope_node: NodeID,
+        deleted_by: Option<&proto::DataOpeRoleFuncCall>,
+    ) -> WSResult<(
+        Option<(KvVersion, DataSetMetaV2)>,
+        Option<impl Future<Output = ()> + Send + 'static>,
+    )> {
+        let kv_store_engine = self.kv_store_engine();
+        let key = KeyTypeDataSetMeta(&unique_id);
+        let is_master = self.p2p().nodes_config.this.1.is_master();
+
+        if let Some(expected) = expected_version {
+            let cur_version = kv_store_engine
+                .get(&key, true, KvAdditionalConf {})
+                .map(|(_, meta)| meta.version);
+            if cur_version != Some(expected) {
+                return Ok((None, None));
+            }
         }
+        // master records the delete before it's made, one that can't be recorded fails
+        if is_master {
+            if let Some((_, meta)) = kv_store_engine.get(&key, true, KvAdditionalConf {}) {
+                self.data_master()
+                    .audit_delete(unique_id, meta.version, ope_node, deleted_by)?;
+            }
+        }
+        let meta_opt = kv_store_engine.del(key, true)?;
+        // fn kv index only exists on master, deleting a missing key is a no-op
+        if meta_opt.is_some() && unique_id.starts_with(DATA_UID_PREFIX_FN_KV.as_bytes()) {
+            let _ = kv_store_engine.del(KeyTypeFnKvIndex(unique_id), false)?;
+        }
+        // expiry is also only on master
+        if meta_opt.is_some() {
+            kv_store_engine.set_data_ttl(unique_id, 0)?;
+        }
+        // the meta on master is the source of truth, data nodes only drop their copies
+        let mut invalidated = None;
+        if meta_opt.is_some() && is_master {
+            self.kv_watch()
+                .notify(unique_id, 0, proto::kv::KvWatchOp::Delete);
+            invalidated = Some(self.data_master().invalidate_meta_caches(
+                unique_id,
+                0,
+                &HashSet::new(),
+            ));
+            self.data_master().release_usage(unique_id)?;
+        }
+        Ok((meta_opt, invalidated))
     }
-}
-
-/// 数据集元信息
-#[derive(Serialize, Deserialize)]
-pub struct DataSetMetaV1 {
-    // unique_id: Vec<u8>,
-    pub version: u64,
-    pub data_metas: Vec<DataMetaSys>,
-    pub synced_nodes: HashSet<NodeID>,
-}
 
-/// 数据集元信息
-///
-/// 注意：新建元信息请使用 `DataSetMetaBuilder`
-///
-/// https://fvd360f8oos.feishu.cn/docx/XoFudWhAgox84MxKC3ccP1TcnUh#share-Tqqkdxubpokwi5xREincb1sFnLc
-#[derive(Serialize, Deserialize, Debug,Clone)]
-pub struct DataSetMetaV2 {
-    // unique_id: Vec<u8>,
-    api_version: u8,
-    pub version: u64,
-    pub datas_splits: Vec<DataSplit>,
-    pub data_metas: Vec<DataMetaSys>,
-    pub synced_nodes: HashSet<NodeID>,
-    pub cache_mode: Vec<CacheMode>,
-}
+    pub async fn get_metadata(
+        &self,
+        unique_id: &[u8],
+        delete: bool,
+        txn_id: u64,
+    ) -> WSResult<DataSetMetaV2> {
+        // 先尝试从本地获取
+        let this_node = self.p2p().nodes_config.this_node();
+        if let Some((_version, meta)) = self
+            .get_data_meta_local(unique_id, delete, this_node, None)
+            .await?
+        {
+            return Ok(meta);
+        }
 
-impl DataSetMetaV2 {
-    pub fn cache_mode_visitor(&self, idx: DataItemIdx) -> CacheModeVisitor {
-        CacheModeVisitor(self.cache_mode[idx as usize])
-    }
-    
-    pub fn data_item_cnt(&self) -> usize {
-        self.datas_splits.len()
+        // 本地不存在，从 master 获取
+        self.data_general()
+            .get_or_del_datameta_from_master(unique_id, delete, txn_id)
+            .await
     }
+}
 
-    pub fn get_data_node(&self, idx: DataItemIdx) -> NodeID {
-        // 获取指定数据项的主节点
-        self.datas_splits[idx as usize].splits[0].node_id
+impl From<JoinError> for WSError {
+    fn from(err: JoinError) -> Self {
+        WsNetworkLogicErr::TaskJoinError { err }.into()
     }
 }
 
-pub type DataSetMeta = DataSetMetaV2;
+#[async_trait]
+impl LogicalModule for DataGeneral {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: DataGeneralView::new(args.logical_modules_ref.clone()),
+            rpc_call_data_version_schedule: RPCCaller::new(),
+            rpc_call_write_once_data: RPCCaller::new(),
+            rpc_call_batch_data: RPCCaller::new(),
+            rpc_call_batch_resume: RPCCaller::new(),
+            rpc_call_get_data_meta: RPCCaller::new(),
+            rpc_call_get_data: RPCCaller::new(),
+            rpc_call_data_stream_open: RPCCaller::new(),
+            rpc_call_data_stream_segment: RPCCaller::new(),
+            rpc_call_data_stream_commit: RPCCaller::new(),
 
-// message EachNodeSplit{
-//     uint32 node_id=1;
-//     uint32 data_offset=2;
-//     uint32 data_size=3;
-//   }
+            // //费新文
+            // rpc_call_distribute_task: RPCCaller::new(),
+            // rpc_handler_distribute_task: RPCHandler::new(),
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct EachNodeSplit {
-    pub node_id: NodeID,
-    pub data_offset: u32,
-    pub data_size: u32,
-    pub cache_mode: u32,  // 添加 cache_mode 字段
-}
 
-impl EachNodeSplit {
-    pub fn cache_mode_visitor(&self) -> CacheModeVisitor {
-        CacheModeVisitor(self.cache_mode as u16)
+            rpc_handler_write_once_data: RPCHandler::new(),
+            rpc_handler_batch_data: RPCHandler::new(),
+            rpc_handler_batch_resume: RPCHandler::new(),
+            rpc_handler_data_meta_update: RPCHandler::new(),
+            rpc_handler_get_data_meta: RPCHandler::new(),
+            rpc_handler_get_data: RPCHandler::new(),
+            rpc_handler_data_stream_segment: RPCHandler::new(),
+            rpc_handler_data_stream_commit: RPCHandler::new(),
+            
+            // 批量数据接收状态管理
+            batch_receive_states: AsyncInitMap::new(),
+            meta_cache: new_meta_cache(),
+            meta_cache_epoch: AtomicU64::new(0),
+            read_latency: dashmap::DashMap::new(),
+        }
     }
-}
 
-/// 数据项的分片信息
-/// 我们需要知道每个数据项的分片大小
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct DataSplit {
-    pub splits: Vec<EachNodeSplit>,
-}
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        tracing::info!("start as master");
 
-pub type DataSplitIdx = usize;
+        let p2p = self.view.p2p();
+        // register rpc callers
+        {
+            self.rpc_call_data_version_schedule.regist(p2p);
+            self.rpc_call_write_once_data.regist(p2p);
+            self.rpc_call_batch_data.regist(p2p);
+            self.rpc_call_batch_resume.regist(p2p);
+            self.rpc_call_get_data_meta.regist(p2p);
+            self.rpc_call_get_data.regist(p2p);
+            self.rpc_call_data_stream_open.regist(p2p);
+            self.rpc_call_data_stream_segment.regist(p2p);
+            self.rpc_call_data_stream_commit.regist(p2p);
+            self.rpc_call_replicate_item.regist(p2p);
 
-// impl DataSplit {
-//     /// node_2_datas will be consumed partially
-//     pub fn recorver_data(
-//         &self,
-//         unique_id: &[u8],
-//         idx: DataItemIdx,
-//         node_2_datas: &mut HashMap<(NodeID, DataItemIdx), proto::DataItem>,
-//     ) -> WSResult<Vec<u8>> {
-//         let nodes = node_2_datas
-//             .iter()
-//             .filter(|v| v.0 .1 == idx)
-//             .map(|v| v.0 .0)
-//             .collect::<Vec<_>>();
 
-//         let mut each_node_splits: HashMap<NodeID, (proto::DataItem, Option<EachNodeSplit>)> =
-//             HashMap::new();
+            //费新文
+            // self.rpc_call_distribute_task.regist(p2p);
+        }
 
-//         for node in nodes {
-//             let data = node_2_datas.remove(&(node, idx)).unwrap();
-//             let _ = each_node_splits.insert(node, (data, None));
-//         }
+        // register rpc handlers
+        {
 
-//         let mut max_size = 0;
-//         let mut missing = vec![];
+            //费新文
+            // let view = self.view.clone();
+            // self.rpc_handler_distribute_task.regist(
+            //     p2p,
+            //     move |responsor: RPCResponsor<DistributeTaskReq>,
+            //           req: DistributeTaskReq| {
+            //         let view = view.clone();
+            //         let _ = tokio::spawn(async move {
+            //             view.data_general().rpc_handle_distribute_task(responsor, req).await;
+            //         });
+            //         Ok(())
+            //     },
+            // );
+            
 
-//         // zip with split info
-//         //  by the way, check if the split is missing
-//         for split in &self.splits {
-//             let Some(find) = each_node_splits.get_mut(&split.node_id) else {
-//                 missing.push((*split).clone());
-//                 continue;
-//             };
-//             find.1 = Some(split.clone());
-//             if split.data_offset + split.data_size > max_size {
-//                 max_size = split.data_offset + split.data_size;
-//             }
-//         }
+            let view = self.view.clone();
+            self.rpc_handler_write_once_data
+                .regist(p2p, move |responsor, req| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        view.data_general().rpc_handle_write_one_data(responsor, req).await;
+                    });
+                    Ok(())
+                });
 
-//         if missing.len() > 0 {
-//             return Err(WsDataError::SplitRecoverMissing {
-//                 unique_id: unique_id.to_owned(),
-//                 idx,
-//                 missing,
-//             }
-//             .into());
-//         }
+            let view = self.view.clone();
+            self.rpc_handler_batch_data.regist(
+                p2p,
+                move |responsor: RPCResponsor<proto::BatchDataRequest>,
+                      req: proto::BatchDataRequest| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        let _ = view.data_general().rpc_handle_batch_data(responsor, req).await;
+                    });
+                    Ok(())
+                },
+            );
 
-//         let mut recover = vec![0; max_size.try_into().unwrap()];
+            let view = self.view.clone();
+            self.rpc_handler_batch_resume.regist(
+                p2p,
+                move |responsor: RPCResponsor<proto::BatchResumeRequest>,
+                      req: proto::BatchResumeRequest| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        let _ = view.data_general().rpc_handle_batch_resume(responsor, req).await;
+                    });
+                    Ok(())
+                },
+            );
+
+            let view = self.view.clone();
+            self.rpc_handler_data_meta_update.regist(
+                p2p,
+                move |responsor: RPCResponsor<proto::DataMetaUpdateRequest>,
+                      req: proto::DataMetaUpdateRequest| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        view.data_general().rpc_handle_data_meta_update(responsor, req).await
+                    });
+                    Ok(())
+                },
+            );
+
+            let view = self.view.clone();
+            self.rpc_handler_get_data_meta
+                .regist(p2p, move |responsor, req| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        if let Err(e) = view.data_general().rpc_handle_get_data_meta(req, responsor)             //返回结果未处理    曾俊
+                            .await{
+                                tracing::error!("Failed to handle get data meta: {}", e);
+                            }
+                        // .todo_handle("rpc_handle_get_data_meta err");
+                    });
+                    Ok(())
+                });
+
+            let view = self.view.clone();
+            self.rpc_handler_get_data.regist(
+                p2p,
+                move |responsor: RPCResponsor<proto::GetOneDataRequest>,
+                      req: proto::GetOneDataRequest| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move { 
+                        view.data_general().rpc_handle_get_one_data(responsor, req).await 
+                    });
+                    Ok(())
+                },
+            );
 
-//         for (_node, (data, splitmeta)) in each_node_splits {
-//             let splitmeta = splitmeta.unwrap();
-//             let begin = splitmeta.data_offset as usize;
-//             let end = begin + splitmeta.data_size as usize;
-//             recover[begin..end].copy_from_slice(data.as_ref());
-//         }
+            let view = self.view.clone();
+            self.rpc_handler_data_stream_segment
+                .regist(p2p, move |responsor, req| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        view.data_general().rpc_handle_data_stream_segment(responsor, req).await;
+                    });
+                    Ok(())
+                });
 
-//         Ok(recover)
-//     }
-// }
+            let view = self.view.clone();
+            self.rpc_handler_data_stream_commit
+                .regist(p2p, move |responsor, req| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        view.data_general().rpc_handle_data_stream_commit(responsor, req).await;
+                    });
+                    Ok(())
+                });
 
-impl Into<proto::EachNodeSplit> for EachNodeSplit {
-    fn into(self) -> proto::EachNodeSplit {
-        proto::EachNodeSplit {
-            node_id: self.node_id,
-            data_offset: self.data_offset,
-            data_size: self.data_size,
+            let view = self.view.clone();
+            self.rpc_handler_replicate_item
+                .regist(p2p, move |responsor, req| {
+                    let view = view.clone();
+                    let _ = tokio::spawn(async move {
+                        view.data_general()
+                            .rpc_handle_replicate_item(responsor, req)
+                            .await;
+                    });
+                    Ok(())
+                });
         }
+
+        Ok(vec![])
     }
 }
 
-impl Into<proto::DataSplit> for DataSplit {
-    fn into(self) -> proto::DataSplit {
-        proto::DataSplit {
-            splits: self.splits.into_iter().map(|s| s.into()).collect(),
-        }
+#[derive(Debug, Clone, Copy)]
+pub struct CacheModeVisitor(pub u16);
+
+#[cfg(test)]
+mod test {
+    use super::{
+        bytes_checksum,
+        compress::DataCompression,
+        dataitem::{
+            batch_stage_path, decode_persist_item, file_read_range, item_range,
+            mapped_item_file_name, parse_mapped_item_file_name, DataItemArgWrapper,
+            PERSIST_TAG_MAPPED_FILE,
+        },
+        proto, Bytes, CondWriteRes, DataCache, DataGeneral, DataMaster, DataMetaSys,
+        DataSetMetaBuilder, DataSetMetaV2, DataSplit, EachNodeSplit, GetOrDelDataArg,
+        GetOrDelDataArgType, KeyTypeDataSetItem, KeyTypeDataSetItemVersion, KeyTypeDataSetMeta,
+        KvAdditionalConf, KvStoreEngine, NodeID, P2PModule, WSError, WriteDataOpts, WsDataError,
+        CACHE_MODE_SPLIT_EXTRA_COPY, DATA_SET_META_API_VERSION,
+    };
+    use super::{fn_kv_ns_key, fn_kv_user_key, new_data_unique_id_fn_kv, split_fn_kv_ns_key};
+    use super::{read_hedge_delay, smoothed_read_latency, READ_HEDGE_DEFAULT_DELAY};
+    use crate::{
+        general::{
+            app::{
+                AppMeta, AppType, CacheMap, CachePolicy, CachePos, CacheTime, DataAccess, FnMeta,
+                FnSyncAsyncSupport, KeyPattern,
+            },
+            test_utils,
+        },
+        logical_module_view_impl,
+        master::app::m_app_master::MasterAppMgmt,
+        master::data::data_audit::{AuditOp, AuditQuery},
+        sys::LogicalModulesRef,
+    };
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    };
+
+    logical_module_view_impl!(TestView);
+    logical_module_view_impl!(TestView, data_general, DataGeneral);
+    logical_module_view_impl!(TestView, kv_store_engine, KvStoreEngine);
+    logical_module_view_impl!(TestView, app_master, Option<MasterAppMgmt>);
+    logical_module_view_impl!(TestView, p2p, P2PModule);
+    logical_module_view_impl!(TestView, data_cache, DataCache);
+    logical_module_view_impl!(TestView, data_master, Option<DataMaster>);
+
+    #[test]
+    fn test_decode_persist_meta_layouts() {
+        // api version 2 with checksums
+        let old_meta = (
+            2u8,
+            4u64,
+            vec![vec![(1 as NodeID, 0u32, u32::MAX, 0u32)]],
+            Vec::<DataMetaSys>::new(),
+            [2 as NodeID].into_iter().collect::<HashSet<_>>(),
+            vec![0u16],
+            vec![bytes_checksum(b"x")],
+        );
+        let meta = DataSetMetaV2::decode_persist(&bincode::serialize(&old_meta).unwrap()).unwrap();
+        assert_eq!(meta.api_version, DATA_SET_META_API_VERSION);
+        assert_eq!(meta.version, 4);
+        assert_eq!(meta.datas_splits[0].splits[0].data_size, u32::MAX as u64);
+        assert_eq!(meta.data_checksums, vec![bytes_checksum(b"x")]);
+        assert!(meta.synced_nodes.contains(&2));
+        assert_eq!(meta.compression, DataCompression::Raw);
+
+        // api version 3, u64 sizes without compression
+        let old_meta = (
+            3u8,
+            6u64,
+            vec![vec![(1 as NodeID, 0u64, 5u64 << 30, 0u32)]],
+            Vec::<DataMetaSys>::new(),
+            HashSet::<NodeID>::new(),
+            vec![0u16],
+            Vec::<Vec<u8>>::new(),
+        );
+        let meta = DataSetMetaV2::decode_persist(&bincode::serialize(&old_meta).unwrap()).unwrap();
+        assert_eq!(meta.version, 6);
+        assert_eq!(meta.datas_splits[0].splits[0].data_size, 5 << 30);
+        assert_eq!(meta.compression, DataCompression::Raw);
+
+        // current layout with sizes past u32
+        let mut builder = DataSetMetaBuilder::new();
+        let _ = builder.set_data_splits(vec![DataSplit {
+            splits: vec![EachNodeSplit {
+                node_id: 1,
+                data_offset: 1 << 32,
+                data_size: 5 << 30,
+                cache_mode: 0,
+            }],
+        }]);
+        let _ = builder.set_compression(DataCompression::Zstd);
+        let bytes = bincode::serialize(&builder.build()).unwrap();
+        let meta = DataSetMetaV2::decode_persist(&bytes).unwrap();
+        assert_eq!(meta.datas_splits[0].splits[0].data_offset, 1 << 32);
+        assert_eq!(meta.datas_splits[0].splits[0].data_size, 5 << 30);
+        assert_eq!(meta.compression, DataCompression::Zstd);
+
+        let mut unknown = bytes.clone();
+        unknown[0] = DATA_SET_META_API_VERSION + 1;
+        assert!(DataSetMetaV2::decode_persist(&unknown).is_none());
     }
-}
-//     uint32 split_size = 1;
-//   repeated uint32 node_ids = 2;
 
-macro_rules! generate_cache_mode_methods {
-    // The macro takes a list of pairs of the form [time, mask] and generates methods.
-    ($(($group:ident, $mode:ident)),*) => {
-        paste!{
-            impl CacheModeVisitor {
-                $(
-                    pub fn [<is _ $group _ $mode>](&self) -> bool {
-                        (self.0 & [<CACHE_MODE_ $group:upper _MASK>]) ==
-                            ([<CACHE_MODE_ $group:upper _ $mode:upper _MASK>] & [<CACHE_MODE_ $group:upper _MASK>])
-                    }
-                )*
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_read_erasure_coded_item_with_lost_holders() {
+        // nodes are taken down, so the cluster is not the shared one
+        let cluster = test_utils::start_test_cluster(2411, 4).await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        // node 0 is the master, the others are workers
+        let views: Vec<TestView> = cluster
+            .iter()
+            .map(|(_, modules)| TestView::new(modules.clone()))
+            .collect();
+        // 3 shards on 4 nodes, an item survives the loss of any 1 of its holders
+        let access: DataAccess = serde_yaml::from_str(
+            "{set: true, get: true, delete: false, event: null, \
+              erasure: {data_shards: 2, parity_shards: 1}}",
+        )
+        .unwrap();
+        let app_meta = AppMeta::new(AppType::Wasm, HashMap::new());
+        views[0].app_master().fddg.add_redundancy_rules(
+            ("test", &app_meta),
+            &FnMeta {
+                sync_async: FnSyncAsyncSupport::Sync,
+                calls: vec![],
+                data_accesses: Some(HashMap::from([(
+                    KeyPattern::new("test_erasure_{}".to_owned()),
+                    access,
+                )])),
+                affinity: None,
+            },
+        );
+
+        // the rule of the app covers the keys in its namespace, the shards span 2 stripes
+        let uid = new_data_unique_id_fn_kv(&fn_kv_ns_key("test", b"test_erasure_item"));
+        let data: Vec<u8> = (0..10 * 1024 * 1024u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        views[1]
+            .data_general()
+            .write_data(
+                uid.clone(),
+                vec![DataItemArgWrapper::from_bytes(data.clone())],
+                Some((
+                    1,
+                    proto::DataOpeType::Write,
+                    proto::data_schedule_context::OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+                        app_func: "test/erasure".to_owned(),
+                        node_id: 1,
+                    }),
+                )),
+            )
+            .await
+            .unwrap();
+        let (meta, _) = views[0]
+            .data_general()
+            .get_datameta_cached(&uid)
+            .await
+            .unwrap();
+        assert_eq!(meta.cache_mode_visitor(0).erasure_parity_shards(), Some(1));
+        let holders: Vec<NodeID> = meta.datas_splits[0]
+            .splits
+            .iter()
+            .map(|s| s.node_id)
+            .collect();
+        assert_eq!(holders.len(), 3);
+        // the master reads, so the lost holders are workers, at least 2 of the 3 holders are
+        let lost: Vec<NodeID> = holders.iter().copied().filter(|n| *n != 0).collect();
+        assert!(lost.len() >= 2);
+
+        let read_all = || {
+            let view = views[0].clone();
+            let uid = uid.clone();
+            async move {
+                view.data_general()
+                    .get_or_del_data(GetOrDelDataArg {
+                        meta: None,
+                        unique_id: uid,
+                        ty: GetOrDelDataArgType::All,
+                    })
+                    .await
+                    .map(
+                        |(_, mut items)| match items.remove(&0).unwrap().data_item_dispatch {
+                            Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) => bytes,
+                            other => panic!("unexpected item {:?}", other),
+                        },
+                    )
             }
-            impl DataSetMetaBuilder {
-                $(
-                    pub fn [<cache_mode_ $group _ $mode>](&mut self, idx: DataItemIdx) -> &mut Self {
-                        self.assert_cache_mode_len();
-                        self.building.as_mut().unwrap().cache_mode[idx as usize] =
-                            (self.building.as_mut().unwrap().cache_mode[idx as usize] & ![<CACHE_MODE_ $group:upper _MASK>]) |
-                            ([<CACHE_MODE_ $group:upper _ $mode:upper _MASK>] & [<CACHE_MODE_ $group:upper _MASK>]);
-                        self
-                    }
-                )*
+        };
+        assert_eq!(read_all().await.unwrap(), data);
+
+        // up to m holders lost
+        views[lost[0] as usize].p2p().p2p_kernel.test_set_down(true);
+        assert_eq!(read_all().await.unwrap(), data);
+        let (range, size) = views[0]
+            .data_general()
+            .get_item_range(&meta, &uid, 0, 1024 * 1024 + 100, 10)
+            .await
+            .unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(range, data[1024 * 1024 + 100..1024 * 1024 + 110]);
+
+        // more than m lost
+        views[lost[1] as usize].p2p().p2p_kernel.test_set_down(true);
+        match read_all().await {
+            Err(WSError::WsDataError(WsDataError::ErasureShardsLacking { needed, .. })) => {
+                assert_eq!(needed, 2)
             }
+            res => panic!("unexpected read result {:?}", res.map(|b| b.len())),
         }
-    };
-}
-generate_cache_mode_methods!(
-    (time, forever),
-    (time, auto),
-    (pos, allnode),
-    (pos, specnode),
-    (pos, auto),
-    (map, common_kv),
-    (map, file)
-);
-
-#[test]
-fn test_cache_mode_visitor() {
-    let cache_mode_visitor = CacheModeVisitor(CACHE_MODE_TIME_FOREVER_MASK);
-    assert!(cache_mode_visitor.is_time_forever());
-    assert!(!cache_mode_visitor.is_time_auto());
+    }
 
-    let cache_mode_visitor = CacheModeVisitor(CACHE_MODE_POS_ALLNODE_MASK);
-    assert!(cache_mode_visitor.is_pos_allnode());
-    assert!(!cache_mode_visitor.is_pos_specnode());
-    assert!(!cache_mode_visitor.is_pos_auto());
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_re_replicate_item_of_lost_holder() {
+        // a holder is taken down, so the cluster is not the shared one
+        let cluster = test_utils::start_test_cluster_with(2451, 4, |config| {
+            config.replication_factor = 2;
+        })
+        .await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let views: Vec<TestView> = cluster
+            .iter()
+            .map(|(_, modules)| TestView::new(modules.clone()))
+            .collect();
+        // the copy is pulled in several blocks
+        let block_size = views[0].p2p().nodes_config.batch_transfer.block_size;
+        let data: Vec<u8> = (0..(2 * block_size + 100) as u32)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let uid = b"test_re_replicate_item".to_vec();
+        views[1]
+            .data_general()
+            .write_data(
+                uid.clone(),
+                vec![DataItemArgWrapper::from_bytes(data.clone())],
+                Some((
+                    1,
+                    proto::DataOpeType::Write,
+                    proto::data_schedule_context::OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+                        app_func: "test/re_replicate".to_owned(),
+                        node_id: 1,
+                    }),
+                )),
+            )
+            .await
+            .unwrap();
+        let (meta, _) = views[0]
+            .data_general()
+            .get_datameta_cached(&uid)
+            .await
+            .unwrap();
+        let holders = meta.get_data_holders(0);
+        assert_eq!(holders.len(), 2);
+
+        // the master is always alive, so a worker holder is lost
+        let lost = holders.iter().copied().find(|node| *node != 0).unwrap();
+        views[lost as usize].p2p().p2p_kernel.test_set_down(true);
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        views[0].data_master().re_replicate().await;
+
+        let (_, meta) = views[0]
+            .kv_store_engine()
+            .get(&KeyTypeDataSetMeta(&uid), true, KvAdditionalConf {})
+            .unwrap();
+        let new_holders = meta.get_data_holders(0);
+        assert_eq!(new_holders.len(), 2);
+        assert!(!new_holders.contains(&lost));
+        let new_node = new_holders
+            .iter()
+            .copied()
+            .find(|node| !holders.contains(node))
+            .unwrap();
+        // stored on the new node at the version of the data
+        let new_kv = views[new_node as usize].kv_store_engine();
+        let (_, version) = new_kv
+            .get(&KeyTypeDataSetItemVersion(&uid), false, KvAdditionalConf {})
+            .unwrap();
+        assert_eq!(version, meta.version);
+        let (_, persisted) = new_kv
+            .get(
+                &KeyTypeDataSetItem { uid: &uid, idx: 0 },
+                false,
+                KvAdditionalConf {},
+            )
+            .unwrap();
+        assert_eq!(
+            decode_persist_item(persisted)
+                .await
+                .unwrap()
+                .data_item_dispatch,
+            Some(proto::data_item::DataItemDispatch::RawBytes(data))
+        );
+    }
 
-    let cache_mode_visitor = CacheModeVisitor(CACHE_MODE_MAP_FILE_MASK);
-    assert!(cache_mode_visitor.is_map_file());
-    assert!(!cache_mode_visitor.is_map_common_kv());
+    #[tokio::test]
+    async fn test_batch_transfer_resumes_after_partial_transfer() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let view = TestView::new(sys1);
+        let _receiver = TestView::new(sys2);
+        let (sender, p2p) = (view.data_general(), view.p2p());
+        let block_size = p2p.nodes_config.batch_transfer.block_size;
+        let data: Vec<u8> = (0..(block_size + 1000) as u32).map(|i| (i % 251) as u8).collect();
+        let uid = b"test_batch_resume".to_vec();
+        let resume_req = proto::BatchResumeRequest {
+            unique_id: uid.clone(),
+            data_item_idx: 0,
+            version: 1,
+            total_size: data.len() as u64,
+            block_size: block_size as u64,
+            item_checksum: bytes_checksum(&data),
+        };
 
-    // test builder
+        // only the first of the 2 blocks arrives
+        let block = data[..block_size].to_vec();
+        let resp = sender
+            .rpc_call_batch_data
+            .call(
+                p2p,
+                1,
+                proto::BatchDataRequest {
+                    request_id: Some(proto::BatchRequestId {
+                        node_id: 1,
+                        sequence: 0,
+                    }),
+                    dataset_unique_id: uid.clone(),
+                    data_item_idx: 0,
+                    block_type: Some(proto::DataItem {
+                        data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(
+                            vec![],
+                        )),
+                    }),
+                    block_index: 0,
+                    block_checksum: bytes_checksum(&block),
+                    data: block,
+                    operation: proto::DataOpeType::Write as i32,
+                    unique_id: uid.clone(),
+                    version: 1,
+                    total_size: data.len() as u64,
+                    item_checksum: bytes_checksum(&data),
+                    compression: proto::DataCompression::Raw as i32,
+                    block_size: block_size as u64,
+                },
+                Some(Duration::from_secs(10)),
+            )
+            .await
+            .unwrap();
+        assert!(resp.success, "{}", resp.error_message);
+        let resumed = sender
+            .rpc_call_batch_resume
+            .call(p2p, 1, resume_req.clone(), Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert_eq!(resumed.received, vec![0b01]);
+
+        // another block size is another transfer
+        let mut other = resume_req.clone();
+        other.block_size /= 2;
+        let resumed = sender
+            .rpc_call_batch_resume
+            .call(p2p, 1, other, Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert!(resumed.received.is_empty());
+
+        // the second block completes the item, nothing is left to resume
+        sender
+            .write_data_batch(
+                uid.clone(),
+                1,
+                proto::DataItem {
+                    data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(data)),
+                },
+                0,
+                1,
+                DataCompression::Raw,
+            )
+            .await
+            .unwrap();
+        let resumed = sender
+            .rpc_call_batch_resume
+            .call(p2p, 1, resume_req, Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert!(resumed.received.is_empty());
+    }
 
-    let meta = DataSetMetaBuilder::new()
-        .set_data_splits(vec![DataSplit { splits: vec![] }])
-        .cache_mode_map_file(0)
-        .cache_mode_time_forever(0)
-        .build();
-    assert!(meta.cache_mode_visitor(0).is_map_file());
-    assert!(!meta.cache_mode_visitor(0).is_map_common_kv());
-    assert!(meta.cache_mode_visitor(0).is_time_forever());
-    assert!(!meta.cache_mode_visitor(0).is_time_auto());
-    let meta = DataSetMetaBuilder::new()
-        .set_data_splits(vec![DataSplit { splits: vec![] }])
-        .cache_mode_map_common_kv(0)
-        .cache_mode_time_forever(0)
-        .build();
-    assert!(meta.cache_mode_visitor(0).is_map_common_kv());
-    assert!(!meta.cache_mode_visitor(0).is_map_file());
-    assert!(meta.cache_mode_visitor(0).is_time_forever());
-    assert!(!meta.cache_mode_visitor(0).is_time_auto());
-}
+    #[tokio::test]
+    async fn test_batch_transfer_resumes_file_from_disk() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let view = TestView::new(sys1);
+        let receiver = TestView::new(sys2);
+        let (sender, p2p) = (view.data_general(), view.p2p());
+        let file_path = receiver.data_general().view.os().file_path.clone();
+        let block_size = p2p.nodes_config.batch_transfer.block_size;
+        let data: Vec<u8> = (0..(block_size + 1000) as u32)
+            .map(|i| (i % 241) as u8)
+            .collect();
+        let uid = b"test_batch_resume_file".to_vec();
+        let file_name = |idx: u64| format!("test_batch_resume_file_{}.bin", idx);
+        let block_req = |idx: u64, block_index: usize| {
+            let block = data
+                [block_index * block_size..((block_index + 1) * block_size).min(data.len())]
+                .to_vec();
+            proto::BatchDataRequest {
+                request_id: Some(proto::BatchRequestId {
+                    node_id: 0,
+                    sequence: idx * 2 + block_index as u64,
+                }),
+                dataset_unique_id: uid.clone(),
+                data_item_idx: idx,
+                block_type: Some(proto::DataItem {
+                    data_item_dispatch: Some(proto::data_item::DataItemDispatch::File(
+                        proto::FileData {
+                            file_name_opt: file_name(idx),
+                            is_dir_opt: false,
+                            file_content: vec![],
+                        },
+                    )),
+                }),
+                block_index: block_index as u32,
+                block_checksum: bytes_checksum(&block),
+                data: block,
+                operation: proto::DataOpeType::Write as i32,
+                unique_id: uid.clone(),
+                version: 1,
+                total_size: data.len() as u64,
+                item_checksum: bytes_checksum(&data),
+                compression: proto::DataCompression::Raw as i32,
+                block_size: block_size as u64,
+            }
+        };
+        let resume_req = |idx: u64| proto::BatchResumeRequest {
+            unique_id: uid.clone(),
+            data_item_idx: idx,
+            version: 1,
+            total_size: data.len() as u64,
+            block_size: block_size as u64,
+            item_checksum: bytes_checksum(&data),
+        };
 
-pub struct DataSetMetaBuilder {
-    building: Option<DataSetMetaV2>,
-}
-impl From<DataSetMetaV2> for DataSetMetaBuilder {
-    fn from(d: DataSetMetaV2) -> Self {
-        Self { building: Some(d) }
-    }
-}
-impl DataSetMetaBuilder {
-    pub fn new() -> Self {
-        Self {
-            building: Some(DataSetMetaV2 {
-                version: 0,
-                datas_splits: vec![],
-                data_metas: vec![],
-                api_version: 2,
-                synced_nodes: HashSet::new(),
-                cache_mode: vec![],
-            }),
+        // the first block of two items of the dataset, each staged on its own
+        for idx in [0, 1] {
+            let resp = sender
+                .rpc_call_batch_data
+                .call(p2p, 1, block_req(idx, 0), Some(Duration::from_secs(10)))
+                .await
+                .unwrap();
+            assert!(resp.success, "{}", resp.error_message);
         }
-    }
-    fn assert_cache_mode_len(&self) {
-        if self.building.as_ref().unwrap().cache_mode.len() == 0 {
-            panic!("please set_data_splits before set_cache_mode");
+        for idx in [0, 1] {
+            assert!(batch_stage_path(&file_path, &uid, idx, 1).exists());
         }
-    }
 
-    pub fn version(&mut self, version: u64) -> &mut Self {
-        self.building.as_mut().unwrap().version = version;
-        self
-    }
+        // the receiver restarts, only the persisted progress of item 0 is left
+        let state = receiver
+            .data_general()
+            .batch_receive_states
+            .remove(&(uid.clone(), 0, 1))
+            .unwrap();
+        state.done.lock().await.take().unwrap().abort();
+        let resumed = sender
+            .rpc_call_batch_resume
+            .call(p2p, 1, resume_req(0), Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert_eq!(resumed.received, vec![0b01]);
 
-    #[must_use]
-    pub fn set_data_splits(&mut self, splits: Vec<DataSplit>) -> &mut Self {
-        let building = self.building.as_mut().unwrap();
-        building.datas_splits = splits;
-        building.cache_mode = vec![0; building.datas_splits.len()];
-        self
+        // the last block completes the item on top of the staged first one
+        let resp = sender
+            .rpc_call_batch_data
+            .call(p2p, 1, block_req(0, 1), Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert!(resp.success, "{}", resp.error_message);
+        assert_eq!(std::fs::read(file_path.join(file_name(0))).unwrap(), data);
+        assert!(!batch_stage_path(&file_path, &uid, 0, 1).exists());
+        assert!(batch_stage_path(&file_path, &uid, 1, 1).exists());
+
+        // item 1 is abandoned, the gc drops its state, progress and staged file
+        receiver
+            .data_general()
+            .remove_stale_batch_transfers(Duration::ZERO)
+            .await;
+        assert!(receiver
+            .data_general()
+            .batch_receive_states
+            .get(&(uid.clone(), 1, 1))
+            .is_none());
+        assert!(!batch_stage_path(&file_path, &uid, 1, 1).exists());
+        let resumed = sender
+            .rpc_call_batch_resume
+            .call(p2p, 1, resume_req(1), Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert!(resumed.received.is_empty());
+        let _ = std::fs::remove_file(file_path.join(file_name(0)));
     }
 
-    pub fn set_cache_mode(&mut self, idx: DataItemIdx, mode: u16) -> &mut Self {
-        self.building.as_mut().unwrap().cache_mode[idx as usize] = mode;
-        self
+    #[tokio::test]
+    async fn test_write_data_stream_of_unknown_length() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let master = TestView::new(sys1);
+        let view = TestView::new(sys2);
+        let data_general = view.data_general();
+        let block_size = view.p2p().nodes_config.batch_transfer.block_size;
+        let ctx = || {
+            Some((
+                1,
+                proto::DataOpeType::Write,
+                proto::data_schedule_context::OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+                    app_func: "test/stream".to_owned(),
+                    node_id: 1,
+                }),
+            ))
+        };
+        let chunks = |data: &[u8]| {
+            futures::stream::iter(
+                data.chunks(100_000)
+                    .map(|chunk| Ok::<_, WSError>(Bytes::copy_from_slice(chunk)))
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        // several segments, kept as a file on the holders
+        let uid = b"test_stream_large".to_vec();
+        let data: Vec<u8> = (0..(2 * block_size + 1000) as u32).map(|i| (i % 251) as u8).collect();
+        let CondWriteRes::Written(version) = data_general
+            .write_data_stream(uid.clone(), chunks(&data), ctx(), WriteDataOpts::default())
+            .await
+            .unwrap()
+        else {
+            panic!("unconditional stream write rejected");
+        };
+        let (meta, _) = data_general.get_datameta_cached(&uid).await.unwrap();
+        assert_eq!(meta.version, version);
+        assert!(meta.datas_splits[0]
+            .splits
+            .iter()
+            .all(|s| s.data_offset == 0 && s.data_size == data.len() as u64));
+        let offset = block_size as u64 - 10;
+        let (range, size) = data_general
+            .get_item_range(&meta, &uid, 0, offset, 20)
+            .await
+            .unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(range, data[offset as usize..offset as usize + 20]);
+
+        // small data is stored inline like a regular write
+        let uid = b"test_stream_small".to_vec();
+        let data = b"streamed value".to_vec();
+        let _ = data_general
+            .write_data_stream(uid.clone(), chunks(&data), ctx(), WriteDataOpts::default())
+            .await
+            .unwrap();
+        let (_, mut items) = data_general
+            .get_or_del_data(GetOrDelDataArg {
+                meta: None,
+                unique_id: uid.clone(),
+                ty: GetOrDelDataArgType::All,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            items.remove(&0).unwrap().data_item_dispatch,
+            Some(proto::data_item::DataItemDispatch::RawBytes(data.clone()))
+        );
+
+        // a broken stream publishes nothing
+        let broken = futures::stream::iter(vec![
+            Ok(Bytes::from_static(b"partial")),
+            Err(WSError::from(WsDataError::WriteDataFailed {
+                unique_id: uid.clone(),
+                message: "source broken".to_owned(),
+            })),
+        ]);
+        assert!(data_general
+            .write_data_stream(uid.clone(), broken, ctx(), WriteDataOpts::default())
+            .await
+            .is_err());
+        data_general.invalidate_meta_cache(&uid);
+        let (meta, _) = data_general.get_datameta_cached(&uid).await.unwrap();
+        assert_eq!(meta.version, 1);
+
+        // a holder losing its staged data fails the commit before the version is published
+        let mut writer = data_general
+            .open_data_stream(uid.clone(), ctx(), WriteDataOpts::default())
+            .await
+            .unwrap();
+        writer.write(&vec![7; 2 * block_size]).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        for node in [&master, &view] {
+            let stage_dir = node.data_general().view.os().file_path.join("stream_stage");
+            let Ok(entries) = std::fs::read_dir(stage_dir) else {
+                continue;
+            };
+            for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                if path.extension().map_or(false, |ext| ext == "part") {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+        }
+        assert!(writer.commit().await.is_err());
+        data_general.invalidate_meta_cache(&uid);
+        let (meta, _) = data_general.get_datameta_cached(&uid).await.unwrap();
+        assert_eq!(meta.version, 1);
     }
 
-    pub fn set_cache_mode_for_all(&mut self, mode: Vec<u16>) -> &mut Self {
-        self.building.as_mut().unwrap().cache_mode = mode;
+    #[tokio::test]
+    async fn test_export_and_import_datasets() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let master = TestView::new(sys1);
+        let worker = TestView::new(sys2);
+        let ctx = || {
+            Some((
+                1,
+                proto::DataOpeType::Write,
+                proto::data_schedule_context::OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+                    app_func: "test/archive".to_owned(),
+                    node_id: 1,
+                }),
+            ))
+        };
+        // time forever, pos specnode, map common kv; master would plan auto ones
+        let mode: u16 = 0x0100;
+        let opts = |items: usize| WriteDataOpts {
+            cache_modes: vec![mode; items],
+            ..Default::default()
+        };
+        let single = b"test_archive_single".to_vec();
+        let multi = b"test_archive_multi".to_vec();
+        let _ = worker
+            .data_general()
+            .write_data_with(
+                single.clone(),
+                vec![DataItemArgWrapper::from_bytes(b"single item".to_vec())],
+                ctx(),
+                opts(1),
+            )
+            .await
+            .unwrap();
+        let _ = worker
+            .data_general()
+            .write_data_with(
+                multi.clone(),
+                vec![
+                    DataItemArgWrapper::from_bytes(b"first".to_vec()),
+                    DataItemArgWrapper::from_bytes(vec![7; 1000]),
+                ],
+                ctx(),
+                opts(2),
+            )
+            .await
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        // only master has the metas
+        assert!(worker
+            .data_general()
+            .export_datasets(dir.path())
+            .await
+            .is_err());
+        let report = master
+            .data_general()
+            .export_datasets(dir.path())
+            .await
+            .unwrap();
+        assert!(report.datasets >= 2);
+
+        // keep only ours in the archive, the other tests' datasets are left alone
+        let manifest_path = dir.path().join("manifest.json");
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        let ours = [hex::encode(&single), hex::encode(&multi)];
+        manifest["datasets"]
+            .as_array_mut()
+            .unwrap()
+            .retain(|dataset| ours.iter().any(|uid| dataset["unique_id"] == *uid));
+        assert_eq!(manifest["datasets"].as_array().unwrap().len(), 2);
+        // a dataset without items is written too
+        let empty = b"test_archive_empty".to_vec();
+        manifest["datasets"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({
+                "unique_id": hex::encode(&empty),
+                "version": 1,
+                "items": [],
+            }));
+        std::fs::write(&manifest_path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        for uid in [&single, &multi] {
+            let _ = worker
+                .data_general()
+                .get_or_del_data(GetOrDelDataArg {
+                    meta: None,
+                    unique_id: uid.clone(),
+                    ty: GetOrDelDataArgType::Delete,
+                })
+                .await
+                .unwrap();
+        }
+        let report = worker
+            .data_general()
+            .import_datasets(dir.path())
+            .await
+            .unwrap();
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        assert_eq!(report.datasets, 3);
+        assert_eq!(report.items, 3);
+        let (meta, _) = worker
+            .data_general()
+            .get_datameta_cached(&empty)
+            .await
+            .unwrap();
+        assert_eq!(meta.data_item_cnt(), 0);
+
+        let (meta, items) = worker
+            .data_general()
+            .get_or_del_data(GetOrDelDataArg {
+                meta: None,
+                unique_id: multi.clone(),
+                ty: GetOrDelDataArgType::All,
+            })
+            .await
+            .unwrap();
+        assert_eq!(meta.cache_mode, vec![mode, mode]);
+        assert_eq!(
+            items[&0].data_item_dispatch,
+            Some(proto::data_item::DataItemDispatch::RawBytes(
+                b"first".to_vec()
+            ))
+        );
+        assert_eq!(
+            items[&1].data_item_dispatch,
+            Some(proto::data_item::DataItemDispatch::RawBytes(vec![7; 1000]))
+        );
+        let (meta, _) = worker
+            .data_general()
+            .get_datameta_cached(&single)
+            .await
+            .unwrap();
+        assert_eq!(meta.cache_mode, vec![mode]);
+        let (data, _) = worker
+            .data_general()
+            .get_item_range(&meta, &single, 0, 0, 0)
+            .await
+            .unwrap();
+        assert_eq!(data, b"single item");
+
+        // a tampered item is refused
+        let single_file = manifest["datasets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|dataset| dataset["unique_id"] == ours[0])
+            .unwrap()["items"][0]["file"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        std::fs::write(dir.path().join(single_file), b"single itex").unwrap();
+        let report = worker
+            .data_general()
+            .import_datasets(dir.path())
+            .await
+            .unwrap();
+        assert_eq!(report.datasets, 2);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, ours[0]);
+        worker.data_general().invalidate_meta_cache(&single);
+        let (meta_after, _) = worker
+            .data_general()
+            .get_datameta_cached(&single)
+            .await
+            .unwrap();
+        assert_eq!(meta_after.version, meta.version);
+
+        // over http the archive dir stays under the configured root
+        let root = dir.path();
+        assert!(archive::resolve_archive_dir(None, "backup").is_err());
         assert_eq!(
-            self.building.as_mut().unwrap().cache_mode.len(),
-            self.building.as_mut().unwrap().datas_splits.len(),
-            "cache mode len must be equal to data splits len"
+            archive::resolve_archive_dir(Some(root), "backup/1").unwrap(),
+            root.join("backup/1")
         );
-        self
+        for dir in ["/etc", "../backup", "backup/../../etc"] {
+            assert!(
+                archive::resolve_archive_dir(Some(root), dir).is_err(),
+                "{}",
+                dir
+            );
+        }
+        std::os::unix::fs::symlink("/", root.join("escape")).unwrap();
+        assert!(archive::resolve_archive_dir(Some(root), "escape").is_err());
     }
 
-    pub fn build(&mut self) -> DataSetMetaV2 {
-        self.building.take().unwrap()
+    #[tokio::test]
+    async fn test_read_range_of_sparse_file_over_4gib() {
+        use std::os::unix::fs::FileExt;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let size: u64 = 5 << 30;
+        let offset: u64 = (4 << 30) + 123;
+        file.as_file().set_len(size).unwrap();
+        file.as_file().write_all_at(b"tail", offset).unwrap();
+
+        let (bytes, total) = file_read_range(file.path(), offset, 4).await.unwrap();
+        assert_eq!(total, size);
+        assert_eq!(bytes, b"tail");
+        // clamped at the end of the file
+        let (bytes, _) = file_read_range(file.path(), size - 2, 10).await.unwrap();
+        assert_eq!(bytes, vec![0, 0]);
     }
-}
-
-pub struct GetOrDelDataArg {
-    pub meta: Option<DataSetMetaV2>,
-    pub unique_id: Vec<u8>,
-    pub ty: GetOrDelDataArgType,
-}
-
-#[derive(Debug, Clone)]
-pub enum GetOrDelDataArgType {
-    All,
-    Delete,
-    PartialOne { idx: DataItemIdx },
-    PartialMany { idxs: BTreeSet<DataItemIdx> },
-}
-
-impl DataGeneralView {
-    fn get_data_meta_local(
-        &self,
-        unique_id: &[u8],
-        delete: bool,
-    ) -> WSResult<Option<(KvVersion, DataSetMetaV2)>> {
-        let ope_name = if delete { "delete" } else { "get" };
-        tracing::debug!("{} data meta for uid({:?})", ope_name, unique_id);
 
-        let kv_store_engine = self.kv_store_engine();
-        let key = KeyTypeDataSetMeta(&unique_id);
-        let keybytes = key.make_key();
+    #[test]
+    fn test_item_range() {
+        assert_eq!(item_range(100, 0, 0), 0..100);
+        assert_eq!(item_range(100, 90, 0), 90..100);
+        assert_eq!(item_range(100, 90, 5), 90..95);
+        // clamped to the item end
+        assert_eq!(item_range(100, 90, 20), 90..100);
+        assert_eq!(item_range(100, 150, 10), 100..100);
+        assert_eq!(item_range(100, 10, u64::MAX), 10..100);
+    }
 
-        let write_lock = kv_store_engine.with_rwlock(&keybytes);
-        let _guard = write_lock.write();
+    #[test]
+    fn test_read_hedge_delay() {
+        let ms = Duration::from_millis;
+        assert_eq!(read_hedge_delay(None), READ_HEDGE_DEFAULT_DELAY);
+        // a slow node is waited for longer before the read is hedged
+        assert_eq!(read_hedge_delay(Some(ms(200))), ms(600));
+        assert_eq!(read_hedge_delay(Some(ms(1))), ms(10));
+
+        let mut latency = smoothed_read_latency(None, ms(80));
+        assert_eq!(latency, ms(80));
+        // one slow read moves the average by an eighth only
+        latency = smoothed_read_latency(Some(latency), ms(880));
+        assert_eq!(latency, ms(180));
+    }
 
-        let meta_opt = if delete {
-            kv_store_engine.del(key, true)?
-        } else {
-            kv_store_engine.get(&key, true, KvAdditionalConf {})
+    #[test]
+    fn test_data_holders_order() {
+        let split = |node_id, data_offset, data_size| EachNodeSplit {
+            node_id,
+            data_offset,
+            data_size,
+            cache_mode: 0,
         };
-        Ok(meta_opt)
+        let meta = DataSetMetaV2 {
+            api_version: DATA_SET_META_API_VERSION,
+            version: 1,
+            // node 2 only holds a part, node 3 holds a full copy
+            datas_splits: vec![DataSplit {
+                splits: vec![split(1, 0, 100), split(2, 50, 50), split(3, 0, 100)],
+            }],
+            data_metas: vec![],
+            synced_nodes: [5, 3, 4].into_iter().collect(),
+            cache_mode: vec![0],
+            data_checksums: vec![],
+            compression: DataCompression::Raw,
+        };
+        assert_eq!(meta.get_data_holders(0), vec![1, 3, 4, 5]);
     }
 
-    pub async fn get_metadata(
-        &self,
-        unique_id: &[u8],
-        delete: bool,
-    ) -> WSResult<DataSetMetaV2> {
-        // 先尝试从本地获取
-        if let Some((_version, meta)) = self.get_data_meta_local(unique_id, delete)? {
-            return Ok(meta);
+    #[test]
+    fn test_decode_meta_without_checksums() {
+        let old_meta = (
+            2u8,
+            4u64,
+            vec![vec![(1 as NodeID, 0u32, 10u32, 0u32)]],
+            Vec::<DataMetaSys>::new(),
+            [2 as NodeID].into_iter().collect::<HashSet<_>>(),
+            vec![0u16],
+        );
+        let meta = DataSetMetaV2::decode_persist(&bincode::serialize(&old_meta).unwrap()).unwrap();
+        assert_eq!(meta.version, 4);
+        assert_eq!(meta.datas_splits[0].splits[0].data_size, 10);
+        assert!(meta.data_checksums.is_empty());
+
+        let mut builder = DataSetMetaBuilder::new();
+        let _ = builder.set_data_splits(vec![DataSplit { splits: vec![] }]);
+        let _ = builder.set_data_checksums(vec![bytes_checksum(b"x")]);
+        let bytes = bincode::serialize(&builder.build()).unwrap();
+        let meta = DataSetMetaV2::decode_persist(&bytes).unwrap();
+        assert_eq!(meta.data_checksums, vec![bytes_checksum(b"x")]);
+    }
+
+    #[test]
+    fn test_cache_mode_by_policy() {
+        for time in [CacheTime::Forever, CacheTime::Auto] {
+            for pos in [CachePos::AllNode, CachePos::SpecNode, CachePos::Auto] {
+                for map in [CacheMap::Kv, CacheMap::File] {
+                    let policy = CachePolicy {
+                        time,
+                        pos,
+                        nodes: vec![],
+                        map,
+                    };
+                    let meta = DataSetMetaBuilder::new()
+                        .set_data_splits(vec![DataSplit { splits: vec![] }; 2])
+                        .cache_mode_redundancy_erasure(1, 2)
+                        .cache_mode_by_policy(0, &policy)
+                        .cache_mode_by_policy(1, &policy)
+                        .build();
+                    for idx in 0..2 {
+                        let visitor = meta.cache_mode_visitor(idx);
+                        assert_eq!(visitor.is_time_forever(), time == CacheTime::Forever);
+                        assert_eq!(visitor.is_time_auto(), time == CacheTime::Auto);
+                        assert_eq!(visitor.is_pos_allnode(), pos == CachePos::AllNode);
+                        assert_eq!(visitor.is_pos_specnode(), pos == CachePos::SpecNode);
+                        assert_eq!(visitor.is_pos_auto(), pos == CachePos::Auto);
+                        assert_eq!(visitor.is_map_common_kv(), map == CacheMap::Kv);
+                        assert_eq!(visitor.is_map_file(), map == CacheMap::File);
+                    }
+                    // the redundancy is not the policy's
+                    assert_eq!(meta.cache_mode_visitor(0).erasure_parity_shards(), None);
+                    assert_eq!(meta.cache_mode_visitor(1).erasure_parity_shards(), Some(2));
+                }
+            }
         }
+    }
 
-        // 本地不存在，从 master 获取
-        self.data_general().get_or_del_datameta_from_master(unique_id, delete).await
+    #[test]
+    fn test_evictable_copy() {
+        // copies past the first are beyond a replication factor of 1
+        let meta = |time: CacheTime, splits: Vec<NodeID>, erasure: bool| {
+            let mut builder = DataSetMetaBuilder::new();
+            let _ = builder
+                .set_data_splits(vec![DataSplit {
+                    splits: splits
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, node_id)| EachNodeSplit {
+                            node_id,
+                            data_offset: 0,
+                            data_size: 100,
+                            cache_mode: if i == 0 {
+                                0
+                            } else {
+                                CACHE_MODE_SPLIT_EXTRA_COPY
+                            },
+                        })
+                        .collect(),
+                }])
+                .cache_mode_by_policy(
+                    0,
+                    &CachePolicy {
+                        time,
+                        ..Default::default()
+                    },
+                );
+            if erasure {
+                let _ = builder.cache_mode_redundancy_erasure(0, 1);
+            }
+            builder.build()
+        };
+        let replicas = meta(CacheTime::Auto, vec![1, 2, 3], false);
+        // the primary copy stays
+        assert!(!replicas.evictable_copy(0, 1));
+        assert!(replicas.evictable_copy(0, 2));
+        assert!(replicas.evictable_copy(0, 3));
+        assert!(!replicas.evictable_copy(0, 4));
+        assert!(!replicas.evictable_copy(1, 2));
+        // and so does the last one
+        assert!(!meta(CacheTime::Auto, vec![2], false).evictable_copy(0, 2));
+        assert!(!meta(CacheTime::Forever, vec![1, 2], false).evictable_copy(0, 2));
+        assert!(!meta(CacheTime::Auto, vec![1, 2], true).evictable_copy(0, 2));
+        // a copy counting toward the replication factor stays too
+        let mut kept = meta(CacheTime::Auto, vec![1, 2, 3], false);
+        kept.datas_splits[0].splits[1].cache_mode = 0;
+        assert!(!kept.evictable_copy(0, 2));
+        assert!(kept.evictable_copy(0, 3));
     }
-}
 
-impl From<JoinError> for WSError {
-    fn from(err: JoinError) -> Self {
-        WsNetworkLogicErr::TaskJoinError { err }.into()
+    #[test]
+    fn test_mapped_item_file_name() {
+        let name = mapped_item_file_name(b"a_b/c", 3, 42);
+        assert_eq!(
+            parse_mapped_item_file_name(&name),
+            Some((b"a_b/c".to_vec(), 3, 42))
+        );
+        assert_eq!(parse_mapped_item_file_name("stream_1.data"), None);
     }
-}
 
-#[async_trait]
-impl LogicalModule for DataGeneral {
-    fn inner_new(args: LogicalModuleNewArgs) -> Self
-    where
-        Self: Sized,
-    {
-        Self {
-            view: DataGeneralView::new(args.logical_modules_ref.clone()),
-            rpc_call_data_version_schedule: RPCCaller::new(),
-            rpc_call_write_once_data: RPCCaller::new(),
-            rpc_call_batch_data: RPCCaller::new(),
-            rpc_call_get_data_meta: RPCCaller::new(),
-            rpc_call_get_data: RPCCaller::new(),
+    #[test]
+    fn test_fn_kv_ns_key() {
+        let ns_key = fn_kv_ns_key("word_count", b"wordcount_slice_0");
+        assert_eq!(ns_key, b"word_count\0wordcount_slice_0".to_vec());
+        assert_eq!(
+            split_fn_kv_ns_key(&ns_key),
+            Some(("word_count", &b"wordcount_slice_0"[..]))
+        );
+        // written before namespaces
+        assert_eq!(split_fn_kv_ns_key(b"wordcount_slice_0"), None);
+
+        let uid = new_data_unique_id_fn_kv(&ns_key);
+        assert_eq!(fn_kv_user_key(&uid), b"wordcount_slice_0");
+        let legacy_uid = new_data_unique_id_fn_kv(b"wordcount_slice_0");
+        assert_eq!(fn_kv_user_key(&legacy_uid), b"wordcount_slice_0");
+        assert_eq!(fn_kv_user_key(b"appword_count"), b"appword_count");
+    }
 
-            // //费新文
-            // rpc_call_distribute_task: RPCCaller::new(),
-            // rpc_handler_distribute_task: RPCHandler::new(),
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cache_policies_place_map_and_evict() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        // node 0 is the master, node 1 the worker, the replication factor is 1
+        let views = [TestView::new(sys1), TestView::new(sys2)];
+        let combos: Vec<(CacheTime, CachePos, CacheMap)> = [CacheTime::Forever, CacheTime::Auto]
+            .into_iter()
+            .flat_map(|time| {
+                [CachePos::AllNode, CachePos::SpecNode, CachePos::Auto]
+                    .into_iter()
+                    .flat_map(move |pos| {
+                        [CacheMap::Kv, CacheMap::File]
+                            .into_iter()
+                            .map(move |map| (time, pos, map))
+                    })
+            })
+            .collect();
+        assert_eq!(combos.len(), 12);
+        fn yaml_name(v: &impl serde::Serialize) -> String {
+            serde_yaml::to_string(v).unwrap().trim().to_owned()
+        }
+        let prefix = |(time, pos, map): (CacheTime, CachePos, CacheMap)| {
+            format!(
+                "test_cache_{}_{}_{}_",
+                yaml_name(&time),
+                yaml_name(&pos),
+                yaml_name(&map)
+            )
+        };
+        // rules of a native app match the unique ids as they are
+        let app_meta = AppMeta::new(AppType::Native, HashMap::new());
+        views[0].app_master().fddg.add_redundancy_rules(
+            ("test", &app_meta),
+            &FnMeta {
+                sync_async: FnSyncAsyncSupport::Sync,
+                calls: vec![],
+                data_accesses: Some(
+                    combos
+                        .iter()
+                        .map(|&combo| {
+                            let (time, pos, map) = combo;
+                            let access: DataAccess = serde_yaml::from_str(&format!(
+                                "{{set: true, get: true, delete: false, event: null, \
+                                 cache: {{time: {}, pos: {}, nodes: [1], map: {}}}}}",
+                                yaml_name(&time),
+                                yaml_name(&pos),
+                                yaml_name(&map)
+                            ))
+                            .unwrap();
+                            (KeyPattern::new(format!("{}{{}}", prefix(combo))), access)
+                        })
+                        .collect(),
+                ),
+                affinity: None,
+            },
+        );
+        let write = |uid: Vec<u8>, data: Vec<u8>| {
+            let view = views[0].clone();
+            async move {
+                view.data_general()
+                    .write_data(
+                        uid,
+                        vec![DataItemArgWrapper::from_bytes(data)],
+                        Some((
+                            0,
+                            proto::DataOpeType::Write,
+                            proto::data_schedule_context::OpeRole::FuncCall(
+                                proto::DataOpeRoleFuncCall {
+                                    app_func: "test/cache".to_owned(),
+                                    node_id: 0,
+                                },
+                            ),
+                        )),
+                    )
+                    .await
+                    .unwrap();
+            }
+        };
+        let read = |view: &TestView, uid: Vec<u8>| {
+            let view = view.clone();
+            async move {
+                let (_, mut items) = view
+                    .data_general()
+                    .get_or_del_data(GetOrDelDataArg {
+                        meta: None,
+                        unique_id: uid,
+                        ty: GetOrDelDataArgType::All,
+                    })
+                    .await
+                    .unwrap();
+                match items.remove(&0).unwrap().data_item_dispatch {
+                    Some(proto::data_item::DataItemDispatch::RawBytes(bytes)) => bytes,
+                    other => panic!("unexpected item {:?}", other),
+                }
+            }
+        };
+        let data: Vec<u8> = (0..32 * 1024u32).map(|i| (i % 253) as u8).collect();
+
+        for combo in combos {
+            let (time, pos, map) = combo;
+            let uid = format!("{}a", prefix(combo)).into_bytes();
+            write(uid.clone(), data.clone()).await;
+            let (meta, _) = views[0]
+                .data_general()
+                .get_datameta_cached(&uid)
+                .await
+                .unwrap();
+            let visitor = meta.cache_mode_visitor(0);
+            assert_eq!(
+                visitor.is_time_auto(),
+                time == CacheTime::Auto,
+                "{:?}",
+                combo
+            );
+            assert_eq!(
+                visitor.is_pos_allnode(),
+                pos == CachePos::AllNode,
+                "{:?}",
+                combo
+            );
+            assert_eq!(
+                visitor.is_pos_specnode(),
+                pos == CachePos::SpecNode,
+                "{:?}",
+                combo
+            );
+            assert_eq!(visitor.is_pos_auto(), pos == CachePos::Auto, "{:?}", combo);
+            assert_eq!(visitor.is_map_file(), map == CacheMap::File, "{:?}", combo);
+
+            // placement
+            let holders = meta.get_data_holders(0);
+            match pos {
+                CachePos::AllNode => assert_eq!(
+                    holders.iter().copied().collect::<HashSet<_>>(),
+                    HashSet::from([0, 1])
+                ),
+                CachePos::SpecNode => assert_eq!(holders, vec![1]),
+                CachePos::Auto => assert_eq!(holders.len(), 1),
+            }
 
+            // map: a file of its own or a blob in the kv store of each holder
+            for &holder in &holders {
+                let (_, persisted) = views[holder as usize]
+                    .kv_store_engine()
+                    .get(
+                        &KeyTypeDataSetItem { uid: &uid, idx: 0 },
+                        false,
+                        KvAdditionalConf {},
+                    )
+                    .unwrap();
+                if map == CacheMap::File {
+                    assert_eq!(persisted[0], PERSIST_TAG_MAPPED_FILE, "{:?}", combo);
+                    let path = String::from_utf8(persisted[1..].to_vec()).unwrap();
+                    assert_eq!(std::fs::read(&path).unwrap(), data);
+                } else {
+                    assert_ne!(persisted[0], PERSIST_TAG_MAPPED_FILE, "{:?}", combo);
+                }
+            }
+            for view in views.iter() {
+                assert_eq!(read(view, uid.clone()).await, data, "{:?}", combo);
+                let (range, size) = view
+                    .data_general()
+                    .get_item_range(&meta, &uid, 0, 1000, 24)
+                    .await
+                    .unwrap();
+                assert_eq!(size, data.len() as u64);
+                assert_eq!(range, data[1000..1024]);
+            }
 
-            rpc_handler_write_once_data: RPCHandler::new(),
-            rpc_handler_batch_data: RPCHandler::new(),
-            rpc_handler_data_meta_update: RPCHandler::new(),
-            rpc_handler_get_data_meta: RPCHandler::new(),
-            rpc_handler_get_data: RPCHandler::new(),
-            
-            // 批量数据接收状态管理
-            batch_receive_states: AsyncInitMap::new(),
+            // eviction: only the copy of a time auto item beyond the replication factor
+            let mut evicted = 0;
+            for view in views.iter() {
+                evicted += view
+                    .data_cache()
+                    .evict_cold_copies(16, Duration::ZERO)
+                    .await
+                    .0;
+            }
+            let extra_copy = time == CacheTime::Auto && pos == CachePos::AllNode;
+            assert_eq!(evicted, extra_copy as u64, "{:?}", combo);
+            let (meta, _) = views[0]
+                .data_general()
+                .get_datameta_cached(&uid)
+                .await
+                .unwrap();
+            let left = meta.get_data_holders(0);
+            if extra_copy {
+                assert_eq!(left, vec![holders[0]]);
+                assert!(views[holders[1] as usize]
+                    .kv_store_engine()
+                    .get(
+                        &KeyTypeDataSetItem { uid: &uid, idx: 0 },
+                        false,
+                        KvAdditionalConf {},
+                    )
+                    .is_none());
+            } else {
+                assert_eq!(left, holders);
+            }
+            for view in views.iter() {
+                assert_eq!(read(view, uid.clone()).await, data, "{:?}", combo);
+            }
         }
     }
 
-    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
-        tracing::info!("start as master");
-
-        let p2p = self.view.p2p();
-        // register rpc callers
-        {
-            self.rpc_call_data_version_schedule.regist(p2p);
-            self.rpc_call_write_once_data.regist(p2p);
-            self.rpc_call_batch_data.regist(p2p);
-            self.rpc_call_get_data_meta.regist(p2p);
-            self.rpc_call_get_data.regist(p2p);
-
-
-            //费新文
-            // self.rpc_call_distribute_task.regist(p2p);
+    #[tokio::test]
+    async fn test_app_quota() {
+        // only quota_test is limited, to hard_bytes 1024 and hard_keys 2
+        let cluster = test_utils::start_test_cluster_with(2441, 2, |config| {
+            config.quotas = serde_yaml::from_str(
+                "apps: {quota_test: {soft_bytes: 512, hard_bytes: 1024, hard_keys: 2}}",
+            )
+            .unwrap();
+        })
+        .await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let master = TestView::new(cluster[0].1.clone());
+        let worker = TestView::new(cluster[1].1.clone());
+        let write = |uid: &'static str, len: usize| {
+            let worker = worker.clone();
+            async move {
+                worker
+                    .data_general()
+                    .write_data(
+                        uid,
+                        vec![DataItemArgWrapper::from_bytes(vec![1; len])],
+                        Some((
+                            1,
+                            proto::DataOpeType::Write,
+                            proto::data_schedule_context::OpeRole::FuncCall(
+                                proto::DataOpeRoleFuncCall {
+                                    app_func: "quota_test/fn".to_owned(),
+                                    node_id: 1,
+                                },
+                            ),
+                        )),
+                    )
+                    .await
+            }
+        };
+        let usage = || master.data_master().app_usages()["quota_test"].0;
+
+        // over the soft limit only
+        write("test_quota_a", 600).await.unwrap();
+        match write("test_quota_b", 600).await {
+            Err(WSError::WsDataError(WsDataError::QuotaExceeded {
+                app,
+                resource,
+                used: 1200,
+                limit: 1024,
+            })) => assert_eq!((app.as_str(), resource.as_str()), ("quota_test", "bytes")),
+            res => panic!("expect bytes over quota, got {:?}", res),
+        }
+        // an overwrite is charged by the difference
+        write("test_quota_a", 1000).await.unwrap();
+        write("test_quota_b", 23).await.unwrap();
+        assert_eq!(usage().bytes, 1023);
+        assert_eq!(usage().keys, 2);
+        match write("test_quota_c", 1).await {
+            Err(WSError::WsDataError(WsDataError::QuotaExceeded {
+                resource, used: 3, ..
+            })) => assert_eq!(resource, "keys"),
+            res => panic!("expect keys over quota, got {:?}", res),
         }
 
-        // register rpc handlers
-        {
-
-            //费新文
-            // let view = self.view.clone();
-            // self.rpc_handler_distribute_task.regist(
-            //     p2p,
-            //     move |responsor: RPCResponsor<DistributeTaskReq>,
-            //           req: DistributeTaskReq| {
-            //         let view = view.clone();
-            //         let _ = tokio::spawn(async move {
-            //             view.data_general().rpc_handle_distribute_task(responsor, req).await;
-            //         });
-            //         Ok(())
-            //     },
-            // );
-            
-
-            let view = self.view.clone();
-            self.rpc_handler_write_once_data
-                .regist(p2p, move |responsor, req| {
-                    let view = view.clone();
-                    let _ = tokio::spawn(async move {
-                        view.data_general().rpc_handle_write_one_data(responsor, req).await;
-                    });
-                    Ok(())
-                });
+        let _ = worker
+            .data_general()
+            .get_or_del_data(GetOrDelDataArg {
+                meta: None,
+                unique_id: b"test_quota_a".to_vec(),
+                ty: GetOrDelDataArgType::Delete,
+            })
+            .await
+            .unwrap();
+        assert_eq!(usage().bytes, 23);
+        assert_eq!(usage().keys, 1);
+        // the key freed by the delete
+        write("test_quota_c", 1).await.unwrap();
+    }
 
-            let view = self.view.clone();
-            self.rpc_handler_batch_data.regist(
-                p2p,
-                move |responsor: RPCResponsor<proto::BatchDataRequest>,
-                      req: proto::BatchDataRequest| {
-                    let view = view.clone();
-                    let _ = tokio::spawn(async move {
-                        let _ = view.data_general().rpc_handle_batch_data(responsor, req).await;
-                    });
-                    Ok(())
+    #[tokio::test]
+    async fn test_data_audit_log() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let master = TestView::new(sys1);
+        let worker = TestView::new(sys2);
+        let start_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let write = |uid: &'static str, app_func: &'static str| {
+            let worker = worker.clone();
+            async move {
+                worker
+                    .data_general()
+                    .write_data(
+                        uid,
+                        vec![DataItemArgWrapper::from_bytes(b"audit".to_vec())],
+                        Some((
+                            1,
+                            proto::DataOpeType::Write,
+                            proto::data_schedule_context::OpeRole::FuncCall(
+                                proto::DataOpeRoleFuncCall {
+                                    app_func: app_func.to_owned(),
+                                    node_id: 1,
+                                },
+                            ),
+                        )),
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+        write("test_audit_a", "audit_app/fn1").await;
+        write("test_audit_a", "audit_app/fn2").await;
+        write("test_audit_b", "audit_other/fn1").await;
+        let _ = worker
+            .data_general()
+            .delete_data_for(
+                b"test_audit_a".to_vec(),
+                proto::DataOpeRoleFuncCall {
+                    app_func: "audit_other/fn3".to_owned(),
+                    node_id: 1,
                 },
-            );
+            )
+            .await
+            .unwrap();
 
-            let view = self.view.clone();
-            self.rpc_handler_data_meta_update.regist(
-                p2p,
-                move |responsor: RPCResponsor<proto::DataMetaUpdateRequest>,
-                      req: proto::DataMetaUpdateRequest| {
-                    let view = view.clone();
-                    let _ = tokio::spawn(async move {
-                        view.data_general().rpc_handle_data_meta_update(responsor, req).await
-                    });
-                    Ok(())
-                },
-            );
+        // one record a page
+        let mut query = AuditQuery {
+            uid_prefix: b"test_audit_".to_vec(),
+            start_ms,
+            limit: 1,
+            ..Default::default()
+        };
+        let mut records = vec![];
+        loop {
+            let page = master.data_master().query_audit(&query);
+            records.extend(page.records);
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        let got: Vec<_> = records
+            .iter()
+            .map(|r| (r.unique_id.as_slice(), r.op, r.old_version, r.new_version))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                (&b"test_audit_a"[..], AuditOp::Write, 0, 1),
+                (&b"test_audit_a"[..], AuditOp::Write, 1, 2),
+                (&b"test_audit_b"[..], AuditOp::Write, 0, 1),
+                (&b"test_audit_a"[..], AuditOp::Delete, 2, 0),
+            ]
+        );
+        let actors: Vec<_> = records
+            .iter()
+            .map(|r| (r.app.as_deref(), r.func.as_deref()))
+            .collect();
+        assert_eq!(
+            actors,
+            vec![
+                (Some("audit_app"), Some("fn1")),
+                (Some("audit_app"), Some("fn2")),
+                (Some("audit_other"), Some("fn1")),
+                (Some("audit_other"), Some("fn3")),
+            ]
+        );
+        assert!(records[..3].iter().all(|r| r.node == 1));
+        assert!(records.windows(2).all(|w| w[0].ts_ms <= w[1].ts_ms));
 
-            let view = self.view.clone();
-            self.rpc_handler_get_data_meta
-                .regist(p2p, move |responsor, req| {
-                    let view = view.clone();
-                    let _ = tokio::spawn(async move {
-                        if let Err(e) = view.data_general().rpc_handle_get_data_meta(req, responsor)             //返回结果未处理    曾俊
-                            .await{
-                                tracing::error!("Failed to handle get data meta: {}", e);
-                            }
-                        // .todo_handle("rpc_handle_get_data_meta err");
-                    });
-                    Ok(())
-                });
+        let page = master.data_master().query_audit(&AuditQuery {
+            app: Some("audit_other".to_owned()),
+            start_ms,
+            limit: 10,
+            ..Default::default()
+        });
+        let got: Vec<_> = page
+            .records
+            .iter()
+            .map(|r| (r.unique_id.as_slice(), r.op))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                (&b"test_audit_b"[..], AuditOp::Write),
+                (&b"test_audit_a"[..], AuditOp::Delete),
+            ]
+        );
+        // nothing after the end
+        let page = master.data_master().query_audit(&AuditQuery {
+            uid_prefix: b"test_audit_".to_vec(),
+            start_ms,
+            end_ms: start_ms,
+            limit: 10,
+            ..Default::default()
+        });
+        assert!(page.records.is_empty());
+    }
 
-            let view = self.view.clone();
-            self.rpc_handler_get_data.regist(
-                p2p,
-                move |responsor: RPCResponsor<proto::GetOneDataRequest>,
-                      req: proto::GetOneDataRequest| {
-                    let view = view.clone();
-                    let _ = tokio::spawn(async move { 
-                        view.data_general().rpc_handle_get_one_data(responsor, req).await 
-                    });
-                    Ok(())
-                },
+    #[tokio::test]
+    async fn test_ttl_sweep_races_rewrite() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let master = TestView::new(sys1);
+        let worker = TestView::new(sys2);
+        let write = |uid: Vec<u8>, value: &'static [u8], ttl_ms: u64| {
+            let worker = worker.clone();
+            async move {
+                let _ = worker
+                    .data_general()
+                    .write_data_with(
+                        uid,
+                        vec![DataItemArgWrapper::from_bytes(value.to_vec())],
+                        Some((
+                            1,
+                            proto::DataOpeType::Write,
+                            proto::data_schedule_context::OpeRole::FuncCall(
+                                proto::DataOpeRoleFuncCall {
+                                    app_func: "ttl_app/fn1".to_owned(),
+                                    node_id: 1,
+                                },
+                            ),
+                        )),
+                        WriteDataOpts {
+                            ttl_ms,
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+        };
+        let uids: Vec<Vec<u8>> = (0..16)
+            .map(|i| format!("test_ttl_race_{}", i).into_bytes())
+            .collect();
+        for uid in &uids {
+            write(uid.clone(), b"expiring", 200).await;
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // rewrites without ttl land while the sweep deletes the expired versions
+        let rewrites =
+            futures::future::join_all(uids.iter().map(|uid| write(uid.clone(), b"kept", 0)));
+        let _ = tokio::join!(master.data_master().sweep_expired_data(), rewrites);
+        master.data_master().sweep_expired_data().await;
+
+        for uid in &uids {
+            worker.data_general().invalidate_meta_cache(uid);
+            let (_, mut items) = worker
+                .data_general()
+                .get_or_del_data(GetOrDelDataArg {
+                    meta: None,
+                    unique_id: uid.clone(),
+                    ty: GetOrDelDataArgType::All,
+                })
+                .await
+                .unwrap();
+            assert_eq!(
+                items.remove(&0).unwrap().data_item_dispatch,
+                Some(proto::data_item::DataItemDispatch::RawBytes(
+                    b"kept".to_vec()
+                ))
             );
         }
+    }
 
-        Ok(vec![])
+    #[test]
+    fn test_verify_item_checksum() {
+        let item = |bytes: &[u8]| proto::DataItem {
+            data_item_dispatch: Some(proto::data_item::DataItemDispatch::RawBytes(bytes.to_vec())),
+        };
+        let mut builder = DataSetMetaBuilder::new();
+        let _ = builder.set_data_splits(vec![DataSplit { splits: vec![] }]);
+        let _ = builder.set_data_checksums(vec![bytes_checksum(b"hello")]);
+        let meta = builder.build();
+        assert!(meta.verify_item(b"uid", 0, &item(b"hello")).is_ok());
+        assert!(matches!(
+            meta.verify_item(b"uid", 0, &item(b"hellO")),
+            Err(WSError::WsDataError(WsDataError::ChecksumMismatch { idx: 0, .. }))
+        ));
+
+        // writers that give no checksum are not checked
+        let mut builder = DataSetMetaBuilder::new();
+        let _ = builder.set_data_splits(vec![DataSplit { splits: vec![] }]);
+        assert!(builder.build().verify_item(b"uid", 0, &item(b"any")).is_ok());
     }
 }
-
-#[derive(Debug, Clone, Copy)]
-pub struct CacheModeVisitor(pub u16);
\ No newline at end of file
@@ -0,0 +1,799 @@
+/// Streaming Write Interface
+///
+/// Writes data items whose length is unknown when the write starts,
+/// e.g. an http upload or the output of a function piped by a wasm guest.
+///
+/// - Open: master picks the holders like the replicas of a regular write
+/// - Append: data is cut into segments of `batch_transfer.block_size`, each segment is sent
+///   to every holder as soon as it's full and staged there at its offset, so the writer
+///   keeps at most `batch_transfer.concurrency` segments in memory
+/// - Items: a writer opened for several items writes them one after another,
+///   each one staged under its own stream id on the same holders
+/// - Commit: the sizes and md5s are known now, every holder checks its staged data against them,
+///   only then the version is scheduled on master with the holders pinned,
+///   and the holders promote the checked data to the items of the version
+///
+/// The meta and version are set by master in one step as for a regular write,
+/// readers never see a partially streamed item.
+use super::*;
+use md5::{Digest, Md5};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+/// staged segments of the streams being written to this node, under the data dir
+const STREAM_STAGE_DIR: &str = "stream_stage";
+/// committed items kept as files, under the data dir
+const STREAM_ITEM_DIR: &str = "stream_items";
+/// staged data untouched this long belongs to a writer that is gone
+const STREAM_STAGE_MAX_IDLE: Duration = Duration::from_secs(3600);
+/// committed data up to this size is kept in the kv store like a regular write, larger stays a file
+const STREAM_INLINE_MAX: u64 = DEFAULT_BLOCK_SIZE as u64;
+const STREAM_RPC_TIMEOUT: Duration = Duration::from_secs(30);
+/// backoff before the first retry of a segment, doubled on each retry
+const SEGMENT_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Writer of streamed data items, see [`DataGeneral::open_data_stream`].
+///  Dropped without commit, the staged data is discarded.
+pub struct DataStreamWriter {
+    view: DataGeneralView,
+    unique_id: UniqueId,
+    /// stream id of item 0, item i is staged under `stream_id + i`
+    stream_id: u64,
+    item_cnt: usize,
+    /// size and md5 of the items written before the current one
+    items: Vec<(u64, Vec<u8>)>,
+    nodes: Vec<NodeID>,
+    context: Option<(
+        NodeID,
+        proto::DataOpeType,
+        proto::data_schedule_context::OpeRole,
+    )>,
+    opts: WriteDataOpts,
+    segment_size: usize,
+    concurrency: usize,
+    retries: usize,
+    /// bytes not yet sent, less than one segment
+    pending: Vec<u8>,
+    /// bytes of the current item sent to the holders
+    offset: u64,
+    hasher: Md5,
+    sending: FuturesUnordered<JoinHandle<WSResult<()>>>,
+    finished: bool,
+}
+
+impl DataStreamWriter {
+    /// Append to the current item
+    pub async fn write(&mut self, mut data: &[u8]) -> WSResult<()> {
+        if self.items.len() >= self.item_cnt {
+            return Err(self.no_item_left());
+        }
+        self.hasher.update(data);
+        while !data.is_empty() {
+            let take = (self.segment_size - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() == self.segment_size {
+                self.send_pending().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// md5 of the bytes of the current item written so far
+    pub fn item_checksum(&self) -> Vec<u8> {
+        self.hasher.clone().finalize().to_vec()
+    }
+
+    /// End the current item, the following writes go to the next one
+    pub async fn next_item(&mut self) -> WSResult<()> {
+        if self.items.len() + 1 >= self.item_cnt {
+            return Err(self.no_item_left());
+        }
+        self.finish_item().await
+    }
+
+    async fn finish_item(&mut self) -> WSResult<()> {
+        if !self.pending.is_empty() {
+            self.send_pending().await?;
+        }
+        let checksum = std::mem::take(&mut self.hasher).finalize().to_vec();
+        self.items.push((self.offset, checksum));
+        self.offset = 0;
+        Ok(())
+    }
+
+    fn no_item_left(&self) -> WSError {
+        WsDataError::WriteDataFailed {
+            unique_id: self.unique_id.clone(),
+            message: format!("stream opened for {} items", self.item_cnt),
+        }
+        .into()
+    }
+
+    fn stream_ids(&self) -> std::ops::Range<u64> {
+        self.stream_id..self.stream_id + self.item_cnt.max(1) as u64
+    }
+
+    /// Send the pending bytes as one segment, waits when too many segments are in flight
+    async fn send_pending(&mut self) -> WSResult<()> {
+        while self.sending.len() >= self.concurrency {
+            if let Some(res) = self.sending.next().await {
+                res??;
+            }
+        }
+        let segment = std::mem::replace(&mut self.pending, Vec::with_capacity(self.segment_size));
+        let len = segment.len() as u64;
+        let checksum = bytes_checksum(&segment);
+        let (data, compression) = match compress::compress(self.opts.compression, &segment) {
+            Some(compressed) => (compressed, self.opts.compression),
+            None => (segment, DataCompression::Raw),
+        };
+        let request = proto::DataStreamSegmentRequest {
+            stream_id: self.stream_id + self.items.len() as u64,
+            unique_id: self.unique_id.clone(),
+            offset: self.offset,
+            data,
+            checksum,
+            compression: proto::DataCompression::from(compression) as i32,
+        };
+        self.offset += len;
+        let view = self.view.clone();
+        let nodes = self.nodes.clone();
+        let retries = self.retries;
+        self.sending.push(tokio::spawn(async move {
+            let sends = nodes
+                .into_iter()
+                .map(|node| send_segment(view.clone(), node, request.clone(), retries));
+            for res in futures::future::join_all(sends).await {
+                res?;
+            }
+            Ok::<(), WSError>(())
+        }));
+        Ok(())
+    }
+
+    /// Flush the rest and publish the items as a new version of the dataset, items not reached
+    ///  are empty. The staged data is discarded if the write condition is not met or the commit fails
+    pub async fn commit(mut self) -> WSResult<CondWriteRes> {
+        let res = self.try_commit().await;
+        if !matches!(res, Ok(CondWriteRes::Written(_))) {
+            self.abort_holders().await;
+        }
+        self.finished = true;
+        res
+    }
+
+    async fn try_commit(&mut self) -> WSResult<CondWriteRes> {
+        while self.items.len() < self.item_cnt {
+            self.finish_item().await?;
+        }
+        while let Some(res) = self.sending.next().await {
+            res??;
+        }
+        // a holder missing some data fails here, before the version is published
+        self.commit_holders(0, true).await?;
+        let data_general = self.view.data_general();
+        let resp = data_general
+            .schedule_write_version(
+                &self.unique_id,
+                self.context.clone(),
+                self.items.iter().map(|(size, _)| *size).collect(),
+                self.items.iter().map(|(_, md5)| md5.clone()).collect(),
+                self.opts.clone(),
+                self.nodes.clone(),
+            )
+            .await?;
+        if resp.condition_failed {
+            return Ok(CondWriteRes::Rejected(resp.version));
+        }
+        self.commit_holders(resp.version, false).await?;
+        tracing::debug!(
+            "streamed {} items, {} bytes to data({:?}) version {}",
+            self.item_cnt,
+            self.items.iter().map(|(size, _)| size).sum::<u64>(),
+            String::from_utf8_lossy(&self.unique_id),
+            resp.version
+        );
+        Ok(CondWriteRes::Written(resp.version))
+    }
+
+    /// Check the staged data of every item on every holder with `prepare`,
+    ///  or store it as the items of `version`
+    async fn commit_holders(&self, version: DataVersion, prepare: bool) -> WSResult<()> {
+        let mut targets = vec![];
+        let mut commits = vec![];
+        for (idx, (total_size, item_checksum)) in self.items.iter().enumerate() {
+            for &node in &self.nodes {
+                targets.push((idx, node));
+                commits.push(self.view.data_general().rpc_call_data_stream_commit.call(
+                    self.view.p2p(),
+                    node,
+                    proto::DataStreamCommitRequest {
+                        stream_id: self.stream_id + idx as u64,
+                        unique_id: self.unique_id.clone(),
+                        version,
+                        total_size: *total_size,
+                        item_checksum: item_checksum.clone(),
+                        abort: false,
+                        idx: idx as u64,
+                        inline: false,
+                        prepare,
+                    },
+                    Some(Duration::from_secs(60)),
+                ));
+            }
+        }
+        for ((idx, node), res) in targets
+            .into_iter()
+            .zip(futures::future::join_all(commits).await)
+        {
+            let res = res?;
+            if !res.success {
+                return Err(WsDataError::WriteDataFailed {
+                    unique_id: self.unique_id.clone(),
+                    message: format!(
+                        "commit stream of item {} on node {} failed: {}",
+                        idx, node, res.message
+                    ),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard the staged data on the holders
+    pub async fn abort(mut self) {
+        self.abort_holders().await;
+        self.finished = true;
+    }
+
+    async fn abort_holders(&mut self) {
+        for sending in self.sending.iter() {
+            sending.abort();
+        }
+        self.sending.clear();
+        abort_stream(self.view.clone(), self.stream_ids(), self.nodes.clone()).await;
+    }
+}
+
+impl Drop for DataStreamWriter {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // outside the runtime the staged data is left to the data gc of the holders
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let _ = runtime.spawn(abort_stream(
+                self.view.clone(),
+                self.stream_ids(),
+                std::mem::take(&mut self.nodes),
+            ));
+        }
+    }
+}
+
+pub(super) async fn abort_stream(
+    view: DataGeneralView,
+    stream_ids: std::ops::Range<u64>,
+    nodes: Vec<NodeID>,
+) {
+    let targets: Vec<(u64, NodeID)> = stream_ids
+        .flat_map(|stream_id| nodes.iter().map(move |&node| (stream_id, node)))
+        .collect();
+    let aborts = targets.iter().map(|&(stream_id, node)| {
+        view.data_general().rpc_call_data_stream_commit.call(
+            view.p2p(),
+            node,
+            proto::DataStreamCommitRequest {
+                stream_id,
+                unique_id: vec![],
+                version: 0,
+                total_size: 0,
+                item_checksum: vec![],
+                abort: true,
+                idx: 0,
+                inline: false,
+                prepare: false,
+            },
+            Some(STREAM_RPC_TIMEOUT),
+        )
+    });
+    for ((stream_id, node), res) in targets.iter().zip(futures::future::join_all(aborts).await) {
+        if let Err(err) = res {
+            // left to the data gc of the holder
+            tracing::warn!(
+                "abort stream {} on node {} failed: {:?}",
+                stream_id,
+                node,
+                err
+            );
+        }
+    }
+}
+
+/// Send one segment to a holder, retried with backoff, a segment rewritten at its offset is harmless
+pub(super) async fn send_segment(
+    view: DataGeneralView,
+    node: NodeID,
+    request: proto::DataStreamSegmentRequest,
+    retries: usize,
+) -> WSResult<()> {
+    let mut attempt = 0;
+    loop {
+        let err: WSError = match view
+            .data_general()
+            .rpc_call_data_stream_segment
+            .call(view.p2p(), node, request.clone(), Some(STREAM_RPC_TIMEOUT))
+            .await
+        {
+            Ok(resp) if resp.success => return Ok(()),
+            Ok(resp) => WsDataError::WriteDataFailed {
+                unique_id: request.unique_id.clone(),
+                message: format!(
+                    "stage segment at {} on node {}: {}",
+                    request.offset, node, resp.message
+                ),
+            }
+            .into(),
+            Err(err) => err,
+        };
+        if attempt >= retries {
+            return Err(err);
+        }
+        let backoff = SEGMENT_RETRY_BACKOFF * 2u32.pow(attempt.min(6) as u32);
+        tracing::warn!(
+            "stream segment at {} to node {} failed, retry in {:?}: {:?}",
+            request.offset,
+            node,
+            backoff,
+            err
+        );
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+/// Write bytes at offset of the staging file, created on the first segment
+async fn write_stage_at(path: &Path, offset: u64, data: &[u8]) -> WSResult<()> {
+    let file_err = |err| WsDataError::FileWriteErr {
+        path: path.to_path_buf(),
+        err,
+    };
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await.map_err(file_err)?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|err| WsDataError::FileOpenErr {
+            path: path.to_path_buf(),
+            err,
+        })?;
+    let _ = file
+        .seek(std::io::SeekFrom::Start(offset))
+        .await
+        .map_err(|err| WsDataError::FileSeekErr {
+            path: path.to_path_buf(),
+            err,
+        })?;
+    file.write_all(data).await.map_err(file_err)?;
+    file.flush().await.map_err(file_err)?;
+    Ok(())
+}
+
+impl DataGeneral {
+    /// Start a streaming write of `unique_id`, the data becomes item 0 of a new version on commit
+    pub async fn open_data_stream(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+        opts: WriteDataOpts,
+    ) -> WSResult<DataStreamWriter> {
+        self.open_data_stream_items(unique_id, 1, context_openode_opetype_operole, opts)
+            .await
+    }
+
+    /// [`Self::open_data_stream`] of a version with `item_cnt` items,
+    ///  [`DataStreamWriter::next_item`] moves to the next one
+    pub async fn open_data_stream_items(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        item_cnt: usize,
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+        mut opts: WriteDataOpts,
+    ) -> WSResult<DataStreamWriter> {
+        let unique_id = unique_id.into();
+        let opened = self
+            .rpc_call_data_stream_open
+            .call(
+                self.view.p2p(),
+                self.view.p2p().nodes_config.get_master_node(),
+                proto::DataStreamOpenRequest {
+                    unique_id: unique_id.clone(),
+                    compression: proto::DataCompression::from(opts.compression) as i32,
+                    item_cnt: item_cnt as u32,
+                },
+                Some(STREAM_RPC_TIMEOUT),
+            )
+            .await?;
+        tracing::debug!(
+            "open stream {} of data({:?}) on nodes {:?}",
+            opened.stream_id,
+            String::from_utf8_lossy(&unique_id),
+            opened.nodes
+        );
+        // the holders may not decode the compression asked for
+        opts.compression = opened.compression().into();
+        let conf = &self.view.p2p().nodes_config.batch_transfer;
+        Ok(DataStreamWriter {
+            view: self.view.clone(),
+            unique_id,
+            stream_id: opened.stream_id,
+            item_cnt,
+            items: Vec::with_capacity(item_cnt),
+            nodes: opened.nodes,
+            context: context_openode_opetype_operole,
+            opts,
+            segment_size: conf.block_size,
+            concurrency: conf.concurrency,
+            retries: conf.block_retries,
+            pending: Vec::with_capacity(conf.block_size),
+            offset: 0,
+            hasher: Md5::new(),
+            sending: FuturesUnordered::new(),
+            finished: false,
+        })
+    }
+
+    /// Write the whole byte stream as item 0 of a new version of `unique_id`,
+    ///  nothing is published if the stream fails
+    pub async fn write_data_stream<S>(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        stream: S,
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+        opts: WriteDataOpts,
+    ) -> WSResult<CondWriteRes>
+    where
+        S: Stream<Item = WSResult<Bytes>>,
+    {
+        self.write_data_stream_with_md5(unique_id, stream, context_openode_opetype_operole, opts)
+            .await
+            .map(|(res, _)| res)
+    }
+
+    /// [`Self::write_data_stream`] that also gives the md5 of the whole stream
+    pub async fn write_data_stream_with_md5<S>(
+        &self,
+        unique_id: impl Into<Vec<u8>>,
+        stream: S,
+        context_openode_opetype_operole: Option<(
+            NodeID,
+            proto::DataOpeType,
+            proto::data_schedule_context::OpeRole,
+        )>,
+        opts: WriteDataOpts,
+    ) -> WSResult<(CondWriteRes, Vec<u8>)>
+    where
+        S: Stream<Item = WSResult<Bytes>>,
+    {
+        futures::pin_mut!(stream);
+        let mut writer = self
+            .open_data_stream(unique_id, context_openode_opetype_operole, opts)
+            .await?;
+        while let Some(chunk) = stream.next().await {
+            let res = match chunk {
+                Ok(chunk) => writer.write(&chunk).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = res {
+                writer.abort().await;
+                return Err(err);
+            }
+        }
+        let md5 = writer.item_checksum();
+        writer.commit().await.map(|res| (res, md5))
+    }
+
+    fn stream_stage_path(&self, stream_id: u64) -> PathBuf {
+        self.view
+            .os()
+            .file_path
+            .join(STREAM_STAGE_DIR)
+            .join(format!("{}.part", stream_id))
+    }
+
+    /// Remove the staged data of streams untouched for long, called by the data gc
+    pub async fn remove_stale_stream_stages(&self) {
+        let dir = self.view.os().file_path.join(STREAM_STAGE_DIR);
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let idle = match entry.metadata().await.and_then(|m| m.modified()) {
+                Ok(modified) => modified.elapsed().unwrap_or_default(),
+                Err(_) => continue,
+            };
+            if idle > STREAM_STAGE_MAX_IDLE {
+                tracing::info!("remove stale stream stage {:?}", entry.path());
+                if let Err(err) = tokio::fs::remove_file(entry.path()).await {
+                    tracing::warn!("remove stale stream stage failed: {:?}", err);
+                }
+            }
+        }
+    }
+
+    pub(super) async fn rpc_handle_data_stream_segment(
+        &self,
+        responsor: RPCResponsor<proto::DataStreamSegmentRequest>,
+        req: proto::DataStreamSegmentRequest,
+    ) {
+        let stream_id = req.stream_id;
+        let resp = match self.stage_segment(req).await {
+            Ok(()) => proto::DataStreamSegmentResponse {
+                success: true,
+                message: String::new(),
+            },
+            Err(err) => {
+                tracing::warn!("stage segment of stream {} failed: {:?}", stream_id, err);
+                proto::DataStreamSegmentResponse {
+                    success: false,
+                    message: format!("{:?}", err),
+                }
+            }
+        };
+        if let Err(e) = responsor.send_resp(resp).await {
+            tracing::error!("Failed to send data stream segment response: {}", e);
+        }
+    }
+
+    async fn stage_segment(&self, req: proto::DataStreamSegmentRequest) -> WSResult<()> {
+        let compression = req.compression().into();
+        let data = decompress(compression, req.data)?;
+        // corrupted in transfer, the writer retries the segment
+        let actual = bytes_checksum(&data);
+        if !req.checksum.is_empty() && actual != req.checksum {
+            return Err(WsDataError::ChecksumMismatch {
+                unique_id: req.unique_id,
+                idx: 0,
+                expected: req.checksum,
+                actual,
+            }
+            .into());
+        }
+        write_stage_at(&self.stream_stage_path(req.stream_id), req.offset, &data).await
+    }
+
+    pub(super) async fn rpc_handle_data_stream_commit(
+        &self,
+        responsor: RPCResponsor<proto::DataStreamCommitRequest>,
+        req: proto::DataStreamCommitRequest,
+    ) {
+        let stage = self.stream_stage_path(req.stream_id);
+        let prepared = stage.with_extension("ready");
+        let res = if req.abort {
+            Ok(proto::DataStreamCommitResponse {
+                remote_version: 0,
+                success: true,
+                message: String::new(),
+            })
+        } else if req.prepare {
+            self.prepare_stream_stage(&stage, &prepared, &req).await
+        } else {
+            self.commit_stream_stage(&stage, &prepared, &req).await
+        };
+        let resp = match res {
+            Ok(resp) if resp.success => {
+                if req.abort {
+                    let _ = tokio::fs::remove_file(&stage).await;
+                    let _ = tokio::fs::remove_file(&prepared).await;
+                }
+                resp
+            }
+            res => {
+                let resp = res.unwrap_or_else(|err| proto::DataStreamCommitResponse {
+                    remote_version: 0,
+                    success: false,
+                    message: format!("{:?}", err),
+                });
+                tracing::warn!("commit stream {} failed: {}", req.stream_id, resp.message);
+                let _ = tokio::fs::remove_file(&stage).await;
+                let _ = tokio::fs::remove_file(&prepared).await;
+                resp
+            }
+        };
+        if let Err(e) = responsor.send_resp(resp).await {
+            tracing::error!("Failed to send data stream commit response: {}", e);
+        }
+    }
+
+    /// Size of the staged data once it matches the committed size and md5
+    async fn check_stream_stage(
+        &self,
+        stage: &Path,
+        req: &proto::DataStreamCommitRequest,
+    ) -> WSResult<u64> {
+        let staged_size = match tokio::fs::metadata(stage).await {
+            Ok(metadata) => metadata.len(),
+            // nothing was streamed
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => {
+                return Err(WsDataError::FileOpenErr {
+                    path: stage.to_path_buf(),
+                    err,
+                }
+                .into())
+            }
+        };
+        if staged_size != req.total_size {
+            return Err(WsDataError::SizeMismatch {
+                expected: req.total_size as usize,
+                actual: staged_size as usize,
+            }
+            .into());
+        }
+        let actual = if staged_size == 0 {
+            bytes_checksum(&[])
+        } else {
+            file_checksum(stage).await?
+        };
+        if actual != req.item_checksum {
+            return Err(WsDataError::ChecksumMismatch {
+                unique_id: req.unique_id.clone(),
+                idx: req.idx,
+                expected: req.item_checksum.clone(),
+                actual,
+            }
+            .into());
+        }
+        Ok(staged_size)
+    }
+
+    /// Check the staged data and set it aside for the commit of the version,
+    ///  no segment of the stream is accepted after that
+    async fn prepare_stream_stage(
+        &self,
+        stage: &Path,
+        prepared: &Path,
+        req: &proto::DataStreamCommitRequest,
+    ) -> WSResult<proto::DataStreamCommitResponse> {
+        if self.check_stream_stage(stage, req).await? > 0 {
+            tokio::fs::rename(stage, prepared)
+                .await
+                .map_err(|err| WsDataError::FileRenameErr {
+                    from: stage.to_path_buf(),
+                    to: prepared.to_path_buf(),
+                    err,
+                })?;
+        }
+        Ok(proto::DataStreamCommitResponse {
+            remote_version: 0,
+            success: true,
+            message: String::new(),
+        })
+    }
+
+    /// Store the staged data as the item once the meta of the version arrives,
+    ///  the data set aside by the prepare is checked already
+    async fn commit_stream_stage(
+        &self,
+        stage: &Path,
+        prepared: &Path,
+        req: &proto::DataStreamCommitRequest,
+    ) -> WSResult<proto::DataStreamCommitResponse> {
+        let (stage, staged_size) = match tokio::fs::metadata(prepared).await {
+            Ok(metadata) => (prepared, metadata.len()),
+            Err(_) => (stage, self.check_stream_stage(stage, req).await?),
+        };
+
+        let (source, stored_file) = if staged_size == 0 {
+            (DataItemSource::Memory { data: vec![] }, None)
+        } else if staged_size <= STREAM_INLINE_MAX || req.inline {
+            let data = tokio::fs::read(stage)
+                .await
+                .map_err(|err| WsDataError::FileReadErr {
+                    path: stage.to_path_buf(),
+                    err,
+                })?;
+            let _ = tokio::fs::remove_file(stage).await;
+            (DataItemSource::Memory { data }, None)
+        } else {
+            // relative to the data dir, like the other file items
+            let name = stream_item_path(&req.unique_id, req.idx, req.version);
+            let target = self.view.os().abs_file_path(name.clone());
+            if let Some(dir) = target.parent() {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .map_err(|err| WsDataError::FileWriteErr {
+                        path: dir.to_path_buf(),
+                        err,
+                    })?;
+            }
+            tokio::fs::rename(stage, &target)
+                .await
+                .map_err(|err| WsDataError::FileRenameErr {
+                    from: stage.to_path_buf(),
+                    to: target.clone(),
+                    err,
+                })?;
+            (DataItemSource::File { path: name }, Some(target))
+        };
+
+        let resp = self
+            .store_items_of_version(&req.unique_id, req.version, vec![(req.idx, source)])
+            .await;
+        if let (false, Some(target)) = (resp.success, stored_file) {
+            let _ = tokio::fs::remove_file(target).await;
+        }
+        Ok(proto::DataStreamCommitResponse {
+            remote_version: resp.remote_version,
+            success: resp.success,
+            message: resp.message,
+        })
+    }
+
+    /// Remove the files of streamed items no longer stored here, overwritten or deleted since,
+    ///  called by the data gc
+    pub async fn remove_unused_stream_items(&self) {
+        let Ok(mut entries) =
+            tokio::fs::read_dir(self.view.os().file_path.join(STREAM_ITEM_DIR)).await
+        else {
+            return;
+        };
+        let kv_store_engine = self.view.kv_store_engine();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            // a commit may have stored the file but not yet the item
+            let age = match entry.metadata().await.and_then(|m| m.modified()) {
+                Ok(modified) => modified.elapsed().unwrap_or_default(),
+                Err(_) => continue,
+            };
+            if age < MAPPED_ITEM_MIN_AGE {
+                continue;
+            }
+            let in_use = entry
+                .file_name()
+                .to_str()
+                .and_then(parse_mapped_item_file_name)
+                .map_or(false, |(uid, idx, version)| {
+                    let persisted = DataItemSource::File {
+                        path: stream_item_path(&uid, idx, version),
+                    }
+                    .encode_persist();
+                    kv_store_engine
+                        .get(
+                            &KeyTypeDataSetItem { uid: &uid, idx },
+                            false,
+                            KvAdditionalConf {},
+                        )
+                        .map_or(false, |(_, stored)| stored == persisted)
+                });
+            if !in_use {
+                tracing::debug!("remove unused stream item {:?}", entry.path());
+                if let Err(err) = tokio::fs::remove_file(entry.path()).await {
+                    tracing::warn!("remove unused stream item failed: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Path of a streamed item kept as a file, relative to the data dir
+fn stream_item_path(unique_id: &[u8], idx: DataItemIdx, version: DataVersion) -> PathBuf {
+    Path::new(STREAM_ITEM_DIR).join(mapped_item_file_name(unique_id, idx, version))
+}
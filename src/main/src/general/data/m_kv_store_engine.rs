@@ -20,12 +20,22 @@ use std::sync::OnceLock;
 use std::time::Duration;
 
 use crate::general::{
-    data::m_data_general::DataSetMetaV2, m_os::OperatingSystem, network::m_p2p::P2PModule,
+    data::m_data_general::{
+        batch_handler::BatchProgress,
+        compress::{decompress_persist_range, PERSIST_TAG_ZSTD},
+        dataitem::{item_range, PERSIST_TAG_MAPPED_FILE},
+        DataItemIdx, DataSetMetaV2, DATA_SET_META_API_VERSION,
+    },
+    m_os::OperatingSystem,
+    network::m_p2p::P2PModule,
 };
 
+use crate::master::data::data_audit::AuditRecord;
+use crate::master::data::data_quota::DatasetUsage;
+use crate::master::data::kv_txn::KvTxnRecord;
 use crate::{
     logical_module_view_impl,
-    result::WSResult,
+    result::{WSResult, WsDataError},
     sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef, NodeID},
     util::JoinHandleWrapper,
 };
@@ -109,6 +119,7 @@ impl LogicalModule for KvStoreEngine {
                 );
             db
         });
+        self.migrate_layout()?;
         Ok(vec![])
     }
 }
@@ -142,13 +153,10 @@ impl KvStoreEngine {
         //  首先用户坑定时先校验了没有要求的key，才会监听
         //  有个问题是，监听还没完成插入的时候，进来了新key怎么办？
         //    所以要保证用户判断到没有key的时候持有锁，直到插入完成才解锁
-        let _ = self
-            .key_waitings
+        self.key_waitings
             .entry(key.to_owned())
-            .and_modify(|v| {
-                v.push(tokio::sync::oneshot::channel().0);
-            })
-            .or_insert_with(|| vec![wait_tx]);
+            .or_default()
+            .push(wait_tx);
         drop(hold_key_guard);
         wait_rx
     }
@@ -202,9 +210,8 @@ impl KvStoreEngine {
         // if let Some(mut key_waitings) = self.key_waitings.get_mut(key) {
         if let Some((_, key_waitings)) = self.key_waitings.remove(key) {
             for wait_tx in key_waitings {
-                wait_tx
-                    .send((kvversion, KvValue::RawData(value.clone())))
-                    .unwrap_or_else(|_| panic!("send new key event failed"));
+                // the waiter may have given up
+                let _ = wait_tx.send((kvversion, KvValue::RawData(value.clone())));
             }
         }
         Ok((kvversion, additinal_res))
@@ -316,6 +323,58 @@ impl KvStoreEngine {
         )
     }
 
+    /// Range of a persisted data item without decoding the whole value,
+    ///  returns (persist type tag, payload size, payload in range).
+    ///  The payload of a file item (tag 0) or a map file item (tag 3) is its path and is returned whole,
+    ///  of a compressed item only the blocks covering the range are decompressed and returned
+    ///  as raw bytes (tag 1).
+    pub fn get_data_item_range(
+        &self,
+        key: &KeyTypeDataSetItem,
+        offset: u64,
+        length: u64,
+        locked: bool,
+    ) -> Option<(u8, u64, Vec<u8>)> {
+        let keybytes = key.make_key();
+
+        let hold_lock = if locked {
+            None
+        } else {
+            Some(self.with_rwlock(&keybytes))
+        };
+        let _hold_lock_guard = hold_lock.as_ref().map(|lock| lock.read());
+
+        let value = match self.db.get().unwrap().get(keybytes) {
+            Ok(value) => value?,
+            Err(e) => {
+                tracing::error!("get data item range error: {:?}", e);
+                return None;
+            }
+        };
+        // kv version (8) + bincode length of the item bytes (8) + persist tag (1)
+        let tag = *value.get(16)?;
+        let payload = &value[17..];
+        if tag == PERSIST_TAG_ZSTD {
+            return match decompress_persist_range(payload, offset, length) {
+                Ok((total, bytes)) => Some((1, total, bytes)),
+                Err(e) => {
+                    tracing::error!("decompress data item error: {:?}", e);
+                    None
+                }
+            };
+        }
+        let total = payload.len() as u64;
+        if tag == 0 || tag == PERSIST_TAG_MAPPED_FILE {
+            return Some((tag, total, payload.to_vec()));
+        }
+        let range = item_range(total, offset, length);
+        Some((
+            tag,
+            total,
+            payload[range.start as usize..range.end as usize].to_vec(),
+        ))
+    }
+
     pub fn del_raw(&self, key: &[u8], locked: bool) -> WSResult<Option<(KvVersion, Vec<u8>)>> {
         let hold_lock = if locked {
             None
@@ -350,9 +409,266 @@ impl KvStoreEngine {
         let res = self.db.get().unwrap().remove(keybytes).unwrap();
         Ok(res.map(|v| Self::decode_kv(&key, &v)))
     }
+    /// Rewrite the data of older layouts: metas with u32 split sizes and
+    ///  items keyed by u8 idx. Runs before any other module touches the store.
+    fn migrate_layout(&self) -> WSResult<()> {
+        let layout = self
+            .get(&KeyTypeStoreLayout, false, KvAdditionalConf {})
+            .map_or(0, |(_, layout)| layout);
+        if layout >= DATA_SET_META_API_VERSION {
+            return Ok(());
+        }
+        let db = self.db.get().unwrap();
+
+        let mut metas = 0;
+        for entry in db.scan_prefix([KeyTypeDataSetMeta(&[]).id()]) {
+            let (key, value) = entry.map_err(|inner| WsDataError::KvEngineInnerError {
+                inner,
+                context: "scan data set metas for migration".to_owned(),
+            })?;
+            if value.get(8) == Some(&DATA_SET_META_API_VERSION) {
+                continue;
+            }
+            let Some(meta) = DataSetMetaV2::decode_persist(&value[8..]) else {
+                tracing::warn!("skip undecodable data set meta {:?} in migration", key);
+                continue;
+            };
+            // keep the kv version of the meta
+            let mut new_value = value[..8].to_vec();
+            bincode::serialize_into(&mut new_value, &meta).unwrap();
+            let _ = db.insert(key, new_value).unwrap();
+            metas += 1;
+        }
+
+        let mut items = 0;
+        for entry in db.scan_prefix([KeyTypeDataSetItem { uid: &[], idx: 0 }.id()]) {
+            let (key, value) = entry.map_err(|inner| WsDataError::KvEngineInnerError {
+                inner,
+                context: "scan data set items for migration".to_owned(),
+            })?;
+            // id + uid length (u64) + uid + idx, the old idx is 1 byte
+            let Some(uid_len) = key
+                .get(1..9)
+                .map(|len| u64::from_le_bytes(len.try_into().unwrap()) as usize)
+            else {
+                continue;
+            };
+            if key.len() != 9 + uid_len + 1 {
+                continue;
+            }
+            let new_key = KeyTypeDataSetItem {
+                uid: &key[9..9 + uid_len],
+                idx: key[9 + uid_len] as DataItemIdx,
+            }
+            .make_key();
+            let _ = db.insert(new_key, value).unwrap();
+            let _ = db.remove(key).unwrap();
+            items += 1;
+        }
+
+        let _ = self.set(KeyTypeStoreLayout, &DATA_SET_META_API_VERSION, false)?;
+        self.flush();
+        tracing::info!(
+            "migrated kv store layout {} -> {}, {} metas, {} items",
+            layout,
+            DATA_SET_META_API_VERSION,
+            metas,
+            items
+        );
+        Ok(())
+    }
+
     pub fn flush(&self) {
         let _ = self.db.get().unwrap().flush().unwrap();
     }
+
+    /// ordered unique ids in the fn kv index within [start, end), empty end means no upper bound
+    pub fn fn_kv_index_range<'a>(
+        &'a self,
+        start: &[u8],
+        end: &[u8],
+    ) -> impl Iterator<Item = Vec<u8>> + 'a {
+        let lower = KeyTypeFnKvIndex(start).make_key();
+        let upper = if end.is_empty() {
+            // all keys of the index type
+            vec![KeyTypeFnKvIndex(&[]).id() + 1]
+        } else {
+            KeyTypeFnKvIndex(end).make_key()
+        };
+        // empty range when start >= end
+        let upper = upper.max(lower.clone());
+        self.db
+            .get()
+            .unwrap()
+            .range(lower..upper)
+            .keys()
+            .filter_map(|k| match k {
+                Ok(k) => Some(k[1..].to_vec()),
+                Err(e) => {
+                    tracing::error!("scan fn kv index error: {:?}", e);
+                    None
+                }
+            })
+    }
+
+    /// (deadline, unique id) of datasets expired at `now_ms`, ordered by deadline
+    pub fn data_expiry_due(&self, now_ms: u64, limit: usize) -> Vec<(u64, Vec<u8>)> {
+        let lower = vec![KeyTypeDataExpiry { deadline_ms: 0, uid: &[] }.id()];
+        let upper = KeyTypeDataExpiry {
+            deadline_ms: now_ms.saturating_add(1),
+            uid: &[],
+        }
+        .make_key();
+        self.db
+            .get()
+            .unwrap()
+            .range(lower..upper)
+            .keys()
+            .filter_map(|k| {
+                let k = k.ok()?;
+                let deadline = u64::from_be_bytes(k.get(1..9)?.try_into().ok()?);
+                Some((deadline, k[9..].to_vec()))
+            })
+            .take(limit)
+            .collect()
+    }
+
+    fn audit_iter(&self, from: (u64, u64), end_ms: u64) -> sled::Iter {
+        let lower = KeyTypeDataAudit {
+            ts_ms: from.0,
+            seq: from.1,
+        }
+        .make_key();
+        let upper = if end_ms == 0 {
+            vec![lower[0] + 1]
+        } else {
+            KeyTypeDataAudit {
+                ts_ms: end_ms,
+                seq: 0,
+            }
+            .make_key()
+        };
+        // empty range when from >= end
+        let upper = upper.max(lower.clone());
+        self.db.get().unwrap().range(lower..upper)
+    }
+
+    /// ((timestamp, seq), record) of the audit log from `from` on, before `end_ms`,
+    ///  ordered by time. 0 end means no upper bound
+    pub fn audit_range<'a>(
+        &'a self,
+        from: (u64, u64),
+        end_ms: u64,
+    ) -> impl Iterator<Item = ((u64, u64), AuditRecord)> + 'a {
+        self.audit_iter(from, end_ms).filter_map(|entry| {
+            let (k, v) = entry.ok()?;
+            let ts_ms = u64::from_be_bytes(k.get(1..9)?.try_into().ok()?);
+            let seq = u64::from_be_bytes(k.get(9..17)?.try_into().ok()?);
+            let (_, record) = Self::decode_kv(&KeyTypeDataAudit { ts_ms, seq }, &v);
+            Some(((ts_ms, seq), record))
+        })
+    }
+
+    /// (timestamp, seq) of the audit log from `from` on, before `end_ms`, ordered by time
+    pub fn audit_keys<'a>(
+        &'a self,
+        from: (u64, u64),
+        end_ms: u64,
+    ) -> impl DoubleEndedIterator<Item = (u64, u64)> + 'a {
+        self.audit_iter(from, end_ms).keys().filter_map(|k| {
+            let k = k.ok()?;
+            let ts_ms = u64::from_be_bytes(k.get(1..9)?.try_into().ok()?);
+            let seq = u64::from_be_bytes(k.get(9..17)?.try_into().ok()?);
+            Some((ts_ms, seq))
+        })
+    }
+
+    /// kv transactions prepared on master and not finished yet
+    pub fn kv_txns(&self) -> Vec<(u64, KvTxnRecord)> {
+        let lower = KeyTypeKvTxn(0).make_key();
+        self.db
+            .get()
+            .unwrap()
+            .range(lower[..1].to_vec()..vec![lower[0] + 1])
+            .filter_map(|entry| {
+                let (k, v) = entry.ok()?;
+                let txn_id: u64 = bincode::deserialize(k.get(1..)?).ok()?;
+                let (_, record) = Self::decode_kv(&KeyTypeKvTxn(txn_id), &v);
+                Some((txn_id, record))
+            })
+            .collect()
+    }
+
+    /// replace the expiry of the dataset, 0 deadline means never expire.
+    ///  caller should hold the meta lock of the dataset
+    pub fn set_data_ttl(&self, uid: &[u8], deadline_ms: u64) -> WSResult<()> {
+        if let Some((_, old)) = self.del(KeyTypeDataTtl(uid), false)? {
+            let _ = self.del(
+                KeyTypeDataExpiry {
+                    deadline_ms: old,
+                    uid,
+                },
+                false,
+            )?;
+        }
+        if deadline_ms > 0 {
+            let _ = self.set(KeyTypeDataTtl(uid), &deadline_ms, false)?;
+            let _ = self.set(KeyTypeDataExpiry { deadline_ms, uid }, &(), false)?;
+        }
+        Ok(())
+    }
+
+    /// (unique id, idx, stored bytes) of the data items on this node, ordered by unique id,
+    ///  only the items of `uid` if it's given
+    pub fn data_items(&self, uid: Option<&[u8]>) -> Vec<(Vec<u8>, DataItemIdx, u64)> {
+        let mut prefix = vec![KeyTypeDataSetItem { uid: &[], idx: 0 }.id()];
+        if let Some(uid) = uid {
+            bincode::serialize_into(&mut prefix, uid).unwrap();
+        }
+        self.db
+            .get()
+            .unwrap()
+            .scan_prefix(prefix)
+            .filter_map(|entry| {
+                let (k, v) = entry.ok()?;
+                // id + uid length (u64) + uid + idx (u64)
+                let uid_len = u64::from_le_bytes(k.get(1..9)?.try_into().ok()?) as usize;
+                if k.len() != 9 + uid_len + 8 {
+                    return None;
+                }
+                let idx = u64::from_le_bytes(k[9 + uid_len..].try_into().ok()?);
+                Some((k[9..9 + uid_len].to_vec(), idx, v.len() as u64))
+            })
+            .collect()
+    }
+
+    /// (unique id, idx) of the unfinished batch transfers recorded on this node
+    pub fn batch_progress_keys(&self) -> Vec<(Vec<u8>, DataItemIdx)> {
+        self.db
+            .get()
+            .unwrap()
+            .scan_prefix([KeyTypeBatchProgress { uid: &[], idx: 0 }.id()])
+            .keys()
+            .filter_map(|k| {
+                let k = k.ok()?;
+                bincode::deserialize::<(Vec<u8>, DataItemIdx)>(&k[1..]).ok()
+            })
+            .collect()
+    }
+
+    /// unique ids of all dataset metas stored on this node
+    pub fn data_set_meta_uids(&self) -> Vec<Vec<u8>> {
+        self.db
+            .get()
+            .unwrap()
+            .scan_prefix([KeyTypeDataSetMeta(&[]).id()])
+            .keys()
+            .filter_map(|k| {
+                let k = k.ok()?;
+                bincode::deserialize::<Vec<u8>>(&k[1..]).ok()
+            })
+            .collect()
+    }
 }
 
 pub trait KeyType: Serialize {
@@ -463,14 +779,113 @@ pub struct KeyTypeServiceList;
 generate_key_struct!([KeyTypeServiceList], 3, Vec<u8>);
 
 pub struct KeyTypeDataSetMeta<'a>(pub &'a [u8]);
-generate_key_struct!([KeyTypeDataSetMeta,'_], 4, DataSetMetaV2);
+impl KeyType for KeyTypeDataSetMeta<'_> {
+    type Value = DataSetMetaV2;
+    fn id(&self) -> u8 {
+        4
+    }
+    // dispatched by the api version at the head of the meta
+    fn deserialize_from(&self, bytes: &[u8]) -> Option<DataSetMetaV2> {
+        DataSetMetaV2::decode_persist(bytes)
+    }
+}
 
 pub struct KeyTypeDataSetItem<'a> {
     pub uid: &'a [u8],
-    pub idx: u8,
+    pub idx: DataItemIdx,
 }
 generate_key_struct!([KeyTypeDataSetItem,'_], 5, Vec<u8>);
 
+/// dataset version of the items stored on this node
+pub struct KeyTypeDataSetItemVersion<'a>(pub &'a [u8]);
+generate_key_struct!([KeyTypeDataSetItemVersion,'_], 6, u64);
+
+/// index of fn kv dataset unique ids on master,
+///  the raw id is used as key (no length prefix) so that sled keeps them in order for range scans
+pub struct KeyTypeFnKvIndex<'a>(pub &'a [u8]);
+impl KeyType for KeyTypeFnKvIndex<'_> {
+    type Value = ();
+    fn id(&self) -> u8 {
+        7
+    }
+    fn make_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(1 + self.0.len());
+        key.push(self.id());
+        key.extend_from_slice(self.0);
+        key
+    }
+    fn deserialize_from(&self, _bytes: &[u8]) -> Option<()> {
+        Some(())
+    }
+}
+
+/// expiry index on master, ordered by deadline: id, deadline(u64 be), raw unique id
+pub struct KeyTypeDataExpiry<'a> {
+    pub deadline_ms: u64,
+    pub uid: &'a [u8],
+}
+impl KeyType for KeyTypeDataExpiry<'_> {
+    type Value = ();
+    fn id(&self) -> u8 {
+        8
+    }
+    fn make_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(9 + self.uid.len());
+        key.push(self.id());
+        key.extend_from_slice(&self.deadline_ms.to_be_bytes());
+        key.extend_from_slice(self.uid);
+        key
+    }
+    fn deserialize_from(&self, _bytes: &[u8]) -> Option<()> {
+        Some(())
+    }
+}
+
+/// expiry deadline(unix ms) of the dataset, used to find its entry in the expiry index
+pub struct KeyTypeDataTtl<'a>(pub &'a [u8]);
+generate_key_struct!([KeyTypeDataTtl,'_], 9, u64);
+
+/// layout version of the whole store, missing in stores written before it's introduced
+pub struct KeyTypeStoreLayout;
+generate_key_struct!([KeyTypeStoreLayout], 10, u8);
+
+/// received blocks of an unfinished batch transfer of a file item
+pub struct KeyTypeBatchProgress<'a> {
+    pub uid: &'a [u8],
+    pub idx: DataItemIdx,
+}
+generate_key_struct!([KeyTypeBatchProgress,'_], 11, BatchProgress);
+
+/// app charged for a dataset on master, see `master::data::data_quota`
+pub struct KeyTypeDataUsage<'a>(pub &'a [u8]);
+generate_key_struct!([KeyTypeDataUsage,'_], 12, DatasetUsage);
+
+/// audit log of the data mutations on master, ordered by time: id, timestamp(u64 be), seq(u64 be)
+pub struct KeyTypeDataAudit {
+    pub ts_ms: u64,
+    pub seq: u64,
+}
+impl KeyType for KeyTypeDataAudit {
+    type Value = AuditRecord;
+    fn id(&self) -> u8 {
+        13
+    }
+    fn make_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(17);
+        key.push(self.id());
+        key.extend_from_slice(&self.ts_ms.to_be_bytes());
+        key.extend_from_slice(&self.seq.to_be_bytes());
+        key
+    }
+    fn deserialize_from(&self, bytes: &[u8]) -> Option<AuditRecord> {
+        bincode::deserialize(bytes).ok()
+    }
+}
+
+/// kv transaction prepared on master, removed when it finishes
+pub struct KeyTypeKvTxn(pub u64);
+generate_key_struct!([KeyTypeKvTxn], 14, KvTxnRecord);
+
 // impl KeyType for KeyTypeKvPosition<'_> {
 //     type Value = NodeID;
 //     fn id(&self) -> u8 {
@@ -534,12 +949,75 @@ impl Serialize for KeyTypeServiceList {
     }
 }
 
+impl Serialize for KeyTypeStoreLayout {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
+}
+
 impl Serialize for KeyTypeDataSetMeta<'_> {
     fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         self.0.serialize(serializer)
     }
 }
 
+impl Serialize for KeyTypeDataSetItemVersion<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeDataTtl<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeDataExpiry<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(&self.deadline_ms)?;
+        tup.serialize_element(self.uid)?;
+        tup.end()
+    }
+}
+
+impl Serialize for KeyTypeDataAudit {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(&self.ts_ms)?;
+        tup.serialize_element(&self.seq)?;
+        tup.end()
+    }
+}
+
+impl Serialize for KeyTypeKvTxn {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeDataUsage<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeFnKvIndex<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Serialize for KeyTypeBatchProgress<'_> {
+    fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(self.uid)?;
+        tup.serialize_element(&self.idx)?;
+        tup.end()
+    }
+}
+
 impl Serialize for KeyTypeDataSetItem<'_> {
     fn serialize<S: serde::ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let mut tup = serializer.serialize_tuple(2)?;
@@ -554,13 +1032,25 @@ mod test {
     use crate::{
         general::{
             data::{
-                m_data_general::DataSetMetaBuilder,
-                m_kv_store_engine::{KeyTypeDataSetMeta, KvAdditionalConf},
+                m_data_general::{
+                    dataitem::{
+                        file_read_range, mapped_item_persist, persisted_path,
+                        PERSIST_TAG_MAPPED_FILE,
+                    },
+                    CacheMode, DataItemIdx, DataMetaSys, DataSetMetaBuilder, DataSplit,
+                    EachNodeSplit, DATA_SET_META_API_VERSION,
+                },
+                m_kv_store_engine::{
+                    KeyLockGuard, KeyType, KeyTypeDataSetItem, KeyTypeDataSetMeta,
+                    KeyTypeDataTtl, KeyTypeFnKvIndex, KeyTypeStoreLayout, KvAdditionalConf,
+                },
             },
             test_utils,
         },
         result::WSResultExt,
+        sys::NodeID,
     };
+    use std::collections::HashSet;
 
     use super::View;
 
@@ -613,4 +1103,248 @@ mod test {
             .unwrap()
             .is_none());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_all_waiters_woken_by_new_key() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = View::new(sys2);
+        let kv_store_engine = view.kv_store_engine();
+        let key = "test_waiter_key".as_bytes();
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let lock = kv_store_engine.with_rwlock(key);
+                let guard = KeyLockGuard::Write(lock.write());
+                kv_store_engine.register_waiter_for_new(key, guard)
+            })
+            .collect();
+        let (version, _) = kv_store_engine.set_raw(key, vec![1, 2], false).unwrap();
+        for waiter in waiters {
+            let (got_version, value) = waiter.await.unwrap();
+            assert_eq!(got_version, version);
+            assert_eq!(value.as_raw_data().unwrap(), &vec![1, 2]);
+        }
+        let _ = kv_store_engine.del_raw(key, false).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fn_kv_index_range() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = View::new(sys2);
+        let kv_store_engine = view.kv_store_engine();
+        for key in ["idx_b", "idx_a2", "idx_a1", "idx_c"] {
+            let _ = kv_store_engine
+                .set(KeyTypeFnKvIndex(key.as_bytes()), &(), false)
+                .unwrap();
+        }
+        let got: Vec<Vec<u8>> = kv_store_engine
+            .fn_kv_index_range("idx_a".as_bytes(), "idx_c".as_bytes())
+            .collect();
+        assert_eq!(
+            got,
+            vec![b"idx_a1".to_vec(), b"idx_a2".to_vec(), b"idx_b".to_vec()]
+        );
+        let got: Vec<Vec<u8>> = kv_store_engine
+            .fn_kv_index_range("idx_b".as_bytes(), &[])
+            .take_while(|k| k.starts_with(b"idx_"))
+            .collect();
+        assert_eq!(got, vec![b"idx_b".to_vec(), b"idx_c".to_vec()]);
+        assert_eq!(
+            kv_store_engine
+                .fn_kv_index_range("idx_c".as_bytes(), "idx_a".as_bytes())
+                .count(),
+            0
+        );
+        for key in ["idx_b", "idx_a2", "idx_a1", "idx_c"] {
+            let _ = kv_store_engine
+                .del(KeyTypeFnKvIndex(key.as_bytes()), false)
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_data_expiry_due() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = View::new(sys2);
+        let kv_store_engine = view.kv_store_engine();
+        let ours = |due: Vec<(u64, Vec<u8>)>| -> Vec<(u64, Vec<u8>)> {
+            due.into_iter()
+                .filter(|(_, uid)| uid.starts_with(b"ttl_test_"))
+                .collect()
+        };
+        kv_store_engine.set_data_ttl(b"ttl_test_b", 200).unwrap();
+        kv_store_engine.set_data_ttl(b"ttl_test_a", 100).unwrap();
+        kv_store_engine.set_data_ttl(b"ttl_test_c", 300).unwrap();
+        assert_eq!(
+            ours(kv_store_engine.data_expiry_due(200, 100)),
+            vec![(100, b"ttl_test_a".to_vec()), (200, b"ttl_test_b".to_vec())]
+        );
+
+        // reset replaces the old deadline, 0 clears it
+        kv_store_engine.set_data_ttl(b"ttl_test_a", 250).unwrap();
+        kv_store_engine.set_data_ttl(b"ttl_test_b", 0).unwrap();
+        assert_eq!(
+            ours(kv_store_engine.data_expiry_due(299, 100)),
+            vec![(250, b"ttl_test_a".to_vec())]
+        );
+        assert!(kv_store_engine
+            .get(&KeyTypeDataTtl(b"ttl_test_b"), false, KvAdditionalConf::default())
+            .is_none());
+
+        for uid in ["ttl_test_a", "ttl_test_c"] {
+            kv_store_engine.set_data_ttl(uid.as_bytes(), 0).unwrap();
+        }
+        assert!(ours(kv_store_engine.data_expiry_due(u64::MAX - 1, 100)).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_migrate_layout_api2() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = View::new(sys2);
+        let kv_store_engine = view.kv_store_engine();
+        let db = kv_store_engine.db.get().unwrap();
+        let uid: &[u8] = b"test_migrate_layout_uid";
+
+        // api version 2 meta without checksums, kv version 1
+        let old_meta = (
+            2u8,
+            5u64,
+            vec![vec![(1 as NodeID, 0u32, 10u32, 0u32)]],
+            Vec::<DataMetaSys>::new(),
+            HashSet::<NodeID>::new(),
+            vec![0 as CacheMode],
+        );
+        let mut value = bincode::serialize(&1u64).unwrap();
+        bincode::serialize_into(&mut value, &old_meta).unwrap();
+        let _ = db.insert(KeyTypeDataSetMeta(uid).make_key(), value).unwrap();
+        // item keyed by u8 idx
+        let mut old_item_key = vec![KeyTypeDataSetItem { uid, idx: 0 }.id()];
+        bincode::serialize_into(&mut old_item_key, &(uid, 3u8)).unwrap();
+        let mut value = bincode::serialize(&1u64).unwrap();
+        bincode::serialize_into(&mut value, &vec![1u8, 7, 7]).unwrap();
+        let _ = db.insert(old_item_key.clone(), value).unwrap();
+
+        let _ = kv_store_engine.del(KeyTypeStoreLayout, false).unwrap();
+        kv_store_engine.migrate_layout().unwrap();
+
+        let (kv_version, meta) = kv_store_engine
+            .get(&KeyTypeDataSetMeta(uid), false, KvAdditionalConf {})
+            .unwrap();
+        assert_eq!(kv_version, 1);
+        assert_eq!(meta.version, 5);
+        assert_eq!(meta.datas_splits[0].splits[0].data_size, 10);
+        assert!(meta.data_checksums.is_empty());
+        let raw = db.get(KeyTypeDataSetMeta(uid).make_key()).unwrap().unwrap();
+        assert_eq!(raw[8], DATA_SET_META_API_VERSION);
+        assert!(db.get(&old_item_key).unwrap().is_none());
+        let (_, item) = kv_store_engine
+            .get(&KeyTypeDataSetItem { uid, idx: 3 }, false, KvAdditionalConf {})
+            .unwrap();
+        assert_eq!(item, vec![1, 7, 7]);
+        assert_eq!(
+            kv_store_engine
+                .get(&KeyTypeStoreLayout, false, KvAdditionalConf {})
+                .unwrap()
+                .1,
+            DATA_SET_META_API_VERSION
+        );
+
+        let _ = kv_store_engine.del(KeyTypeDataSetMeta(uid), false).unwrap();
+        let _ = kv_store_engine
+            .del(KeyTypeDataSetItem { uid, idx: 3 }, false)
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_thousands_of_items_over_4gib() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = View::new(sys2);
+        let kv_store_engine = view.kv_store_engine();
+        let uid: &[u8] = b"test_thousands_of_items_uid";
+        let item_cnt: DataItemIdx = 3000;
+        let item_size: u64 = 5 << 30;
+
+        let splits = (0..item_cnt)
+            .map(|_| DataSplit {
+                splits: vec![EachNodeSplit {
+                    node_id: 1,
+                    data_offset: item_size / 2,
+                    data_size: item_size - item_size / 2,
+                    cache_mode: 0,
+                }],
+            })
+            .collect();
+        let mut builder = DataSetMetaBuilder::new();
+        let _ = builder.set_data_splits(splits).version(1);
+        let _ = kv_store_engine
+            .set(KeyTypeDataSetMeta(uid), &builder.build(), false)
+            .unwrap();
+        for idx in 0..item_cnt {
+            let _ = kv_store_engine
+                .set(
+                    KeyTypeDataSetItem { uid, idx },
+                    &idx.to_le_bytes().to_vec(),
+                    false,
+                )
+                .unwrap();
+        }
+
+        let (_, meta) = kv_store_engine
+            .get(&KeyTypeDataSetMeta(uid), false, KvAdditionalConf {})
+            .unwrap();
+        assert_eq!(meta.data_item_cnt(), item_cnt as usize);
+        let last = &meta.datas_splits[item_cnt as usize - 1].splits[0];
+        assert_eq!(last.data_offset + last.data_size, item_size);
+        // indexes past u8 don't collide
+        for idx in [0, 255, 256, 2999] {
+            let (_, item) = kv_store_engine
+                .get(&KeyTypeDataSetItem { uid, idx }, false, KvAdditionalConf {})
+                .unwrap();
+            assert_eq!(item, idx.to_le_bytes().to_vec());
+        }
+
+        // a map file item really past 4GiB, sparse so only the written block takes disk
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("over_4gib");
+        let tail_offset = (4u64 << 30) + 4096;
+        {
+            use std::io::{Seek, SeekFrom, Write};
+            let mut file = std::fs::File::create(&path).unwrap();
+            file.set_len(item_size).unwrap();
+            let _ = file.seek(SeekFrom::Start(tail_offset)).unwrap();
+            file.write_all(b"past 4gib").unwrap();
+        }
+        let file_item = KeyTypeDataSetItem { uid, idx: item_cnt };
+        let _ = kv_store_engine
+            .set(file_item, &mapped_item_persist(&path), false)
+            .unwrap();
+        let (tag, _, payload) = kv_store_engine
+            .get_data_item_range(
+                &KeyTypeDataSetItem { uid, idx: item_cnt },
+                tail_offset,
+                9,
+                false,
+            )
+            .unwrap();
+        assert_eq!(tag, PERSIST_TAG_MAPPED_FILE);
+        let stored_path = persisted_path(&payload).unwrap();
+        let (bytes, total) = file_read_range(&stored_path, tail_offset, 9).await.unwrap();
+        assert_eq!(total, item_size);
+        assert_eq!(bytes, b"past 4gib".to_vec());
+        // the hole before it reads as zeros, the range at the end is cut at the file size
+        let (bytes, _) = file_read_range(&stored_path, tail_offset - 4, 4)
+            .await
+            .unwrap();
+        assert_eq!(bytes, vec![0; 4]);
+        let (bytes, _) = file_read_range(&stored_path, item_size - 2, 10)
+            .await
+            .unwrap();
+        assert_eq!(bytes, vec![0; 2]);
+
+        let _ = kv_store_engine.del(KeyTypeDataSetMeta(uid), false).unwrap();
+        for idx in 0..=item_cnt {
+            let _ = kv_store_engine
+                .del(KeyTypeDataSetItem { uid, idx }, false)
+                .unwrap();
+        }
+    }
 }
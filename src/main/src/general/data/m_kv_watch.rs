@@ -0,0 +1,514 @@
+//! Watch changes of function kv keys.
+//!  Master keeps the subscriptions and pushes the changes scheduled on it,
+//!  the subscribing node dispatches them to its local watchers.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::async_trait;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+use ws_derive::LogicalModule;
+
+use crate::general::data::m_data_general::{
+    new_data_unique_id_fn_kv, DataGeneral, DataVersion, DATA_UID_PREFIX_FN_KV,
+};
+use crate::general::network::{
+    m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor},
+    proto,
+};
+use crate::master::m_metric_observor::MetricObservor;
+use crate::result::{WSError, WsDataError};
+use crate::sys::{LogicalModule, LogicalModulesRef, NodeID};
+use crate::{
+    logical_module_view_impl, result::WSResult, sys::LogicalModuleNewArgs, util::JoinHandleWrapper,
+};
+
+logical_module_view_impl!(View);
+logical_module_view_impl!(View, p2p, P2PModule);
+logical_module_view_impl!(View, data_general, DataGeneral);
+logical_module_view_impl!(View, kv_watch, KvWatch);
+logical_module_view_impl!(View, metric_observor, Option<MetricObservor>);
+
+/// how often master drops the subscriptions of the nodes that left
+const DEAD_SUBS_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A change of a watched key
+#[derive(Debug, Clone)]
+pub struct KvWatchEvent {
+    /// user key, without the dataset prefix
+    pub key: Vec<u8>,
+    /// dataset version after the change, 0 for delete
+    pub version: DataVersion,
+    pub op: proto::kv::KvWatchOp,
+}
+
+struct WatchSub {
+    key: Vec<u8>,
+    prefix: bool,
+}
+
+impl WatchSub {
+    fn matches(&self, key: &[u8]) -> bool {
+        if self.prefix {
+            key.starts_with(&self.key)
+        } else {
+            key == self.key.as_slice()
+        }
+    }
+}
+
+#[derive(LogicalModule)]
+pub struct KvWatch {
+    view: View,
+    rpc_caller_subscribe: RPCCaller<proto::kv::KvWatchSubscribeRequest>,
+    rpc_caller_unsubscribe: RPCCaller<proto::kv::KvWatchUnsubscribeRequest>,
+    rpc_caller_event: RPCCaller<proto::kv::KvWatchEvent>,
+    rpc_handler_subscribe: RPCHandler<proto::kv::KvWatchSubscribeRequest>,
+    rpc_handler_unsubscribe: RPCHandler<proto::kv::KvWatchUnsubscribeRequest>,
+    rpc_handler_event: RPCHandler<proto::kv::KvWatchEvent>,
+    /// on master: (subscribing node, watch id) -> watched key
+    subs: Mutex<HashMap<(NodeID, u64), WatchSub>>,
+    /// on subscribing node: watch id -> local watcher
+    watchers: DashMap<u64, mpsc::UnboundedSender<KvWatchEvent>>,
+    next_watch_id: AtomicU64,
+}
+
+#[async_trait]
+impl LogicalModule for KvWatch {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: View::new(args.logical_modules_ref.clone()),
+            rpc_caller_subscribe: RPCCaller::new(),
+            rpc_caller_unsubscribe: RPCCaller::new(),
+            rpc_caller_event: RPCCaller::new(),
+            rpc_handler_subscribe: RPCHandler::new(),
+            rpc_handler_unsubscribe: RPCHandler::new(),
+            rpc_handler_event: RPCHandler::new(),
+            subs: Mutex::new(HashMap::new()),
+            watchers: DashMap::new(),
+            next_watch_id: AtomicU64::new(1),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        // register rpc caller
+        {
+            self.rpc_caller_subscribe.regist(self.view.p2p());
+            self.rpc_caller_unsubscribe.regist(self.view.p2p());
+            self.rpc_caller_event.regist(self.view.p2p());
+        }
+
+        // register rpc handler
+        {
+            let view = self.view.clone();
+            self.rpc_handler_subscribe.regist(
+                self.view.p2p(),
+                move |responsor: RPCResponsor<proto::kv::KvWatchSubscribeRequest>,
+                      req: proto::kv::KvWatchSubscribeRequest| {
+                    let _ = view.kv_watch().subs.lock().insert(
+                        (responsor.node_id(), req.watch_id),
+                        WatchSub {
+                            key: req.key,
+                            prefix: req.prefix,
+                        },
+                    );
+                    let _ = tokio::spawn(async move {
+                        if let Err(err) = responsor
+                            .send_resp(proto::kv::KvWatchSubscribeResponse {})
+                            .await
+                        {
+                            tracing::warn!("send kv watch subscribe response failed: {:?}", err);
+                        }
+                    });
+                    Ok(())
+                },
+            );
+
+            let view = self.view.clone();
+            self.rpc_handler_unsubscribe.regist(
+                self.view.p2p(),
+                move |responsor: RPCResponsor<proto::kv::KvWatchUnsubscribeRequest>,
+                      req: proto::kv::KvWatchUnsubscribeRequest| {
+                    let _ = view
+                        .kv_watch()
+                        .subs
+                        .lock()
+                        .remove(&(responsor.node_id(), req.watch_id));
+                    let _ = tokio::spawn(async move {
+                        if let Err(err) = responsor
+                            .send_resp(proto::kv::KvWatchUnsubscribeResponse {})
+                            .await
+                        {
+                            tracing::warn!("send kv watch unsubscribe response failed: {:?}", err);
+                        }
+                    });
+                    Ok(())
+                },
+            );
+
+            let view = self.view.clone();
+            self.rpc_handler_event.regist(
+                self.view.p2p(),
+                move |responsor: RPCResponsor<proto::kv::KvWatchEvent>,
+                      req: proto::kv::KvWatchEvent| {
+                    let watching = view.kv_watch().dispatch_event(req);
+                    let _ = tokio::spawn(async move {
+                        if let Err(err) = responsor
+                            .send_resp(proto::kv::KvWatchEventAck { watching })
+                            .await
+                        {
+                            tracing::warn!("send kv watch event ack failed: {:?}", err);
+                        }
+                    });
+                    Ok(())
+                },
+            );
+        }
+
+        if !self.view.p2p().nodes_config.this.1.is_master() {
+            return Ok(vec![]);
+        }
+        let view = self.view.clone();
+        let sweeper = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(DEAD_SUBS_SWEEP_INTERVAL).await;
+                view.kv_watch().drop_dead_subs();
+            }
+        });
+        Ok(vec![JoinHandleWrapper::from(sweeper)])
+    }
+}
+
+/// Receives the changes of the watched key, unsubscribes when dropped
+pub struct KvWatcher {
+    watch_id: u64,
+    rx: mpsc::UnboundedReceiver<KvWatchEvent>,
+    view: View,
+}
+
+impl KvWatcher {
+    /// next change, events of different changes may arrive out of order,
+    ///  compare the versions if it matters
+    pub async fn next(&mut self) -> Option<KvWatchEvent> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for KvWatcher {
+    fn drop(&mut self) {
+        let _ = self.view.kv_watch().watchers.remove(&self.watch_id);
+        // master also drops the subscription on the next event if this fails
+        let view = self.view.clone();
+        let watch_id = self.watch_id;
+        let _ = tokio::spawn(async move {
+            let p2p = view.p2p();
+            if let Err(err) = view
+                .kv_watch()
+                .rpc_caller_unsubscribe
+                .call(
+                    p2p,
+                    p2p.nodes_config.get_master_node(),
+                    proto::kv::KvWatchUnsubscribeRequest { watch_id },
+                    Some(Duration::from_secs(10)),
+                )
+                .await
+            {
+                tracing::warn!("unsubscribe kv watch {} failed: {:?}", watch_id, err);
+            }
+        });
+    }
+}
+
+impl KvWatch {
+    /// Subscribe to the changes of `key`, or of all keys starting with `key` when `prefix`
+    pub async fn watch(&self, key: &[u8], prefix: bool) -> WSResult<KvWatcher> {
+        let watch_id = self.next_watch_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::unbounded_channel();
+        // register before subscribing, so that no event is missed
+        let _ = self.watchers.insert(watch_id, tx);
+        let watcher = KvWatcher {
+            watch_id,
+            rx,
+            view: self.view.clone(),
+        };
+
+        let p2p = self.view.p2p();
+        let _ = self
+            .rpc_caller_subscribe
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                proto::kv::KvWatchSubscribeRequest {
+                    watch_id,
+                    key: key.to_vec(),
+                    prefix,
+                },
+                Some(Duration::from_secs(10)),
+            )
+            .await?;
+        Ok(watcher)
+    }
+
+    /// Block until the version of `key` is no longer `after_version` (0 means not exist),
+    ///  returns None on timeout
+    pub async fn wait_key(
+        &self,
+        key: &[u8],
+        after_version: DataVersion,
+        timeout: Duration,
+    ) -> WSResult<Option<KvWatchEvent>> {
+        // reordered events of older changes don't extend the wait
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut watcher = self.watch(key, false).await?;
+
+        // the key may have changed before the subscription took effect
+        let cur_version = match self
+            .view
+            .data_general()
+            .get_or_del_datameta_from_master(&new_data_unique_id_fn_kv(key), false, 0)
+            .await
+        {
+            Ok(meta) => meta.version,
+            Err(WSError::WsDataError(WsDataError::DataSetNotFound { .. })) => 0,
+            Err(err) => return Err(err),
+        };
+        if cur_version != after_version {
+            return Ok(Some(KvWatchEvent {
+                key: key.to_vec(),
+                version: cur_version,
+                op: if cur_version == 0 {
+                    proto::kv::KvWatchOp::Delete
+                } else {
+                    proto::kv::KvWatchOp::Set
+                },
+            }));
+        }
+
+        loop {
+            match tokio::time::timeout_at(deadline, watcher.next()).await {
+                Ok(Some(event)) if event.version != after_version => return Ok(Some(event)),
+                // reordered event of an older change
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => return Ok(None),
+            }
+        }
+    }
+
+    /// Push the change of a dataset to the watching nodes, called on master
+    ///  after the dataset meta is updated
+    pub fn notify(&self, unique_id: &[u8], version: DataVersion, op: proto::kv::KvWatchOp) {
+        let Some(key) = unique_id.strip_prefix(DATA_UID_PREFIX_FN_KV.as_bytes()) else {
+            return;
+        };
+        let targets: Vec<(NodeID, u64)> = self
+            .subs
+            .lock()
+            .iter()
+            .filter(|(_, sub)| sub.matches(key))
+            .map(|(target, _)| *target)
+            .collect();
+        for (node, watch_id) in targets {
+            let view = self.view.clone();
+            let event = proto::kv::KvWatchEvent {
+                watch_id,
+                key: key.to_vec(),
+                version,
+                op: op as i32,
+            };
+            let _ = tokio::spawn(async move {
+                let watching = match view
+                    .kv_watch()
+                    .rpc_caller_event
+                    .call(view.p2p(), node, event, Some(Duration::from_secs(10)))
+                    .await
+                {
+                    Ok(ack) => ack.watching,
+                    Err(err) => {
+                        tracing::warn!(
+                            "push kv watch {} to node {} failed: {:?}",
+                            watch_id,
+                            node,
+                            err
+                        );
+                        false
+                    }
+                };
+                if !watching {
+                    let _ = view.kv_watch().subs.lock().remove(&(node, watch_id));
+                }
+            });
+        }
+    }
+
+    /// Subscriptions of the nodes that left get no event to find they are gone, called on master
+    fn drop_dead_subs(&self) {
+        let observor = self.view.metric_observor();
+        self.subs.lock().retain(|(node, watch_id), _| {
+            let alive = observor.is_node_alive(*node);
+            if !alive {
+                tracing::debug!("drop kv watch {} of left node {}", watch_id, node);
+            }
+            alive
+        });
+    }
+
+    /// return false if the watcher is gone
+    fn dispatch_event(&self, event: proto::kv::KvWatchEvent) -> bool {
+        let Some(watcher) = self.watchers.get(&event.watch_id) else {
+            return false;
+        };
+        watcher
+            .send(KvWatchEvent {
+                op: event.op(),
+                key: event.key,
+                version: event.version,
+            })
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{KvWatch, WatchSub};
+    use crate::{
+        general::{
+            app::AppMetaManager,
+            data::m_data_general::fn_kv_ns_key,
+            network::{
+                proto::{
+                    self,
+                    kv::{KvRequest, KvRequests},
+                },
+                proto_ext::KvRequestExt,
+            },
+            test_utils,
+        },
+        logical_module_view_impl,
+        sys::LogicalModulesRef,
+        worker::m_kv_user_client::KvUserClient,
+    };
+
+    logical_module_view_impl!(TestView);
+    logical_module_view_impl!(TestView, kv_watch, KvWatch);
+    logical_module_view_impl!(TestView, kv_user_client, Option<KvUserClient>);
+    logical_module_view_impl!(TestView, appmeta_manager, AppMetaManager);
+
+    #[test]
+    fn test_watch_sub_matches() {
+        let key_sub = WatchSub {
+            key: b"user_1".to_vec(),
+            prefix: false,
+        };
+        assert!(key_sub.matches(b"user_1"));
+        assert!(!key_sub.matches(b"user_10"));
+
+        let prefix_sub = WatchSub {
+            key: b"user_".to_vec(),
+            prefix: true,
+        };
+        assert!(prefix_sub.matches(b"user_1"));
+        assert!(prefix_sub.matches(b"user_"));
+        assert!(!prefix_sub.matches(b"use"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_watch_key_changes() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let view = TestView::new(sys2);
+        let (app, func) = ("watch_test", "setter");
+        view.appmeta_manager()
+            .insert_test_app_meta(
+                app,
+                r#"
+fns:
+  setter:
+    kvs:
+      watch_{}: [set]
+"#,
+            )
+            .await;
+        let set = |key: &str, value: &str| {
+            let view = view.clone();
+            let request = KvRequest::new_set(proto::kv::KvPair {
+                key: key.as_bytes().to_owned(),
+                value: value.as_bytes().to_owned(),
+            });
+            async move {
+                let _ = view
+                    .kv_user_client()
+                    .kv_requests(
+                        app,
+                        func,
+                        KvRequests {
+                            app: app.to_owned(),
+                            func: func.to_owned(),
+                            prev_kv_opeid: -1,
+                            atomic: false,
+                            requests: vec![request],
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+        };
+
+        // keys of the app are watched in its namespace
+        let ns_key = |key: &str| fn_kv_ns_key(app, key.as_bytes());
+        let mut prefix_watcher = view
+            .kv_watch()
+            .watch(&ns_key("watch_"), true)
+            .await
+            .unwrap();
+        set("watch_a", "1").await;
+        let event = tokio::time::timeout(Duration::from_secs(5), prefix_watcher.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.key, ns_key("watch_a"));
+        assert_eq!(event.op, proto::kv::KvWatchOp::Set);
+
+        // known version returns after the next change
+        let waiting = {
+            let view = view.clone();
+            let version = event.version;
+            let key = ns_key("watch_a");
+            tokio::spawn(async move {
+                view.kv_watch()
+                    .wait_key(&key, version, Duration::from_secs(10))
+                    .await
+                    .unwrap()
+            })
+        };
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        set("watch_a", "2").await;
+        let changed = waiting.await.unwrap().unwrap();
+        assert!(changed.version > event.version);
+
+        // stale known version returns at once, unchanged key times out
+        let got = view
+            .kv_watch()
+            .wait_key(&ns_key("watch_a"), event.version, Duration::from_secs(10))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(got.version, changed.version);
+        assert!(view
+            .kv_watch()
+            .wait_key(
+                &ns_key("watch_a"),
+                changed.version,
+                Duration::from_millis(300)
+            )
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
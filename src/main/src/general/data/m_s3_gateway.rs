@@ -0,0 +1,805 @@
+//! S3 compatible object gateway over the datasets of the cluster.
+//!  Objects are fn kv datasets keyed `<bucket>/<key>` in the namespace of the `s3` app, with one
+//!  item, written as streams, so functions importing them in `shared_kvs` can read them as kv too. Buckets are implicit, a bucket is just a key prefix.
+//!  Only the core object api with path style requests is served:
+//!  PutObject, GetObject (single Range), HeadObject, DeleteObject and ListObjects (v1 and v2).
+//!  There's no auth, the gateway is meant for local tools and tests.
+
+use std::collections::HashMap;
+
+use axum::{
+    async_trait,
+    body::{BodyStream, Bytes, StreamBody},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures::{Stream, StreamExt};
+use ws_derive::LogicalModule;
+
+use crate::general::data::m_data_general::{
+    fn_kv_ns_key, new_data_unique_id_fn_kv, CondWriteRes, DataGeneral, DataSetMetaV2, WriteDataOpts,
+};
+use crate::general::network::{m_p2p::P2PModule, proto};
+use crate::master::data::m_data_master::{key_successor, DataMaster};
+use crate::result::{WSError, WsDataError};
+use crate::sys::{LogicalModule, LogicalModulesRef};
+use crate::worker::m_kv_user_client::KvUserClient;
+use crate::{
+    logical_module_view_impl, result::WSResult, sys::LogicalModuleNewArgs, util::JoinHandleWrapper,
+};
+
+logical_module_view_impl!(View);
+logical_module_view_impl!(View, p2p, P2PModule);
+logical_module_view_impl!(View, data_general, DataGeneral);
+logical_module_view_impl!(View, data_master, Option<DataMaster>);
+logical_module_view_impl!(View, kv_user_client, Option<KvUserClient>);
+
+/// app the objects are charged to, they live in its fn kv namespace
+const S3_GATEWAY_APP: &str = "s3";
+/// default and max of `max-keys` in one listing
+const LIST_MAX_KEYS: usize = 1000;
+/// datasets carry no modification time, listings report this one
+const LAST_MODIFIED_UNKNOWN: &str = "1970-01-01T00:00:00.000Z";
+/// a whole object is sent in chunks of this size
+const GET_OBJECT_CHUNK_SIZE: u64 = 1 << 20;
+
+#[derive(LogicalModule)]
+pub struct S3Gateway {
+    view: View,
+}
+
+#[async_trait]
+impl LogicalModule for S3Gateway {
+    fn inner_new(args: LogicalModuleNewArgs) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            view: View::new(args.logical_modules_ref.clone()),
+        }
+    }
+    async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
+        let Some(conf) = self.view.p2p().nodes_config.s3_gateway.clone() else {
+            return Ok(vec![]);
+        };
+        let app = Router::new()
+            .route("/:bucket", get(list_objects).head(head_bucket))
+            .route(
+                "/:bucket/*key",
+                get(get_object)
+                    .head(head_object)
+                    .put(put_object)
+                    .delete(delete_object),
+            )
+            .with_state(self.view.clone());
+        let server = tokio::spawn(async move {
+            tracing::info!("s3 gateway start on {}", conf.addr);
+            if let Err(err) = axum::Server::bind(&conf.addr)
+                .serve(app.into_make_service())
+                .await
+            {
+                tracing::error!("s3 gateway on {} stopped: {:?}", conf.addr, err);
+            }
+        });
+        Ok(vec![JoinHandleWrapper::from(server)])
+    }
+}
+
+/// fn kv key of an object, bucket names can't contain '/' so the key is unambiguous
+pub fn object_kv_key(bucket: &str, key: &str) -> Vec<u8> {
+    fn_kv_ns_key(S3_GATEWAY_APP, format!("{}/{}", bucket, key).as_bytes())
+}
+
+fn object_etag(meta: &DataSetMetaV2) -> String {
+    match meta.data_checksums.first() {
+        Some(md5) if !md5.is_empty() => format!("\"{}\"", hex::encode(md5)),
+        // written without a checksum, the version still tells two contents apart
+        _ => format!("\"v{}\"", meta.version),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ObjectRange {
+    Full,
+    /// inclusive bounds
+    Part {
+        start: u64,
+        end: u64,
+    },
+    Unsatisfiable,
+}
+
+/// Only a single `bytes=` range is served, anything else is ignored as http allows
+fn parse_range(range: Option<&str>, size: u64) -> ObjectRange {
+    let Some(spec) = range.and_then(|r| r.trim().strip_prefix("bytes=")) else {
+        return ObjectRange::Full;
+    };
+    if spec.contains(',') {
+        return ObjectRange::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return ObjectRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    match (start.parse::<u64>(), end.parse::<u64>()) {
+        // suffix range, the last n bytes
+        _ if start.is_empty() => match end.parse::<u64>() {
+            Ok(0) => ObjectRange::Unsatisfiable,
+            Ok(_) if size == 0 => ObjectRange::Unsatisfiable,
+            Ok(n) => ObjectRange::Part {
+                start: size.saturating_sub(n),
+                end: size - 1,
+            },
+            Err(_) => ObjectRange::Full,
+        },
+        (Ok(start), _) if start >= size => ObjectRange::Unsatisfiable,
+        (Ok(start), _) if end.is_empty() => ObjectRange::Part {
+            start,
+            end: size - 1,
+        },
+        (Ok(start), Ok(end)) if start <= end => ObjectRange::Part {
+            start,
+            end: end.min(size - 1),
+        },
+        _ => ObjectRange::Full,
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn s3_error(status: StatusCode, code: &str, message: &str, resource: &str) -> Response {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{}</Code><Message>{}</Message><Resource>{}</Resource></Error>",
+        code,
+        xml_escape(message),
+        xml_escape(resource)
+    );
+    (status, [(header::CONTENT_TYPE, "application/xml")], body).into_response()
+}
+
+fn ws_error_response(err: WSError, resource: &str) -> Response {
+    match err {
+        WSError::WsDataError(WsDataError::DataSetNotFound { .. }) => s3_error(
+            StatusCode::NOT_FOUND,
+            "NoSuchKey",
+            "The specified key does not exist.",
+            resource,
+        ),
+        err => {
+            tracing::warn!("s3 gateway request on {} failed: {:?}", resource, err);
+            s3_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "InternalError",
+                &format!("{:?}", err),
+                resource,
+            )
+        }
+    }
+}
+
+fn object_headers(meta: &DataSetMetaV2, content_length: u64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let _ = headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    let _ = headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    let _ = headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(etag) = HeaderValue::from_str(&object_etag(meta)) {
+        let _ = headers.insert(header::ETAG, etag);
+    }
+    headers
+}
+
+/// the gateway writes and deletes objects as a function of its own app
+fn gateway_role(view: &View) -> proto::DataOpeRoleFuncCall {
+    proto::DataOpeRoleFuncCall {
+        app_func: format!("{}/gateway", S3_GATEWAY_APP),
+        node_id: view.p2p().nodes_config.this_node(),
+    }
+}
+
+async fn put_object(
+    State(view): State<View>,
+    Path((bucket, key)): Path<(String, String)>,
+    body: BodyStream,
+) -> Response {
+    let resource = format!("/{}/{}", bucket, key);
+    let this_node = view.p2p().nodes_config.this_node();
+    let unique_id = new_data_unique_id_fn_kv(&object_kv_key(&bucket, &key));
+    let body = body.map(|chunk| {
+        chunk.map_err(|e| {
+            WSError::from(WsDataError::WriteDataFailed {
+                unique_id: unique_id.clone(),
+                message: format!("read request body failed: {}", e),
+            })
+        })
+    });
+    let res = view
+        .data_general()
+        .write_data_stream_with_md5(
+            unique_id.clone(),
+            body,
+            Some((
+                this_node,
+                proto::DataOpeType::Write,
+                proto::data_schedule_context::OpeRole::FuncCall(gateway_role(&view)),
+            )),
+            WriteDataOpts::default(),
+        )
+        .await;
+    match res {
+        Ok((CondWriteRes::Written(_), md5)) => {
+            let etag = format!("\"{}\"", hex::encode(md5));
+            (StatusCode::OK, [(header::ETAG, etag)]).into_response()
+        }
+        // nothing was stored
+        Ok((CondWriteRes::Rejected(_), _)) => s3_error(
+            StatusCode::CONFLICT,
+            "OperationAborted",
+            "The object was not written, retry the request.",
+            &resource,
+        ),
+        Err(err) => ws_error_response(err, &resource),
+    }
+}
+
+async fn get_object(
+    State(view): State<View>,
+    Path((bucket, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let resource = format!("/{}/{}", bucket, key);
+    let unique_id = new_data_unique_id_fn_kv(&object_kv_key(&bucket, &key));
+    let data_general = view.data_general();
+    let meta = match data_general.get_datameta_cached(&unique_id).await {
+        Ok((meta, _)) => meta,
+        Err(err) => return ws_error_response(err, &resource),
+    };
+    let size = meta.item_size(0);
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    let (status, offset, length) = match parse_range(range, size) {
+        ObjectRange::Full => (StatusCode::OK, 0, size),
+        ObjectRange::Part { start, end } => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        ObjectRange::Unsatisfiable => {
+            return s3_error(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "InvalidRange",
+                "The requested range is not satisfiable",
+                &resource,
+            )
+        }
+    };
+    if status == StatusCode::OK && length > 0 {
+        let body = object_body(view.clone(), unique_id);
+        return (status, object_headers(&meta, size), body).into_response();
+    }
+    // a zero length range would read the whole item
+    let data = if length == 0 {
+        vec![]
+    } else {
+        match data_general
+            .get_item_range(&meta, &unique_id, 0, offset, length)
+            .await
+        {
+            Ok((data, _)) => data,
+            Err(err) => return ws_error_response(err, &resource),
+        }
+    };
+    let mut headers = object_headers(&meta, data.len() as u64);
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", offset, offset + length - 1, size);
+        if let Ok(content_range) = HeaderValue::from_str(&content_range) {
+            let _ = headers.insert(header::CONTENT_RANGE, content_range);
+        }
+    }
+    (status, headers, data).into_response()
+}
+
+/// Body of a whole object, read in chunks by a task of its own so the response
+///  doesn't borrow the view
+fn object_body(
+    view: View,
+    unique_id: Vec<u8>,
+) -> StreamBody<impl Stream<Item = Result<Bytes, std::io::Error>>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let _ = tokio::spawn(async move {
+        let chunks = view
+            .data_general()
+            .read_data_stream(&unique_id, 0, GET_OBJECT_CHUNK_SIZE);
+        futures::pin_mut!(chunks);
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|err| {
+                tracing::warn!("s3 gateway read of {:?} failed: {:?}", unique_id, err);
+                std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", err))
+            });
+            let failed = chunk.is_err();
+            // the client is gone when the send fails
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    StreamBody::new(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }))
+}
+
+async fn head_object(
+    State(view): State<View>,
+    Path((bucket, key)): Path<(String, String)>,
+) -> Response {
+    let resource = format!("/{}/{}", bucket, key);
+    let unique_id = new_data_unique_id_fn_kv(&object_kv_key(&bucket, &key));
+    match view.data_general().get_datameta_cached(&unique_id).await {
+        Ok((meta, _)) => (StatusCode::OK, object_headers(&meta, meta.item_size(0))).into_response(),
+        Err(err) => ws_error_response(err, &resource),
+    }
+}
+
+async fn delete_object(
+    State(view): State<View>,
+    Path((bucket, key)): Path<(String, String)>,
+) -> Response {
+    let resource = format!("/{}/{}", bucket, key);
+    let unique_id = new_data_unique_id_fn_kv(&object_kv_key(&bucket, &key));
+    match view
+        .data_general()
+        .delete_data_for(unique_id, gateway_role(&view))
+        .await
+    {
+        // deleting a missing key succeeds in s3 too
+        Ok(_) | Err(WSError::WsDataError(WsDataError::DataSetNotFound { .. })) => {
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(err) => ws_error_response(err, &resource),
+    }
+}
+
+/// Buckets are implicit, they always exist
+async fn head_bucket() -> StatusCode {
+    StatusCode::OK
+}
+
+/// One page of a listing, keys are relative to the bucket
+#[derive(Default)]
+struct ListPage {
+    contents: Vec<(String, DataSetMetaV2)>,
+    common_prefixes: Vec<String>,
+    /// set when there are more, the listing continues after it
+    next_marker: Option<String>,
+}
+
+/// Where a key of the listing goes: itself, or rolled up into a common prefix
+fn common_prefix_of<'a>(key: &'a str, prefix: &str, delimiter: &str) -> Option<&'a str> {
+    if delimiter.is_empty() {
+        return None;
+    }
+    let rest = key.strip_prefix(prefix)?;
+    rest.find(delimiter)
+        .map(|pos| &key[..prefix.len() + pos + delimiter.len()])
+}
+
+impl View {
+    /// one page of the fn kv index on master, master reads its own index
+    async fn scan_kv_keys(
+        &self,
+        start: Vec<u8>,
+        prefix: Vec<u8>,
+        limit: u32,
+    ) -> WSResult<proto::kv::KvScanIndexResponse> {
+        let req = proto::kv::KvScanIndexRequest {
+            start,
+            end: vec![],
+            prefix,
+            limit,
+            cursor: vec![],
+        };
+        if self.p2p().nodes_config.this.1.is_master() {
+            Ok(self.data_master().scan_fn_kv_index(req))
+        } else {
+            self.kv_user_client()
+                .scan_kv_keys(
+                    proto::kv::KeyRange {
+                        start: req.start,
+                        end: req.end,
+                    },
+                    req.prefix,
+                    req.limit,
+                    req.cursor,
+                )
+                .await
+        }
+    }
+
+    /// Keys of the bucket with the prefix and after the marker, keys containing the delimiter
+    ///  after the prefix are rolled up into common prefixes, which count as one key each.
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        delimiter: &str,
+        after: Option<&str>,
+        max_keys: usize,
+    ) -> WSResult<ListPage> {
+        let bucket_key = object_kv_key(bucket, "");
+        let scan_prefix = kv_key_of(&bucket_key, prefix);
+        let mut start = match after {
+            Some(after) => {
+                let mut start = kv_key_of(&bucket_key, after);
+                start.push(0);
+                start
+            }
+            None => scan_prefix.clone(),
+        };
+
+        let mut page = ListPage::default();
+        let mut last: Option<String> = None;
+        loop {
+            let scanned = self
+                .scan_kv_keys(start.clone(), scan_prefix.clone(), LIST_MAX_KEYS as u32)
+                .await?;
+            let mut restart = None;
+            for kv_key in &scanned.keys {
+                let Some(key) = kv_key
+                    .strip_prefix(bucket_key.as_slice())
+                    .and_then(|key| std::str::from_utf8(key).ok())
+                else {
+                    continue;
+                };
+                let rolled_up = common_prefix_of(key, prefix, delimiter);
+                // the keys under the common prefix the marker points at were listed before
+                if let (Some(common_prefix), Some(after)) = (rolled_up, after) {
+                    if common_prefix <= after {
+                        continue;
+                    }
+                }
+                if page.contents.len() + page.common_prefixes.len() >= max_keys {
+                    page.next_marker = last;
+                    return Ok(page);
+                }
+                if let Some(common_prefix) = rolled_up {
+                    page.common_prefixes.push(common_prefix.to_owned());
+                    last = Some(common_prefix.to_owned());
+                    // skip the rest of the keys under it
+                    restart = key_successor(&kv_key_of(&bucket_key, common_prefix));
+                    break;
+                }
+                let unique_id = new_data_unique_id_fn_kv(kv_key);
+                match self.data_general().get_datameta_cached(&unique_id).await {
+                    Ok((meta, _)) => page.contents.push((key.to_owned(), meta)),
+                    // deleted since the scan
+                    Err(WSError::WsDataError(WsDataError::DataSetNotFound { .. })) => continue,
+                    Err(err) => return Err(err),
+                }
+                last = Some(key.to_owned());
+            }
+            start = match restart {
+                Some(restart) => restart,
+                None if scanned.next_cursor.is_empty() => return Ok(page),
+                None => {
+                    let mut start = scanned.next_cursor;
+                    start.push(0);
+                    start
+                }
+            };
+        }
+    }
+}
+
+fn kv_key_of(bucket_key: &[u8], key: &str) -> Vec<u8> {
+    [bucket_key, key.as_bytes()].concat()
+}
+
+fn list_result_xml(
+    bucket: &str,
+    query: &HashMap<String, String>,
+    max_keys: usize,
+    page: &ListPage,
+) -> String {
+    let v2 = query.get("list-type").map(|t| t.as_str()) == Some("2");
+    let field = |name: &str| query.get(name).map(|v| v.as_str()).unwrap_or("");
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">",
+    );
+    xml.push_str(&format!("<Name>{}</Name>", xml_escape(bucket)));
+    xml.push_str(&format!("<Prefix>{}</Prefix>", xml_escape(field("prefix"))));
+    if !field("delimiter").is_empty() {
+        xml.push_str(&format!(
+            "<Delimiter>{}</Delimiter>",
+            xml_escape(field("delimiter"))
+        ));
+    }
+    xml.push_str(&format!("<MaxKeys>{}</MaxKeys>", max_keys));
+    xml.push_str(&format!(
+        "<IsTruncated>{}</IsTruncated>",
+        page.next_marker.is_some()
+    ));
+    if v2 {
+        xml.push_str(&format!(
+            "<KeyCount>{}</KeyCount>",
+            page.contents.len() + page.common_prefixes.len()
+        ));
+        if let Some(token) = query.get("continuation-token") {
+            xml.push_str(&format!(
+                "<ContinuationToken>{}</ContinuationToken>",
+                xml_escape(token)
+            ));
+        }
+        if let Some(start_after) = query.get("start-after") {
+            xml.push_str(&format!(
+                "<StartAfter>{}</StartAfter>",
+                xml_escape(start_after)
+            ));
+        }
+        if let Some(next) = &page.next_marker {
+            xml.push_str(&format!(
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                hex::encode(next)
+            ));
+        }
+    } else {
+        xml.push_str(&format!("<Marker>{}</Marker>", xml_escape(field("marker"))));
+        if let Some(next) = &page.next_marker {
+            xml.push_str(&format!("<NextMarker>{}</NextMarker>", xml_escape(next)));
+        }
+    }
+    for (key, meta) in &page.contents {
+        xml.push_str(&format!(
+            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            xml_escape(key),
+            LAST_MODIFIED_UNKNOWN,
+            xml_escape(&object_etag(meta)),
+            meta.item_size(0)
+        ));
+    }
+    for common_prefix in &page.common_prefixes {
+        xml.push_str(&format!(
+            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+            xml_escape(common_prefix)
+        ));
+    }
+    xml.push_str("</ListBucketResult>");
+    xml
+}
+
+async fn list_objects(
+    State(view): State<View>,
+    Path(bucket): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let resource = format!("/{}", bucket);
+    let max_keys = query
+        .get("max-keys")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(LIST_MAX_KEYS)
+        .min(LIST_MAX_KEYS);
+    // v2 continues after the token, or after start-after; v1 after the marker
+    let after = match query.get("continuation-token") {
+        Some(token) => match hex::decode(token)
+            .ok()
+            .and_then(|t| String::from_utf8(t).ok())
+        {
+            Some(after) => Some(after),
+            None => {
+                return s3_error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    "The continuation token provided is incorrect",
+                    &resource,
+                )
+            }
+        },
+        None => query
+            .get("start-after")
+            .or_else(|| query.get("marker"))
+            .filter(|v| !v.is_empty())
+            .cloned(),
+    };
+    let prefix = query.get("prefix").map(|v| v.as_str()).unwrap_or("");
+    let delimiter = query.get("delimiter").map(|v| v.as_str()).unwrap_or("");
+    let page = if max_keys == 0 {
+        ListPage::default()
+    } else {
+        match view
+            .list_objects(&bucket, prefix, delimiter, after.as_deref(), max_keys)
+            .await
+        {
+            Ok(page) => page,
+            Err(err) => return ws_error_response(err, &resource),
+        }
+    };
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/xml")],
+        list_result_xml(&bucket, &query, max_keys, &page),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        common_prefix_of, list_result_xml, object_kv_key, parse_range, ListPage, ObjectRange,
+    };
+    use crate::{config::S3GatewayConfig, general::test_utils};
+    use md5::{Digest, Md5};
+    use reqwest::{header, StatusCode};
+    use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+    #[test]
+    fn test_parse_range() {
+        let part = |start, end| ObjectRange::Part { start, end };
+        assert_eq!(parse_range(None, 100), ObjectRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), part(0, 9));
+        assert_eq!(parse_range(Some("bytes=90-"), 100), part(90, 99));
+        assert_eq!(parse_range(Some("bytes=90-200"), 100), part(90, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), part(90, 99));
+        assert_eq!(parse_range(Some("bytes=-200"), 100), part(0, 99));
+        assert_eq!(
+            parse_range(Some("bytes=100-"), 100),
+            ObjectRange::Unsatisfiable
+        );
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ObjectRange::Unsatisfiable);
+        assert_eq!(
+            parse_range(Some("bytes=-0"), 100),
+            ObjectRange::Unsatisfiable
+        );
+        // ignored
+        assert_eq!(parse_range(Some("bytes=9-0"), 100), ObjectRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ObjectRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 100), ObjectRange::Full);
+    }
+
+    #[test]
+    fn test_list_layout() {
+        assert_eq!(object_kv_key("bk", "a/b"), b"s3\0bk/a/b".to_vec());
+        assert_eq!(common_prefix_of("a/b/c", "a/", "/"), Some("a/b/"));
+        assert_eq!(common_prefix_of("a/b", "a/", "/"), None);
+        assert_eq!(common_prefix_of("a/b/c", "", ""), None);
+
+        let page = ListPage {
+            contents: vec![],
+            common_prefixes: vec!["a/<b>/".to_owned()],
+            next_marker: Some("a/<b>/".to_owned()),
+        };
+        let mut query = HashMap::new();
+        let _ = query.insert("list-type".to_owned(), "2".to_owned());
+        let _ = query.insert("delimiter".to_owned(), "/".to_owned());
+        let xml = list_result_xml("bk", &query, 1, &page);
+        assert!(xml.contains("<IsTruncated>true</IsTruncated>"));
+        assert!(xml.contains("<KeyCount>1</KeyCount>"));
+        assert!(xml.contains("<CommonPrefixes><Prefix>a/&lt;b&gt;/</Prefix></CommonPrefixes>"));
+        assert!(xml.contains(&format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            hex::encode("a/<b>/")
+        )));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_object_api_on_running_gateway() {
+        let addr: SocketAddr = "127.0.0.1:2439".parse().unwrap();
+        // served by the worker, its listings go through the kv client to master
+        let _cluster = test_utils::start_test_cluster_with(2431, 2, |config| {
+            if config.this.0 == 1 {
+                config.s3_gateway = Some(S3GatewayConfig {
+                    addr,
+                    nodes: vec![],
+                });
+            }
+        })
+        .await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://{}{}", addr, path);
+
+        // put, the etag is the md5 of the content
+        let body = b"hello gateway".to_vec();
+        for key in ["dir/a.txt", "dir/b.txt", "top.txt"] {
+            let resp = client
+                .put(url(&format!("/bk/{}", key)))
+                .body(body.clone())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let etag = format!("\"{}\"", hex::encode(Md5::digest(&body)));
+            assert_eq!(resp.headers()[header::ETAG], etag.as_str());
+        }
+
+        // get, whole and a range
+        let resp = client.get(url("/bk/dir/a.txt")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.bytes().await.unwrap(), body);
+        let resp = client
+            .get(url("/bk/dir/a.txt"))
+            .header(header::RANGE, "bytes=6-12")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()[header::CONTENT_RANGE], "bytes 6-12/13");
+        assert_eq!(resp.bytes().await.unwrap(), &b"gateway"[..]);
+        let resp = client
+            .get(url("/bk/dir/a.txt"))
+            .header(header::RANGE, "bytes=13-")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        // an object over several chunks is streamed whole
+        let large: Vec<u8> = (0..5 << 19).map(|i| (i % 251) as u8).collect();
+        let resp = client
+            .put(url("/bk/large.bin"))
+            .body(large.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client.get(url("/bk/large.bin")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::CONTENT_LENGTH],
+            large.len().to_string().as_str()
+        );
+        assert_eq!(resp.bytes().await.unwrap(), large);
+
+        let resp = client.get(url("/bk/missing")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(resp
+            .text()
+            .await
+            .unwrap()
+            .contains("<Code>NoSuchKey</Code>"));
+
+        // head
+        let resp = client.head(url("/bk/top.txt")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_LENGTH], "13");
+
+        // list, v2 rolls the keys under the delimiter up, v1 pages by marker
+        let list = |query: &'static str| {
+            let client = client.clone();
+            let url = url(&format!("/bk?{}", query));
+            async move { client.get(url).send().await.unwrap().text().await.unwrap() }
+        };
+        let xml = list("list-type=2&delimiter=/").await;
+        assert!(xml.contains("<Key>top.txt</Key>"));
+        assert!(xml.contains("<CommonPrefixes><Prefix>dir/</Prefix></CommonPrefixes>"));
+        assert!(!xml.contains("<Key>dir/a.txt</Key>"));
+        let xml = list("prefix=dir/&max-keys=1").await;
+        assert!(xml.contains("<Key>dir/a.txt</Key>"));
+        assert!(xml.contains("<NextMarker>dir/a.txt</NextMarker>"));
+        let xml = list("prefix=dir/&marker=dir/a.txt").await;
+        assert!(xml.contains("<Key>dir/b.txt</Key>"));
+        assert!(xml.contains("<IsTruncated>false</IsTruncated>"));
+
+        // delete, a missing key is deleted too
+        for _ in 0..2 {
+            let resp = client.delete(url("/bk/dir/a.txt")).send().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+        let resp = client.head(url("/bk/dir/a.txt")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let xml = list("prefix=dir/").await;
+        assert!(!xml.contains("<Key>dir/a.txt</Key>"));
+        assert!(xml.contains("<Key>dir/b.txt</Key>"));
+    }
+}
@@ -1,4 +1,8 @@
 pub mod kv_interface;
+pub mod m_data_cache;
+pub mod m_data_gc;
 pub mod m_data_general;
 pub mod m_dist_lock;
 pub mod m_kv_store_engine;
+pub mod m_kv_watch;
+pub mod m_s3_gateway;
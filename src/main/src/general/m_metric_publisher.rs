@@ -9,6 +9,7 @@ use crate::{
     util::JoinHandleWrapper,
 };
 
+use super::data::m_data_general::compress;
 use super::network::{
     m_p2p::{MsgSender, P2PModule},
     proto,
@@ -67,11 +68,19 @@ async fn report_metric_task(view: MetricPublisherView) {
         let cpu_used =
             sys.cpus().iter().map(|c| c.cpu_usage()).sum::<f32>() / sys.cpus().len() as f32;
 
+        let kv_access = crate::worker::kv_access::metrics();
         let metric = proto::metric::RscMetric {
             cpu_used,
             mem_used: sys.used_memory() as f32,
             cpu_all: cpu_all as f32,
             mem_all: sys.total_memory() as f32,
+            kv_denied_get: kv_access.denied_get,
+            kv_denied_set: kv_access.denied_set,
+            kv_denied_delete: kv_access.denied_delete,
+            compressions: compress::SUPPORTED
+                .iter()
+                .map(|c| proto::DataCompression::from(*c) as i32)
+                .collect(),
         };
         // println!("send metrics to master");
         // let node_config = view.p2p().nodes_config;
@@ -86,12 +86,31 @@ logical_module_view_impl!(View, p2p, P2PModule);
 pub struct P2PQuicNode {
     logical_modules_view: View,
     shared: Arc<P2PQuicNodeShared>,
+    #[cfg(test)]
+    down: std::sync::atomic::AtomicBool,
 }
 
 impl P2PQuicNode {
     fn p2p_base(&self) -> &P2PModule {
         self.logical_modules_view.p2p()
     }
+
+    /// A node taken down neither sends nor handles what it receives, like a crashed one
+    #[cfg(test)]
+    pub fn test_set_down(&self, down: bool) {
+        self.down.store(down, Ordering::Release);
+    }
+
+    fn is_down(&self) -> bool {
+        #[cfg(test)]
+        {
+            self.down.load(Ordering::Acquire)
+        }
+        #[cfg(not(test))]
+        {
+            false
+        }
+    }
 }
 
 #[async_trait]
@@ -108,6 +127,8 @@ impl LogicalModule for P2PQuicNode {
                 peer_connections: HashMap::new().into(),
             }
             .into(),
+            #[cfg(test)]
+            down: false.into(),
             // name: format!("{}::{}", args.parent_name, Self::self_name()),
         }
     }
@@ -356,6 +377,9 @@ async fn handle_connection(
         match res {
             Ok(msg) => {
                 if let Some(WireMsg((_, _, mut bytes))) = msg {
+                    if view.p2p().p2p_kernel.is_down() {
+                        continue;
+                    }
                     let headlen=bytes.split_to(1)[0];
                     let head=bytes.split_to(headlen as usize);
                     match deserialize_msg_id_task_id(&head) {
@@ -420,6 +444,9 @@ impl P2PKernel for P2PQuicNode {
         msg_id: MsgId,
         req_data: Vec<u8>,
     ) -> WSResult<()> {
+        if self.is_down() {
+            return Err(WsNetworkConnErr::ConnectionNotEstablished(node).into());
+        }
         let addr = self.p2p_base().get_addr_by_id(node)?;
 
         let peerconns = {
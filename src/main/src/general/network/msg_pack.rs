@@ -91,6 +91,22 @@ define_msg_ids!(
                         return false;
                     }
                 }
+                proto::kv::kv_request::Op::Cas(kv_cas_request) => {
+                    if kv_cas_request.kv.is_none() {
+                        return false;
+                    }
+                }
+                proto::kv::kv_request::Op::Incr(_) => {}
+                proto::kv::kv_request::Op::PutIfAbsent(kv_put_if_absent_request) => {
+                    if kv_put_if_absent_request.kv.is_none() {
+                        return false;
+                    }
+                }
+                proto::kv::kv_request::Op::Scan(kv_scan_request) => {
+                    if kv_scan_request.range.is_none() {
+                        return false;
+                    }
+                }
             }
         }
         true
@@ -144,7 +160,41 @@ define_msg_ids!(
             _ => false
         }
     }),
-    (proto::BatchDataResponse, _pack, { true })
+    (proto::BatchDataResponse, _pack, { true }),
+    (proto::sche::FnCallReq, pack, {
+        !pack.app.is_empty() && !pack.func.is_empty()
+    }),
+    (proto::sche::FnCallResp, _pack, { true }),
+    (proto::kv::KvScanIndexRequest, _pack, { true }),
+    (proto::kv::KvScanIndexResponse, _pack, { true }),
+    (proto::kv::KvTxnPrepareRequest, pack, { !pack.keys.is_empty() }),
+    (proto::kv::KvTxnPrepareResponse, _pack, { true }),
+    (proto::kv::KvTxnFinishRequest, pack, { pack.txn_id != 0 }),
+    (proto::kv::KvTxnFinishResponse, _pack, { true }),
+    (proto::kv::KvWatchSubscribeRequest, pack, { pack.watch_id != 0 }),
+    (proto::kv::KvWatchSubscribeResponse, _pack, { true }),
+    (proto::kv::KvWatchUnsubscribeRequest, pack, { pack.watch_id != 0 }),
+    (proto::kv::KvWatchUnsubscribeResponse, _pack, { true }),
+    (proto::kv::KvWatchEvent, pack, { pack.watch_id != 0 }),
+    (proto::kv::KvWatchEventAck, _pack, { true }),
+    (proto::DataGcTombstoneRequest, pack, { !pack.unique_id.is_empty() }),
+    (proto::DataGcTombstoneResponse, _pack, { true }),
+    (proto::BatchResumeRequest, pack, {
+        !pack.unique_id.is_empty() && pack.block_size != 0
+    }),
+    (proto::BatchResumeResponse, _pack, { true }),
+    (proto::DataStreamOpenRequest, pack, { !pack.unique_id.is_empty() }),
+    (proto::DataStreamOpenResponse, _pack, { true }),
+    (proto::DataStreamSegmentRequest, pack, { pack.stream_id != 0 }),
+    (proto::DataStreamSegmentResponse, _pack, { true }),
+    (proto::DataStreamCommitRequest, pack, {
+        pack.stream_id != 0 && (pack.abort || !pack.unique_id.is_empty())
+    }),
+    (proto::DataStreamCommitResponse, _pack, { true }),
+    (proto::DataCacheEvictRequest, pack, { !pack.unique_id.is_empty() }),
+    (proto::DataCacheEvictResponse, _pack, { true }),
+    (proto::DataReplicateItemRequest, pack, { !pack.unique_id.is_empty() }),
+    (proto::DataReplicateItemResponse, _pack, { true })
 );
 
 pub trait RPCReq: MsgPack + Default {
@@ -203,6 +253,62 @@ impl RPCReq for proto::BatchDataRequest {
     type Resp = proto::BatchDataResponse;
 }
 
+impl RPCReq for proto::sche::FnCallReq {
+    type Resp = proto::sche::FnCallResp;
+}
+
+impl RPCReq for proto::kv::KvScanIndexRequest {
+    type Resp = proto::kv::KvScanIndexResponse;
+}
+
+impl RPCReq for proto::kv::KvTxnPrepareRequest {
+    type Resp = proto::kv::KvTxnPrepareResponse;
+}
+
+impl RPCReq for proto::kv::KvTxnFinishRequest {
+    type Resp = proto::kv::KvTxnFinishResponse;
+}
+
+impl RPCReq for proto::kv::KvWatchSubscribeRequest {
+    type Resp = proto::kv::KvWatchSubscribeResponse;
+}
+
+impl RPCReq for proto::kv::KvWatchUnsubscribeRequest {
+    type Resp = proto::kv::KvWatchUnsubscribeResponse;
+}
+
+impl RPCReq for proto::kv::KvWatchEvent {
+    type Resp = proto::kv::KvWatchEventAck;
+}
+
+impl RPCReq for proto::DataGcTombstoneRequest {
+    type Resp = proto::DataGcTombstoneResponse;
+}
+
+impl RPCReq for proto::BatchResumeRequest {
+    type Resp = proto::BatchResumeResponse;
+}
+
+impl RPCReq for proto::DataStreamOpenRequest {
+    type Resp = proto::DataStreamOpenResponse;
+}
+
+impl RPCReq for proto::DataStreamSegmentRequest {
+    type Resp = proto::DataStreamSegmentResponse;
+}
+
+impl RPCReq for proto::DataStreamCommitRequest {
+    type Resp = proto::DataStreamCommitResponse;
+}
+
+impl RPCReq for proto::DataCacheEvictRequest {
+    type Resp = proto::DataCacheEvictResponse;
+}
+
+impl RPCReq for proto::DataReplicateItemRequest {
+    type Resp = proto::DataReplicateItemResponse;
+}
+
 // impl RPCReq for proto::kv::KvLockWaitAcquireNotifyRequest {
 //     type Resp = proto::kv::KvLockWaitAcquireNotifyResponse;
 // }
@@ -1,9 +1,11 @@
 use crate::general::app::DataEventTrigger;
 use crate::general::data::m_data_general::dataitem::DataItemSource;
+use crate::general::data::m_data_general::compress;
+use crate::general::data::m_data_general::dataitem;
 use crate::general::data::m_data_general::DataItemIdx;
 use crate::general::data::m_dist_lock::DistLockOpe;
 use crate::general::network::proto::sche::distribute_task_req::{
-    DataEventTriggerNew, DataEventTriggerWrite, Trigger,
+    DataEventTriggerDelete, DataEventTriggerNew, DataEventTriggerWrite, Trigger,
 };
 
 use super::proto::{self, kv::KvResponse, FileData};
@@ -206,8 +208,12 @@ impl AsRef<[u8]> for proto::DataItem {
 pub trait ProtoExtKvResponse {
     fn new_lock(lock_id: u32) -> KvResponse;
     fn new_common(kvs: Vec<proto::kv::KvPair>) -> KvResponse;
+    fn new_atomic(success: bool, version: u64, value: Vec<u8>) -> KvResponse;
+    fn new_scan(kvs: Vec<proto::kv::KvPair>, next_cursor: Vec<u8>) -> KvResponse;
     fn lock_id(&self) -> Option<u32>;
     fn common_kvs(&self) -> Option<&Vec<proto::kv::KvPair>>;
+    fn atomic_resp(&self) -> Option<&proto::kv::kv_response::KvAtomicResponse>;
+    fn scan_resp(&self) -> Option<&proto::kv::kv_response::KvScanResponse>;
 }
 
 impl ProtoExtKvResponse for KvResponse {
@@ -223,16 +229,46 @@ impl ProtoExtKvResponse for KvResponse {
             resp: Some(proto::kv::kv_response::Resp::LockId(lock_id)),
         }
     }
+    fn new_atomic(success: bool, version: u64, value: Vec<u8>) -> KvResponse {
+        KvResponse {
+            resp: Some(proto::kv::kv_response::Resp::AtomicResp(
+                proto::kv::kv_response::KvAtomicResponse {
+                    success,
+                    version,
+                    value,
+                },
+            )),
+        }
+    }
+    fn new_scan(kvs: Vec<proto::kv::KvPair>, next_cursor: Vec<u8>) -> KvResponse {
+        KvResponse {
+            resp: Some(proto::kv::kv_response::Resp::ScanResp(
+                proto::kv::kv_response::KvScanResponse { kvs, next_cursor },
+            )),
+        }
+    }
     fn lock_id(&self) -> Option<u32> {
         match self.resp.as_ref().unwrap() {
-            proto::kv::kv_response::Resp::CommonResp(_) => None,
             proto::kv::kv_response::Resp::LockId(id) => Some(*id),
+            _ => None,
         }
     }
     fn common_kvs(&self) -> Option<&Vec<proto::kv::KvPair>> {
         match self.resp.as_ref().unwrap() {
             proto::kv::kv_response::Resp::CommonResp(resp) => Some(&resp.kvs),
-            proto::kv::kv_response::Resp::LockId(_) => None,
+            _ => None,
+        }
+    }
+    fn atomic_resp(&self) -> Option<&proto::kv::kv_response::KvAtomicResponse> {
+        match self.resp.as_ref().unwrap() {
+            proto::kv::kv_response::Resp::AtomicResp(resp) => Some(resp),
+            _ => None,
+        }
+    }
+    fn scan_resp(&self) -> Option<&proto::kv::kv_response::KvScanResponse> {
+        match self.resp.as_ref().unwrap() {
+            proto::kv::kv_response::Resp::ScanResp(resp) => Some(resp),
+            _ => None,
         }
     }
 }
@@ -242,13 +278,21 @@ pub trait KvRequestExt {
     fn new_get(key: Vec<u8>) -> Self;
     fn new_delete(key: Vec<u8>) -> Self;
     fn new_lock(ope: DistLockOpe, key: Vec<u8>) -> Self;
+    fn new_cas(kv: proto::kv::KvPair, expected_version: u64) -> Self;
+    fn new_incr(key: Vec<u8>, delta: i64) -> Self;
+    fn new_put_if_absent(kv: proto::kv::KvPair) -> Self;
+    fn new_scan(range: proto::kv::KeyRange, prefix: Vec<u8>, limit: u32, cursor: Vec<u8>)
+        -> Self;
 }
 
 impl KvRequestExt for proto::kv::KvRequest {
     fn new_set(kv: proto::kv::KvPair) -> Self {
         proto::kv::KvRequest {
             op: Some(proto::kv::kv_request::Op::Set(
-                proto::kv::kv_request::KvPutRequest { kv: Some(kv) },
+                proto::kv::kv_request::KvPutRequest {
+                    kv: Some(kv),
+                    ttl_ms: 0,
+                },
             )),
         }
     }
@@ -294,6 +338,47 @@ impl KvRequestExt for proto::kv::KvRequest {
             )),
         }
     }
+    fn new_cas(kv: proto::kv::KvPair, expected_version: u64) -> Self {
+        proto::kv::KvRequest {
+            op: Some(proto::kv::kv_request::Op::Cas(
+                proto::kv::kv_request::KvCasRequest {
+                    kv: Some(kv),
+                    expected_version,
+                },
+            )),
+        }
+    }
+    fn new_incr(key: Vec<u8>, delta: i64) -> Self {
+        proto::kv::KvRequest {
+            op: Some(proto::kv::kv_request::Op::Incr(
+                proto::kv::kv_request::KvIncrRequest { key, delta },
+            )),
+        }
+    }
+    fn new_put_if_absent(kv: proto::kv::KvPair) -> Self {
+        proto::kv::KvRequest {
+            op: Some(proto::kv::kv_request::Op::PutIfAbsent(
+                proto::kv::kv_request::KvPutIfAbsentRequest { kv: Some(kv) },
+            )),
+        }
+    }
+    fn new_scan(
+        range: proto::kv::KeyRange,
+        prefix: Vec<u8>,
+        limit: u32,
+        cursor: Vec<u8>,
+    ) -> Self {
+        proto::kv::KvRequest {
+            op: Some(proto::kv::kv_request::Op::Scan(
+                proto::kv::kv_request::KvScanRequest {
+                    range: Some(range),
+                    prefix,
+                    limit,
+                    cursor,
+                },
+            )),
+        }
+    }
 }
 
 pub trait DataItemExt {
@@ -324,6 +409,12 @@ impl DataItemExt for proto::DataItem {
                 })
             },
             1 => proto::data_item::DataItemDispatch::RawBytes(data[1..].to_vec()),
+            compress::PERSIST_TAG_ZSTD => proto::data_item::DataItemDispatch::RawBytes(
+                compress::decompress_persist(&data[1..])?,
+            ),
+            dataitem::PERSIST_TAG_MAPPED_FILE => {
+                return Err(dataitem::mapped_item_decode_err("proto::DataItem"));
+            }
             _ => {
                 return Err(WSError::WsDataError(WsDataError::DataDecodeError {
                     reason: format!("Unknown data item type id: {}", data[0]),
@@ -364,6 +455,7 @@ impl ProtoExtDataEventTrigger for DataEventTrigger {
             DataEventTrigger::New | DataEventTrigger::NewWithCondition { .. } => {
                 Trigger::EventNew(DataEventTriggerNew { key, opeid })
             }
+            DataEventTrigger::Delete => Trigger::EventDelete(DataEventTriggerDelete { key, opeid }),
         }
     }
 }
@@ -429,5 +521,14 @@ mod tests {
         } else {
             panic!("Expected EventNew trigger");
         }
+
+        // Test Delete
+        let delete_trigger = DataEventTrigger::Delete.into_proto_trigger(key.clone(), opeid);
+        if let Trigger::EventDelete(trigger) = delete_trigger {
+            assert_eq!(trigger.key, key);
+            assert_eq!(trigger.opeid, opeid);
+        } else {
+            panic!("Expected EventDelete trigger");
+        }
     }
 }
@@ -4,9 +4,9 @@ use lazy_static::lazy_static;
 use tokio::sync::Mutex;
 
 use crate::{
-    config::{NodeConfig, NodesConfig},
+    config::{AuditConfig, BatchTransferConfig, NodeConfig, NodesConfig, QuotaConfig},
     start_tracing,
-    sys::{LogicalModulesRef, Sys},
+    sys::{LogicalModulesRef, NodeID, Sys},
 };
 
 lazy_static! {
@@ -63,6 +63,12 @@ spec: [meta,worker]
         },
         this: (1, node1.clone()),
         file_dir: "test_temp_dir2".into(),
+        replication_factor: 1,
+        batch_transfer: BatchTransferConfig::default(),
+        s3_gateway: None,
+        quotas: QuotaConfig::default(),
+        audit: AuditConfig::default(),
+        archive_root: None,
     });
 
     let sys0 = Sys::new(NodesConfig {
@@ -73,6 +79,12 @@ spec: [meta,worker]
         },
         this: (0, node0.clone()),
         file_dir: "test_temp_dir1".into(),
+        replication_factor: 1,
+        batch_transfer: BatchTransferConfig::default(),
+        s3_gateway: None,
+        quotas: QuotaConfig::default(),
+        audit: AuditConfig::default(),
+        archive_root: None,
     });
 
     tracing::info!("starting sys1");
@@ -82,3 +94,60 @@ spec: [meta,worker]
 
     ((sys0, sys0_handle), (sys1, sys1_handle))
 }
+
+/// A cluster of its own for tests that take nodes down, node 0 is the master and the others
+///  are workers listening on the ports after `port_base`. Keep the sys alive during the test.
+pub async fn start_test_cluster(port_base: u16, node_cnt: u32) -> Vec<(Sys, LogicalModulesRef)> {
+    start_test_cluster_with(port_base, node_cnt, |_| {}).await
+}
+
+/// [`start_test_cluster`] with the config of each node adjusted by the test before it starts
+pub async fn start_test_cluster_with(
+    port_base: u16,
+    node_cnt: u32,
+    configure: impl Fn(&mut NodesConfig),
+) -> Vec<(Sys, LogicalModulesRef)> {
+    start_tracing();
+    let nodes: HashMap<NodeID, NodeConfig> = (0..node_cnt)
+        .map(|nid| {
+            let spec = if nid == 0 {
+                "[meta,master]"
+            } else {
+                "[meta,worker]"
+            };
+            let node = serde_yaml::from_str(&format!(
+                "{{addr: 127.0.0.1:{}, spec: {}}}",
+                port_base + nid as u16,
+                spec
+            ))
+            .unwrap();
+            (nid, node)
+        })
+        .collect();
+
+    let mut cluster = vec![];
+    for nid in 0..node_cnt {
+        // absolute, each sys changes the current dir
+        let file_dir = std::env::temp_dir().join(format!("test_temp_cluster{}_{}", port_base, nid));
+        let _ = fs::remove_dir_all(&file_dir);
+        fs::create_dir_all(&file_dir).unwrap();
+        let mut peers = nodes.clone();
+        let this = peers.remove(&nid).unwrap();
+        let mut config = NodesConfig {
+            peers,
+            this: (nid, this),
+            file_dir,
+            replication_factor: 1,
+            batch_transfer: BatchTransferConfig::default(),
+            s3_gateway: None,
+            quotas: QuotaConfig::default(),
+            audit: AuditConfig::default(),
+            archive_root: None,
+        };
+        configure(&mut config);
+        let sys = Sys::new(config);
+        let handle = sys.test_start_all().await;
+        cluster.push((sys, handle));
+    }
+    cluster
+}
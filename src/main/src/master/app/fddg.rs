@@ -2,7 +2,8 @@ use crate::new_map;
 use crate::util::container::sync_trie::SyncedTrie;
 use crate::{
     general::{
-        app::{AppType, FnMeta},
+        app::{AppMeta, AppType, CachePolicy, DataEventTrigger, ErasureCoding, FnMeta},
+        data::m_data_general::{split_fn_kv_ns_key, DATA_UID_PREFIX_FN_KV},
     },
     result::WSResult,
 };
@@ -12,9 +13,17 @@ use std::collections::HashMap;
 // - need update when app uploaded
 // - to find data binded functions
 //   - co-scheduling data & functions
+// - key patterns are registered by the data unique id prefix in the app's namespace,
+//   see `AppMeta::data_uid_prefix`
 pub struct FDDGMgmt {
     // data_unique_id prefix -> app name -> (app_type, function names -> fn_meta)
     prefix_key_to_functions: SyncedTrie<HashMap<String, (AppType, HashMap<String, FnMeta>)>>,
+    // data_unique_id prefix -> replication factor
+    prefix_key_to_replication: SyncedTrie<usize>,
+    // data_unique_id prefix -> erasure coding
+    prefix_key_to_erasure: SyncedTrie<ErasureCoding>,
+    // data_unique_id prefix -> cache policy
+    prefix_key_to_cache: SyncedTrie<CachePolicy>,
 }
 
 // https://fvd360f8oos.feishu.cn/wiki/GGUnw0H1diVoHSkgm3vcMhtbnjI#share-QElHdn6dSoKVBUx5UssccxAZnnd
@@ -28,6 +37,9 @@ impl FDDGMgmt {
     pub fn new() -> Self {
         Self {
             prefix_key_to_functions: SyncedTrie::new(),
+            prefix_key_to_replication: SyncedTrie::new(),
+            prefix_key_to_erasure: SyncedTrie::new(),
+            prefix_key_to_cache: SyncedTrie::new(),
         }
     }
 
@@ -50,27 +62,130 @@ impl FDDGMgmt {
         binded_funcs
     }
 
+    /// (app, fn) whose data access on the matched key pattern is triggered by `ope`
+    pub fn get_triggered_fns(
+        &self,
+        data_unique_id: &str,
+        ope: FuncTriggerType,
+    ) -> Vec<(String, String)> {
+        let mut fns = vec![];
+        for (prefix_len, node) in self.prefix_key_to_functions.match_partial(data_unique_id) {
+            let matched: String = data_unique_id.chars().take(prefix_len).collect();
+            // back to the fixed prefix of the key pattern in app.yaml
+            let pattern_prefix = matched
+                .strip_prefix(DATA_UID_PREFIX_FN_KV)
+                .and_then(|ns_key| split_fn_kv_ns_key(ns_key.as_bytes()))
+                .map_or(matched.clone(), |(_, key)| {
+                    String::from_utf8_lossy(key).into_owned()
+                });
+            let node = node.read();
+            for (app_name, (_app_type, fn_metas)) in node.iter() {
+                for (fn_name, fn_meta) in fn_metas.iter() {
+                    let event = fn_meta
+                        .data_accesses
+                        .as_ref()
+                        .and_then(|accesses| {
+                            accesses
+                                .iter()
+                                .find(|(pattern, _)| pattern.prefix() == pattern_prefix)
+                        })
+                        .and_then(|(_, access)| access.event.as_ref());
+                    let matched = match (event, &ope) {
+                        (
+                            Some(DataEventTrigger::Write | DataEventTrigger::WriteWithCondition { .. }),
+                            FuncTriggerType::DataWrite,
+                        )
+                        | (
+                            Some(DataEventTrigger::New | DataEventTrigger::NewWithCondition { .. }),
+                            FuncTriggerType::DataNew,
+                        )
+                        | (Some(DataEventTrigger::Delete), FuncTriggerType::DataDelete) => true,
+                        _ => false,
+                    };
+                    if matched {
+                        fns.push((app_name.clone(), fn_name.clone()));
+                    }
+                }
+            }
+        }
+        fns
+    }
+
+    /// Replication factor of the longest key pattern matching the data
+    pub fn get_replication_factor(&self, data_unique_id: &str) -> Option<usize> {
+        self.prefix_key_to_replication
+            .match_partial(data_unique_id)
+            .last()
+            .map(|(_, node)| **node.read())
+    }
+
+    /// Erasure coding of the longest key pattern matching the data
+    pub fn get_erasure_coding(&self, data_unique_id: &str) -> Option<ErasureCoding> {
+        self.prefix_key_to_erasure
+            .match_partial(data_unique_id)
+            .last()
+            .map(|(_, node)| **node.read())
+    }
+
+    /// Cache policy of the longest key pattern matching the data
+    pub fn get_cache_policy(&self, data_unique_id: &str) -> Option<CachePolicy> {
+        self.prefix_key_to_cache
+            .match_partial(data_unique_id)
+            .last()
+            .map(|(_, node)| (**node.read()).clone())
+    }
+
+    /// Replication factors, erasure codings and cache policies of the key patterns, the later registered
+    ///  one wins if two functions set the same pattern
+    pub fn add_redundancy_rules(&self, (app_name, app_meta): (&str, &AppMeta), fn_meta: &FnMeta) {
+        let Some(data_accesses) = fn_meta.data_accesses.as_ref() else {
+            return;
+        };
+        for (key_pattern, data_access) in data_accesses {
+            let prefix = &*app_meta.data_uid_prefix(app_name, key_pattern);
+            if let Some(factor) = data_access.replication {
+                let node = self
+                    .prefix_key_to_replication
+                    .search_or_insert(prefix, || factor);
+                let mut node = node.write();
+                **node = factor;
+            }
+            if let Some(ec) = data_access.erasure {
+                let node = self.prefix_key_to_erasure.search_or_insert(prefix, || ec);
+                let mut node = node.write();
+                **node = ec;
+            }
+            if let Some(policy) = data_access.cache.as_ref() {
+                let node = self
+                    .prefix_key_to_cache
+                    .search_or_insert(prefix, || policy.clone());
+                let mut node = node.write();
+                **node = policy.clone();
+            }
+        }
+    }
+
     pub fn add_fn_trigger(
         &self,
-        (app_name, app_type): (&str, AppType),
+        (app_name, app_meta): (&str, &AppMeta),
         (fn_name, fn_meta): (&str, &FnMeta),
     ) -> WSResult<()> {
+        let app_type = app_meta.app_type;
         if let Some(data_accesses) = fn_meta.data_accesses.as_ref() {
             for (key_pattern, data_access) in data_accesses {
                 let Some(_event) = data_access.event.as_ref() else {
                     continue;
                 };
-                let node = self
-                    .prefix_key_to_functions
-                    .search_or_insert(&key_pattern.0, || {
-                        new_map! (HashMap {
-                            app_name.to_string() => {
-                                (app_type, new_map! (HashMap {
-                                    fn_name.to_string() => fn_meta.clone(),
-                                }))
-                            }
-                        })
-                    });
+                let prefix = app_meta.data_uid_prefix(app_name, key_pattern);
+                let node = self.prefix_key_to_functions.search_or_insert(&prefix, || {
+                    new_map! (HashMap {
+                        app_name.to_string() => {
+                            (app_type, new_map! (HashMap {
+                                fn_name.to_string() => fn_meta.clone(),
+                            }))
+                        }
+                    })
+                });
                 let mut node = node.write();
                 let _ = node
                     .entry(app_name.to_string())
@@ -83,3 +198,105 @@ impl FDDGMgmt {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::FDDGMgmt;
+    use crate::general::app::{
+        AppMeta, AppType, CacheMap, CachePolicy, CachePos, CacheTime, DataAccess, FnMeta,
+        FnSyncAsyncSupport, KeyPattern,
+    };
+    use crate::general::data::m_data_general::{fn_kv_ns_key, new_data_unique_id_fn_kv};
+    use std::collections::HashMap;
+
+    /// data unique id of a fn kv key in the namespace of `app`
+    fn uid(app: &str, key: &str) -> String {
+        String::from_utf8(new_data_unique_id_fn_kv(&fn_kv_ns_key(app, key.as_bytes()))).unwrap()
+    }
+
+    #[test]
+    fn test_replication_rules_longest_match() {
+        let access = |replication: Option<usize>| {
+            serde_yaml::from_str::<DataAccess>(&format!(
+                "{{set: true, get: true, delete: false, event: null, replication: {}}}",
+                replication.map_or("null".to_owned(), |r| r.to_string())
+            ))
+            .unwrap()
+        };
+        let fddg = FDDGMgmt::new();
+        let fn_meta = FnMeta {
+            sync_async: FnSyncAsyncSupport::Sync,
+            calls: vec![],
+            data_accesses: Some(HashMap::from([
+                (KeyPattern::new("img_{}".to_owned()), access(Some(2))),
+                (KeyPattern::new("img_raw_{}".to_owned()), access(Some(3))),
+                (KeyPattern::new("log_{}".to_owned()), access(None)),
+            ])),
+            affinity: None,
+        };
+        let app_meta = AppMeta::new(AppType::Wasm, HashMap::new());
+        fddg.add_redundancy_rules(("img_app", &app_meta), &fn_meta);
+        let replication = |key: &str| fddg.get_replication_factor(&uid("img_app", key));
+        assert_eq!(replication("img_a"), Some(2));
+        assert_eq!(replication("img_raw_a"), Some(3));
+        assert_eq!(replication("log_a"), None);
+        assert_eq!(fddg.get_erasure_coding(&uid("img_app", "img_a")), None);
+        assert_eq!(fddg.get_cache_policy(&uid("img_app", "img_a")), None);
+        // the same key of another app is not covered
+        let other_app = uid("other_app", "img_a");
+        assert_eq!(fddg.get_replication_factor(&other_app), None);
+
+        // native apps access the datasets by their unique ids
+        let native_meta = AppMeta::new(AppType::Native, HashMap::new());
+        fddg.add_redundancy_rules(("native_app", &native_meta), &fn_meta);
+        assert_eq!(fddg.get_replication_factor("img_raw_a"), Some(3));
+    }
+
+    #[test]
+    fn test_cache_policy_rules() {
+        let fddg = FDDGMgmt::new();
+        let app_meta = AppMeta::new(AppType::Wasm, HashMap::new());
+        fddg.add_redundancy_rules(("img_app", &app_meta), &FnMeta {
+            sync_async: FnSyncAsyncSupport::Sync,
+            calls: vec![],
+            data_accesses: Some(HashMap::from([
+                (
+                    KeyPattern::new("img_{}".to_owned()),
+                    serde_yaml::from_str(
+                        "{set: true, get: true, delete: false, event: null, \
+                          cache: {time: forever, pos: specnode, nodes: [1, 2], map: file}}",
+                    )
+                    .unwrap(),
+                ),
+                (
+                    KeyPattern::new("img_tmp_{}".to_owned()),
+                    serde_yaml::from_str(
+                        "{set: true, get: true, delete: false, event: null, cache: {pos: allnode}}",
+                    )
+                    .unwrap(),
+                ),
+            ])),
+            affinity: None,
+        });
+        assert_eq!(
+            fddg.get_cache_policy(&uid("img_app", "img_a")),
+            Some(CachePolicy {
+                time: CacheTime::Forever,
+                pos: CachePos::SpecNode,
+                nodes: vec![1, 2],
+                map: CacheMap::File,
+            })
+        );
+        // unset fields are auto and kv
+        assert_eq!(
+            fddg.get_cache_policy(&uid("img_app", "img_tmp_a")),
+            Some(CachePolicy {
+                time: CacheTime::Auto,
+                pos: CachePos::AllNode,
+                nodes: vec![],
+                map: CacheMap::Kv,
+            })
+        );
+        assert_eq!(fddg.get_cache_policy(&uid("img_app", "log_a")), None);
+    }
+}
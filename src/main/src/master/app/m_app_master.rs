@@ -1,21 +1,31 @@
 use crate::general::app::m_executor::Executor;
-use crate::general::app::AppMetaManager;
+use crate::general::app::{AppMeta, AppMetaManager};
+use crate::general::data::m_data_general::DATA_UID_PREFIX_APP_META;
+use crate::general::data::m_kv_store_engine::KvStoreEngine;
 use crate::general::network::m_p2p::P2PModule;
+use crate::general::network::proto;
 use crate::logical_module_view_impl;
 use crate::master::app::fddg::FDDGMgmt;
 use crate::master::m_master::{Master};
-use crate::result::{WSResult};
+use crate::result::{WSError, WSResult, WsFuncError};
 use crate::sys::{LogicalModule, LogicalModuleNewArgs, LogicalModulesRef};
 use crate::util::JoinHandleWrapper;
 use async_trait::async_trait;
+use std::time::Duration;
 use ws_derive::LogicalModule;
 
+/// stored apps are read from their holders, which may still be starting
+const LOAD_APPS_RETRY_INTERVAL: Duration = Duration::from_secs(3);
+const LOAD_APPS_MAX_ROUNDS: usize = 20;
+
 logical_module_view_impl!(MasterAppMgmtView);
 // access general app
 logical_module_view_impl!(MasterAppMgmtView, appmeta_manager, AppMetaManager);
 logical_module_view_impl!(MasterAppMgmtView, p2p, P2PModule);
 logical_module_view_impl!(MasterAppMgmtView, executor, Executor);
 logical_module_view_impl!(MasterAppMgmtView, master, Option<Master>);
+logical_module_view_impl!(MasterAppMgmtView, app_master, Option<MasterAppMgmt>);
+logical_module_view_impl!(MasterAppMgmtView, kv_store_engine, KvStoreEngine);
 
 #[derive(LogicalModule)]
 pub struct MasterAppMgmt {
@@ -35,29 +45,84 @@ impl LogicalModule for MasterAppMgmt {
         }
     }
 
-    async fn init(&self) -> WSResult<()> {
-        self.load_apps().await?;
-        Ok(())
-    }
-
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
-        Ok(vec![])
+        let loader = self.load_apps()?;
+        Ok(vec![loader])
     }
 }
 
 impl MasterAppMgmt {
-    async fn load_apps(&self) -> WSResult<()> {
+    /// Data rules of an app, from the data accesses of its functions
+    pub fn app_uploaded(&self, app_name: &str, app_meta: &AppMeta) {
+        for fn_meta in app_meta.fns.values() {
+            self.fddg.add_redundancy_rules((app_name, app_meta), fn_meta);
+        }
+    }
+
+    /// Data rules of an app, registered from the write of its meta on any node
+    pub fn app_meta_written(&self, unique_id: &[u8], upload: &proto::DataOpeRoleUploadApp) {
+        let Some(app_name) = unique_id.strip_prefix(DATA_UID_PREFIX_APP_META.as_bytes()) else {
+            return;
+        };
+        let app_name = String::from_utf8_lossy(app_name);
+        match bincode::deserialize::<AppMeta>(&upload.app_meta) {
+            Ok(app_meta) => self.app_uploaded(&app_name, &app_meta),
+            Err(err) => tracing::warn!("undecodable meta of uploaded app {}: {:?}", app_name, err),
+        }
+    }
+
+    /// Data rules of the apps stored before this master started
+    async fn load_stored_apps(&self) {
+        let prefix = DATA_UID_PREFIX_APP_META.as_bytes();
+        let mut pending: Vec<String> = self
+            .view
+            .kv_store_engine()
+            .data_set_meta_uids()
+            .into_iter()
+            .filter_map(|uid| {
+                let app = uid.strip_prefix(prefix)?;
+                Some(String::from_utf8_lossy(app).into_owned())
+            })
+            .collect();
+        for _ in 0..LOAD_APPS_MAX_ROUNDS {
+            let mut failed = vec![];
+            for app in pending {
+                match self.view.appmeta_manager().get_app_meta(&app).await {
+                    Ok(Some((app_meta, _))) => self.app_uploaded(&app, &app_meta),
+                    // deleted, or a dataset sharing the prefix that is not an app
+                    Ok(None)
+                    | Err(WSError::WsFuncError(WsFuncError::InvalidAppMetaDataItem { .. })) => {}
+                    Err(err) => {
+                        tracing::debug!("load stored app {} failed: {:?}", app, err);
+                        failed.push(app);
+                    }
+                }
+            }
+            if failed.is_empty() {
+                return;
+            }
+            pending = failed;
+            tokio::time::sleep(LOAD_APPS_RETRY_INTERVAL).await;
+        }
+        tracing::warn!("data rules of stored apps {:?} are not loaded", pending);
+    }
+
+    fn load_apps(&self) -> WSResult<JoinHandleWrapper> {
         // load app triggers to fddg
         // - for each native apps
         for (app_name, app_meta) in &self.view.appmeta_manager().native_apps {
             for (fn_name, fn_meta) in app_meta.fns.iter() {
                 self.fddg
-                    .add_fn_trigger((&app_name, app_meta.app_type), (&fn_name, &fn_meta))?;
+                    .add_fn_trigger((&app_name, app_meta), (&fn_name, &fn_meta))?;
+                self.fddg.add_redundancy_rules((&app_name, app_meta), fn_meta);
             }
         }
 
         // - for each existing apps
-
-        Ok(())
+        let view = self.view.clone();
+        let loader = tokio::spawn(async move {
+            view.app_master().load_stored_apps().await;
+        });
+        Ok(JoinHandleWrapper::from(loader))
     }
 }
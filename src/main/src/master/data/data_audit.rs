@@ -0,0 +1,286 @@
+//! Audit log of the data mutations, appended by master when it versions a write or deletes
+//!  a dataset. Records are kept in the kv store ordered by time, the ones out of the retention
+//!  of `AuditConfig` are purged in the background.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AuditConfig;
+use crate::general::data::m_kv_store_engine::{KeyTypeDataAudit, KvStoreEngine};
+use crate::general::network::proto::data_schedule_context::OpeRole;
+use crate::result::WSResult;
+use crate::sys::NodeID;
+
+/// max records in a page of a query
+pub const MAX_AUDIT_PAGE: usize = 1000;
+/// max records looked at for a page, a query filtering out most of them returns a short page
+///  with the cursor to go on
+const AUDIT_SCAN_BUDGET: usize = 10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditOp {
+    Write,
+    Delete,
+}
+
+/// One mutation of a dataset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub ts_ms: u64,
+    pub unique_id: Vec<u8>,
+    pub op: AuditOp,
+    /// 0 when the dataset didn't exist
+    pub old_version: u64,
+    /// 0 when the dataset is deleted
+    pub new_version: u64,
+    /// node asking for the mutation
+    pub node: NodeID,
+    /// app and function making the mutation, an upload has no function and an expiry neither
+    pub app: Option<String>,
+    pub func: Option<String>,
+}
+
+/// Function of the calling app that made a write
+pub fn write_func(ope_role: Option<&OpeRole>) -> Option<String> {
+    match ope_role? {
+        OpeRole::FuncCall(call) => call
+            .app_func
+            .split_once('/')
+            .map(|(_, func)| func.to_owned())
+            .filter(|func| !func.is_empty()),
+        OpeRole::UploadApp(_) => None,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub uid_prefix: Vec<u8>,
+    pub app: Option<String>,
+    /// records in [start_ms, end_ms), 0 end means no upper bound
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// go on after this record, given by the previous page
+    pub cursor: Option<(u64, u64)>,
+    pub limit: usize,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        record.unique_id.starts_with(&self.uid_prefix)
+            && self
+                .app
+                .as_ref()
+                .map_or(true, |app| record.app.as_ref() == Some(app))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditPage {
+    pub records: Vec<AuditRecord>,
+    /// None when the range is done
+    pub next_cursor: Option<(u64, u64)>,
+}
+
+/// `{ts_ms}-{seq}`
+pub fn format_audit_cursor((ts_ms, seq): (u64, u64)) -> String {
+    format!("{}-{}", ts_ms, seq)
+}
+
+pub fn parse_audit_cursor(cursor: &str) -> Option<(u64, u64)> {
+    let (ts_ms, seq) = cursor.split_once('-')?;
+    Some((ts_ms.parse().ok()?, seq.parse().ok()?))
+}
+
+/// Records out of the retention, the oldest ones either too old or beyond the max records
+fn out_of_retention(
+    keys: impl Iterator<Item = (u64, u64)>,
+    records: u64,
+    conf: &AuditConfig,
+    now_ms: u64,
+    batch: usize,
+) -> Vec<(u64, u64)> {
+    let over = if conf.max_records > 0 {
+        records.saturating_sub(conf.max_records)
+    } else {
+        0
+    };
+    // nothing is older than 0
+    let age_end = if conf.max_age_secs > 0 {
+        now_ms.saturating_sub(conf.max_age_secs.saturating_mul(1000))
+    } else {
+        0
+    };
+    keys.enumerate()
+        .take_while(|(i, (ts_ms, _))| (*i as u64) < over || *ts_ms < age_end)
+        .map(|(_, key)| key)
+        .take(batch)
+        .collect()
+}
+
+#[derive(Default)]
+pub struct AuditLog {
+    /// key of the last appended record, keys only grow
+    last_key: Mutex<(u64, u64)>,
+    /// records in the log, to find the ones beyond the max records
+    records: AtomicU64,
+    /// the records before this key are purged, the purge goes on from here
+    purged_to: Mutex<(u64, u64)>,
+}
+
+impl AuditLog {
+    /// Continue the log persisted before
+    pub fn rebuild(&self, kv_store_engine: &KvStoreEngine) {
+        let last_key = kv_store_engine
+            .audit_keys((0, 0), 0)
+            .next_back()
+            .unwrap_or_default();
+        *self.last_key.lock() = last_key;
+        *self.purged_to.lock() = (0, 0);
+        self.records.store(
+            kv_store_engine.audit_keys((0, 0), 0).count() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// The record is keyed by its time, or the time of the last one if the clock went back
+    pub fn append(&self, kv_store_engine: &KvStoreEngine, record: &AuditRecord) -> WSResult<()> {
+        let mut last_key = self.last_key.lock();
+        let (ts_ms, seq) = (record.ts_ms.max(last_key.0), last_key.1 + 1);
+        // counted before the purge can see it
+        let _ = self.records.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = kv_store_engine.set(KeyTypeDataAudit { ts_ms, seq }, record, false) {
+            let _ = self.records.fetch_sub(1, Ordering::Relaxed);
+            return Err(err);
+        }
+        *last_key = (ts_ms, seq);
+        Ok(())
+    }
+
+    /// Page of the records matching the query, ordered by time
+    pub fn query(&self, kv_store_engine: &KvStoreEngine, query: &AuditQuery) -> AuditPage {
+        let limit = query.limit.clamp(1, MAX_AUDIT_PAGE);
+        let from = query
+            .cursor
+            .map_or((query.start_ms, 0), |(ts_ms, seq)| (ts_ms, seq + 1))
+            .max((query.start_ms, 0));
+        let mut page = AuditPage::default();
+        for (scanned, (key, record)) in kv_store_engine.audit_range(from, query.end_ms).enumerate()
+        {
+            if query.matches(&record) {
+                page.records.push(record);
+            }
+            if page.records.len() >= limit || scanned + 1 >= AUDIT_SCAN_BUDGET {
+                page.next_cursor = Some(key);
+                break;
+            }
+        }
+        page
+    }
+
+    /// Drop at most `batch` records out of the retention, returns how many are dropped
+    pub fn purge(
+        &self,
+        kv_store_engine: &KvStoreEngine,
+        conf: &AuditConfig,
+        now_ms: u64,
+        batch: usize,
+    ) -> WSResult<usize> {
+        let mut purged_to = self.purged_to.lock();
+        let expired = out_of_retention(
+            kv_store_engine.audit_keys(*purged_to, 0),
+            self.records.load(Ordering::Relaxed),
+            conf,
+            now_ms,
+            batch,
+        );
+        for &(ts_ms, seq) in &expired {
+            if kv_store_engine
+                .del(KeyTypeDataAudit { ts_ms, seq }, false)?
+                .is_some()
+            {
+                let _ = self.records.fetch_sub(1, Ordering::Relaxed);
+            }
+            *purged_to = (ts_ms, seq + 1);
+        }
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::general::network::proto;
+
+    fn record(uid: &str, app: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            ts_ms: 1,
+            unique_id: uid.as_bytes().to_vec(),
+            op: AuditOp::Write,
+            old_version: 0,
+            new_version: 1,
+            node: 1,
+            app: app.map(str::to_owned),
+            func: None,
+        }
+    }
+
+    #[test]
+    fn test_audit_query_matches() {
+        let call = OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+            app_func: "app1/fn1".to_owned(),
+            node_id: 1,
+        });
+        assert_eq!(write_func(Some(&call)), Some("fn1".to_owned()));
+        let upload = OpeRole::UploadApp(proto::DataOpeRoleUploadApp::default());
+        assert_eq!(write_func(Some(&upload)), None);
+
+        let query = AuditQuery {
+            uid_prefix: b"fkvapp1".to_vec(),
+            app: Some("app1".to_owned()),
+            ..Default::default()
+        };
+        assert!(query.matches(&record("fkvapp1\0k", Some("app1"))));
+        assert!(!query.matches(&record("fkvapp1\0k", Some("app2"))));
+        assert!(!query.matches(&record("fkvapp1\0k", None)));
+        assert!(!query.matches(&record("fkvapp2\0k", Some("app1"))));
+        assert!(AuditQuery::default().matches(&record("any", None)));
+
+        assert_eq!(
+            parse_audit_cursor(&format_audit_cursor((12, 3))),
+            Some((12, 3))
+        );
+        assert_eq!(parse_audit_cursor("12"), None);
+        assert_eq!(parse_audit_cursor("a-3"), None);
+    }
+
+    #[test]
+    fn test_out_of_retention() {
+        let keys = [(1000, 0), (2000, 1), (3000, 2), (4000, 3)];
+        let conf = |max_age_secs, max_records| AuditConfig {
+            max_age_secs,
+            max_records,
+        };
+        // older than 2s at 4.5s
+        assert_eq!(
+            out_of_retention(keys.into_iter(), 4, &conf(2, 0), 4500, 100),
+            vec![(1000, 0), (2000, 1)]
+        );
+        // beyond the newest 1
+        assert_eq!(
+            out_of_retention(keys.into_iter(), 4, &conf(0, 1), 4500, 100),
+            vec![(1000, 0), (2000, 1), (3000, 2)]
+        );
+        // the longer of both, in batches
+        assert_eq!(
+            out_of_retention(keys.into_iter(), 4, &conf(2, 3), 4500, 100),
+            vec![(1000, 0), (2000, 1)]
+        );
+        assert_eq!(
+            out_of_retention(keys.into_iter(), 4, &conf(2, 3), 4500, 1),
+            vec![(1000, 0)]
+        );
+        assert!(out_of_retention(keys.into_iter(), 4, &conf(0, 0), 4500, 100).is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+//! Storage usage of the apps, kept on master with their quotas.
+//!  A dataset is charged to the app that wrote its current version, by the size of its items
+//!  as written, the copies and parity shards are not counted.
+
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AppQuota, QuotaConfig};
+use crate::general::app::DATA_UID_PREFIX_APP_META;
+use crate::general::network::proto::{self, data_schedule_context::OpeRole};
+
+/// App and size of a dataset at its current version, persisted on master
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatasetUsage {
+    pub app: String,
+    pub bytes: u64,
+}
+
+/// Usage of a dataset before and after a charged write, kept to refund the write if it fails.
+///  `new` is none when nobody is charged for the write
+#[derive(Debug, Clone)]
+pub struct UsageCharge {
+    pub old: Option<DatasetUsage>,
+    pub new: Option<DatasetUsage>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AppUsage {
+    pub bytes: u64,
+    /// datasets
+    pub keys: u64,
+}
+
+/// App charged for a write, the one of the calling function or the uploaded app
+pub fn write_app(unique_id: &[u8], ope_role: Option<&OpeRole>) -> Option<String> {
+    match ope_role? {
+        OpeRole::FuncCall(call) => call
+            .app_func
+            .split('/')
+            .next()
+            .filter(|app| !app.is_empty())
+            .map(str::to_owned),
+        OpeRole::UploadApp(_) => unique_id
+            .strip_prefix(DATA_UID_PREFIX_APP_META.as_bytes())
+            .map(|app| String::from_utf8_lossy(app).into_owned()),
+    }
+}
+
+/// The hard limit refuses usage that grows over it, what's already over is kept as it is
+fn check_hard_limit(
+    app: &str,
+    resource: &str,
+    before: u64,
+    after: u64,
+    limit: Option<u64>,
+) -> Result<(), proto::DataQuotaExceeded> {
+    match limit {
+        Some(limit) if after > limit && after > before => Err(proto::DataQuotaExceeded {
+            app: app.to_owned(),
+            resource: resource.to_owned(),
+            used: after,
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn check_quota(
+    app: &str,
+    quota: &AppQuota,
+    before: AppUsage,
+    after: AppUsage,
+) -> Result<(), proto::DataQuotaExceeded> {
+    check_hard_limit(app, "bytes", before.bytes, after.bytes, quota.hard_bytes)?;
+    check_hard_limit(app, "keys", before.keys, after.keys, quota.hard_keys)?;
+    if quota.soft_bytes.map_or(false, |soft| after.bytes > soft)
+        || quota.soft_keys.map_or(false, |soft| after.keys > soft)
+    {
+        tracing::warn!(
+            "app {} is over its soft quota, usage {:?}, quota {:?}",
+            app,
+            after,
+            quota
+        );
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct UsageLedger {
+    apps: Mutex<HashMap<String, AppUsage>>,
+}
+
+impl UsageLedger {
+    /// A dataset found when rebuilding the ledger
+    pub fn add(&self, usage: &DatasetUsage) {
+        let mut apps = self.apps.lock();
+        let app = apps.entry(usage.app.clone()).or_default();
+        app.bytes += usage.bytes;
+        app.keys += 1;
+    }
+
+    /// The dataset is deleted
+    pub fn release(&self, usage: &DatasetUsage) {
+        let mut apps = self.apps.lock();
+        if let Some(app) = apps.get_mut(&usage.app) {
+            app.bytes = app.bytes.saturating_sub(usage.bytes);
+            app.keys = app.keys.saturating_sub(1);
+        }
+    }
+
+    /// Charge the new version of a dataset in place of the old one,
+    ///  nothing is charged when it takes the app over a hard limit
+    pub fn charge(
+        &self,
+        quotas: &QuotaConfig,
+        old: Option<&DatasetUsage>,
+        new: &DatasetUsage,
+    ) -> Result<(), proto::DataQuotaExceeded> {
+        let mut apps = self.apps.lock();
+        let before = apps.get(&new.app).copied().unwrap_or_default();
+        let mut after = before;
+        match old {
+            Some(old) if old.app == new.app => {
+                after.bytes = after.bytes.saturating_sub(old.bytes);
+            }
+            _ => after.keys += 1,
+        }
+        after.bytes += new.bytes;
+        if let Some(quota) = quotas.of_app(&new.app) {
+            check_quota(&new.app, quota, before, after)?;
+        }
+
+        // written by another app before, that one is released
+        if let Some(old) = old.filter(|old| old.app != new.app) {
+            if let Some(app) = apps.get_mut(&old.app) {
+                app.bytes = app.bytes.saturating_sub(old.bytes);
+                app.keys = app.keys.saturating_sub(1);
+            }
+        }
+        let _ = apps.insert(new.app.clone(), after);
+        Ok(())
+    }
+
+    /// Undo a charge, the dataset is charged as before it
+    pub fn refund(&self, charge: &UsageCharge) {
+        if let Some(new) = &charge.new {
+            self.release(new);
+        }
+        if let Some(old) = &charge.old {
+            self.add(old);
+        }
+    }
+
+    pub fn of_app(&self, app: &str) -> AppUsage {
+        self.apps.lock().get(app).copied().unwrap_or_default()
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, AppUsage> {
+        self.apps.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn usage(app: &str, bytes: u64) -> DatasetUsage {
+        DatasetUsage {
+            app: app.to_owned(),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_write_app() {
+        let call = OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+            app_func: "app1/fn1".to_owned(),
+            node_id: 1,
+        });
+        assert_eq!(write_app(b"fkvkey", Some(&call)), Some("app1".to_owned()));
+        let upload = OpeRole::UploadApp(proto::DataOpeRoleUploadApp::default());
+        assert_eq!(
+            write_app(b"appapp1", Some(&upload)),
+            Some("app1".to_owned())
+        );
+        assert_eq!(write_app(b"fkvkey", Some(&upload)), None);
+        assert_eq!(write_app(b"fkvkey", None), None);
+    }
+
+    #[test]
+    fn test_charge_quota() {
+        let quotas: QuotaConfig = serde_yaml::from_str(
+            r#"
+default: { hard_bytes: 100 }
+apps:
+  app1: { soft_bytes: 10, hard_bytes: 50, hard_keys: 2 }
+"#,
+        )
+        .unwrap();
+        let ledger = UsageLedger::default();
+
+        ledger.charge(&quotas, None, &usage("app1", 30)).unwrap();
+        // over the soft limit only
+        ledger.charge(&quotas, None, &usage("app1", 20)).unwrap();
+        assert_eq!(ledger.of_app("app1"), AppUsage { bytes: 50, keys: 2 });
+
+        // a third key
+        let err = ledger.charge(&quotas, None, &usage("app1", 0)).unwrap_err();
+        assert_eq!((err.resource.as_str(), err.used, err.limit), ("keys", 3, 2));
+        // overwriting grows the bytes over the limit
+        let err = ledger
+            .charge(&quotas, Some(&usage("app1", 30)), &usage("app1", 31))
+            .unwrap_err();
+        assert_eq!(
+            (err.resource.as_str(), err.used, err.limit),
+            ("bytes", 51, 50)
+        );
+        assert_eq!(ledger.of_app("app1"), AppUsage { bytes: 50, keys: 2 });
+
+        // shrinking is fine
+        ledger
+            .charge(&quotas, Some(&usage("app1", 30)), &usage("app1", 10))
+            .unwrap();
+        assert_eq!(ledger.of_app("app1"), AppUsage { bytes: 30, keys: 2 });
+
+        // taken over by an app with the default quota
+        ledger
+            .charge(&quotas, Some(&usage("app1", 10)), &usage("app2", 90))
+            .unwrap();
+        assert_eq!(ledger.of_app("app1"), AppUsage { bytes: 20, keys: 1 });
+        assert_eq!(ledger.of_app("app2"), AppUsage { bytes: 90, keys: 1 });
+        assert!(ledger.charge(&quotas, None, &usage("app2", 11)).is_err());
+
+        ledger.release(&usage("app2", 90));
+        assert_eq!(ledger.of_app("app2"), AppUsage::default());
+        // no quota without a default
+        let ledger = UsageLedger::default();
+        ledger
+            .charge(&QuotaConfig::default(), None, &usage("app3", u64::MAX / 2))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_refund_charge() {
+        let quotas = QuotaConfig::default();
+        let ledger = UsageLedger::default();
+        ledger.charge(&quotas, None, &usage("app1", 30)).unwrap();
+        // an app refunded down to nothing is kept in the ledger
+        let used = || -> HashMap<_, _> {
+            ledger
+                .snapshot()
+                .into_iter()
+                .filter(|(_, usage)| *usage != AppUsage::default())
+                .collect()
+        };
+        let snapshot = used();
+
+        // each kind of charge is undone by its refund
+        let charges = [
+            (None, Some(usage("app1", 20))),
+            (Some(usage("app1", 30)), Some(usage("app1", 50))),
+            (Some(usage("app1", 30)), Some(usage("app2", 10))),
+        ];
+        for (old, new) in charges {
+            ledger
+                .charge(&quotas, old.as_ref(), new.as_ref().unwrap())
+                .unwrap();
+            ledger.refund(&UsageCharge { old, new });
+            assert_eq!(used(), snapshot);
+        }
+
+        // released as nobody is charged for the write
+        ledger.release(&usage("app1", 30));
+        ledger.refund(&UsageCharge {
+            old: Some(usage("app1", 30)),
+            new: None,
+        });
+        assert_eq!(used(), snapshot);
+    }
+}
@@ -0,0 +1,119 @@
+//! Kv transactions prepared on master. The changes are persisted with the reservation of the
+//!  datasets, so a prepared transaction is committed: the coordinator applies the changes and
+//!  master applies what's left if the coordinator is gone before finishing.
+
+use serde::{Deserialize, Serialize};
+
+use crate::general::network::proto;
+use crate::sys::NodeID;
+
+/// A change of the transaction on a dataset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvTxnChange {
+    pub unique_id: Vec<u8>,
+    pub delete: bool,
+    pub value: Vec<u8>,
+    pub ttl_ms: u64,
+}
+
+/// Prepared transaction, persisted on master until it finishes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvTxnRecord {
+    /// dataset versions checked when prepared, 0 means not exist
+    pub versions: Vec<(Vec<u8>, u64)>,
+    pub changes: Vec<KvTxnChange>,
+    /// app/func of the coordinator, the changes applied by master are made on its behalf
+    pub app_func: String,
+    pub node: NodeID,
+}
+
+impl KvTxnRecord {
+    pub fn new(req: &proto::kv::KvTxnPrepareRequest, node: NodeID) -> Self {
+        Self {
+            versions: req
+                .keys
+                .iter()
+                .map(|k| (k.unique_id.clone(), k.version))
+                .collect(),
+            changes: req
+                .writes
+                .iter()
+                .map(|w| KvTxnChange {
+                    unique_id: w.unique_id.clone(),
+                    delete: w.delete,
+                    value: w.value.clone(),
+                    ttl_ms: w.ttl_ms,
+                })
+                .collect(),
+            app_func: req.app_func.clone(),
+            node,
+        }
+    }
+
+    /// Changes not applied yet with their prepared version. The reserved datasets are only
+    ///  changed by the transaction, so the ones still at the prepared version are left.
+    pub fn pending_changes(&self, cur_version: impl Fn(&[u8]) -> u64) -> Vec<(&KvTxnChange, u64)> {
+        self.changes
+            .iter()
+            .filter_map(|change| {
+                let prepared = self
+                    .versions
+                    .iter()
+                    .find(|(uid, _)| *uid == change.unique_id)
+                    .map_or(0, |(_, version)| *version);
+                (cur_version(&change.unique_id) == prepared).then_some((change, prepared))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pending_changes() {
+        let change = |uid: &str, delete: bool| proto::kv::KvTxnWrite {
+            unique_id: uid.as_bytes().to_vec(),
+            delete,
+            value: if delete { vec![] } else { b"v".to_vec() },
+            ttl_ms: 0,
+        };
+        let key = |uid: &str, version: u64| proto::kv::KvTxnKey {
+            unique_id: uid.as_bytes().to_vec(),
+            version,
+        };
+        let record = KvTxnRecord::new(
+            &proto::kv::KvTxnPrepareRequest {
+                keys: vec![key("a", 3), key("b", 0), key("c", 5), key("r", 2)],
+                writes: vec![change("a", false), change("b", false), change("c", true)],
+                app_func: "app1/fn1".to_owned(),
+            },
+            2,
+        );
+        assert_eq!(record.changes.len(), 3);
+        assert_eq!(record.node, 2);
+
+        // nothing applied
+        let pending = record.pending_changes(|uid| match uid {
+            b"a" => 3,
+            b"c" => 5,
+            _ => 0,
+        });
+        assert_eq!(
+            pending
+                .iter()
+                .map(|(c, v)| (c.unique_id.as_slice(), *v))
+                .collect::<Vec<_>>(),
+            vec![(&b"a"[..], 3), (&b"b"[..], 0), (&b"c"[..], 5)]
+        );
+
+        // a and c applied before the coordinator is gone
+        let pending = record.pending_changes(|uid| match uid {
+            b"a" => 4,
+            _ => 0,
+        });
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0.unique_id, b"b");
+    }
+}
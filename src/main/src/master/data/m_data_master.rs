@@ -1,11 +1,16 @@
+use crate::config::AppQuota;
 use crate::general::app::m_executor::Executor;
 use crate::general::app::AppMetaManager;
+use crate::general::app::{CachePolicy, CachePos};
 use crate::general::app::DataEventTrigger;
+use crate::general::app::ErasureCoding;
+use crate::general::data::m_data_general::erasure::{shard_ranges, ERASURE_MIN_BYTES};
 use crate::general::data::m_data_general::CacheModeVisitor;
 use crate::general::network::m_p2p::{P2PModule, RPCCaller, RPCHandler, RPCResponsor};
 use crate::general::network::proto::{
     self, DataVersionScheduleRequest, DataVersionScheduleResponse,
 };
+use crate::general::network::proto_ext::ProtoExtDataItem;
 use crate::master::m_master::{FunctionTriggerContext, Master};
 use crate::result::{WSResult, WSResultExt};
 use crate::sys::{LogicalModulesRef, NodeID};
@@ -13,12 +18,27 @@ use crate::util::JoinHandleWrapper;
 use crate::{
     general::data::{
         m_data_general::{
-            CacheMode, DataGeneral, DataSetMetaBuilder, DataSplit,
-            EachNodeSplit, CACHE_MODE_MAP_COMMON_KV_MASK, CACHE_MODE_TIME_FOREVER_MASK,
+            dataitem::{bytes_checksum, DataItemArgWrapper},
+            new_data_unique_id_fn_kv, CacheMode, DataGeneral,
+            DataItemIdx, DataSetMetaBuilder, DataSetMetaV2, DataSplit, EachNodeSplit,
+            WriteDataOpts, CACHE_MODE_MAP_COMMON_KV_MASK,
+            CACHE_MODE_REDUNDANCY_MASK, CACHE_MODE_REDUNDANCY_REPLICA_MASK,
+            CACHE_MODE_SPLIT_EXTRA_COPY, CACHE_MODE_TIME_FOREVER_MASK, DATA_UID_PREFIX_FN_KV,
         },
-        m_kv_store_engine::{KeyType, KeyTypeDataSetMeta, KvAdditionalConf, KvStoreEngine},
+        m_data_gc::DataGc,
+        m_kv_watch::KvWatch,
+        m_kv_store_engine::{
+            KeyType, KeyTypeDataExpiry, KeyTypeDataSetMeta, KeyTypeDataTtl, KeyTypeDataUsage,
+            KeyTypeFnKvIndex, KeyTypeKvTxn, KvAdditionalConf, KvStoreEngine, KvVersion,
+        },
+    },
+    master::{
+        app::{fddg::FuncTriggerType, m_app_master::MasterAppMgmt},
+        data::data_audit::{write_func, AuditLog, AuditOp, AuditPage, AuditQuery, AuditRecord},
+        data::data_quota::{write_app, AppUsage, DatasetUsage, UsageCharge, UsageLedger},
+        data::kv_txn::KvTxnRecord,
+        m_metric_observor::MetricObservor,
     },
-    master::app::{fddg::FuncTriggerType, m_app_master::MasterAppMgmt},
 };
 use crate::{
     general::network::http_handler::HttpHandler,
@@ -26,9 +46,12 @@ use crate::{
     sys::{LogicalModule, LogicalModuleNewArgs},
 };
 use async_trait::async_trait;
-use rand::{thread_rng, Rng};
-use std::collections::HashSet;
-use std::time::Duration;
+use parking_lot::Mutex;
+use rand::{seq::SliceRandom, thread_rng};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use ws_derive::LogicalModule;
 
 logical_module_view_impl!(DataMasterView);
@@ -39,14 +62,156 @@ logical_module_view_impl!(DataMasterView, app_master, Option<MasterAppMgmt>);
 logical_module_view_impl!(DataMasterView, p2p, P2PModule);
 logical_module_view_impl!(DataMasterView, http_handler, Box<dyn HttpHandler>);
 logical_module_view_impl!(DataMasterView, kv_store_engine, KvStoreEngine);
+logical_module_view_impl!(DataMasterView, kv_watch, KvWatch);
+logical_module_view_impl!(DataMasterView, data_gc, DataGc);
 logical_module_view_impl!(DataMasterView, executor, Executor);
 logical_module_view_impl!(DataMasterView, master, Option<Master>);
+logical_module_view_impl!(DataMasterView, metric_observor, Option<MetricObservor>);
+
+/// page size of a kv scan when the request doesn't specify one
+const DEFAULT_KV_SCAN_LIMIT: u32 = 100;
+const MAX_KV_SCAN_LIMIT: u32 = 1000;
+/// a kv transaction not finished after this is taken over by master, in case the coordinator is gone
+const KV_TXN_RESERVE_TIMEOUT: Duration = Duration::from_secs(30);
+/// how often the master looks for kv transactions to take over
+const KV_TXN_RECOVER_INTERVAL: Duration = Duration::from_secs(5);
+/// how often the master looks for expired datasets
+const DATA_TTL_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// max expired datasets deleted in one sweep round
+const DATA_TTL_SWEEP_BATCH: usize = 256;
+/// how often the master purges the audit log out of its retention
+const AUDIT_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+/// max audit records purged in one round
+const AUDIT_PURGE_BATCH: usize = 4096;
+/// how often the master looks for data items held by lost nodes
+const RE_REPLICATION_INTERVAL: Duration = Duration::from_secs(5);
+/// datasets whose last incr value is kept, the others are read from their holders
+const INCR_VALUE_CACHE_CAPACITY: u64 = 100_000;
+
+/// Holders of a replicated item by the cache position, the primary one first.
+///  Spec nodes that are all down fall back to auto.
+fn place_replicas(
+    policy: &CachePolicy,
+    alive_nodes: &[NodeID],
+    cache_nodes: &[NodeID],
+    replication_factor: usize,
+) -> Vec<NodeID> {
+    let mut nodes: Vec<NodeID> = match policy.pos {
+        CachePos::AllNode => {
+            let mut nodes = alive_nodes.to_vec();
+            nodes.shuffle(&mut thread_rng());
+            nodes
+        }
+        // in the listed order, the first live one is the primary
+        CachePos::SpecNode => policy
+            .nodes
+            .iter()
+            .copied()
+            .filter(|node| alive_nodes.contains(node))
+            .collect(),
+        CachePos::Auto => vec![],
+    };
+    if !nodes.is_empty() {
+        // the primary copy next to a bound function if it may be there
+        if let Some(pos) = nodes.iter().position(|node| cache_nodes.contains(node)) {
+            let node = nodes.remove(pos);
+            nodes.insert(0, node);
+        }
+        return nodes;
+    }
+    if policy.pos == CachePos::SpecNode {
+        tracing::warn!("no spec node of {:?} is alive, placed as auto", policy.nodes);
+    }
+
+    // 主分片: 第一个缓存节点或随机选择的存活节点, 然后是其余缓存节点
+    let primary_node = cache_nodes.first().copied().unwrap_or_else(|| {
+        *alive_nodes
+            .choose(&mut thread_rng())
+            .expect("master itself is always alive")
+    });
+    nodes.push(primary_node);
+    for &cache_node in cache_nodes.iter() {
+        if cache_node != primary_node {
+            nodes.push(cache_node);
+        }
+    }
+    // 其余存活节点补足副本数
+    let mut candidates: Vec<NodeID> = alive_nodes
+        .iter()
+        .copied()
+        .filter(|node| !nodes.contains(node))
+        .collect();
+    candidates.shuffle(&mut thread_rng());
+    let lacking = replication_factor.saturating_sub(nodes.len());
+    nodes.extend(candidates.into_iter().take(lacking));
+    nodes
+}
+
+/// Whole copies on `nodes` in placement order, the ones past the replication factor are
+///  extra copies, the only ones a time auto item may lose
+fn replica_splits(nodes: &[NodeID], size: u64, replication_factor: usize) -> Vec<EachNodeSplit> {
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, &node_id)| EachNodeSplit {
+            node_id,
+            data_offset: 0,
+            data_size: size,
+            cache_mode: if i < replication_factor.max(1) {
+                0
+            } else {
+                CACHE_MODE_SPLIT_EXTRA_COPY
+            },
+        })
+        .collect()
+}
+
+/// Why master schedules nothing for a write
+enum WriteRejected {
+    /// the write condition is not met at the current version
+    Condition(u64),
+    Quota(proto::DataQuotaExceeded),
+}
+
+/// Integer of an incr dataset stored as decimal string, empty counts as 0
+fn parse_incr_value(value: &[u8]) -> Option<i64> {
+    if value.is_empty() {
+        return Some(0);
+    }
+    std::str::from_utf8(value).ok()?.trim().parse().ok()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
 
 #[derive(LogicalModule)]
 pub struct DataMaster {
     view: DataMasterView,
     rpc_handler: RPCHandler<proto::DataVersionScheduleRequest>,
     rpc_caller_data_meta_update: RPCCaller<proto::DataMetaUpdateRequest>,
+    rpc_handler_kv_scan_index: RPCHandler<proto::kv::KvScanIndexRequest>,
+    rpc_handler_kv_txn_prepare: RPCHandler<proto::kv::KvTxnPrepareRequest>,
+    rpc_handler_kv_txn_finish: RPCHandler<proto::kv::KvTxnFinishRequest>,
+    rpc_handler_data_stream_open: RPCHandler<proto::DataStreamOpenRequest>,
+    rpc_handler_data_cache_evict: RPCHandler<proto::DataCacheEvictRequest>,
+    /// unique id -> (txn id, reserve time)
+    txn_reserved: Mutex<HashMap<Vec<u8>, (u64, Instant)>>,
+    /// notified when a transaction releases its reservation
+    txn_released: tokio::sync::Notify,
+    next_txn_id: AtomicU64,
+    /// staging files on holders are named by it, so it's seeded by time to stay unique across restarts
+    next_stream_id: AtomicU64,
+    /// unique id -> nodes that read the meta since its last change, they may cache it
+    meta_cache_holders: Mutex<HashMap<Vec<u8>, HashSet<NodeID>>>,
+    /// storage used by each app, rebuilt from the persisted usage of the datasets at start
+    usage: UsageLedger,
+    /// append-only log of the data mutations
+    audit: AuditLog,
+    /// unique id -> (version, value) written by the last incr, dropped by any other write
+    incr_values: moka::sync::Cache<Vec<u8>, (u64, i64)>,
 }
 
 #[async_trait]
@@ -59,6 +224,19 @@ impl LogicalModule for DataMaster {
             rpc_handler: RPCHandler::new(),
             view: DataMasterView::new(args.logical_modules_ref.clone()),
             rpc_caller_data_meta_update: RPCCaller::new(),
+            rpc_handler_kv_scan_index: RPCHandler::new(),
+            rpc_handler_kv_txn_prepare: RPCHandler::new(),
+            rpc_handler_kv_txn_finish: RPCHandler::new(),
+            rpc_handler_data_stream_open: RPCHandler::new(),
+            rpc_handler_data_cache_evict: RPCHandler::new(),
+            txn_reserved: Mutex::new(HashMap::new()),
+            txn_released: tokio::sync::Notify::new(),
+            next_txn_id: AtomicU64::new(1),
+            next_stream_id: AtomicU64::new(now_ms() << 16),
+            meta_cache_holders: Mutex::new(HashMap::new()),
+            usage: UsageLedger::default(),
+            audit: AuditLog::default(),
+            incr_values: moka::sync::Cache::new(INCR_VALUE_CACHE_CAPACITY),
             // view: DataMasterView::new(args.logical_modules_ref.clone()),
         }
     }
@@ -82,7 +260,114 @@ impl LogicalModule for DataMaster {
 
                 Ok(())
             });
-        Ok(vec![])
+
+        self.rebuild_fn_kv_index();
+        self.rebuild_usage();
+        self.audit.rebuild(self.view.kv_store_engine());
+        self.rebuild_kv_txns();
+        let view = self.view.clone();
+        self.rpc_handler_kv_scan_index
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let resp = view.data_master().scan_fn_kv_index(req);
+                    if let Err(e) = responsor.send_resp(resp).await {
+                        tracing::error!("Failed to send kv scan index response: {}", e);
+                    }
+                });
+                Ok(())
+            });
+        let view = self.view.clone();
+        self.rpc_handler_kv_txn_prepare
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let resp = view.data_master().kv_txn_prepare(req, responsor.node_id());
+                    if let Err(e) = responsor.send_resp(resp).await {
+                        tracing::error!("Failed to send kv txn prepare response: {}", e);
+                    }
+                });
+                Ok(())
+            });
+        let view = self.view.clone();
+        self.rpc_handler_kv_txn_finish
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let _ = view
+                        .data_master()
+                        .kv_txn_finish(req.txn_id)
+                        .todo_handle("finish kv txn");
+                    if let Err(e) = responsor.send_resp(proto::kv::KvTxnFinishResponse {}).await {
+                        tracing::error!("Failed to send kv txn finish response: {}", e);
+                    }
+                });
+                Ok(())
+            });
+
+        let view = self.view.clone();
+        self.rpc_handler_data_stream_open
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let resp = view.data_master().open_data_stream(req);
+                    if let Err(e) = responsor.send_resp(resp).await {
+                        tracing::error!("Failed to send data stream open response: {}", e);
+                    }
+                });
+                Ok(())
+            });
+
+        let view = self.view.clone();
+        self.rpc_handler_data_cache_evict
+            .regist(self.view.p2p(), move |responsor, req| {
+                let view = view.clone();
+                let _ = tokio::spawn(async move {
+                    let resp = view
+                        .data_master()
+                        .evict_cached_copy(responsor.node_id(), req)
+                        .await;
+                    if let Err(e) = responsor.send_resp(resp).await {
+                        tracing::error!("Failed to send data cache evict response: {}", e);
+                    }
+                });
+                Ok(())
+            });
+
+        let view = self.view.clone();
+        let txn_recoverer = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(KV_TXN_RECOVER_INTERVAL).await;
+                view.data_master().recover_kv_txns().await;
+            }
+        });
+        let view = self.view.clone();
+        let sweeper = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(DATA_TTL_SWEEP_INTERVAL).await;
+                view.data_master().sweep_expired_data().await;
+            }
+        });
+        let view = self.view.clone();
+        let re_replicator = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RE_REPLICATION_INTERVAL).await;
+                view.data_master().re_replicate().await;
+            }
+        });
+        let view = self.view.clone();
+        let audit_purger = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(AUDIT_PURGE_INTERVAL).await;
+                view.data_master().purge_audit_log();
+            }
+        });
+        Ok(vec![
+            JoinHandleWrapper::from(txn_recoverer),
+            JoinHandleWrapper::from(sweeper),
+            JoinHandleWrapper::from(re_replicator),
+            JoinHandleWrapper::from(audit_purger),
+        ])
     }
 }
 
@@ -92,6 +377,7 @@ impl DataMaster {
         data_unique_id: &[u8],
         context: &proto::DataScheduleContext,
         func_trigger_type: FuncTriggerType,
+        pinned_nodes: &[NodeID],
     ) -> WSResult<(Vec<CacheMode>, Vec<DataSplit>, Vec<NodeID>)> {
         // 如果不是有效的 UTF-8 字符串，直接返回空结果
         let data_unique_id_str = match std::str::from_utf8(data_unique_id) {
@@ -143,47 +429,60 @@ impl DataMaster {
 
         // 将缓存节点集合转换为向量
         let cache_nodes: Vec<NodeID> = cache_nodes.into_iter().collect();
+        let alive_nodes = self.view.metric_observor().alive_nodes();
+        let replication_factor = self.replication_factor(data_unique_id_str);
+        let erasure = self.erasure_coding(data_unique_id_str);
+        let cache_policy = self.cache_policy(data_unique_id_str);
+        // (item idx, parity shards)
+        let mut erasure_items = vec![];
 
         // 根据缓存节点生成数据分片
         let mut splits = Vec::new();
-        for sz in context.each_data_sz_bytes.iter() {
-            // 选择主分片节点 (使用第一个缓存节点或随机选择)
-            let primary_node = cache_nodes.first().copied().unwrap_or_else(|| {
-                let p2p = self.view.p2p();
-                let rand_node_idx = thread_rng().gen_range(0..p2p.nodes_config.node_cnt());
-                let mut iter = p2p.nodes_config.all_nodes_iter();
-                for _ in 0..rand_node_idx {
-                    let _ = iter.next();
-                }
-                *iter
-                    .next()
-                    .expect("node count doesn't match all_nodes_iter")
-                    .0
+        for (idx, sz) in context.each_data_sz_bytes.iter().enumerate() {
+            // streamed data is already staged on its holders
+            if !pinned_nodes.is_empty() {
+                splits.push(DataSplit {
+                    splits: replica_splits(pinned_nodes, *sz, replication_factor),
+                });
+                continue;
+            }
+            // one shard on each node, co-scheduled functions want a whole local copy instead,
+            //  and so does a position other than auto
+            let ec = erasure.filter(|ec| {
+                cache_nodes.is_empty()
+                    && cache_policy.pos == CachePos::Auto
+                    && *sz >= ERASURE_MIN_BYTES
+                    && alive_nodes.len() >= ec.data_shards + ec.parity_shards
             });
-
-            // 创建主分片
-            let mut split = DataSplit {
-                splits: vec![EachNodeSplit {
-                    node_id: primary_node,
-                    data_offset: 0,
-                    data_size: *sz,
-                    cache_mode: 0,
-                }],
-            };
-
-            // 为每个缓存节点添加完整数据副本 (除了主分片节点)
-            for &cache_node in cache_nodes.iter() {
-                if cache_node != primary_node {
-                    split.splits.push(EachNodeSplit {
-                        node_id: cache_node,
-                        data_offset: 0,
-                        data_size: *sz,
-                        cache_mode: 0,
-                    });
-                }
+            if let Some(ec) = ec {
+                let mut nodes = alive_nodes.clone();
+                nodes.shuffle(&mut thread_rng());
+                splits.push(DataSplit {
+                    splits: shard_ranges(*sz, ec.data_shards, ec.parity_shards)
+                        .into_iter()
+                        .zip(nodes)
+                        .map(|((data_offset, data_size), node_id)| EachNodeSplit {
+                            node_id,
+                            data_offset,
+                            data_size,
+                            cache_mode: 0,
+                        })
+                        .collect(),
+                });
+                erasure_items.push((idx, ec.parity_shards));
+                continue;
             }
 
-            splits.push(split);
+            // 主分片在前, 其余为完整副本
+            let nodes = place_replicas(
+                &cache_policy,
+                &alive_nodes,
+                &cache_nodes,
+                replication_factor,
+            );
+            splits.push(DataSplit {
+                splits: replica_splits(&nodes, *sz, replication_factor),
+            });
         }
 
         // 设置缓存模式
@@ -191,15 +490,1230 @@ impl DataMaster {
 
         // 设置数据分片
         let _ = builder.set_data_splits(splits.clone());
-        // 暂时用zui'lzuil        
         for idx in 0..splits.len() {
-            let _= builder.cache_mode_time_auto(idx as u8).cache_mode_pos_auto(idx as u8);
+            let _ = builder.cache_mode_by_policy(idx as DataItemIdx, &cache_policy);
+        }
+        for (idx, parity_shards) in erasure_items {
+            let _ = builder.cache_mode_redundancy_erasure(idx as DataItemIdx, parity_shards);
         }
         let cache_modes=builder.build().cache_mode;
         tracing::debug!("planned for write data({:?}) cache_modes: {:?}", data_unique_id, cache_modes);
         Ok((cache_modes, splits, cache_nodes))
     }
 
+    /// Set by the longest matching key pattern in app.yaml, or the global one.
+    ///  Fn kv keys only match the patterns of the app owning their namespace.
+    fn replication_factor(&self, data_unique_id: &str) -> usize {
+        self.view
+            .app_master()
+            .fddg
+            .get_replication_factor(data_unique_id)
+            .unwrap_or(self.view.p2p().nodes_config.replication_factor)
+    }
+
+    /// Cache policy of the longest matching key pattern in app.yaml, auto if none
+    fn cache_policy(&self, data_unique_id: &str) -> CachePolicy {
+        self.view
+            .app_master()
+            .fddg
+            .get_cache_policy(data_unique_id)
+            .unwrap_or_default()
+    }
+
+    /// Holders of a streaming write, picked like the replicas of a regular write.
+    ///  The data is bound to them by `pinned_nodes` when the writer schedules the version.
+    fn open_data_stream(&self, req: proto::DataStreamOpenRequest) -> proto::DataStreamOpenResponse {
+        let (replication_factor, cache_policy) = match std::str::from_utf8(&req.unique_id) {
+            Ok(uid) => (self.replication_factor(uid), self.cache_policy(uid)),
+            Err(_) => (
+                self.view.p2p().nodes_config.replication_factor,
+                CachePolicy::default(),
+            ),
+        };
+        let nodes = place_replicas(
+            &cache_policy,
+            &self.view.metric_observor().alive_nodes(),
+            &[],
+            replication_factor.max(1),
+        );
+        let compression = self
+            .view
+            .metric_observor()
+            .grant_compression(req.compression().into());
+        proto::DataStreamOpenResponse {
+            stream_id: self
+                .next_stream_id
+                .fetch_add(req.item_cnt.max(1) as u64, Ordering::Relaxed),
+            nodes,
+            compression: proto::DataCompression::from(compression) as i32,
+        }
+    }
+
+    /// Erasure coding of the longest matching key pattern in app.yaml, replicated if none
+    fn erasure_coding(&self, data_unique_id: &str) -> Option<ErasureCoding> {
+        self.view
+            .app_master()
+            .fddg
+            .get_erasure_coding(data_unique_id)
+    }
+
+    /// Copy the items whose holders stopped reporting to live nodes
+    pub(crate) async fn re_replicate(&self) {
+        let kv_store_engine = self.view.kv_store_engine();
+        let observor = self.view.metric_observor();
+        for uid in kv_store_engine.data_set_meta_uids() {
+            let mut idx = 0;
+            // the meta is read again for each item, the last one may have changed it
+            while let Some((_, meta)) =
+                kv_store_engine.get(&KeyTypeDataSetMeta(&uid), false, KvAdditionalConf::default())
+            {
+                if idx >= meta.datas_splits.len() {
+                    break;
+                }
+                let lost = meta.datas_splits[idx]
+                    .splits
+                    .iter()
+                    .any(|split| !observor.is_node_alive(split.node_id));
+                if lost {
+                    if let Err(err) = self.re_replicate_item(&uid, meta, idx as DataItemIdx).await {
+                        tracing::warn!(
+                            "re-replicate data({:?}) item {} failed: {:?}",
+                            uid,
+                            idx,
+                            err
+                        );
+                    }
+                }
+                idx += 1;
+            }
+        }
+    }
+
+    /// Full copies on lost nodes move to live nodes without the item, the version is kept.
+    ///  Each new node pulls the copy from a live holder through the batch transfer.
+    ///  Partial splits on lost nodes are left as they are, except the shards of an erasure
+    ///  coded item, which the new node encodes again from the other shards.
+    async fn re_replicate_item(
+        &self,
+        unique_id: &[u8],
+        meta: DataSetMetaV2,
+        idx: DataItemIdx,
+    ) -> WSResult<()> {
+        let observor = self.view.metric_observor();
+        let erasure = meta.cache_mode_visitor(idx).erasure_parity_shards();
+        let holders: Vec<NodeID> = match erasure {
+            Some(_) => meta.datas_splits[idx as usize]
+                .splits
+                .iter()
+                .map(|s| s.node_id)
+                .collect(),
+            None => meta.get_data_holders(idx),
+        };
+        if !holders.iter().any(|node| observor.is_node_alive(*node)) {
+            tracing::error!("all holders of data({:?}) item {} are lost", unique_id, idx);
+            return Ok(());
+        }
+
+        let mut new_meta = meta.clone();
+        new_meta
+            .synced_nodes
+            .retain(|node| observor.is_node_alive(*node));
+        // a spec node item stays on the listed nodes
+        let spec_nodes = meta.cache_mode_visitor(idx).is_pos_specnode().then(|| {
+            std::str::from_utf8(unique_id).map_or(vec![], |uid| self.cache_policy(uid).nodes)
+        });
+        let mut candidates: Vec<NodeID> = observor
+            .alive_nodes()
+            .into_iter()
+            .filter(|node| {
+                !holders.contains(node)
+                    && spec_nodes.as_ref().map_or(true, |nodes| nodes.contains(node))
+            })
+            .collect();
+        candidates.shuffle(&mut thread_rng());
+        // (split idx, target node)
+        let mut targets = vec![];
+        for (split_idx, split) in new_meta.datas_splits[idx as usize]
+            .splits
+            .iter_mut()
+            .enumerate()
+        {
+            if observor.is_node_alive(split.node_id) || !holders.contains(&split.node_id) {
+                continue;
+            }
+            let Some(target) = candidates.pop() else {
+                tracing::warn!("no spare node to re-replicate data({:?}) item {}", unique_id, idx);
+                break;
+            };
+            split.node_id = target;
+            targets.push((split_idx, target));
+        }
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        // the targets pull from a live holder, the master doesn't load the item
+        let source = holders
+            .iter()
+            .copied()
+            .find(|node| observor.is_node_alive(*node))
+            .unwrap();
+        // the target checks the item against its local meta, so the meta goes first
+        let serialized_meta = bincode::serialize(&new_meta).unwrap();
+        for &(split_idx, target) in &targets {
+            let _ = self
+                .rpc_caller_data_meta_update
+                .call(
+                    self.view.p2p(),
+                    target,
+                    proto::DataMetaUpdateRequest {
+                        unique_id: unique_id.to_vec(),
+                        version: new_meta.version,
+                        serialized_meta: serialized_meta.clone(),
+                        cache_only: false,
+                    },
+                    Some(Duration::from_secs(60)),
+                )
+                .await?;
+            self.view
+                .data_general()
+                .replicate_item(proto::DataReplicateItemRequest {
+                    unique_id: unique_id.to_vec(),
+                    idx: idx as u64,
+                    version: new_meta.version,
+                    source,
+                    target,
+                    split_idx: split_idx as u32,
+                })
+                .await?;
+        }
+
+        // a write during the copy planned new holders, the copies are then simply unused
+        {
+            let kv_store_engine = self.view.kv_store_engine();
+            let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(unique_id).make_key());
+            let _guard = lock.write();
+            let unchanged = kv_store_engine
+                .get(&KeyTypeDataSetMeta(unique_id), true, KvAdditionalConf::default())
+                .map_or(false, |(_, cur)| {
+                    cur.version == meta.version
+                        && cur.datas_splits == meta.datas_splits
+                        && cur.synced_nodes == meta.synced_nodes
+                });
+            if !unchanged {
+                tracing::debug!("data({:?}) changed during re-replication", unique_id);
+                return Ok(());
+            }
+            let _ = kv_store_engine.set(KeyTypeDataSetMeta(unique_id), &new_meta, true)?;
+            kv_store_engine.flush();
+        }
+        tracing::info!(
+            "re-replicated data({:?}) item {} to (split, node) {:?}",
+            unique_id,
+            idx,
+            targets
+        );
+
+        let data_nodes: HashSet<NodeID> = new_meta
+            .datas_splits
+            .iter()
+            .flat_map(|split| split.splits.iter().map(|s| s.node_id))
+            .collect();
+        self.invalidate_meta_caches(unique_id, new_meta.version, &data_nodes)
+            .await;
+        for node in data_nodes {
+            if targets.iter().any(|(_, target)| *target == node) || !observor.is_node_alive(node) {
+                continue;
+            }
+            let res = self
+                .rpc_caller_data_meta_update
+                .call(
+                    self.view.p2p(),
+                    node,
+                    proto::DataMetaUpdateRequest {
+                        unique_id: unique_id.to_vec(),
+                        version: new_meta.version,
+                        serialized_meta: serialized_meta.clone(),
+                        cache_only: false,
+                    },
+                    Some(Duration::from_secs(60)),
+                )
+                .await;
+            if let Err(err) = res {
+                tracing::warn!("update meta of data({:?}) on node {} failed: {:?}", unique_id, node, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the copy of `node` out of the plan of a time auto item, the node drops it after.
+    ///  Refused when the item changed since the node looked or the copy must stay.
+    async fn evict_cached_copy(
+        &self,
+        node: NodeID,
+        req: proto::DataCacheEvictRequest,
+    ) -> proto::DataCacheEvictResponse {
+        let refused = |message: String| {
+            tracing::debug!("refuse to evict data({:?}) item {}: {}", req.unique_id, req.idx, message);
+            proto::DataCacheEvictResponse {
+                evicted: false,
+                message,
+            }
+        };
+        let kv_store_engine = self.view.kv_store_engine();
+        let new_meta = {
+            let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(&req.unique_id).make_key());
+            let _guard = lock.write();
+            let Some((_, meta)) = kv_store_engine.get(
+                &KeyTypeDataSetMeta(&req.unique_id),
+                true,
+                KvAdditionalConf::default(),
+            ) else {
+                return refused("no such data".to_owned());
+            };
+            if meta.version != req.version {
+                return refused(format!("version is {} now", meta.version));
+            }
+            if !meta.evictable_copy(req.idx, node) {
+                return refused(format!("copy on node {} must stay", node));
+            }
+            let mut new_meta = meta;
+            new_meta.datas_splits[req.idx as usize]
+                .splits
+                .retain(|split| split.node_id != node);
+            if let Err(err) = kv_store_engine.set(KeyTypeDataSetMeta(&req.unique_id), &new_meta, true)
+            {
+                return refused(format!("{:?}", err));
+            }
+            kv_store_engine.flush();
+            new_meta
+        };
+        tracing::info!(
+            "evicted the copy of data({:?}) item {} on node {}",
+            req.unique_id,
+            req.idx,
+            node
+        );
+
+        // the version is kept, so the nodes are told like after a re-replication
+        let data_nodes: HashSet<NodeID> = new_meta
+            .datas_splits
+            .iter()
+            .flat_map(|split| split.splits.iter().map(|s| s.node_id))
+            .chain(std::iter::once(node))
+            .collect();
+        self.invalidate_meta_caches(&req.unique_id, new_meta.version, &data_nodes)
+            .await;
+        let serialized_meta = bincode::serialize(&new_meta).unwrap();
+        let observor = self.view.metric_observor();
+        for data_node in data_nodes {
+            if !observor.is_node_alive(data_node) {
+                continue;
+            }
+            let res = self
+                .rpc_caller_data_meta_update
+                .call(
+                    self.view.p2p(),
+                    data_node,
+                    proto::DataMetaUpdateRequest {
+                        unique_id: req.unique_id.clone(),
+                        version: new_meta.version,
+                        serialized_meta: serialized_meta.clone(),
+                        cache_only: false,
+                    },
+                    Some(Duration::from_secs(60)),
+                )
+                .await;
+            if let Err(err) = res {
+                tracing::warn!(
+                    "update meta of data({:?}) on node {} failed: {:?}",
+                    req.unique_id,
+                    data_node,
+                    err
+                );
+            }
+        }
+        proto::DataCacheEvictResponse {
+            evicted: true,
+            message: String::new(),
+        }
+    }
+
+    /// fn kv datasets created before the index existed
+    fn rebuild_fn_kv_index(&self) {
+        let kv_store_engine = self.view.kv_store_engine();
+        let mut cnt = 0;
+        for uid in kv_store_engine.data_set_meta_uids() {
+            if !uid.starts_with(DATA_UID_PREFIX_FN_KV.as_bytes()) {
+                continue;
+            }
+            if kv_store_engine
+                .get(&KeyTypeFnKvIndex(&uid), false, KvAdditionalConf::default())
+                .is_none()
+            {
+                let _ = kv_store_engine
+                    .set(KeyTypeFnKvIndex(&uid), &(), false)
+                    .todo_handle("rebuild fn kv index");
+                cnt += 1;
+            }
+        }
+        if cnt > 0 {
+            kv_store_engine.flush();
+            tracing::info!("rebuilt {} fn kv index entries", cnt);
+        }
+    }
+
+    fn rebuild_usage(&self) {
+        let kv_store_engine = self.view.kv_store_engine();
+        for uid in kv_store_engine.data_set_meta_uids() {
+            if let Some((_, usage)) =
+                kv_store_engine.get(&KeyTypeDataUsage(&uid), false, KvAdditionalConf::default())
+            {
+                self.usage.add(&usage);
+            }
+        }
+        for (app, usage) in self.usage.snapshot() {
+            self.view.metric_observor().set_app_usage(&app, usage);
+        }
+    }
+
+    /// Usage and quota of each app that stores something or has a quota
+    pub fn app_usages(&self) -> HashMap<String, (AppUsage, Option<AppQuota>)> {
+        let quotas = &self.view.p2p().nodes_config.quotas;
+        let mut usages: HashMap<String, (AppUsage, Option<AppQuota>)> = self
+            .usage
+            .snapshot()
+            .into_iter()
+            .map(|(app, usage)| {
+                let quota = quotas.of_app(&app).cloned();
+                (app, (usage, quota))
+            })
+            .collect();
+        for (app, quota) in &quotas.apps {
+            let _ = usages
+                .entry(app.clone())
+                .or_insert_with(|| (AppUsage::default(), Some(quota.clone())));
+        }
+        usages
+    }
+
+    /// The dataset is deleted, called under its meta lock
+    pub fn release_usage(&self, unique_id: &[u8]) -> WSResult<()> {
+        let kv_store_engine = self.view.kv_store_engine();
+        if let Some((_, usage)) = kv_store_engine.del(KeyTypeDataUsage(unique_id), false)? {
+            self.usage.release(&usage);
+            self.view
+                .metric_observor()
+                .set_app_usage(&usage.app, self.usage.of_app(&usage.app));
+        }
+        Ok(())
+    }
+
+    /// Charge the write to its app, called under the meta lock of the dataset.
+    ///  The charge is refunded with `refund_usage` when the write fails afterwards
+    fn charge_usage(
+        &self,
+        unique_id: &[u8],
+        ctx: &proto::DataScheduleContext,
+    ) -> WSResult<Result<UsageCharge, proto::DataQuotaExceeded>> {
+        let kv_store_engine = self.view.kv_store_engine();
+        let old = kv_store_engine
+            .get(
+                &KeyTypeDataUsage(unique_id),
+                true,
+                KvAdditionalConf::default(),
+            )
+            .map(|(_, usage)| usage);
+        let Some(app) = write_app(unique_id, ctx.ope_role.as_ref()) else {
+            // nobody to charge, the previous writer isn't charged anymore
+            if old.is_some() {
+                self.release_usage(unique_id)?;
+            }
+            return Ok(Ok(UsageCharge { old, new: None }));
+        };
+        let new = DatasetUsage {
+            app,
+            bytes: ctx.each_data_sz_bytes.iter().sum(),
+        };
+        let quotas = &self.view.p2p().nodes_config.quotas;
+        if let Err(exceeded) = self.usage.charge(quotas, old.as_ref(), &new) {
+            return Ok(Err(exceeded));
+        }
+        let persisted = kv_store_engine.set(KeyTypeDataUsage(unique_id), &new, false);
+        let charge = UsageCharge {
+            old,
+            new: Some(new),
+        };
+        if let Err(err) = persisted {
+            self.usage.refund(&charge);
+            return Err(err);
+        }
+        self.update_usage_metrics(&charge);
+        Ok(Ok(charge))
+    }
+
+    /// The charged write failed, the dataset is charged as before it
+    fn refund_usage(&self, unique_id: &[u8], charge: UsageCharge) {
+        let kv_store_engine = self.view.kv_store_engine();
+        let restored = match &charge.old {
+            Some(old) => kv_store_engine
+                .set(KeyTypeDataUsage(unique_id), old, false)
+                .map(|_| ()),
+            None => kv_store_engine
+                .del(KeyTypeDataUsage(unique_id), false)
+                .map(|_| ()),
+        };
+        let _ = restored.todo_handle("restore usage of a failed write");
+        self.usage.refund(&charge);
+        self.update_usage_metrics(&charge);
+    }
+
+    fn update_usage_metrics(&self, charge: &UsageCharge) {
+        let metric_observor = self.view.metric_observor();
+        for usage in charge.old.iter().chain(charge.new.iter()) {
+            metric_observor.set_app_usage(&usage.app, self.usage.of_app(&usage.app));
+        }
+    }
+
+    /// A mutation is recorded before it's made, it fails when it can't be recorded
+    fn record_audit(&self, record: AuditRecord) -> WSResult<()> {
+        self.audit.append(self.view.kv_store_engine(), &record)
+    }
+
+    /// The dataset is about to be deleted, called under its meta lock.
+    ///  A delete not made for a function, e.g. by expiry, has no app
+    pub fn audit_delete(
+        &self,
+        unique_id: &[u8],
+        old_version: u64,
+        node: NodeID,
+        deleted_by: Option<&proto::DataOpeRoleFuncCall>,
+    ) -> WSResult<()> {
+        let ope_role =
+            deleted_by.map(|call| proto::data_schedule_context::OpeRole::FuncCall(call.clone()));
+        self.record_audit(AuditRecord {
+            ts_ms: now_ms(),
+            unique_id: unique_id.to_vec(),
+            op: AuditOp::Delete,
+            old_version,
+            new_version: 0,
+            node,
+            app: write_app(unique_id, ope_role.as_ref()),
+            func: write_func(ope_role.as_ref()),
+        })
+    }
+
+    pub fn query_audit(&self, query: &AuditQuery) -> AuditPage {
+        self.audit.query(self.view.kv_store_engine(), query)
+    }
+
+    /// Purge in batches until the log is within its retention
+    fn purge_audit_log(&self) {
+        let kv_store_engine = self.view.kv_store_engine();
+        let conf = &self.view.p2p().nodes_config.audit;
+        let mut total = 0;
+        while let Ok(purged) = self
+            .audit
+            .purge(kv_store_engine, conf, now_ms(), AUDIT_PURGE_BATCH)
+            .todo_handle("purge audit log")
+        {
+            total += purged;
+            if purged < AUDIT_PURGE_BATCH {
+                break;
+            }
+        }
+        if total > 0 {
+            tracing::debug!("purged {} audit records", total);
+        }
+    }
+
+    /// Page of fn kv keys in the requested range, ordered by key.
+    ///  Entries whose dataset is already deleted are cleaned up while scanning.
+    pub fn scan_fn_kv_index(&self, req: proto::kv::KvScanIndexRequest) -> proto::kv::KvScanIndexResponse {
+        let kv_store_engine = self.view.kv_store_engine();
+        let limit = match req.limit {
+            0 => DEFAULT_KV_SCAN_LIMIT,
+            limit => limit.min(MAX_KV_SCAN_LIMIT),
+        } as usize;
+
+        // lower bound: max(start, prefix, cursor + 1)
+        let mut start = req.start.max(req.prefix.clone());
+        if !req.cursor.is_empty() {
+            let mut after_cursor = req.cursor;
+            after_cursor.push(0);
+            start = start.max(after_cursor);
+        }
+        // upper bound: min(end, successor of prefix), empty means unbounded
+        let mut end = req.end;
+        if let Some(prefix_end) = key_successor(&req.prefix) {
+            if end.is_empty() || prefix_end < end {
+                end = prefix_end;
+            }
+        }
+        let start_uid = new_data_unique_id_fn_kv(&start);
+        let end_uid = if end.is_empty() {
+            key_successor(DATA_UID_PREFIX_FN_KV.as_bytes()).unwrap()
+        } else {
+            new_data_unique_id_fn_kv(&end)
+        };
+
+        let prefix_len = DATA_UID_PREFIX_FN_KV.len();
+        let mut keys: Vec<Vec<u8>> = vec![];
+        let mut next_cursor = vec![];
+        for uid in kv_store_engine.fn_kv_index_range(&start_uid, &end_uid) {
+            let metakey = KeyTypeDataSetMeta(&uid);
+            if kv_store_engine
+                .get(&metakey, false, KvAdditionalConf::default())
+                .is_none()
+            {
+                // recheck under the meta lock, a concurrent write may have recreated it
+                let lock = kv_store_engine.with_rwlock(&metakey.make_key());
+                let _guard = lock.write();
+                if kv_store_engine
+                    .get(&metakey, true, KvAdditionalConf::default())
+                    .is_none()
+                {
+                    tracing::debug!("remove stale fn kv index {:?}", uid);
+                    let _ = kv_store_engine
+                        .del(KeyTypeFnKvIndex(&uid), false)
+                        .todo_handle("remove stale fn kv index");
+                    continue;
+                }
+            }
+            if keys.len() == limit {
+                // there is more, next page continues after the last returned key
+                next_cursor = keys.last().cloned().unwrap_or_default();
+                break;
+            }
+            keys.push(uid[prefix_len..].to_vec());
+        }
+        proto::kv::KvScanIndexResponse { keys, next_cursor }
+    }
+
+    /// Phase one of a kv transaction: under the meta locks of all the datasets,
+    ///  check they are still at the read versions and reserve them for the transaction.
+    ///  The changes are persisted with the reservation, from then on the transaction is committed.
+    fn kv_txn_prepare(
+        &self,
+        req: proto::kv::KvTxnPrepareRequest,
+        node: NodeID,
+    ) -> proto::kv::KvTxnPrepareResponse {
+        let kv_store_engine = self.view.kv_store_engine();
+        let record = KvTxnRecord::new(&req, node);
+        let mut keys = req.keys;
+        // fixed lock order across transactions
+        keys.sort_by(|a, b| a.unique_id.cmp(&b.unique_id));
+        keys.dedup_by(|a, b| a.unique_id == b.unique_id);
+
+        let locks: Vec<_> = keys
+            .iter()
+            .map(|k| kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(&k.unique_id).make_key()))
+            .collect();
+        let _guards: Vec<_> = locks.iter().map(|lock| lock.write()).collect();
+
+        let mut reserved = self.txn_reserved.lock();
+        if let Some(k) = keys.iter().find(|k| reserved.contains_key(&k.unique_id)) {
+            return proto::kv::KvTxnPrepareResponse {
+                success: false,
+                txn_id: 0,
+                conflicts: vec![],
+                message: format!(
+                    "data({:?}) is reserved by another transaction",
+                    String::from_utf8_lossy(&k.unique_id)
+                ),
+            };
+        }
+        let conflicts: Vec<_> = keys
+            .iter()
+            .filter_map(|k| {
+                let cur_version = kv_store_engine
+                    .get(
+                        &KeyTypeDataSetMeta(&k.unique_id),
+                        true,
+                        KvAdditionalConf::default(),
+                    )
+                    .map_or(0, |(_, meta)| meta.version);
+                (cur_version != k.version).then(|| proto::kv::KvTxnKey {
+                    unique_id: k.unique_id.clone(),
+                    version: cur_version,
+                })
+            })
+            .collect();
+        if !conflicts.is_empty() {
+            return proto::kv::KvTxnPrepareResponse {
+                success: false,
+                txn_id: 0,
+                conflicts,
+                message: "data changed since read".to_owned(),
+            };
+        }
+
+        let txn_id = self.next_txn_id.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = kv_store_engine.set(KeyTypeKvTxn(txn_id), &record, true) {
+            return proto::kv::KvTxnPrepareResponse {
+                success: false,
+                txn_id: 0,
+                conflicts: vec![],
+                message: format!("persist transaction failed: {}", err),
+            };
+        }
+        let now = Instant::now();
+        for k in keys {
+            let _ = reserved.insert(k.unique_id, (txn_id, now));
+        }
+        tracing::debug!("kv txn {} prepared", txn_id);
+        proto::kv::KvTxnPrepareResponse {
+            success: true,
+            txn_id,
+            conflicts: vec![],
+            message: "".to_owned(),
+        }
+    }
+
+    /// Phase two is done by the coordinator with `txn_id` writes, this releases the reservation
+    fn kv_txn_finish(&self, txn_id: u64) -> WSResult<()> {
+        let _ = self
+            .view
+            .kv_store_engine()
+            .del(KeyTypeKvTxn(txn_id), true)?;
+        self.txn_reserved.lock().retain(|_, (id, _)| *id != txn_id);
+        self.txn_released.notify_waiters();
+        tracing::debug!("kv txn {} finished", txn_id);
+        Ok(())
+    }
+
+    /// Reads and writes out of the reserving transaction wait until it finishes
+    pub async fn wait_txn_released(&self, unique_id: &[u8], txn_id: u64) {
+        loop {
+            // registered before the check, a release in between still wakes it
+            let released = self.txn_released.notified();
+            let reserved_by_other = match self.txn_reserved.lock().get(unique_id) {
+                Some((id, _)) => *id != txn_id,
+                None => false,
+            };
+            if !reserved_by_other {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    /// Reserve the datasets of the transactions left unfinished before the restart,
+    ///  they are taken over by the next recovery round
+    fn rebuild_kv_txns(&self) {
+        let txns = self.view.kv_store_engine().kv_txns();
+        let next_txn_id = txns.iter().map(|(txn_id, _)| txn_id + 1).max().unwrap_or(1);
+        self.next_txn_id.store(next_txn_id, Ordering::Relaxed);
+        let expired = Instant::now()
+            .checked_sub(KV_TXN_RESERVE_TIMEOUT)
+            .unwrap_or_else(Instant::now);
+        let mut reserved = self.txn_reserved.lock();
+        for (txn_id, record) in txns {
+            for (uid, _) in record.versions {
+                let _ = reserved.insert(uid, (txn_id, expired));
+            }
+        }
+    }
+
+    /// Apply the changes left by the coordinators that didn't finish in time, then finish them.
+    ///  A transaction failing to apply stays reserved and is retried in the next round.
+    async fn recover_kv_txns(&self) {
+        let stale: HashSet<u64> = self
+            .txn_reserved
+            .lock()
+            .values()
+            .filter(|(_, at)| at.elapsed() >= KV_TXN_RESERVE_TIMEOUT)
+            .map(|(txn_id, _)| *txn_id)
+            .collect();
+        if stale.is_empty() {
+            return;
+        }
+        let kv_store_engine = self.view.kv_store_engine();
+        let this_node = self.view.p2p().nodes_config.this_node();
+        for (txn_id, record) in kv_store_engine.kv_txns() {
+            if !stale.contains(&txn_id) {
+                continue;
+            }
+            tracing::warn!("kv txn {} is not finished in time, take it over", txn_id);
+            let pending = record.pending_changes(|uid| {
+                kv_store_engine
+                    .get(&KeyTypeDataSetMeta(uid), false, KvAdditionalConf::default())
+                    .map_or(0, |(_, meta)| meta.version)
+            });
+            let mut failed = false;
+            for (change, version) in pending {
+                let res = if change.delete {
+                    self.view
+                        .data_general()
+                        .delete_data_in_txn(
+                            &change.unique_id,
+                            txn_id,
+                            version,
+                            &proto::DataOpeRoleFuncCall {
+                                app_func: record.app_func.clone(),
+                                node_id: record.node,
+                            },
+                        )
+                        .await
+                } else {
+                    self.view
+                        .data_general()
+                        .write_data_with(
+                            change.unique_id.clone(),
+                            vec![DataItemArgWrapper::new(change.value.clone())],
+                            Some((
+                                this_node,
+                                proto::DataOpeType::Write,
+                                proto::data_schedule_context::OpeRole::FuncCall(
+                                    proto::DataOpeRoleFuncCall {
+                                        app_func: record.app_func.clone(),
+                                        node_id: record.node,
+                                    },
+                                ),
+                            )),
+                            WriteDataOpts {
+                                condition: Some(
+                                    proto::data_write_condition::Cond::ExpectedVersion(version),
+                                ),
+                                txn_id,
+                                ttl_ms: change.ttl_ms,
+                                ..Default::default()
+                            },
+                        )
+                        .await
+                };
+                if let Err(err) = res {
+                    tracing::warn!(
+                        "kv txn {} apply change of data({:?}) failed: {}",
+                        txn_id,
+                        change.unique_id,
+                        err
+                    );
+                    failed = true;
+                    break;
+                }
+            }
+            if !failed {
+                let _ = self
+                    .kv_txn_finish(txn_id)
+                    .todo_handle("finish recovered kv txn");
+            }
+        }
+    }
+
+    /// Remember the node reading the meta, it's told when the meta changes
+    pub fn add_meta_cache_holder(&self, unique_id: &[u8], node: NodeID) {
+        let _ = self
+            .meta_cache_holders
+            .lock()
+            .entry(unique_id.to_vec())
+            .or_default()
+            .insert(node);
+    }
+
+    /// Tell the nodes that may cache the meta of the dataset to drop it, except `skip`.
+    ///  Holders register again when they read the meta next time.
+    ///  The returned future is done when the live holders acked, a change waiting for it
+    ///  is not acked while one of them may still serve the old meta from its cache.
+    pub fn invalidate_meta_caches(
+        &self,
+        unique_id: &[u8],
+        version: u64,
+        skip: &HashSet<NodeID>,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let holders = self
+            .meta_cache_holders
+            .lock()
+            .remove(unique_id)
+            .unwrap_or_default();
+        let observor = self.view.metric_observor();
+        let mut acks = vec![];
+        for node in holders.into_iter().filter(|node| !skip.contains(node)) {
+            let view = self.view.clone();
+            let unique_id = unique_id.to_vec();
+            let task = tokio::spawn(async move {
+                let res = view
+                    .data_master()
+                    .rpc_caller_data_meta_update
+                    .call(
+                        view.p2p(),
+                        node,
+                        proto::DataMetaUpdateRequest {
+                            unique_id,
+                            version,
+                            serialized_meta: vec![],
+                            cache_only: true,
+                        },
+                        Some(Duration::from_secs(10)),
+                    )
+                    .await;
+                if let Err(err) = res {
+                    tracing::warn!("invalidate meta cache on node {} failed: {:?}", node, err);
+                }
+            });
+            // a lost node drops the meta when its cache expires
+            if observor.is_node_alive(node) {
+                acks.push(task);
+            }
+        }
+        async move {
+            let _ = futures::future::join_all(acks).await;
+        }
+    }
+
+    /// Delete datasets whose ttl passed and fire their delete triggers.
+    /// The meta of `uid` if it's still due at `deadline`, a stale expiry entry or the ttl
+    ///  of missing data is cleaned up
+    fn expired_meta(&self, uid: &[u8], deadline: u64) -> Option<DataSetMetaV2> {
+        let kv_store_engine = self.view.kv_store_engine();
+        let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(uid).make_key());
+        let _guard = lock.write();
+        self.expired_meta_locked(uid, deadline)
+    }
+
+    fn expired_meta_locked(&self, uid: &[u8], deadline: u64) -> Option<DataSetMetaV2> {
+        let kv_store_engine = self.view.kv_store_engine();
+        // the expiry entry is stale if the ttl was reset after it was listed
+        let cur_deadline = kv_store_engine
+            .get(&KeyTypeDataTtl(uid), false, KvAdditionalConf::default())
+            .map(|(_, d)| d);
+        if cur_deadline != Some(deadline) {
+            let _ = kv_store_engine
+                .del(
+                    KeyTypeDataExpiry {
+                        deadline_ms: deadline,
+                        uid,
+                    },
+                    false,
+                )
+                .todo_handle("remove stale data expiry");
+            return None;
+        }
+        match kv_store_engine.get(&KeyTypeDataSetMeta(uid), true, KvAdditionalConf {}) {
+            Some((_, meta)) => Some(meta),
+            None => {
+                // deleted by others, only the ttl is left
+                kv_store_engine
+                    .set_data_ttl(uid, 0)
+                    .todo_handle("clear ttl of missing data");
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn sweep_expired_data(&self) {
+        let kv_store_engine = self.view.kv_store_engine();
+        for (deadline, uid) in kv_store_engine.data_expiry_due(now_ms(), DATA_TTL_SWEEP_BATCH) {
+            self.wait_txn_released(&uid, 0).await;
+            let Some(meta) = self.expired_meta(&uid, deadline) else {
+                continue;
+            };
+
+            // holders only reclaim items up to the expired version, a write racing the sweep
+            //  keeps its items and the meta delete below backs off
+            let holders: HashSet<NodeID> = meta
+                .datas_splits
+                .iter()
+                .flat_map(|split| split.splits.iter().map(|s| s.node_id))
+                .chain(meta.synced_nodes.iter().copied())
+                .collect();
+            let mut reclaimed = true;
+            for node in holders {
+                if let Err(err) = self
+                    .view
+                    .data_gc()
+                    .tombstone(&uid, meta.version, node)
+                    .await
+                {
+                    tracing::warn!(
+                        "reclaim expired data({:?}) on {} failed: {:?}",
+                        uid,
+                        node,
+                        err
+                    );
+                    reclaimed = false;
+                }
+            }
+            if !reclaimed {
+                // retried in the next round
+                continue;
+            }
+
+            // the data may be rewritten since, only the expired version is deleted
+            let swept = {
+                let lock = kv_store_engine.with_rwlock(&KeyTypeDataSetMeta(&uid).make_key());
+                let _guard = lock.write();
+                if self.expired_meta_locked(&uid, deadline).map(|m| m.version) != Some(meta.version)
+                {
+                    continue;
+                }
+                let this_node = self.view.p2p().nodes_config.this_node();
+                self.view.data_general().del_data_meta_locked(
+                    &uid,
+                    Some(meta.version),
+                    this_node,
+                    None,
+                )
+            };
+            match swept {
+                Ok((Some(_), invalidated)) => {
+                    if let Some(invalidated) = invalidated {
+                        invalidated.await;
+                    }
+                }
+                Ok((None, _)) => continue,
+                Err(err) => {
+                    tracing::warn!("delete expired data({:?}) failed: {:?}", uid, err);
+                    continue;
+                }
+            }
+            tracing::debug!("data({:?}) expired at {}", uid, deadline);
+
+            let Ok(uid_str) = std::str::from_utf8(&uid) else {
+                continue;
+            };
+            let fns = self
+                .view
+                .app_master()
+                .fddg
+                .get_triggered_fns(uid_str, FuncTriggerType::DataDelete);
+            for (app_name, fn_name) in fns {
+                let target_node = self.view.master().select_node();
+                let ctx = FunctionTriggerContext {
+                    app_name: app_name.clone(),
+                    fn_name: fn_name.clone(),
+                    data_unique_id: uid.clone(),
+                    target_nodes: vec![target_node],
+                    timeout: Duration::from_secs(60),
+                    event_type: DataEventTrigger::Delete,
+                };
+                if let Err(e) = self.view.master().trigger_func_call(ctx).await {
+                    tracing::error!(
+                        "Failed to trigger delete function {}/{} on node {}: {:?}",
+                        app_name,
+                        fn_name,
+                        target_node,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// `cur_version` is 0 when the dataset doesn't exist
+    fn write_condition_met(condition: &proto::data_write_condition::Cond, cur_version: u64) -> bool {
+        match condition {
+            proto::data_write_condition::Cond::ExpectedVersion(expected) => *expected == cur_version,
+            proto::data_write_condition::Cond::Absent(absent) => !*absent || cur_version == 0,
+            // checked against the value when it's added
+            proto::data_write_condition::Cond::Incr(_) => true,
+        }
+    }
+
+    /// Version and integer an incr adds to, read from the holders unless the last write
+    ///  was an incr. The integer is None if the value isn't one
+    async fn incr_base(&self, unique_id: &[u8]) -> WSResult<(u64, Option<i64>)> {
+        let cur_version = self
+            .view
+            .kv_store_engine()
+            .get(
+                &KeyTypeDataSetMeta(unique_id),
+                false,
+                KvAdditionalConf::default(),
+            )
+            .map_or(0, |(_, meta)| meta.version);
+        if cur_version == 0 {
+            return Ok((0, Some(0)));
+        }
+        if let Some((version, value)) = self.incr_values.get(unique_id) {
+            if version == cur_version {
+                return Ok((version, Some(value)));
+            }
+        }
+        let Some((meta, items)) = self.view.data_general().get_data_latest(unique_id).await? else {
+            return Ok((0, Some(0)));
+        };
+        let value = items
+            .get(&0)
+            .and_then(|item| item.as_raw_bytes())
+            .and_then(parse_incr_value);
+        Ok((meta.version, value))
+    }
+
+    async fn reply_quota_exceeded(
+        responsor: RPCResponsor<DataVersionScheduleRequest>,
+        unique_id: &[u8],
+        exceeded: proto::DataQuotaExceeded,
+    ) -> WSResult<()> {
+        tracing::warn!(
+            "write of data({:?}) refused, app {} would use {} {} over its hard quota {}",
+            unique_id,
+            exceeded.app,
+            exceeded.used,
+            exceeded.resource,
+            exceeded.limit
+        );
+        if let Err(e) = responsor
+            .send_resp(DataVersionScheduleResponse {
+                version: 0,
+                cache_mode: vec![],
+                split: vec![],
+                cache_nodes: vec![],
+                condition_failed: false,
+                compression: proto::DataCompression::Raw as i32,
+                quota_exceeded: Some(exceeded),
+                incr_value: vec![],
+            })
+            .await
+        {
+            tracing::error!("Failed to send data version schedule response: {}", e);
+        }
+        Ok(())
+    }
+
+    async fn reply_condition_failed(
+        responsor: RPCResponsor<DataVersionScheduleRequest>,
+        unique_id: &[u8],
+        cur_version: u64,
+    ) -> WSResult<()> {
+        tracing::debug!(
+            "write condition not met for data({:?}), current version {}",
+            unique_id,
+            cur_version
+        );
+        if let Err(e) = responsor
+            .send_resp(DataVersionScheduleResponse {
+                version: cur_version,
+                cache_mode: vec![],
+                split: vec![],
+                cache_nodes: vec![],
+                condition_failed: true,
+                compression: proto::DataCompression::Raw as i32,
+                quota_exceeded: None,
+                incr_value: vec![],
+            })
+            .await
+        {
+            tracing::error!("Failed to send data version schedule response: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Write the next version of the meta, called under its lock once the write is charged.
+    ///  Returns the meta with the nodes that kept something of the old version
+    fn expand_meta_locked(
+        &self,
+        req: &DataVersionScheduleRequest,
+        dataset_meta: Option<(KvVersion, DataSetMetaV2)>,
+        mut new_splits: Vec<DataSplit>,
+        item_cache_modes: Vec<CacheMode>,
+        node: NodeID,
+        incr: Option<i64>,
+    ) -> WSResult<(DataSetMetaV2, HashSet<NodeID>)> {
+        let kv_store_engine = self.view.kv_store_engine();
+        let ctx = req
+            .context
+            .as_ref()
+            .expect("context is required for DataScheduleContext");
+        let cur_version = dataset_meta.as_ref().map_or(0, |(_, meta)| meta.version);
+        // the value of an incr is known only now, the plan was made for an empty one
+        let mut checksums = ctx.each_data_checksum.clone();
+        if let Some(value) = incr {
+            let value = value.to_string().into_bytes();
+            for split in new_splits
+                .iter_mut()
+                .flat_map(|split| split.splits.iter_mut())
+            {
+                split.data_size = value.len() as u64;
+            }
+            checksums = vec![bytes_checksum(&value)];
+        }
+        // let takeonce=Some((new_meta,new_))
+        let compression = self
+            .view
+            .metric_observor()
+            .grant_compression(req.compression().into());
+        // nodes keeping something of the old version, to find the ones dropped from the plan
+        let old_nodes: HashSet<NodeID> =
+            dataset_meta
+                .as_ref()
+                .map_or_else(HashSet::new, |(_, meta)| {
+                    meta.datas_splits
+                        .iter()
+                        .flat_map(|split| split.splits.iter().map(|s| s.node_id))
+                        .chain(meta.synced_nodes.iter().copied())
+                        .collect()
+                });
+        let set_meta = if let Some((_kv_version, set_meta)) = dataset_meta {
+            tracing::debug!("update dataset meta for data({:?})", req.unique_id);
+            let version = set_meta.version;
+            let mut builder = DataSetMetaBuilder::from(set_meta);
+            // version
+            let _ = builder.version(version + 1);
+            // data splits bf cache mod
+            let _ = builder.set_data_splits(new_splits);
+            // cache mode
+            let _ = builder.set_cache_mode_for_all(item_cache_modes);
+            let _ = builder.set_data_checksums(checksums);
+            let _ = builder.set_compression(compression);
+            builder.build()
+        } else {
+            tracing::debug!("new dataset meta for data({:?})", req.unique_id);
+            let mut builder = DataSetMetaBuilder::new();
+            // version
+            let _ = builder.version(1);
+            // data splits bf cache mod
+            let _ = builder.set_data_splits(new_splits);
+            // cache mode
+            let _ = builder.set_cache_mode_for_all(item_cache_modes);
+            let _ = builder.set_data_checksums(checksums);
+            let _ = builder.set_compression(compression);
+            builder.build()
+        };
+
+        self.record_audit(AuditRecord {
+            ts_ms: now_ms(),
+            unique_id: req.unique_id.clone(),
+            op: AuditOp::Write,
+            old_version: cur_version,
+            new_version: set_meta.version,
+            node,
+            app: write_app(&req.unique_id, ctx.ope_role.as_ref()),
+            func: write_func(ctx.ope_role.as_ref()),
+        })?;
+
+        // ##  update version local
+        tracing::debug!(
+            "update version local for data({:?}), the updated meta is {:?}",
+            req.unique_id,
+            set_meta
+        );
+        let _ = kv_store_engine.set(KeyTypeDataSetMeta(&req.unique_id), &set_meta, true)?;
+        match incr {
+            Some(value) => self
+                .incr_values
+                .insert(req.unique_id.clone(), (set_meta.version, value)),
+            None => self.incr_values.invalidate(&req.unique_id),
+        }
+        if req.unique_id.starts_with(DATA_UID_PREFIX_FN_KV.as_bytes()) {
+            let _ = kv_store_engine.set(KeyTypeFnKvIndex(&req.unique_id), &(), false)?;
+        }
+        // every write resets the ttl, a write without ttl makes the data permanent
+        kv_store_engine.set_data_ttl(
+            &req.unique_id,
+            if req.ttl_ms > 0 {
+                now_ms().saturating_add(req.ttl_ms)
+            } else {
+                0
+            },
+        )?;
+        kv_store_engine.flush();
+        self.view
+            .kv_watch()
+            .notify(&req.unique_id, set_meta.version, proto::kv::KvWatchOp::Set);
+        Ok((set_meta, old_nodes))
+    }
+
     /// Check the dataset sync flow here:
     ///
     ///   https://fvd360f8oos.feishu.cn/docx/XoFudWhAgox84MxKC3ccP1TcnUh#share-Wg7Nd5iwooJiUAx79YqceHcHn4c
@@ -216,54 +1730,119 @@ impl DataMaster {
         let metakey = KeyTypeDataSetMeta(&req.unique_id);
         let metakey_bytes = metakey.make_key();
         tracing::debug!("check version for data({:?})", req.unique_id);
+        let condition = req.condition.as_ref().and_then(|c| c.cond.as_ref());
+
+        // a write racing with the reservation is still caught by the version check
+        //  of the transaction, because its writes are conditional
+        self.wait_txn_released(&req.unique_id, req.txn_id).await;
+
+        // precheck the condition so that a rejected write won't trigger functions in most cases
+        if let Some(condition) = condition {
+            let cur_version = kv_store_engine
+                .get(&metakey, false, KvAdditionalConf::default())
+                .map_or(0, |(_, meta)| meta.version);
+            if !Self::write_condition_met(condition, cur_version) {
+                return Self::reply_condition_failed(responsor, &req.unique_id, cur_version).await;
+            }
+        }
 
         // now we expand the meta
-        let (new_meta, cache_nodes) = {
+        let expanded = {
             // the we will make the  split plan and cache plan
             //  then expand the meta
             //  this process will fail if other write updated the unique id
             let (item_cache_modes, new_splits, cache_nodes) = self
-                .plan_for_write_data(&req.unique_id, ctx, FuncTriggerType::DataWrite)
+                .plan_for_write_data(
+                    &req.unique_id,
+                    ctx,
+                    FuncTriggerType::DataWrite,
+                    &req.pinned_nodes,
+                )
                 .await?;
+            // the redundancy follows the splits planned above, the rest is as the writer asked
+            let item_cache_modes: Vec<CacheMode> =
+                if req.cache_modes.len() == item_cache_modes.len() {
+                    item_cache_modes
+                        .iter()
+                        .zip(&req.cache_modes)
+                        .map(|(&planned, &asked)| {
+                            (asked as CacheMode & CACHE_MODE_REDUNDANCY_REPLICA_MASK)
+                                | (planned & CACHE_MODE_REDUNDANCY_MASK)
+                        })
+                        .collect()
+                } else {
+                    item_cache_modes
+                };
 
-            let update_version_lock = kv_store_engine.with_rwlock(&metakey_bytes);
-            let _guard = update_version_lock.write();
-            let dataset_meta = kv_store_engine.get(&metakey, true, KvAdditionalConf::default());
-
-            // let takeonce=Some((new_meta,new_))
-            let set_meta = if let Some((_kv_version, set_meta)) = dataset_meta {
-                tracing::debug!("update dataset meta for data({:?})", req.unique_id);
-                let version = set_meta.version;
-                let mut builder = DataSetMetaBuilder::from(set_meta);
-                // version
-                let _ = builder.version(version + 1);
-                // data splits bf cache mod
-                let _ = builder.set_data_splits(new_splits);
-                // cache mode
-                let _ = builder.set_cache_mode_for_all(item_cache_modes);
-                builder.build()
-            } else {
-                tracing::debug!("new dataset meta for data({:?})", req.unique_id);
-                let mut builder = DataSetMetaBuilder::new();
-                // version
-                let _ = builder.version(1);
-                // data splits bf cache mod
-                let _ = builder.set_data_splits(new_splits);
-                // cache mode
-                let _ = builder.set_cache_mode_for_all(item_cache_modes);
-                builder.build()
-            };
+            // an incr adds to the value read at the current version,
+            //  it's read again if a write other than an incr came in between
+            loop {
+                let incr_base = match condition {
+                    Some(proto::data_write_condition::Cond::Incr(_)) => {
+                        Some(self.incr_base(&req.unique_id).await?)
+                    }
+                    _ => None,
+                };
+                let update_version_lock = kv_store_engine.with_rwlock(&metakey_bytes);
+                let _guard = update_version_lock.write();
+                let dataset_meta = kv_store_engine.get(&metakey, true, KvAdditionalConf::default());
 
-            // ##  update version local
-            tracing::debug!(
-                "update version local for data({:?}), the updated meta is {:?}",
-                req.unique_id,
-                set_meta
-            );
-            let _ = kv_store_engine.set(KeyTypeDataSetMeta(&req.unique_id), &set_meta, true)?;
-            kv_store_engine.flush();
-            (set_meta, cache_nodes)
+                // the final check, other writer may have updated the version after precheck
+                let cur_version = dataset_meta.as_ref().map_or(0, |(_, meta)| meta.version);
+                if !condition.map_or(true, |condition| {
+                    Self::write_condition_met(condition, cur_version)
+                }) {
+                    break Err(WriteRejected::Condition(cur_version));
+                }
+                let incr = match (condition, incr_base) {
+                    (Some(proto::data_write_condition::Cond::Incr(delta)), Some(base)) => {
+                        let base = match self.incr_values.get(&req.unique_id) {
+                            Some((version, value)) if version == cur_version => Some(value),
+                            _ if base.0 == cur_version => base.1,
+                            _ => continue,
+                        };
+                        match base.and_then(|base| base.checked_add(*delta)) {
+                            Some(value) => Some(value),
+                            None => break Err(WriteRejected::Condition(cur_version)),
+                        }
+                    }
+                    _ => None,
+                };
+                break match self.charge_usage(&req.unique_id, ctx)? {
+                    Err(exceeded) => Err(WriteRejected::Quota(exceeded)),
+                    Ok(charge) => match self.expand_meta_locked(
+                        &req,
+                        dataset_meta,
+                        new_splits,
+                        item_cache_modes,
+                        responsor.node_id(),
+                        incr,
+                    ) {
+                        Ok((set_meta, old_nodes)) => Ok((set_meta, cache_nodes, old_nodes, incr)),
+                        Err(err) => {
+                            self.refund_usage(&req.unique_id, charge);
+                            return Err(err);
+                        }
+                    },
+                };
+            }
+        };
+        let (new_meta, cache_nodes, old_nodes, incr) = match expanded {
+            Ok(expanded) => expanded,
+            Err(WriteRejected::Condition(cur_version)) => {
+                return Self::reply_condition_failed(responsor, &req.unique_id, cur_version).await;
+            }
+            Err(WriteRejected::Quota(exceeded)) => {
+                return Self::reply_quota_exceeded(responsor, &req.unique_id, exceeded).await;
+            }
         };
+        if let Some(proto::data_schedule_context::OpeRole::UploadApp(upload)) =
+            ctx.ope_role.as_ref()
+        {
+            self.view
+                .app_master()
+                .app_meta_written(&req.unique_id, upload);
+        }
 
         // update version peers
         {
@@ -278,6 +1857,24 @@ impl DataMaster {
                 // TODO: do we need to notify cache nodes?
                 need_notify_nodes
             };
+            // data nodes drop their cached meta when updated, the others are told separately,
+            //  the writer gets the version after they dropped it
+            let invalidated =
+                self.invalidate_meta_caches(&req.unique_id, new_meta.version, &need_notify_nodes);
+
+            let dropped_nodes: Vec<NodeID> = old_nodes
+                .into_iter()
+                .filter(|node| {
+                    !need_notify_nodes.contains(node) && !new_meta.synced_nodes.contains(node)
+                })
+                .collect();
+            if !dropped_nodes.is_empty() {
+                self.view.data_gc().send_tombstones(
+                    &req.unique_id,
+                    new_meta.version - 1,
+                    dropped_nodes,
+                );
+            }
 
             for need_notify_node in need_notify_nodes {
                 let view = self.view.clone();
@@ -309,6 +1906,7 @@ impl DataMaster {
                                 unique_id,
                                 version,
                                 serialized_meta,
+                                cache_only: false,
                             },
                             Some(Duration::from_secs(60)),
                         )
@@ -326,6 +1924,7 @@ impl DataMaster {
                     }
                 });
             }
+            invalidated.await;
         }
     
 
@@ -346,6 +1945,10 @@ impl DataMaster {
                     .map(|v| v.into())
                     .collect(),
                 cache_nodes,
+                condition_failed: false,
+                compression: proto::DataCompression::from(new_meta.compression) as i32,
+                quota_exceeded: None,
+                incr_value: incr.map_or_else(Vec::new, |value| value.to_string().into_bytes()),
             })
             .await{
                 tracing::error!("Failed to send data version schedule response: {}", e);
@@ -404,3 +2007,102 @@ impl DataMaster {
     //     Ok(())
     // }
 }
+
+/// smallest key greater than all keys with the given prefix, None if unbounded
+pub fn key_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut succ = prefix.to_vec();
+    while let Some(last) = succ.pop() {
+        if last < u8::MAX {
+            succ.push(last + 1);
+            return Some(succ);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{key_successor, place_replicas, replica_splits};
+    use crate::general::app::{CachePolicy, CachePos, CacheTime};
+    use crate::general::data::m_data_general::{
+        DataSetMetaBuilder, DataSplit, CACHE_MODE_SPLIT_EXTRA_COPY,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_key_successor() {
+        assert_eq!(key_successor(b"fkv"), Some(b"fkw".to_vec()));
+        assert_eq!(key_successor(&[1, 0xff, 0xff]), Some(vec![2]));
+        assert_eq!(key_successor(&[0xff]), None);
+        assert_eq!(key_successor(&[]), None);
+    }
+
+    #[test]
+    fn test_place_replicas() {
+        let alive = [1, 2, 3, 4];
+        let policy = |pos: CachePos, nodes: Vec<u32>| CachePolicy {
+            pos,
+            nodes,
+            ..Default::default()
+        };
+        let set = |nodes: Vec<u32>| nodes.into_iter().collect::<HashSet<_>>();
+
+        // auto: the replication factor, bound function nodes first
+        let nodes = place_replicas(&policy(CachePos::Auto, vec![]), &alive, &[3], 2);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0], 3);
+        let nodes = place_replicas(&policy(CachePos::Auto, vec![]), &alive, &[3, 4], 1);
+        assert_eq!(nodes, vec![3, 4]);
+
+        // all node: every live node whatever the factor
+        let nodes = place_replicas(&policy(CachePos::AllNode, vec![]), &alive, &[2], 1);
+        assert_eq!(set(nodes.clone()), set(alive.to_vec()));
+        assert_eq!(nodes[0], 2);
+
+        // spec node: the live listed nodes in order, a bound function node first
+        let spec = policy(CachePos::SpecNode, vec![4, 9, 2]);
+        assert_eq!(place_replicas(&spec, &alive, &[], 1), vec![4, 2]);
+        assert_eq!(place_replicas(&spec, &alive, &[2], 3), vec![2, 4]);
+        assert_eq!(place_replicas(&spec, &alive, &[1], 3), vec![4, 2]);
+        // none alive, placed as auto
+        let nodes = place_replicas(&policy(CachePos::SpecNode, vec![9]), &alive, &[], 2);
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes.iter().all(|node| alive.contains(node)));
+    }
+
+    #[test]
+    fn test_only_extra_copies_are_evictable() {
+        // nothing is evicted unless asked for
+        assert_eq!(CachePolicy::default().time, CacheTime::Forever);
+
+        let splits = replica_splits(&[3, 1, 2, 4], 10, 2);
+        let extra = CACHE_MODE_SPLIT_EXTRA_COPY;
+        let modes: Vec<u32> = splits.iter().map(|s| s.cache_mode).collect();
+        assert_eq!(modes, vec![0, 0, extra, extra]);
+        // a factor of 0 still keeps the primary
+        assert_eq!(replica_splits(&[3, 1], 10, 0)[0].cache_mode, 0);
+
+        let meta = |time_auto: bool| {
+            let mut builder = DataSetMetaBuilder::new();
+            let _ = builder.set_data_splits(vec![DataSplit {
+                splits: splits.clone(),
+            }]);
+            let _ = if time_auto {
+                builder.cache_mode_time_auto(0)
+            } else {
+                builder.cache_mode_time_forever(0)
+            };
+            builder.build()
+        };
+        let auto = meta(true);
+        assert!(!auto.evictable_copy(0, 3));
+        assert!(!auto.evictable_copy(0, 1));
+        assert!(auto.evictable_copy(0, 2));
+        assert!(auto.evictable_copy(0, 4));
+        assert!(!auto.evictable_copy(0, 5));
+        let forever = meta(false);
+        for node in [3, 1, 2, 4] {
+            assert!(!forever.evictable_copy(0, node));
+        }
+    }
+}
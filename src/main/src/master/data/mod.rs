@@ -1,2 +1,5 @@
+pub mod data_audit;
+pub mod data_quota;
+pub mod kv_txn;
 pub mod m_data_master;
 pub mod m_master_kv;
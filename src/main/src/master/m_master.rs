@@ -228,7 +228,7 @@ impl Master {
         let opeid = self.ope_id_allocator.fetch_add(1, Ordering::Relaxed);
 
         // Create trigger using the ProtoExtDataEventTrigger trait
-        let trigger = DataEventTrigger::Write.into_proto_trigger(ctx.data_unique_id, opeid);
+        let trigger = ctx.event_type.into_proto_trigger(ctx.data_unique_id, opeid);
 
         // Create and send tasks to target nodes
         for &node in &ctx.target_nodes {
@@ -1,7 +1,10 @@
 use crate::{
-    general::network::{
-        m_p2p::{MsgHandler, P2PModule},
-        proto,
+    general::{
+        data::m_data_general::compress::DataCompression,
+        network::{
+            m_p2p::{MsgHandler, P2PModule},
+            proto,
+        },
     },
     logical_module_view_impl,
     result::WSResult,
@@ -9,10 +12,15 @@ use crate::{
     util::JoinHandleWrapper,
 };
 use async_trait::async_trait;
+use dashmap::DashMap;
 use prometheus_client::registry::Registry;
+use std::time::{Duration, Instant};
 use ws_derive::LogicalModule;
 
-use self::prometheus::{Metrics, RscLabels, RscType};
+use self::prometheus::{
+    AppUsageLabels, KvDenialLabels, KvDeniedOpe, Metrics, RscLabels, RscType, UsageResource,
+};
+use super::data::data_quota::AppUsage;
 
 // pub struct NodeRscMetric {
 //     used_cpu: f64,
@@ -63,9 +71,36 @@ pub mod prometheus {
         MemUsed,
     }
 
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+    pub struct AppUsageLabels {
+        pub app: String,
+        pub resource: UsageResource,
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+    pub enum UsageResource {
+        Bytes,
+        Keys,
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+    pub struct KvDenialLabels {
+        pub node_id: NodeID,
+        pub ope: KvDeniedOpe,
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+    pub enum KvDeniedOpe {
+        Get,
+        Set,
+        Delete,
+    }
+
     pub struct Metrics {
         pub requests: Family<RequestLabels, Counter>,
         pub rscs: Family<RscLabels, Gauge<f64, AtomicU64>>,
+        pub app_usage: Family<AppUsageLabels, Gauge>,
+        pub kv_denials: Family<KvDenialLabels, Gauge>,
     }
 
     pub fn new_registry_and_metrics() -> (Metrics, Registry) {
@@ -73,6 +108,8 @@ pub mod prometheus {
         let metrics = Metrics {
             requests: Family::default(),
             rscs: Family::default(),
+            app_usage: Family::default(),
+            kv_denials: Family::default(),
         };
         registry.register(
             "requests",
@@ -80,12 +117,25 @@ pub mod prometheus {
             metrics.requests.clone(),
         );
         registry.register("rscs", "Resource usage record", metrics.rscs.clone());
+        registry.register(
+            "app_usage",
+            "Storage used by each app",
+            metrics.app_usage.clone(),
+        );
+        registry.register(
+            "kv_denials",
+            "Kv operations of functions denied by the declared data accesses",
+            metrics.kv_denials.clone(),
+        );
         (metrics, registry)
     }
 }
 
 pub struct NodeFnCacheMetric();
 
+/// nodes report metrics every second, a node silent for this long is taken as down
+const NODE_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
 logical_module_view_impl!(MetricObservorView);
 logical_module_view_impl!(MetricObservorView, p2p, P2PModule);
 logical_module_view_impl!(MetricObservorView, metric_observor, Option<MetricObservor>);
@@ -97,6 +147,10 @@ pub struct MetricObservor {
     // node_rsc_metric: SkipMap<NodeID, proto::metric::RscMetric>,
     view: MetricObservorView,
     msg_handler: MsgHandler<proto::metric::RscMetric>,
+    node_last_seen: DashMap<NodeID, Instant>,
+    /// compressions reported by each node, see [`MetricObservor::grant_compression`]
+    node_compressions: DashMap<NodeID, Vec<i32>>,
+    started_at: Instant,
 }
 
 #[async_trait]
@@ -112,6 +166,9 @@ impl LogicalModule for MetricObservor {
 
             view: MetricObservorView::new(args.logical_modules_ref.clone()),
             msg_handler: MsgHandler::default(),
+            node_last_seen: DashMap::new(),
+            node_compressions: DashMap::new(),
+            started_at: Instant::now(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
@@ -130,7 +187,77 @@ impl LogicalModule for MetricObservor {
 }
 
 impl MetricObservor {
+    /// Nodes that never reported are alive until the timeout passes since the master started
+    pub fn is_node_alive(&self, nid: NodeID) -> bool {
+        if nid == self.view.p2p().nodes_config.this_node() {
+            return true;
+        }
+        match self.node_last_seen.get(&nid) {
+            Some(last_seen) => last_seen.elapsed() < NODE_ALIVE_TIMEOUT,
+            None => self.started_at.elapsed() < NODE_ALIVE_TIMEOUT,
+        }
+    }
+
+    pub fn alive_nodes(&self) -> Vec<NodeID> {
+        self.view
+            .p2p()
+            .nodes_config
+            .all_nodes_iter()
+            .map(|(nid, _)| *nid)
+            .filter(|nid| self.is_node_alive(*nid))
+            .collect()
+    }
+
+    /// `asked` if every node of the cluster reported it decodes it, raw otherwise.
+    ///  Any node may receive the data later by repair or re-replication, so a node not reported
+    ///  yet or predating compression makes the data written raw
+    pub fn grant_compression(&self, asked: DataCompression) -> DataCompression {
+        if asked == DataCompression::Raw {
+            return asked;
+        }
+        let asked_proto = proto::DataCompression::from(asked) as i32;
+        let this_node = self.view.p2p().nodes_config.this_node();
+        let all_decode = self
+            .view
+            .p2p()
+            .nodes_config
+            .all_nodes_iter()
+            .all(|(nid, _)| {
+                *nid == this_node
+                    || self
+                        .node_compressions
+                        .get(nid)
+                        .is_some_and(|cs| cs.contains(&asked_proto))
+            });
+        if all_decode {
+            asked
+        } else {
+            DataCompression::Raw
+        }
+    }
+
+    pub fn set_app_usage(&self, app: &str, usage: AppUsage) {
+        let _ = self
+            .metrics
+            .app_usage
+            .get_or_create(&AppUsageLabels {
+                app: app.to_owned(),
+                resource: UsageResource::Bytes,
+            })
+            .set(usage.bytes as i64);
+        let _ = self
+            .metrics
+            .app_usage
+            .get_or_create(&AppUsageLabels {
+                app: app.to_owned(),
+                resource: UsageResource::Keys,
+            })
+            .set(usage.keys as i64);
+    }
+
     fn insert_node_rsc_metric(&self, nid: NodeID, msg: proto::metric::RscMetric) {
+        let _ = self.node_last_seen.insert(nid, Instant::now());
+        let _ = self.node_compressions.insert(nid, msg.compressions.clone());
         // let _ = self.node_rsc_metric.insert(nid, msg);
         let _ = self
             .metrics
@@ -164,5 +291,16 @@ impl MetricObservor {
                 rsc_type: RscType::MemUsed,
             })
             .set(msg.mem_used as f64);
+        for (ope, denied) in [
+            (KvDeniedOpe::Get, msg.kv_denied_get),
+            (KvDeniedOpe::Set, msg.kv_denied_set),
+            (KvDeniedOpe::Delete, msg.kv_denied_delete),
+        ] {
+            let _ = self
+                .metrics
+                .kv_denials
+                .get_or_create(&KvDenialLabels { node_id: nid, ope })
+                .set(denied as i64);
+        }
     }
 }
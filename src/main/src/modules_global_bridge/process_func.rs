@@ -1,5 +1,6 @@
 use crate::general::app::app_shared::process_rpc::ProcessRpc;
 use crate::general::app::instance::m_instance_manager::InstanceManager;
+use crate::general::app::m_executor::Executor;
 use crate::general::app::AppMetaManager;
 
 pub trait ModulesGlobalBrigeInstanceManager: Sized + 'static {
@@ -21,3 +22,13 @@ impl ModulesGlobalBrigeAppMetaManager for ProcessRpc {
         &super::modules().appmeta_manager
     }
 }
+
+pub trait ModulesGlobalBrigeExecutor: Sized + 'static {
+    unsafe fn global_m_executor() -> &'static Executor;
+}
+
+impl ModulesGlobalBrigeExecutor for ProcessRpc {
+    unsafe fn global_m_executor() -> &'static Executor {
+        &super::modules().executor
+    }
+}
@@ -122,6 +122,7 @@ pub enum WsPermissionErr {
     AccessKeyPermissionDenied {
         app: String,
         func: String,
+        ope: String,
         access_key: TryUtf8VecU8,
     },
 }
@@ -175,6 +176,24 @@ pub enum WsFuncError {
     InstanceProcessStartFailed(std::io::Error),
     InsranceVerifyFailed(String),
     UnsupportedAppType,
+    FuncRpcNotSupported {
+        app: String,
+        func: String,
+    },
+    FnCallDepthExceeded {
+        app: String,
+        func: String,
+        depth: u32,
+    },
+    FnCallTimeout {
+        app: String,
+        func: String,
+    },
+    FnCallRemoteFailed {
+        app: String,
+        func: String,
+        msg: String,
+    },
 }
 
 #[derive(Debug)]
@@ -244,6 +263,10 @@ pub enum WsDataError {
         path: PathBuf,
         err: std::io::Error,
     },
+    FileWriteErr {
+        path: PathBuf,
+        err: std::io::Error,
+    },
     FileRenameErr {
         from: PathBuf,
         to: PathBuf,
@@ -308,6 +331,26 @@ pub enum WsDataError {
         len: u8,
     },
     ItemIdxEmpty,
+    /// content of the item differs from the checksum given by its writer
+    ChecksumMismatch {
+        unique_id: Vec<u8>,
+        idx: DataItemIdx,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    /// every holder of the item failed, errors are in the order the nodes were tried
+    GetDataFailedOnHolders {
+        unique_id: Vec<u8>,
+        idx: DataItemIdx,
+        errs: Vec<(NodeID, WSError)>,
+    },
+    /// fewer erasure shards readable than the data shards, errors are of the unreadable ones
+    ErasureShardsLacking {
+        unique_id: Vec<u8>,
+        idx: DataItemIdx,
+        needed: usize,
+        errs: Vec<(NodeID, WSError)>,
+    },
     VersionMismatch {
         expected: u64,
         actual: u64,
@@ -330,6 +373,18 @@ pub enum WsDataError {
         /// 数据类型（用于调试）
         data_type: String,
     },
+    /// export or import of a data archive failed, see `m_data_general::archive`
+    DataArchiveErr {
+        path: PathBuf,
+        msg: String,
+    },
+    /// the write would take the app over its hard quota of `resource` (bytes or keys)
+    QuotaExceeded {
+        app: String,
+        resource: String,
+        used: u64,
+        limit: u64,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -6,7 +6,9 @@ use crate::{
     general::{
         app::AppMetaManager,
         data::{
-            m_data_general::DataGeneral, m_dist_lock::DistLock, m_kv_store_engine::KvStoreEngine,
+            m_data_cache::DataCache, m_data_gc::DataGc, m_data_general::DataGeneral,
+            m_dist_lock::DistLock, m_kv_store_engine::KvStoreEngine, m_kv_watch::KvWatch,
+            m_s3_gateway::S3Gateway,
         },
         m_metric_publisher::MetricPublisher,
         m_os::OperatingSystem,
@@ -349,6 +351,14 @@ start_modules!(
         HttpHandlerDispatch,
         dist_lock,
         DistLock,
+        kv_watch,
+        KvWatch,
+        data_gc,
+        DataGc,
+        data_cache,
+        DataCache,
+        s3_gateway,
+        S3Gateway,
         instance_manager,
         InstanceManager,
         executor,
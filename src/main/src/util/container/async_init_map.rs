@@ -111,6 +111,19 @@ where
             .and_then(|(_, value)| value.get().cloned())
     }
 
+    /// 移除所有满足条件的已初始化值, 返回被移除的键值对
+    pub fn remove_ready_if(&self, f: impl Fn(&K, &V) -> bool) -> Vec<(K, V)> {
+        let mut removed = Vec::new();
+        self.inner.retain(|key, value| match value.get() {
+            Some(value) if f(key, value) => {
+                removed.push((key.clone(), value.clone()));
+                false
+            }
+            _ => true,
+        });
+        removed
+    }
+
     /// 获取或初始化一个值
     /// 
     /// # 参数
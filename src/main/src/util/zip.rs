@@ -1,5 +1,5 @@
 use std::path::Path;
-use std::io::{self, Write, Seek, Cursor,Read};
+use std::io::{self, Write, Seek, Cursor};
 use std::fs;
 use walkdir::WalkDir;
 use zip::{write::FileOptions, ZipWriter, result::ZipError};
@@ -51,7 +51,6 @@ where
 {
     let mut zip = ZipWriter::new(writer);
     let prefix = Path::new(prefix);
-    let mut buffer = Vec::new();
     for entry in it {
         let path = entry.path();
         let name = path.strip_prefix(prefix).unwrap();
@@ -75,12 +74,8 @@ where
                 .map_err(|e| WSError::from(WsIoErr::Zip2(e)))?;
             let mut f = fs::File::open(path).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
 
-            let _ = f
-                .read_to_end(&mut buffer)
-                .map_err(|e| WSError::from(WsIoErr::Io(e)))?;
-            zip.write_all(&buffer)
-                .map_err(|e| WSError::from(WsIoErr::Io(e)))?;
-            buffer.clear();
+            // copy in blocks, large files are never held in memory as a whole
+            let _ = io::copy(&mut f, &mut zip).map_err(|e| WSError::from(WsIoErr::Io(e)))?;
         } else if !name.as_os_str().is_empty() {
             // Only if not root! Avoids path spec / warning
             // and mapname conversion failed error on unzip
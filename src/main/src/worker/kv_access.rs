@@ -0,0 +1,297 @@
+//! Data accesses declared in app.yaml, checked on the kv requests of functions.
+//!  A key pattern grants its operations on the keys starting with its fixed part before the
+//!  first `{}`, the same as the redundancy rules. A scan, or a get or delete of a range, is
+//!  granted when all the keys it may touch are under one pattern.
+//!  Accesses are checked on the keys the function sees, before they are namespaced.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::general::app::{AppMeta, DataAccess, FnMeta};
+use crate::general::data::kv_interface::KvOps;
+use crate::general::network::proto::kv::{kv_request::Op, KeyRange, KvPair, KvRequest};
+use crate::master::data::m_data_master::key_successor;
+use crate::result::WsPermissionErr;
+use crate::util::TryUtf8VecU8;
+use crate::worker::kv_namespace::KvNamespace;
+
+static DENIED_GET: AtomicU64 = AtomicU64::new(0);
+static DENIED_SET: AtomicU64 = AtomicU64::new(0);
+static DENIED_DELETE: AtomicU64 = AtomicU64::new(0);
+
+/// Kv operations of functions denied on this node since it started
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct KvAccessMetrics {
+    pub denied_get: u64,
+    pub denied_set: u64,
+    pub denied_delete: u64,
+}
+
+pub fn metrics() -> KvAccessMetrics {
+    KvAccessMetrics {
+        denied_get: DENIED_GET.load(Ordering::Relaxed),
+        denied_set: DENIED_SET.load(Ordering::Relaxed),
+        denied_delete: DENIED_DELETE.load(Ordering::Relaxed),
+    }
+}
+
+fn count_denied(ope: KvOps) {
+    let counter = match ope {
+        KvOps::Get => &DENIED_GET,
+        KvOps::Set => &DENIED_SET,
+        KvOps::Delete => &DENIED_DELETE,
+    };
+    let _ = counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Kv rules of one app: its key namespace and the accesses of its functions
+#[derive(Debug, Default)]
+pub struct AppKvRules {
+    pub namespace: KvNamespace,
+    fns: HashMap<String, FnKvAccesses>,
+    /// for an unknown function
+    none: FnKvAccesses,
+}
+
+impl AppKvRules {
+    pub fn new(app: &str, app_meta: Option<&AppMeta>) -> Self {
+        let fns = app_meta.map_or_else(HashMap::new, |app_meta| {
+            app_meta
+                .fns
+                .iter()
+                .map(|(func, fn_meta)| (func.clone(), FnKvAccesses::new(fn_meta)))
+                .collect()
+        });
+        Self {
+            namespace: KvNamespace::new(app, app_meta),
+            fns,
+            none: FnKvAccesses::default(),
+        }
+    }
+
+    pub fn fn_accesses(&self, func: &str) -> &FnKvAccesses {
+        self.fns.get(func).unwrap_or(&self.none)
+    }
+}
+
+/// Operations granted to one function, nothing for an unknown app or function
+#[derive(Debug, Default)]
+pub struct FnKvAccesses {
+    /// fixed prefix of the key pattern -> its access
+    grants: Vec<(Vec<u8>, DataAccess)>,
+}
+
+impl FnKvAccesses {
+    fn new(fn_meta: &FnMeta) -> Self {
+        let grants = fn_meta
+            .data_accesses
+            .as_ref()
+            .map_or_else(Vec::new, |accesses| {
+                accesses
+                    .iter()
+                    .map(|(pattern, access)| (pattern.prefix().as_bytes().to_vec(), access.clone()))
+                    .collect()
+            });
+        Self { grants }
+    }
+
+    fn allows_key(&self, ope: KvOps, key: &[u8]) -> bool {
+        self.grants
+            .iter()
+            .any(|(prefix, access)| access.allows(ope) && key.starts_with(prefix))
+    }
+
+    /// Keys in [start, end), an empty end is unbounded, that also start with `key_prefix`
+    fn allows_range(&self, ope: KvOps, range: &KeyRange, key_prefix: &[u8]) -> bool {
+        self.grants.iter().any(|(prefix, access)| {
+            access.allows(ope)
+                && (key_prefix.starts_with(prefix)
+                    || (range.start.starts_with(prefix)
+                        && key_successor(prefix)
+                            .map_or(true, |bound| !range.end.is_empty() && range.end <= bound)))
+        })
+    }
+
+    /// The first operation of the request that is not granted
+    fn first_denied(&self, req: &KvRequest) -> Option<(KvOps, Vec<u8>)> {
+        let key_or_range = |ope: KvOps, range: &Option<KeyRange>| {
+            let range = range.clone().unwrap_or_default();
+            let allowed = if range.end.is_empty() {
+                self.allows_key(ope, &range.start)
+            } else {
+                self.allows_range(ope, &range, &[])
+            };
+            (!allowed).then(|| (ope, range.start))
+        };
+        let key =
+            |ope: KvOps, key: &[u8]| (!self.allows_key(ope, key)).then(|| (ope, key.to_vec()));
+        let pair_key = |kv: &Option<KvPair>| kv.as_ref().map_or_else(Vec::new, |kv| kv.key.clone());
+        match req.op.as_ref()? {
+            Op::Set(set) => key(KvOps::Set, &pair_key(&set.kv)),
+            Op::Cas(cas) => key(KvOps::Set, &pair_key(&cas.kv)),
+            Op::PutIfAbsent(put) => key(KvOps::Set, &pair_key(&put.kv)),
+            // incr reads the current value
+            Op::Incr(incr) => key(KvOps::Get, &incr.key).or_else(|| key(KvOps::Set, &incr.key)),
+            Op::Get(get) => key_or_range(KvOps::Get, &get.range),
+            Op::Delete(delete) => key_or_range(KvOps::Delete, &delete.range),
+            Op::Scan(scan) => {
+                let range = scan.range.clone().unwrap_or_default();
+                (!self.allows_range(KvOps::Get, &range, &scan.prefix)).then(|| {
+                    let key = if scan.prefix.is_empty() {
+                        range.start
+                    } else {
+                        scan.prefix.clone()
+                    };
+                    (KvOps::Get, key)
+                })
+            }
+            // a read lock only keeps the key from changing
+            Op::Lock(lock) => key_or_range(
+                if lock.read_or_write {
+                    KvOps::Get
+                } else {
+                    KvOps::Set
+                },
+                &lock.range,
+            ),
+        }
+    }
+
+    /// All the requests of a batch are granted, or the batch is refused as a whole
+    pub fn check(&self, app: &str, func: &str, reqs: &[KvRequest]) -> Result<(), WsPermissionErr> {
+        match reqs.iter().find_map(|req| self.first_denied(req)) {
+            Some((ope, key)) => Err(denied(app, func, ope, key)),
+            None => Ok(()),
+        }
+    }
+
+    /// A single operation outside of a batch, e.g. a stream write
+    pub fn check_key(
+        &self,
+        app: &str,
+        func: &str,
+        ope: KvOps,
+        key: &[u8],
+    ) -> Result<(), WsPermissionErr> {
+        if self.allows_key(ope, key) {
+            Ok(())
+        } else {
+            Err(denied(app, func, ope, key.to_vec()))
+        }
+    }
+}
+
+fn denied(app: &str, func: &str, ope: KvOps, key: Vec<u8>) -> WsPermissionErr {
+    count_denied(ope);
+    tracing::warn!(
+        "{}/{} is not allowed to {:?} key {:?}",
+        app,
+        func,
+        ope,
+        String::from_utf8_lossy(&key)
+    );
+    WsPermissionErr::AccessKeyPermissionDenied {
+        app: app.to_owned(),
+        func: func.to_owned(),
+        ope: format!("{:?}", ope).to_lowercase(),
+        access_key: TryUtf8VecU8(key),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::general::network::proto::kv::kv_request::{
+        KvDeleteRequest, KvGetRequest, KvIncrRequest, KvPutRequest, KvScanRequest,
+    };
+
+    fn access(yaml: &str) -> DataAccess {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn range(start: &str, end: &str) -> Option<KeyRange> {
+        Some(KeyRange {
+            start: start.as_bytes().to_vec(),
+            end: end.as_bytes().to_vec(),
+        })
+    }
+
+    fn req(op: Op) -> KvRequest {
+        KvRequest { op: Some(op) }
+    }
+
+    #[test]
+    fn test_fn_kv_accesses() {
+        let accesses = FnKvAccesses {
+            grants: vec![
+                (
+                    b"user_".to_vec(),
+                    access("{set: true, get: true, delete: false}"),
+                ),
+                (
+                    b"log_".to_vec(),
+                    access("{set: false, get: true, delete: true}"),
+                ),
+            ],
+        };
+        let set = |key: &str| {
+            req(Op::Set(KvPutRequest {
+                kv: Some(KvPair {
+                    key: key.as_bytes().to_vec(),
+                    value: vec![],
+                }),
+                ..Default::default()
+            }))
+        };
+        let get = |start: &str, end: &str| {
+            req(Op::Get(KvGetRequest {
+                range: range(start, end),
+            }))
+        };
+        let check = |reqs: &[KvRequest]| accesses.check("app", "fn", reqs).is_ok();
+
+        assert!(check(&[set("user_1"), get("log_1", "")]));
+        assert!(!check(&[set("user_1"), set("log_1")]));
+        assert!(!check(&[set("other")]));
+        // incr needs both get and set
+        let incr = |key: &str| {
+            req(Op::Incr(KvIncrRequest {
+                key: key.as_bytes().to_vec(),
+                ..Default::default()
+            }))
+        };
+        assert!(check(&[incr("user_1")]));
+        assert!(!check(&[incr("log_1")]));
+        let delete = |start: &str, end: &str| {
+            req(Op::Delete(KvDeleteRequest {
+                range: range(start, end),
+            }))
+        };
+        assert!(check(&[delete("log_1", "")]));
+        assert!(!check(&[delete("user_1", "")]));
+
+        // ranges must stay under one pattern
+        assert!(check(&[get("user_a", "user_z")]));
+        assert!(check(&[get("user_", "user`")]));
+        assert!(!check(&[get("user_a", "zzz")]));
+        let scan = |start: &str, end: &str, prefix: &str| {
+            req(Op::Scan(KvScanRequest {
+                range: range(start, end),
+                prefix: prefix.as_bytes().to_vec(),
+                ..Default::default()
+            }))
+        };
+        assert!(check(&[scan("", "", "user_")]));
+        assert!(check(&[scan("log_", "log`", "")]));
+        assert!(!check(&[scan("log_", "", "")]));
+        assert!(!check(&[scan("", "", "")]));
+
+        // nothing is granted to an unknown function
+        assert!(AppKvRules::new("app", None)
+            .fn_accesses("fn")
+            .check_key("app", "fn", KvOps::Get, b"user_1")
+            .is_err());
+    }
+}
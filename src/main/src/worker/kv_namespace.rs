@@ -0,0 +1,219 @@
+//! Keys of functions are scoped per app, `{app}\0{key}` in the fn kv datasets.
+//!  Patterns listed in `shared_kvs` of app.yaml resolve to the namespace of the sharing app.
+//!  Functions only see their own keys, the namespace is added to the requests and stripped
+//!  from the responses.
+
+use crate::general::app::AppMeta;
+use crate::general::data::m_data_general::{fn_kv_ns_key, split_fn_kv_ns_key, FN_KV_NS_SEP};
+use crate::general::network::proto::kv::{
+    kv_request::Op, kv_response::Resp, KeyRange, KvPair, KvRequest, KvResponses,
+};
+
+#[derive(Debug, Default)]
+pub struct KvNamespace {
+    app: String,
+    /// fixed prefix of an imported key pattern -> the sharing app
+    shared: Vec<(Vec<u8>, String)>,
+}
+
+impl KvNamespace {
+    pub fn new(app: &str, app_meta: Option<&AppMeta>) -> Self {
+        let shared = app_meta.map_or_else(Vec::new, |app_meta| {
+            app_meta
+                .shared_kvs
+                .iter()
+                .flat_map(|shared| {
+                    shared
+                        .kvs
+                        .iter()
+                        .map(|pattern| (pattern.prefix().as_bytes().to_vec(), shared.app.clone()))
+                })
+                .collect()
+        });
+        Self {
+            app: app.to_owned(),
+            shared,
+        }
+    }
+
+    /// App whose namespace the key lives in
+    pub fn owner(&self, key: &[u8]) -> &str {
+        self.shared
+            .iter()
+            .find(|(prefix, _)| key.starts_with(prefix))
+            .map_or(&self.app, |(_, owner)| owner)
+    }
+
+    pub fn key(&self, key: &[u8]) -> Vec<u8> {
+        fn_kv_ns_key(self.owner(key), key)
+    }
+
+    fn range(&self, range: &mut Option<KeyRange>) {
+        if let Some(range) = range.as_mut() {
+            let owner = self.owner(&range.start);
+            // an empty end means a single key
+            if !range.end.is_empty() {
+                range.end = fn_kv_ns_key(owner, &range.end);
+            }
+            range.start = fn_kv_ns_key(owner, &range.start);
+        }
+    }
+
+    fn pair(&self, kv: &mut Option<KvPair>) {
+        if let Some(kv) = kv.as_mut() {
+            kv.key = self.key(&kv.key);
+        }
+    }
+
+    /// Requests on the namespaced keys
+    pub fn requests(&self, mut reqs: Vec<KvRequest>) -> Vec<KvRequest> {
+        for req in reqs.iter_mut() {
+            let Some(op) = req.op.as_mut() else {
+                continue;
+            };
+            match op {
+                Op::Set(set) => self.pair(&mut set.kv),
+                Op::Cas(cas) => self.pair(&mut cas.kv),
+                Op::PutIfAbsent(put) => self.pair(&mut put.kv),
+                Op::Incr(incr) => incr.key = self.key(&incr.key),
+                Op::Get(get) => self.range(&mut get.range),
+                Op::Delete(delete) => self.range(&mut delete.range),
+                Op::Lock(lock) => self.range(&mut lock.range),
+                Op::Scan(scan) => {
+                    let mut range = scan.range.take().unwrap_or_default();
+                    let owner = if scan.prefix.is_empty() {
+                        self.owner(&range.start)
+                    } else {
+                        self.owner(&scan.prefix)
+                    };
+                    if !scan.prefix.is_empty() {
+                        scan.prefix = fn_kv_ns_key(owner, &scan.prefix);
+                    }
+                    range.start = fn_kv_ns_key(owner, &range.start);
+                    range.end = if range.end.is_empty() {
+                        // the end of the namespace
+                        let mut end = owner.as_bytes().to_vec();
+                        end.push(FN_KV_NS_SEP + 1);
+                        end
+                    } else {
+                        fn_kv_ns_key(owner, &range.end)
+                    };
+                    if !scan.cursor.is_empty() {
+                        scan.cursor = fn_kv_ns_key(owner, &scan.cursor);
+                    }
+                    scan.range = Some(range);
+                }
+            }
+        }
+        reqs
+    }
+
+    /// Keys of the responses back to the ones the function sees
+    pub fn strip_responses(&self, responses: &mut KvResponses) {
+        fn strip(key: &mut Vec<u8>) {
+            if let Some((_, user_key)) = split_fn_kv_ns_key(key) {
+                *key = user_key.to_vec();
+            }
+        }
+        for response in responses.responses.iter_mut() {
+            match response.resp.as_mut() {
+                Some(Resp::CommonResp(resp)) => {
+                    resp.kvs.iter_mut().for_each(|kv| strip(&mut kv.key))
+                }
+                Some(Resp::ScanResp(resp)) => {
+                    resp.kvs.iter_mut().for_each(|kv| strip(&mut kv.key));
+                    if !resp.next_cursor.is_empty() {
+                        strip(&mut resp.next_cursor);
+                    }
+                }
+                Some(Resp::LockId(_)) | Some(Resp::AtomicResp(_)) | None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::general::app::{KeyPattern, SharedKvs};
+    use crate::general::network::proto::kv::kv_request::{KvPutRequest, KvScanRequest};
+    use crate::general::network::proto::kv::kv_response::KvScanResponse;
+    use crate::general::network::proto::kv::KvResponse;
+
+    #[test]
+    fn test_kv_namespace() {
+        let ns = KvNamespace {
+            app: "counter".to_owned(),
+            shared: vec![(b"wordcount_".to_vec(), "word_count".to_owned())],
+        };
+        assert_eq!(ns.key(b"total"), b"counter\0total".to_vec());
+        assert_eq!(ns.key(b"wordcount_a"), b"word_count\0wordcount_a".to_vec());
+
+        let reqs = ns.requests(vec![
+            KvRequest {
+                op: Some(Op::Set(KvPutRequest {
+                    kv: Some(KvPair {
+                        key: b"total".to_vec(),
+                        value: vec![],
+                    }),
+                    ..Default::default()
+                })),
+            },
+            KvRequest {
+                op: Some(Op::Scan(KvScanRequest {
+                    prefix: b"wordcount_".to_vec(),
+                    cursor: b"wordcount_a".to_vec(),
+                    ..Default::default()
+                })),
+            },
+        ]);
+        let Some(Op::Set(set)) = &reqs[0].op else {
+            panic!()
+        };
+        assert_eq!(set.kv.as_ref().unwrap().key, b"counter\0total".to_vec());
+        let Some(Op::Scan(scan)) = &reqs[1].op else {
+            panic!()
+        };
+        assert_eq!(scan.prefix, b"word_count\0wordcount_".to_vec());
+        assert_eq!(scan.cursor, b"word_count\0wordcount_a".to_vec());
+        // an unbounded scan stays in the namespace
+        let range = scan.range.as_ref().unwrap();
+        assert_eq!(range.start, b"word_count\0".to_vec());
+        assert_eq!(range.end, b"word_count\x01".to_vec());
+
+        let mut responses = KvResponses {
+            responses: vec![KvResponse {
+                resp: Some(Resp::ScanResp(KvScanResponse {
+                    kvs: vec![KvPair {
+                        key: b"word_count\0wordcount_b".to_vec(),
+                        value: vec![],
+                    }],
+                    next_cursor: b"word_count\0wordcount_b".to_vec(),
+                })),
+            }],
+            txn_status: None,
+        };
+        ns.strip_responses(&mut responses);
+        let Some(Resp::ScanResp(scan)) = &responses.responses[0].resp else {
+            panic!()
+        };
+        assert_eq!(scan.kvs[0].key, b"wordcount_b".to_vec());
+        assert_eq!(scan.next_cursor, b"wordcount_b".to_vec());
+    }
+
+    #[test]
+    fn test_kv_namespace_from_app_meta() {
+        let mut app_meta = AppMeta::new(crate::general::app::AppType::Wasm, Default::default());
+        app_meta.shared_kvs = vec![SharedKvs {
+            app: "word_count".to_owned(),
+            kvs: vec![KeyPattern("wordcount_{}".to_owned())],
+        }];
+        let ns = KvNamespace::new("counter", Some(&app_meta));
+        assert_eq!(ns.owner(b"wordcount_1"), "word_count");
+        assert_eq!(ns.owner(b"word"), "counter");
+        assert_eq!(
+            KvNamespace::new("counter", None).owner(b"wordcount_1"),
+            "counter"
+        );
+    }
+}
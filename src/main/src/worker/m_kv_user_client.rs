@@ -1,10 +1,13 @@
 use crate::general::network::proto_ext::ProtoExtDataItem;
+use crate::worker::kv_access::AppKvRules;
 use crate::{
     general::{
+        app::AppMetaManager,
         data::{
+            kv_interface::KvOps,
             m_data_general::{
-                new_data_unique_id_fn_kv, DataGeneral, DataItemIdx, DataSetMetaV2, GetOrDelDataArg,
-                GetOrDelDataArgType,
+                new_data_unique_id_fn_kv, CondWriteRes, DataGeneral, DataItemIdx, DataSetMetaV2,
+                DataVersion, GetOrDelDataArg, GetOrDelDataArgType, WriteDataOpts,
                 dataitem::DataItemArgWrapper
             },
             m_dist_lock::DistLock,
@@ -24,13 +27,14 @@ use crate::{
     util::JoinHandleWrapper,
 };
 use async_trait::async_trait;
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use ws_derive::LogicalModule;
 
 logical_module_view_impl!(KvUserClientView);
 logical_module_view_impl!(KvUserClientView, p2p, P2PModule);
 logical_module_view_impl!(KvUserClientView, data_general, DataGeneral);
 logical_module_view_impl!(KvUserClientView, dist_lock, DistLock);
+logical_module_view_impl!(KvUserClientView, appmeta_manager, AppMetaManager);
 logical_module_view_impl!(KvUserClientView, kv_user_client, Option<KvUserClient>);
 
 #[derive(LogicalModule)]
@@ -38,6 +42,11 @@ pub struct KvUserClient {
     // testmap: SkipMap<Vec<u8>, Vec<u8>>,
     view: KvUserClientView,
     rpc_caller_kv: RPCCaller<KvRequests>,
+    rpc_caller_kv_scan_index: RPCCaller<proto::kv::KvScanIndexRequest>,
+    rpc_caller_kv_txn_prepare: RPCCaller<proto::kv::KvTxnPrepareRequest>,
+    rpc_caller_kv_txn_finish: RPCCaller<proto::kv::KvTxnFinishRequest>,
+    /// app -> key namespace and declared data accesses
+    app_kv_rules: moka::sync::Cache<String, Arc<AppKvRules>>,
 }
 
 #[async_trait]
@@ -55,10 +64,19 @@ impl LogicalModule for KvUserClient {
             // testmap: SkipMap::new(),
             view: KvUserClientView::new(args.logical_modules_ref.clone()),
             rpc_caller_kv: RPCCaller::default(),
+            rpc_caller_kv_scan_index: RPCCaller::default(),
+            rpc_caller_kv_txn_prepare: RPCCaller::default(),
+            rpc_caller_kv_txn_finish: RPCCaller::default(),
+            app_kv_rules: moka::sync::CacheBuilder::new(APP_KV_RULES_CACHE_CAPACITY)
+                .time_to_live(APP_KV_RULES_CACHE_TTL)
+                .build(),
         }
     }
     async fn start(&self) -> WSResult<Vec<JoinHandleWrapper>> {
         self.rpc_caller_kv.regist(self.view.p2p());
+        self.rpc_caller_kv_scan_index.regist(self.view.p2p());
+        self.rpc_caller_kv_txn_prepare.regist(self.view.p2p());
+        self.rpc_caller_kv_txn_finish.regist(self.view.p2p());
 
         let all = vec![];
 
@@ -77,6 +95,13 @@ impl LogicalModule for KvUserClient {
 //     }
 // }
 
+/// max keys touched by one get or delete with a non-empty range end
+const KV_RANGE_OPE_MAX_KEYS: usize = 1000;
+/// apps whose kv rules are cached on one node
+const APP_KV_RULES_CACHE_CAPACITY: u64 = 1000;
+/// an updated app.yaml takes effect after this long at most
+const APP_KV_RULES_CACHE_TTL: Duration = Duration::from_secs(10);
+
 lazy_static::lazy_static! {
     static ref KV_USER_CLIENT: Option<KvUserClientView>=None;
     // static ref RECENT_Kv_CACHE: Cache<i32, Vec<u8>>=Cache::<i32, Vec<u8>>::builder()
@@ -121,14 +146,73 @@ lazy_static::lazy_static! {
 // }
 
 impl KvUserClient {
+    /// Key namespace and data accesses declared in app.yaml, nothing is granted when the app is unknown
+    pub async fn app_kv_rules(&self, app: &str) -> WSResult<Arc<AppKvRules>> {
+        if let Some(rules) = self.app_kv_rules.get(app) {
+            return Ok(rules);
+        }
+        let appmeta_manager = self.view.appmeta_manager();
+        let rules = if let Some(appmeta) = appmeta_manager.native_apps.get(app) {
+            AppKvRules::new(app, Some(appmeta))
+        } else {
+            let appmeta = appmeta_manager.get_app_meta(app).await?;
+            AppKvRules::new(app, appmeta.as_ref().map(|(appmeta, _)| appmeta))
+        };
+        let rules = Arc::new(rules);
+        self.app_kv_rules.insert(app.to_owned(), rules.clone());
+        Ok(rules)
+    }
+
+    /// Checks the single operation of a function outside of kv_requests,
+    ///  returns the key in the namespace of the app
+    pub async fn fn_kv_ns_key(
+        &self,
+        app_name: &str,
+        func_name: &str,
+        ope: KvOps,
+        key: &[u8],
+    ) -> WSResult<Vec<u8>> {
+        let rules = self.app_kv_rules(app_name).await?;
+        rules
+            .fn_accesses(func_name)
+            .check_key(app_name, func_name, ope, key)?;
+        Ok(rules.namespace.key(key))
+    }
+
+    /// `app_name` and `func_name` come from the executing function, not from the requests.
+    ///  The keys of the requests and responses are the ones the function sees, they are scoped
+    ///  to the namespace of the app here.
     pub async fn kv_requests(
         &self,
         app_name: &str,
         func_name: &str,
-        reqs: proto::kv::KvRequests,
+        mut reqs: proto::kv::KvRequests,
         // responsor: RPCResponsor<KvRequests>,
     ) -> WSResult<proto::kv::KvResponses> {
-        let mut kv_responses = KvResponses { responses: vec![] };
+        let rules = self.app_kv_rules(app_name).await?;
+        rules
+            .fn_accesses(func_name)
+            .check(app_name, func_name, &reqs.requests)?;
+        reqs.requests = rules.namespace.requests(reqs.requests);
+        let mut kv_responses = if reqs.atomic {
+            self.kv_requests_atomic(app_name, func_name, reqs).await?
+        } else {
+            self.kv_requests_each(app_name, func_name, reqs).await?
+        };
+        rules.namespace.strip_responses(&mut kv_responses);
+        Ok(kv_responses)
+    }
+
+    async fn kv_requests_each(
+        &self,
+        app_name: &str,
+        func_name: &str,
+        reqs: proto::kv::KvRequests,
+    ) -> WSResult<proto::kv::KvResponses> {
+        let mut kv_responses = KvResponses {
+            responses: vec![],
+            txn_status: None,
+        };
         // pre-collect each operation's event trigger info
 
         // let mut kv_opeid = None;
@@ -140,8 +224,40 @@ impl KvUserClient {
                 }
                 proto::kv::kv_request::Op::Get(get) => Some(self.handle_kv_get(get).await),
                 proto::kv::kv_request::Op::Delete(delete) => {
-                    Some(self.handle_kv_delete(delete).await)
+                    Some(self.handle_kv_delete(app_name, func_name, delete).await)
+                }
+                proto::kv::kv_request::Op::Cas(cas) => {
+                    let proto::kv::KvPair { key, value } = cas.kv.unwrap();
+                    Some(
+                        self.handle_kv_cond_set(
+                            app_name,
+                            func_name,
+                            key,
+                            value,
+                            proto::data_write_condition::Cond::ExpectedVersion(
+                                cas.expected_version,
+                            ),
+                        )
+                        .await,
+                    )
+                }
+                proto::kv::kv_request::Op::PutIfAbsent(put) => {
+                    let proto::kv::KvPair { key, value } = put.kv.unwrap();
+                    Some(
+                        self.handle_kv_cond_set(
+                            app_name,
+                            func_name,
+                            key,
+                            value,
+                            proto::data_write_condition::Cond::Absent(true),
+                        )
+                        .await,
+                    )
                 }
+                proto::kv::kv_request::Op::Incr(incr) => {
+                    Some(self.handle_kv_incr(app_name, func_name, incr).await)
+                }
+                proto::kv::kv_request::Op::Scan(scan) => Some(self.handle_kv_scan(scan).await),
                 proto::kv::kv_request::Op::Lock(lock) => {
                     let req = if lock.release_id.len() > 0 {
                         proto::kv::KvLockRequest {
@@ -199,6 +315,406 @@ impl KvUserClient {
         Ok(kv_responses)
     }
 
+    /// All or nothing execution of the batch:
+    ///  1. read every touched key at its latest version and run the batch on this snapshot,
+    ///  2. prepare on master, which checks the read versions, reserves the datasets and persists
+    ///     the changes, the transaction is committed from then on,
+    ///  3. apply the changes with the transaction id, others neither read nor write the reserved
+    ///     datasets meanwhile,
+    ///  4. release the reservation. If this node fails before it, master applies what's left.
+    async fn kv_requests_atomic(
+        &self,
+        app_name: &str,
+        func_name: &str,
+        reqs: proto::kv::KvRequests,
+    ) -> WSResult<proto::kv::KvResponses> {
+        use proto::kv::kv_request::Op;
+        let aborted = |reason: String| {
+            tracing::debug!("kv txn aborted: {}", reason);
+            KvResponses {
+                responses: vec![],
+                txn_status: Some(proto::kv::KvTxnStatus {
+                    committed: false,
+                    reason,
+                }),
+            }
+        };
+
+        // 1. snapshot of the touched keys, key -> (dataset version, value)
+        let mut snapshot: HashMap<Vec<u8>, (DataVersion, Option<Vec<u8>>)> = HashMap::new();
+        for req in &reqs.requests {
+            let key = match req.op.as_ref().unwrap() {
+                Op::Set(set) => &set.kv.as_ref().unwrap().key,
+                Op::Cas(cas) => &cas.kv.as_ref().unwrap().key,
+                Op::PutIfAbsent(put) => &put.kv.as_ref().unwrap().key,
+                Op::Incr(incr) => &incr.key,
+                Op::Get(proto::kv::kv_request::KvGetRequest { range })
+                | Op::Delete(proto::kv::kv_request::KvDeleteRequest { range }) => {
+                    let range = range.as_ref().unwrap();
+                    if !range.end.is_empty() {
+                        return Ok(aborted("range get or delete in atomic batch".to_owned()));
+                    }
+                    &range.start
+                }
+                Op::Lock(_) | Op::Scan(_) => {
+                    return Ok(aborted("lock or scan in atomic batch".to_owned()));
+                }
+            };
+            if snapshot.contains_key(key) {
+                continue;
+            }
+            let cur = match self.get_kv_latest(key).await {
+                Ok(Some((version, value))) => (version, Some(value)),
+                Ok(None) => (0, None),
+                Err(err) => return Ok(aborted(format!("read key {:?} failed: {}", key, err))),
+            };
+            let _ = snapshot.insert(key.clone(), cur);
+        }
+
+        // run the batch on the snapshot
+        let mut state: HashMap<Vec<u8>, Option<Vec<u8>>> = snapshot
+            .iter()
+            .map(|(key, (_, value))| (key.clone(), value.clone()))
+            .collect();
+        // ttl of the last set on the key
+        let mut ttl_of: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut responses = Vec::with_capacity(reqs.requests.len());
+        // atomic responses get the committed version at last
+        let mut committed_version_of: Vec<(usize, Vec<u8>)> = vec![];
+        for req in reqs.requests {
+            match req.op.unwrap() {
+                Op::Set(set) => {
+                    let proto::kv::KvPair { key, value } = set.kv.unwrap();
+                    let _ = ttl_of.insert(key.clone(), set.ttl_ms);
+                    let _ = state.insert(key, Some(value));
+                    responses.push(KvResponse::new_common(vec![]));
+                }
+                Op::Get(get) => {
+                    let key = get.range.unwrap().start;
+                    let kvs = state[&key]
+                        .clone()
+                        .map(|value| vec![proto::kv::KvPair { key, value }])
+                        .unwrap_or_default();
+                    responses.push(KvResponse::new_common(kvs));
+                }
+                Op::Delete(delete) => {
+                    let key = delete.range.unwrap().start;
+                    let kvs = state
+                        .insert(key.clone(), None)
+                        .flatten()
+                        .map(|value| vec![proto::kv::KvPair { key, value }])
+                        .unwrap_or_default();
+                    responses.push(KvResponse::new_common(kvs));
+                }
+                Op::Cas(cas) => {
+                    let proto::kv::KvPair { key, value } = cas.kv.unwrap();
+                    let read_version = snapshot[&key].0;
+                    if read_version != cas.expected_version {
+                        return Ok(aborted(format!(
+                            "cas on key {:?} expects version {}, current {}",
+                            key, cas.expected_version, read_version
+                        )));
+                    }
+                    let _ = state.insert(key.clone(), Some(value));
+                    committed_version_of.push((responses.len(), key));
+                    responses.push(KvResponse::new_atomic(true, 0, vec![]));
+                }
+                Op::PutIfAbsent(put) => {
+                    let proto::kv::KvPair { key, value } = put.kv.unwrap();
+                    if state[&key].is_some() {
+                        return Ok(aborted(format!("key {:?} already exists", key)));
+                    }
+                    let _ = state.insert(key.clone(), Some(value));
+                    committed_version_of.push((responses.len(), key));
+                    responses.push(KvResponse::new_atomic(true, 0, vec![]));
+                }
+                Op::Incr(incr) => {
+                    let cur = match &state[&incr.key] {
+                        None => Some(0),
+                        Some(value) => std::str::from_utf8(value)
+                            .ok()
+                            .and_then(|v| v.trim().parse::<i64>().ok()),
+                    };
+                    let Some(new) = cur.and_then(|cur| cur.checked_add(incr.delta)) else {
+                        return Ok(aborted(format!(
+                            "incr key {:?} value is not an integer or overflow",
+                            incr.key
+                        )));
+                    };
+                    let new_value = new.to_string().into_bytes();
+                    let _ = state.insert(incr.key.clone(), Some(new_value.clone()));
+                    committed_version_of.push((responses.len(), incr.key));
+                    responses.push(KvResponse::new_atomic(true, 0, new_value));
+                }
+                Op::Lock(_) | Op::Scan(_) => unreachable!("checked when taking snapshot"),
+            }
+        }
+
+        // changed keys, writes bf deletes
+        let mut changed: Vec<&Vec<u8>> = state
+            .iter()
+            .filter(|(key, value)| snapshot[*key].1 != **value)
+            .map(|(key, _)| key)
+            .collect();
+        changed.sort_by_key(|key| state[*key].is_none());
+
+        // 2. prepare, read only batch also does this to make sure the reads are consistent
+        let p2p = self.view.p2p();
+        let prepare = self
+            .rpc_caller_kv_txn_prepare
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                proto::kv::KvTxnPrepareRequest {
+                    keys: snapshot
+                        .iter()
+                        .map(|(key, (version, _))| proto::kv::KvTxnKey {
+                            unique_id: new_data_unique_id_fn_kv(key),
+                            version: *version,
+                        })
+                        .collect(),
+                    writes: changed
+                        .iter()
+                        .map(|key| proto::kv::KvTxnWrite {
+                            unique_id: new_data_unique_id_fn_kv(key),
+                            delete: state[*key].is_none(),
+                            value: state[*key].clone().unwrap_or_default(),
+                            ttl_ms: ttl_of.get(*key).copied().unwrap_or(0),
+                        })
+                        .collect(),
+                    app_func: format!("{}/{}", app_name, func_name),
+                },
+                Some(Duration::from_secs(30)),
+            )
+            .await;
+        let txn_id = match prepare {
+            Ok(prepare) if prepare.success => prepare.txn_id,
+            Ok(prepare) => {
+                return Ok(aborted(format!(
+                    "prepare failed: {}, conflicts: {:?}",
+                    prepare.message, prepare.conflicts
+                )))
+            }
+            Err(err) => return Ok(aborted(format!("prepare failed: {}", err))),
+        };
+
+        // 3. commit, the reserved datasets are only changed by this transaction,
+        //  so each one goes from its read version to the next
+        let mut committed_versions: HashMap<Vec<u8>, DataVersion> = HashMap::new();
+        let mut left_to_master = false;
+        for key in changed {
+            let unique_id = new_data_unique_id_fn_kv(key);
+            let read_version = snapshot[key].0;
+            let res = match &state[key] {
+                Some(value) => {
+                    let _ = committed_versions.insert(key.clone(), read_version + 1);
+                    self.view
+                        .data_general()
+                        .write_data_with(
+                            unique_id,
+                            vec![DataItemArgWrapper::new(value.clone())],
+                            Some(self.func_call_write_ctx(app_name, func_name)),
+                            WriteDataOpts {
+                                condition: Some(
+                                    proto::data_write_condition::Cond::ExpectedVersion(
+                                        read_version,
+                                    ),
+                                ),
+                                txn_id,
+                                ttl_ms: ttl_of.get(key).copied().unwrap_or(0),
+                                ..Default::default()
+                            },
+                        )
+                        .await
+                }
+                None => {
+                    let _ = committed_versions.insert(key.clone(), 0);
+                    self.view
+                        .data_general()
+                        .delete_data_in_txn(
+                            &unique_id,
+                            txn_id,
+                            read_version,
+                            &self.func_call_role(app_name, func_name),
+                        )
+                        .await
+                }
+            };
+            match res {
+                Ok(CondWriteRes::Written(_)) => {}
+                // only master applying the transaction moves a reserved dataset
+                Ok(CondWriteRes::Rejected(_)) => {}
+                Err(err) => {
+                    tracing::warn!(
+                        "kv txn {} write key {:?} failed: {}, left to master",
+                        txn_id,
+                        key,
+                        err
+                    );
+                    left_to_master = true;
+                    break;
+                }
+            }
+        }
+
+        // 4. release, an unfinished transaction stays reserved until master applies the rest
+        if !left_to_master {
+            self.kv_txn_finish(txn_id).await;
+        }
+        for (idx, key) in committed_version_of {
+            if let Some(proto::kv::kv_response::Resp::AtomicResp(resp)) =
+                responses[idx].resp.as_mut()
+            {
+                resp.version = committed_versions
+                    .get(&key)
+                    .copied()
+                    .unwrap_or(snapshot[&key].0);
+            }
+        }
+        tracing::debug!("kv txn {} committed", txn_id);
+        Ok(KvResponses {
+            responses,
+            txn_status: Some(proto::kv::KvTxnStatus {
+                committed: true,
+                reason: "".to_owned(),
+            }),
+        })
+    }
+
+    async fn kv_txn_finish(&self, txn_id: u64) {
+        let p2p = self.view.p2p();
+        if let Err(err) = self
+            .rpc_caller_kv_txn_finish
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                proto::kv::KvTxnFinishRequest { txn_id },
+                Some(Duration::from_secs(30)),
+            )
+            .await
+        {
+            // master takes the transaction over after the timeout
+            tracing::warn!("kv txn {} finish failed: {}", txn_id, err);
+        }
+    }
+
+    pub fn func_call_write_ctx(
+        &self,
+        app_name: &str,
+        func_name: &str,
+    ) -> (
+        crate::sys::NodeID,
+        proto::DataOpeType,
+        proto::data_schedule_context::OpeRole,
+    ) {
+        (
+            self.view.p2p().nodes_config.this_node(),
+            proto::DataOpeType::Write,
+            proto::data_schedule_context::OpeRole::FuncCall(
+                self.func_call_role(app_name, func_name),
+            ),
+        )
+    }
+
+    /// function on this node a write or delete is made for
+    pub fn func_call_role(&self, app_name: &str, func_name: &str) -> proto::DataOpeRoleFuncCall {
+        proto::DataOpeRoleFuncCall {
+            app_func: format!("{}/{}", app_name, func_name),
+            node_id: self.view.p2p().nodes_config.this_node(),
+        }
+    }
+
+    /// value and dataset version of the key, waits for in-flight writes of the latest version
+    async fn get_kv_latest(&self, key: &[u8]) -> WSResult<Option<(DataVersion, Vec<u8>)>> {
+        let uid = new_data_unique_id_fn_kv(key);
+        let Some((meta, splits)) = self.view.data_general().get_data_latest(&uid).await? else {
+            return Ok(None);
+        };
+        let version = meta.version;
+        let mut kvs = Self::convert_get_data_res_to_kv_response(key.to_owned(), uid, meta, splits)?;
+        Ok(Some((version, kvs.pop().unwrap().value)))
+    }
+
+    async fn handle_kv_cond_set(
+        &self,
+        app_name: &str,
+        func_name: &str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        condition: proto::data_write_condition::Cond,
+    ) -> KvResponse {
+        tracing::debug!("handle_kv_cond_set: key: {:?}, cond: {:?}", key, condition);
+        let res = self
+            .view
+            .data_general()
+            .write_data_cond(
+                new_data_unique_id_fn_kv(&key),
+                vec![DataItemArgWrapper::new(value)],
+                Some(self.func_call_write_ctx(app_name, func_name)),
+                Some(condition),
+            )
+            .await;
+        match res {
+            Ok(CondWriteRes::Written(version)) => KvResponse::new_atomic(true, version, vec![]),
+            Ok(CondWriteRes::Rejected(version)) => {
+                // give the current value back so the caller can retry without another get
+                let cur = match self.get_kv_latest(&key).await {
+                    Ok(cur) => cur.map(|(_, value)| value).unwrap_or_default(),
+                    Err(err) => {
+                        tracing::warn!("get current kv after rejected error:{:?}", err);
+                        vec![]
+                    }
+                };
+                KvResponse::new_atomic(false, version, cur)
+            }
+            Err(err) => {
+                tracing::warn!("conditional set kv error:{:?}", err);
+                KvResponse::new_atomic(false, 0, vec![])
+            }
+        }
+    }
+
+    /// the value is stored as decimal string, missing key counts as 0,
+    ///  the addition is done by master under the version schedule
+    async fn handle_kv_incr(
+        &self,
+        app_name: &str,
+        func_name: &str,
+        incr: proto::kv::kv_request::KvIncrRequest,
+    ) -> KvResponse {
+        tracing::debug!("handle_kv_incr: {:?}", incr);
+        let res = self
+            .view
+            .data_general()
+            .incr_data(
+                new_data_unique_id_fn_kv(&incr.key),
+                incr.delta,
+                self.func_call_write_ctx(app_name, func_name),
+            )
+            .await;
+        match res {
+            Ok(Ok((version, value))) => KvResponse::new_atomic(true, version, value),
+            Ok(Err(version)) => {
+                tracing::warn!(
+                    "incr kv key:{:?} value is not an integer or overflow",
+                    incr.key
+                );
+                let cur = match self.get_kv_latest(&incr.key).await {
+                    Ok(cur) => cur.map(|(_, value)| value).unwrap_or_default(),
+                    Err(err) => {
+                        tracing::warn!("get current kv after rejected incr error:{:?}", err);
+                        vec![]
+                    }
+                };
+                KvResponse::new_atomic(false, version, cur)
+            }
+            Err(err) => {
+                tracing::warn!("incr kv write error:{:?}", err);
+                KvResponse::new_atomic(false, 0, vec![])
+            }
+        }
+    }
+
     async fn handle_kv_set(
         &self,
         app_name: &str,
@@ -212,7 +728,7 @@ impl KvUserClient {
         let data_general = self.view.data_general();
         //返回结果未处理 曾俊
         if let Err(e) = data_general
-            .write_data(
+            .write_data_with(
                 new_data_unique_id_fn_kv(&key),
                 //原代码：
                 // vec![proto::DataItem {
@@ -228,6 +744,10 @@ impl KvUserClient {
                         node_id: cur_node,
                     }),
                 )),
+                WriteDataOpts {
+                    ttl_ms: set.ttl_ms,
+                    ..Default::default()
+                },
             )
             .await{
                 tracing::error!("Failed to write data: {}", e);
@@ -285,11 +805,93 @@ impl KvUserClient {
         }])
     }
 
+    /// one page of keys from the fn kv index on master
+    pub async fn scan_kv_keys(
+        &self,
+        range: proto::kv::KeyRange,
+        prefix: Vec<u8>,
+        limit: u32,
+        cursor: Vec<u8>,
+    ) -> WSResult<proto::kv::KvScanIndexResponse> {
+        let p2p = self.view.p2p();
+        self.rpc_caller_kv_scan_index
+            .call(
+                p2p,
+                p2p.nodes_config.get_master_node(),
+                proto::kv::KvScanIndexRequest {
+                    start: range.start,
+                    end: range.end,
+                    prefix,
+                    limit,
+                    cursor,
+                },
+                Some(Duration::from_secs(30)),
+            )
+            .await
+    }
+
+    /// all keys in the range, at most `KV_RANGE_OPE_MAX_KEYS`
+    async fn scan_kv_keys_all(&self, range: proto::kv::KeyRange) -> WSResult<Vec<Vec<u8>>> {
+        let mut keys = vec![];
+        let mut cursor = vec![];
+        loop {
+            let page = self
+                .scan_kv_keys(range.clone(), vec![], 0, cursor)
+                .await?;
+            keys.extend(page.keys);
+            if page.next_cursor.is_empty() || keys.len() >= KV_RANGE_OPE_MAX_KEYS {
+                keys.truncate(KV_RANGE_OPE_MAX_KEYS);
+                return Ok(keys);
+            }
+            cursor = page.next_cursor;
+        }
+    }
+
+    async fn handle_kv_scan(&self, scan: proto::kv::kv_request::KvScanRequest) -> KvResponse {
+        tracing::debug!("handle_kv_scan:{:?}", scan);
+        let page = match self
+            .scan_kv_keys(scan.range.unwrap(), scan.prefix, scan.limit, scan.cursor)
+            .await
+        {
+            Ok(page) => page,
+            Err(err) => {
+                tracing::warn!("scan kv keys error:{:?}", err);
+                return KvResponse::new_scan(vec![], vec![]);
+            }
+        };
+        let mut kvs = Vec::with_capacity(page.keys.len());
+        for key in page.keys {
+            // key deleted after the index scan is skipped
+            kvs.extend(self.handle_kv_get_one(key).await);
+        }
+        KvResponse::new_scan(kvs, page.next_cursor)
+    }
+
     async fn handle_kv_get(&self, get: proto::kv::kv_request::KvGetRequest) -> KvResponse {
-        tracing::debug!("handle_kv_get:{:?}", get);
+        let range = get.range.unwrap();
+        if range.end.is_empty() {
+            return KvResponse::new_common(self.handle_kv_get_one(range.start).await);
+        }
+        tracing::debug!("handle_kv_get range:{:?}", range);
+        let keys = match self.scan_kv_keys_all(range).await {
+            Ok(keys) => keys,
+            Err(err) => {
+                tracing::warn!("get kv range error:{:?}", err);
+                return KvResponse::new_common(vec![]);
+            }
+        };
+        let mut kvs = Vec::with_capacity(keys.len());
+        for key in keys {
+            kvs.extend(self.handle_kv_get_one(key).await);
+        }
+        KvResponse::new_common(kvs)
+    }
+
+    async fn handle_kv_get_one(&self, key: Vec<u8>) -> Vec<proto::kv::KvPair> {
+        tracing::debug!("handle_kv_get_one:{:?}", key);
 
         let data_general = self.view.data_general();
-        let uid = new_data_unique_id_fn_kv(&get.range.as_ref().unwrap().start);
+        let uid = new_data_unique_id_fn_kv(&key);
         let got = data_general
             .get_or_del_data(GetOrDelDataArg {
                 meta: None,
@@ -298,9 +900,9 @@ impl KvUserClient {
             })
             .await;
 
-        let got = match got {
+        match got {
             Ok((meta, splits)) => match Self::convert_get_data_res_to_kv_response(
-                get.range.unwrap().start,
+                key,
                 uid,
                 meta,
                 splits,
@@ -319,25 +921,53 @@ impl KvUserClient {
                 tracing::warn!("get kv data error:{:?}", err);
                 vec![]
             }
+        }
+    }
+
+    async fn handle_kv_delete(
+        &self,
+        app_name: &str,
+        func_name: &str,
+        delete: proto::kv::kv_request::KvDeleteRequest,
+    ) -> KvResponse {
+        let deleted_by = self.func_call_role(app_name, func_name);
+        let range = delete.range.unwrap();
+        if range.end.is_empty() {
+            return KvResponse::new_common(
+                self.handle_kv_delete_one(range.start, &deleted_by).await,
+            );
+        }
+        tracing::debug!("handle_kv_delete range:{:?}", range);
+        let keys = match self.scan_kv_keys_all(range).await {
+            Ok(keys) => keys,
+            Err(err) => {
+                tracing::warn!("delete kv range error:{:?}", err);
+                return KvResponse::new_common(vec![]);
+            }
         };
-        KvResponse::new_common(got)
+        let mut deleted = Vec::with_capacity(keys.len());
+        for key in keys {
+            deleted.extend(self.handle_kv_delete_one(key, &deleted_by).await);
+        }
+        KvResponse::new_common(deleted)
     }
-    async fn handle_kv_delete(&self, delete: proto::kv::kv_request::KvDeleteRequest) -> KvResponse {
-        tracing::debug!("handle_kv_delete:{:?}", delete);
+
+    async fn handle_kv_delete_one(
+        &self,
+        key: Vec<u8>,
+        deleted_by: &proto::DataOpeRoleFuncCall,
+    ) -> Vec<proto::kv::KvPair> {
+        tracing::debug!("handle_kv_delete_one:{:?}", key);
 
         let data_general = self.view.data_general();
-        let uid = new_data_unique_id_fn_kv(&delete.range.as_ref().unwrap().start);
+        let uid = new_data_unique_id_fn_kv(&key);
         let deleted = data_general
-            .get_or_del_data(GetOrDelDataArg {
-                meta: None,
-                unique_id: uid.clone(),
-                ty: GetOrDelDataArgType::Delete,
-            })
+            .delete_data_for(uid.clone(), deleted_by.clone())
             .await;
 
-        let deleted = match deleted {
+        match deleted {
             Ok((deleted_meta, deleted_splits)) => match Self::convert_get_data_res_to_kv_response(
-                delete.range.unwrap().start,
+                key,
                 uid,
                 deleted_meta,
                 deleted_splits,
@@ -356,8 +986,7 @@ impl KvUserClient {
                 tracing::warn!("delete kv data error:{:?}", err);
                 vec![]
             }
-        };
-        KvResponse::new_common(deleted)
+        }
     }
     // async fn handle_kv_lock(
     //     &self,
@@ -433,71 +1062,102 @@ mod test {
 
     use super::KvUserClientView;
     use crate::general::{
+        data::kv_interface::KvOps,
+        data::m_data_general::{dataitem::DataItemArgWrapper, new_data_unique_id_fn_kv},
         network::{
             proto::{
                 self,
-                kv::{KvRequest, KvRequests},
+                kv::{KvRequest, KvRequests, KvResponses},
             },
-            proto_ext::KvRequestExt,
+            proto_ext::{KvRequestExt, ProtoExtKvResponse},
         },
         test_utils,
     };
+    use crate::result::{WSError, WSResult, WsPermissionErr};
+    use crate::sys::LogicalModulesRef;
+    use crate::worker::kv_access;
+
+    const TEST_APP: &str = "test_app";
+    const TEST_FUNC: &str = "test_func";
+
+    /// Worker view with the test app, its function may set, get and delete any key
+    async fn test_app_view(sys: LogicalModulesRef) -> KvUserClientView {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let view = KvUserClientView::new(sys);
+        view.appmeta_manager()
+            .insert_test_app_meta(
+                TEST_APP,
+                r#"
+fns:
+  test_func:
+    kvs:
+      "{}": [set, get, delete]
+"#,
+            )
+            .await;
+        view
+    }
+
+    /// Batch of requests made by the test function
+    async fn kv_requests(
+        view: &KvUserClientView,
+        atomic: bool,
+        requests: Vec<KvRequest>,
+    ) -> KvResponses {
+        view.kv_user_client()
+            .kv_requests(
+                TEST_APP,
+                TEST_FUNC,
+                KvRequests {
+                    app: TEST_APP.to_owned(),
+                    func: TEST_FUNC.to_owned(),
+                    prev_kv_opeid: -1,
+                    atomic,
+                    requests,
+                },
+            )
+            .await
+            .unwrap()
+    }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_kv_user_client() {
         let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
-        tokio::time::sleep(Duration::from_secs(3)).await;
-        let view = KvUserClientView::new(sys2);
-        let app = "test_app";
-        let func = "test_func";
+        let view = test_app_view(sys2).await;
         let test_key = "test_key";
         let test_value = "test_value";
 
         // first time get should be none
         {
-            let res = view
-                .kv_user_client()
-                .kv_requests(
-                    app,
-                    func,
-                    KvRequests {
-                        app: app.to_owned(),
-                        func: func.to_owned(),
-                        prev_kv_opeid: -1,
-                        requests: vec![KvRequest::new_get(test_key.as_bytes().to_owned())],
-                    },
-                )
-                .await
-                .unwrap();
+            let res = kv_requests(
+                &view,
+                false,
+                vec![KvRequest::new_get(test_key.as_bytes().to_owned())],
+            )
+            .await;
             assert!(res.responses.len() == 1);
             match res.responses[0].resp.clone().unwrap() {
                 proto::kv::kv_response::Resp::CommonResp(kv_response) => {
                     assert!(kv_response.kvs.len() == 0);
                 }
-                proto::kv::kv_response::Resp::LockId(_) => panic!(),
+                proto::kv::kv_response::Resp::LockId(_)
+                | proto::kv::kv_response::Resp::AtomicResp(_)
+                | proto::kv::kv_response::Resp::ScanResp(_) => panic!(),
             }
             tracing::debug!("first time get is none");
         }
 
         // (insert and get then delete twice) *3
         for _ in 0..3 {
-            let res = view
-                .kv_user_client()
-                .kv_requests(
-                    app,
-                    func,
-                    KvRequests {
-                        app: app.to_owned(),
-                        func: func.to_owned(),
-                        prev_kv_opeid: -1,
-                        requests: vec![KvRequest::new_set(proto::kv::KvPair {
-                            key: test_key.as_bytes().to_owned(),
-                            value: test_value.as_bytes().to_owned(),
-                        })],
-                    },
-                )
-                .await
-                .unwrap();
+            let res = kv_requests(
+                &view,
+                false,
+                vec![KvRequest::new_set(proto::kv::KvPair {
+                    key: test_key.as_bytes().to_owned(),
+                    value: test_value.as_bytes().to_owned(),
+                })],
+            )
+            .await;
             assert!(res.responses.len() == 1);
             match res.responses[0].resp.clone().unwrap() {
                 proto::kv::kv_response::Resp::CommonResp(kv_response) => {
@@ -508,25 +1168,19 @@ mod test {
                     //     test_value
                     // );
                 }
-                proto::kv::kv_response::Resp::LockId(_) => panic!(),
+                proto::kv::kv_response::Resp::LockId(_)
+                | proto::kv::kv_response::Resp::AtomicResp(_)
+                | proto::kv::kv_response::Resp::ScanResp(_) => panic!(),
             }
             tracing::debug!("set success");
 
             // get after set
-            let res = view
-                .kv_user_client()
-                .kv_requests(
-                    app,
-                    func,
-                    KvRequests {
-                        app: app.to_owned(),
-                        func: func.to_owned(),
-                        prev_kv_opeid: -1,
-                        requests: vec![KvRequest::new_get(test_key.as_bytes().to_owned())],
-                    },
-                )
-                .await
-                .unwrap();
+            let res = kv_requests(
+                &view,
+                false,
+                vec![KvRequest::new_get(test_key.as_bytes().to_owned())],
+            )
+            .await;
             assert!(res.responses.len() == 1);
             match res.responses[0].resp.clone().unwrap() {
                 proto::kv::kv_response::Resp::CommonResp(kv_response) => {
@@ -534,58 +1188,634 @@ mod test {
                     assert!(kv_response.kvs[0].key == test_key.as_bytes().to_owned());
                     assert!(kv_response.kvs[0].value == test_value.as_bytes().to_owned());
                 }
-                proto::kv::kv_response::Resp::LockId(_) => panic!(),
+                proto::kv::kv_response::Resp::LockId(_)
+                | proto::kv::kv_response::Resp::AtomicResp(_)
+                | proto::kv::kv_response::Resp::ScanResp(_) => panic!(),
             }
             tracing::debug!("get after set success");
 
             // delete after get
-            let res = view
-                .kv_user_client()
-                .kv_requests(
-                    app,
-                    func,
-                    KvRequests {
-                        app: app.to_owned(),
-                        func: func.to_owned(),
-                        prev_kv_opeid: -1,
-                        requests: vec![KvRequest::new_delete(test_key.as_bytes().to_owned())],
-                    },
-                )
-                .await
-                .unwrap();
+            let res = kv_requests(
+                &view,
+                false,
+                vec![KvRequest::new_delete(test_key.as_bytes().to_owned())],
+            )
+            .await;
             assert!(res.responses.len() == 1);
             match res.responses[0].resp.clone().unwrap() {
                 proto::kv::kv_response::Resp::CommonResp(kv_response) => {
                     assert!(kv_response.kvs[0].key == test_key.as_bytes().to_owned());
                     assert!(kv_response.kvs[0].value == test_value.as_bytes().to_owned());
                 }
-                proto::kv::kv_response::Resp::LockId(_) => panic!(),
+                proto::kv::kv_response::Resp::LockId(_)
+                | proto::kv::kv_response::Resp::AtomicResp(_)
+                | proto::kv::kv_response::Resp::ScanResp(_) => panic!(),
             }
             tracing::debug!("delete after get success");
 
             // delete again will be none
-            let res = view
-                .kv_user_client()
-                .kv_requests(
-                    app,
-                    func,
-                    KvRequests {
-                        app: app.to_owned(),
-                        func: func.to_owned(),
-                        prev_kv_opeid: -1,
-                        requests: vec![KvRequest::new_delete(test_key.as_bytes().to_owned())],
-                    },
-                )
-                .await
-                .unwrap();
+            let res = kv_requests(
+                &view,
+                false,
+                vec![KvRequest::new_delete(test_key.as_bytes().to_owned())],
+            )
+            .await;
             assert!(res.responses.len() == 1);
             match res.responses[0].resp.clone().unwrap() {
                 proto::kv::kv_response::Resp::CommonResp(kv_response) => {
                     assert!(kv_response.kvs.len() == 0);
                 }
-                proto::kv::kv_response::Resp::LockId(_) => panic!(),
+                proto::kv::kv_response::Resp::LockId(_)
+                | proto::kv::kv_response::Resp::AtomicResp(_)
+                | proto::kv::kv_response::Resp::ScanResp(_) => panic!(),
             }
             tracing::debug!("delete again is none");
         }
     }
+
+    /// put_if_absent, incr and cas on a fresh key
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kv_atomic_ops() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = test_app_view(sys2).await;
+        let counter_key = "test_counter".as_bytes().to_owned();
+        let atomic = |req: KvRequest| {
+            let view = &view;
+            async move {
+                let res = kv_requests(view, false, vec![req]).await;
+                assert!(res.responses.len() == 1);
+                res.responses[0].atomic_resp().unwrap().clone()
+            }
+        };
+        let kv = |value: &str| proto::kv::KvPair {
+            key: counter_key.clone(),
+            value: value.as_bytes().to_owned(),
+        };
+
+        let first = atomic(KvRequest::new_put_if_absent(kv("1"))).await;
+        assert!(first.success);
+        let second = atomic(KvRequest::new_put_if_absent(kv("5"))).await;
+        assert!(!second.success);
+        assert_eq!(second.value, "1".as_bytes());
+
+        let incr = atomic(KvRequest::new_incr(counter_key.clone(), 2)).await;
+        assert!(incr.success);
+        assert_eq!(incr.value, "3".as_bytes());
+        assert!(incr.version > first.version);
+
+        // stale version is rejected
+        let stale = atomic(KvRequest::new_cas(kv("10"), first.version)).await;
+        assert!(!stale.success);
+        assert_eq!(stale.version, incr.version);
+        let cas = atomic(KvRequest::new_cas(kv("10"), incr.version)).await;
+        assert!(cas.success);
+
+        // concurrent incrs are all added by master, none is lost or refused
+        let incrs = futures::future::join_all(
+            (0..32).map(|_| atomic(KvRequest::new_incr(counter_key.clone(), 1))),
+        )
+        .await;
+        assert!(incrs.iter().all(|incr| incr.success));
+        let mut values: Vec<i64> = incrs
+            .iter()
+            .map(|incr| std::str::from_utf8(&incr.value).unwrap().parse().unwrap())
+            .collect();
+        values.sort();
+        assert_eq!(values, (11..=42).collect::<Vec<i64>>());
+
+        let _ = kv_requests(
+            &view,
+            false,
+            vec![KvRequest::new_delete(counter_key.clone())],
+        )
+        .await;
+    }
+
+    /// prefix scan with pagination, and range get
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kv_scan() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = test_app_view(sys2).await;
+        let keys = ["scan_a1", "scan_a2", "scan_b"];
+        let _ = kv_requests(
+            &view,
+            false,
+            keys.into_iter()
+                .map(|key| {
+                    KvRequest::new_set(proto::kv::KvPair {
+                        key: key.as_bytes().to_owned(),
+                        value: key.as_bytes().to_owned(),
+                    })
+                })
+                .collect(),
+        )
+        .await;
+
+        let mut cursor = vec![];
+        let mut scanned = vec![];
+        loop {
+            let res = kv_requests(
+                &view,
+                false,
+                vec![KvRequest::new_scan(
+                    proto::kv::KeyRange {
+                        start: vec![],
+                        end: vec![],
+                    },
+                    "scan_a".as_bytes().to_owned(),
+                    1,
+                    cursor,
+                )],
+            )
+            .await;
+            let page = res.responses[0].scan_resp().unwrap().clone();
+            assert!(page.kvs.len() <= 1);
+            scanned.extend(page.kvs.into_iter().map(|kv| kv.key));
+            if page.next_cursor.is_empty() {
+                break;
+            }
+            cursor = page.next_cursor;
+        }
+        assert_eq!(scanned, vec![b"scan_a1".to_vec(), b"scan_a2".to_vec()]);
+
+        let res = kv_requests(
+            &view,
+            false,
+            vec![KvRequest {
+                op: Some(proto::kv::kv_request::Op::Get(
+                    proto::kv::kv_request::KvGetRequest {
+                        range: Some(proto::kv::KeyRange {
+                            start: "scan_a2".as_bytes().to_owned(),
+                            end: "scan_c".as_bytes().to_owned(),
+                        }),
+                    },
+                )),
+            }],
+        )
+        .await;
+        let kvs = res.responses[0].common_kvs().unwrap();
+        assert_eq!(kvs.len(), 2);
+        assert_eq!(kvs[1].value, "scan_b".as_bytes());
+
+        let _ = kv_requests(
+            &view,
+            false,
+            keys.into_iter()
+                .map(|key| KvRequest::new_delete(key.as_bytes().to_owned()))
+                .collect(),
+        )
+        .await;
+    }
+
+    /// atomic batch commits all or nothing
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kv_atomic_batch() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = test_app_view(sys2).await;
+        let kv = |key: &str, value: &str| proto::kv::KvPair {
+            key: key.as_bytes().to_owned(),
+            value: value.as_bytes().to_owned(),
+        };
+        let get = |key: &str| KvRequest::new_get(key.as_bytes().to_owned());
+
+        let res = kv_requests(
+            &view,
+            true,
+            vec![
+                KvRequest::new_set(kv("txn_a", "1")),
+                KvRequest::new_put_if_absent(kv("txn_b", "2")),
+                get("txn_a"),
+            ],
+        )
+        .await;
+        assert!(res.txn_status.as_ref().unwrap().committed);
+        assert_eq!(res.responses.len(), 3);
+        assert_eq!(
+            res.responses[2].common_kvs().unwrap()[0].value,
+            "1".as_bytes()
+        );
+
+        // txn_b exists, so nothing of this batch is applied
+        let res = kv_requests(
+            &view,
+            true,
+            vec![
+                KvRequest::new_set(kv("txn_a", "10")),
+                KvRequest::new_put_if_absent(kv("txn_b", "20")),
+            ],
+        )
+        .await;
+        assert!(!res.txn_status.as_ref().unwrap().committed);
+        assert!(res.responses.is_empty());
+
+        let res = kv_requests(&view, true, vec![get("txn_a"), get("txn_b")]).await;
+        assert!(res.txn_status.as_ref().unwrap().committed);
+        assert_eq!(
+            res.responses[0].common_kvs().unwrap()[0].value,
+            "1".as_bytes()
+        );
+        assert_eq!(
+            res.responses[1].common_kvs().unwrap()[0].value,
+            "2".as_bytes()
+        );
+
+        let _ = kv_requests(
+            &view,
+            false,
+            vec![
+                KvRequest::new_delete("txn_a".as_bytes().to_owned()),
+                KvRequest::new_delete("txn_b".as_bytes().to_owned()),
+            ],
+        )
+        .await;
+    }
+
+    /// key with ttl is removed by the master after expiry
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kv_ttl() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = test_app_view(sys2).await;
+        let get = || KvRequest::new_get("ttl_key".as_bytes().to_owned());
+        let _ = kv_requests(
+            &view,
+            false,
+            vec![KvRequest {
+                op: Some(proto::kv::kv_request::Op::Set(
+                    proto::kv::kv_request::KvPutRequest {
+                        kv: Some(proto::kv::KvPair {
+                            key: "ttl_key".as_bytes().to_owned(),
+                            value: "ttl_value".as_bytes().to_owned(),
+                        }),
+                        ttl_ms: 500,
+                    },
+                )),
+            }],
+        )
+        .await;
+        let res = kv_requests(&view, false, vec![get()]).await;
+        assert_eq!(res.responses[0].common_kvs().unwrap().len(), 1);
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let res = kv_requests(&view, false, vec![get()]).await;
+        assert_eq!(res.responses[0].common_kvs().unwrap().len(), 0);
+    }
+
+    /// repeated reads are served by the cached meta, and still see the new writes
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kv_cached_meta() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = &test_app_view(sys2).await;
+        let set = |value: &str| {
+            KvRequest::new_set(proto::kv::KvPair {
+                key: "cached_key".as_bytes().to_owned(),
+                value: value.as_bytes().to_owned(),
+            })
+        };
+        let get = || async move {
+            let res = kv_requests(
+                view,
+                false,
+                vec![KvRequest::new_get("cached_key".as_bytes().to_owned())],
+            )
+            .await;
+            res.responses[0].common_kvs().unwrap()[0].value.clone()
+        };
+        let uid = &new_data_unique_id_fn_kv(
+            &view
+                .kv_user_client()
+                .fn_kv_ns_key(TEST_APP, TEST_FUNC, KvOps::Get, b"cached_key")
+                .await
+                .unwrap(),
+        );
+        // (version, whether it's a cache hit) of the meta the next read uses
+        let cached_meta = || async move {
+            let (meta, hit) = view.data_general().get_datameta_cached(uid).await.unwrap();
+            (meta.version, hit)
+        };
+
+        let _ = kv_requests(view, false, vec![set("v1")]).await;
+        assert_eq!(get().await, "v1".as_bytes());
+        // the read left the meta in the cache, the following ones are served by it
+        let (v1_version, hit) = cached_meta().await;
+        assert!(hit);
+        for _ in 0..3 {
+            assert_eq!(get().await, "v1".as_bytes());
+            assert_eq!(cached_meta().await, (v1_version, true));
+        }
+        // the write drops the cached meta
+        let _ = kv_requests(view, false, vec![set("v2")]).await;
+        let (v2_version, hit) = cached_meta().await;
+        assert!(!hit);
+        assert!(v2_version > v1_version);
+        assert_eq!(get().await, "v2".as_bytes());
+        assert_eq!(cached_meta().await, (v2_version, true));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kv_access_denied() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = KvUserClientView::new(sys2);
+        let app = "access_test";
+        view.appmeta_manager()
+            .insert_test_app_meta(
+                app,
+                r#"
+fns:
+  reader:
+    kvs:
+      access_test_{}: [get]
+"#,
+            )
+            .await;
+        let requests = |func: &'static str, requests: Vec<KvRequest>| {
+            let view = view.clone();
+            async move {
+                view.kv_user_client()
+                    .kv_requests(
+                        app,
+                        func,
+                        KvRequests {
+                            // not trusted, the executing function is checked
+                            app: "test_app".to_owned(),
+                            func: "test_func".to_owned(),
+                            prev_kv_opeid: -1,
+                            atomic: false,
+                            requests,
+                        },
+                    )
+                    .await
+            }
+        };
+        let key = || "access_test_1".as_bytes().to_owned();
+        let denied = |res: WSResult<KvResponses>, ope: &str| match res {
+            Err(WSError::WsPermissionErr(WsPermissionErr::AccessKeyPermissionDenied {
+                ope: denied_ope,
+                ..
+            })) => assert_eq!(denied_ope, ope),
+            res => panic!("expect denied {}, got {:?}", ope, res),
+        };
+        let before = kv_access::metrics();
+
+        let _ = requests("reader", vec![KvRequest::new_get(key())])
+            .await
+            .unwrap();
+        let _ = requests(
+            "reader",
+            vec![KvRequest::new_scan(
+                proto::kv::KeyRange {
+                    start: vec![],
+                    end: vec![],
+                },
+                "access_test_".as_bytes().to_owned(),
+                0,
+                vec![],
+            )],
+        )
+        .await
+        .unwrap();
+
+        let set = || {
+            KvRequest::new_set(proto::kv::KvPair {
+                key: key(),
+                value: "v".as_bytes().to_owned(),
+            })
+        };
+        denied(requests("reader", vec![set()]).await, "set");
+        // the whole batch is refused
+        denied(
+            requests(
+                "reader",
+                vec![KvRequest::new_get(key()), KvRequest::new_delete(key())],
+            )
+            .await,
+            "delete",
+        );
+        let other = KvRequest::new_get("other".as_bytes().to_owned());
+        denied(requests("reader", vec![other]).await, "get");
+        // a function not in app.yaml
+        denied(requests("writer", vec![set()]).await, "set");
+
+        let after = kv_access::metrics();
+        assert!(after.denied_set >= before.denied_set + 2);
+        assert!(after.denied_delete >= before.denied_delete + 1);
+        assert!(after.denied_get >= before.denied_get + 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_kv_app_namespace() {
+        let (_hold, _sys1, sys2) = test_utils::get_test_sys().await;
+        let view = KvUserClientView::new(sys2);
+        let appmeta_manager = view.appmeta_manager();
+        appmeta_manager
+            .insert_test_app_meta(
+                "ns_owner",
+                r#"
+fns:
+  rw:
+    kvs:
+      "{}": [set, get, delete]
+"#,
+            )
+            .await;
+        appmeta_manager
+            .insert_test_app_meta(
+                "ns_other",
+                r#"
+fns:
+  rw:
+    kvs:
+      "{}": [set, get, delete]
+shared_kvs:
+  - app: ns_owner
+    kvs:
+      - ns_shared_{}
+"#,
+            )
+            .await;
+        let requests = |app: &'static str, requests: Vec<KvRequest>| {
+            let view = view.clone();
+            async move {
+                view.kv_user_client()
+                    .kv_requests(
+                        app,
+                        "rw",
+                        KvRequests {
+                            app: app.to_owned(),
+                            func: "rw".to_owned(),
+                            prev_kv_opeid: -1,
+                            atomic: false,
+                            requests,
+                        },
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+        let set = |key: &str, value: &str| {
+            KvRequest::new_set(proto::kv::KvPair {
+                key: key.as_bytes().to_owned(),
+                value: value.as_bytes().to_owned(),
+            })
+        };
+        let get = |app: &'static str, key: &'static str| async move {
+            let res = requests(app, vec![KvRequest::new_get(key.as_bytes().to_owned())]).await;
+            res.responses[0].common_kvs().unwrap().clone()
+        };
+
+        // the same key of two apps doesn't clobber each other
+        let _ = requests("ns_owner", vec![set("ns_slice_0", "owner")]).await;
+        let _ = requests("ns_other", vec![set("ns_slice_0", "other")]).await;
+        let kvs = get("ns_owner", "ns_slice_0").await;
+        assert_eq!(kvs[0].key, "ns_slice_0".as_bytes());
+        assert_eq!(kvs[0].value, "owner".as_bytes());
+        assert_eq!(
+            get("ns_other", "ns_slice_0").await[0].value,
+            "other".as_bytes()
+        );
+
+        // scan stays in the namespace of the app
+        let res = requests(
+            "ns_owner",
+            vec![KvRequest::new_scan(
+                proto::kv::KeyRange {
+                    start: vec![],
+                    end: vec![],
+                },
+                "ns_slice_".as_bytes().to_owned(),
+                0,
+                vec![],
+            )],
+        )
+        .await;
+        let scanned: Vec<_> = res.responses[0]
+            .scan_resp()
+            .unwrap()
+            .kvs
+            .iter()
+            .map(|kv| (kv.key.clone(), kv.value.clone()))
+            .collect();
+        assert_eq!(
+            scanned,
+            vec![(
+                "ns_slice_0".as_bytes().to_vec(),
+                "owner".as_bytes().to_vec()
+            )]
+        );
+
+        // imported keys are the ones of the sharing app
+        let _ = requests("ns_other", vec![set("ns_shared_0", "from other")]).await;
+        assert_eq!(
+            get("ns_owner", "ns_shared_0").await[0].value,
+            "from other".as_bytes()
+        );
+
+        let _ = requests(
+            "ns_owner",
+            vec![
+                KvRequest::new_delete("ns_slice_0".as_bytes().to_owned()),
+                KvRequest::new_delete("ns_shared_0".as_bytes().to_owned()),
+            ],
+        )
+        .await;
+        let _ = requests(
+            "ns_other",
+            vec![KvRequest::new_delete("ns_slice_0".as_bytes().to_owned())],
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_migrate_legacy_kv_keys() {
+        let (_hold, sys1, sys2) = test_utils::get_test_sys().await;
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        // node 0 is the master, node 1 the worker
+        let (master, worker) = (KvUserClientView::new(sys1), KvUserClientView::new(sys2));
+        let app = "migrate_test";
+        worker
+            .appmeta_manager()
+            .insert_test_app_meta(
+                app,
+                r#"
+fns:
+  rw:
+    kvs:
+      migrate_{}: [set, get, delete]
+"#,
+            )
+            .await;
+        // written by the app before keys were scoped per app
+        worker
+            .data_general()
+            .write_data(
+                new_data_unique_id_fn_kv(b"migrate_a"),
+                vec![DataItemArgWrapper::from_bytes(b"legacy".to_vec())],
+                Some((
+                    1,
+                    proto::DataOpeType::Write,
+                    proto::data_schedule_context::OpeRole::FuncCall(proto::DataOpeRoleFuncCall {
+                        app_func: format!("{}/rw", app),
+                        node_id: 1,
+                    }),
+                )),
+            )
+            .await
+            .unwrap();
+        // written by no function, it may be any app's
+        worker
+            .data_general()
+            .write_data(
+                new_data_unique_id_fn_kv(b"migrate_b"),
+                vec![DataItemArgWrapper::from_bytes(b"unowned".to_vec())],
+                None,
+            )
+            .await
+            .unwrap();
+
+        let migrate = |claim_unowned: bool| {
+            let master = master.clone();
+            async move {
+                master
+                    .data_general()
+                    .migrate_fn_kv_namespace(app, b"migrate_", claim_unowned)
+                    .await
+                    .unwrap()
+            }
+        };
+        let report = migrate(false).await;
+        assert_eq!(report.moved, 1);
+        assert_eq!(report.unowned, vec!["migrate_b".to_owned()]);
+        assert!(report.failed.is_empty());
+        // the legacy key is gone, the unowned one is left until claimed
+        let report = migrate(false).await;
+        assert_eq!((report.moved, report.unowned.len()), (0, 1));
+        let report = migrate(true).await;
+        assert_eq!(report.moved, 1);
+        assert!(report.unowned.is_empty());
+
+        let res = worker
+            .kv_user_client()
+            .kv_requests(
+                app,
+                "rw",
+                KvRequests {
+                    app: app.to_owned(),
+                    func: "rw".to_owned(),
+                    prev_kv_opeid: -1,
+                    atomic: false,
+                    requests: vec![
+                        KvRequest::new_get("migrate_a".as_bytes().to_owned()),
+                        KvRequest::new_get("migrate_b".as_bytes().to_owned()),
+                        KvRequest::new_delete("migrate_a".as_bytes().to_owned()),
+                        KvRequest::new_delete("migrate_b".as_bytes().to_owned()),
+                    ],
+                },
+            )
+            .await
+            .unwrap();
+        let kvs = res.responses[0].common_kvs().unwrap();
+        assert_eq!(kvs[0].key, "migrate_a".as_bytes());
+        assert_eq!(kvs[0].value, "legacy".as_bytes());
+        let kvs = res.responses[1].common_kvs().unwrap();
+        assert_eq!(kvs[0].value, "unowned".as_bytes());
+    }
 }
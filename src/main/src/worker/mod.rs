@@ -1,3 +1,5 @@
+pub mod kv_access;
+pub mod kv_namespace;
 pub mod m_http_handler;
 pub mod m_kv_user_client;
 pub mod m_worker;